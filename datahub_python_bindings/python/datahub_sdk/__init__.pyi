@@ -8,6 +8,7 @@ registration in src/lib.rs::datahub_sdk() also adds them.
 from __future__ import annotations
 
 import datetime
+import os
 from typing import Any, Iterable, Iterator, Mapping, Optional, Sequence, Union
 from uuid import UUID
 
@@ -501,6 +502,45 @@ class RetrieveFilter:
     def cursor(self) -> str | None: ...
 
 
+class SpoolSegment:
+    """One segment of a durable ingest spool."""
+    @property
+    def seq(self) -> int: ...
+    @property
+    def sealed(self) -> bool: ...
+    @property
+    def bytes(self) -> int: ...
+    @property
+    def records(self) -> int: ...
+    @property
+    def min_timestamp(self) -> datetime.datetime | None: ...
+    @property
+    def max_timestamp(self) -> datetime.datetime | None: ...
+
+
+class SpoolDropStats:
+    """Records and bytes dropped by the retention window and size cap since startup."""
+    @property
+    def retention_records(self) -> int: ...
+    @property
+    def retention_bytes(self) -> int: ...
+    @property
+    def size_cap_records(self) -> int: ...
+    @property
+    def size_cap_bytes(self) -> int: ...
+
+
+class SpooledDatapoint:
+    @property
+    def id(self) -> int | None: ...
+    @property
+    def external_id(self) -> str | None: ...
+    @property
+    def timestamp(self) -> str: ...
+    @property
+    def value(self) -> str: ...
+
+
 class TimeSeriesServiceSync:
     def list(self, limit: int | None = None) -> list[TimeSeries]: ...
     def create(self, input: list[TimeSeries]) -> list[TimeSeries]: ...
@@ -521,6 +561,17 @@ class TimeSeriesServiceSync:
     def retrieve_latest_datapoints(
         self, input: list[Identifiable]
     ) -> list[DatapointsCollectionDatapoints]: ...
+    # Durable spool administration; local disk I/O, synchronous on both clients.
+    def spool_segments(self) -> list[SpoolSegment]: ...
+    def peek_spool(self, seq: int, limit: int = 10) -> list[SpooledDatapoint]: ...
+    def export_spool_segment(self, seq: int, path: str | os.PathLike[str]) -> int: ...
+    def purge_spool(
+        self,
+        older_than: datetime.datetime | None = None,
+        external_id: str | None = None,
+        id: int | None = None,
+    ) -> int: ...
+    def spool_dropped(self) -> SpoolDropStats: ...
 
 
 class TimeSeriesServiceAsync:
@@ -545,6 +596,17 @@ class TimeSeriesServiceAsync:
     async def retrieve_latest_datapoints(
         self, input: list[Identifiable]
     ) -> list[DatapointsCollectionDatapoints]: ...
+    # Durable spool administration; local disk I/O, synchronous on both clients.
+    def spool_segments(self) -> list[SpoolSegment]: ...
+    def peek_spool(self, seq: int, limit: int = 10) -> list[SpooledDatapoint]: ...
+    def export_spool_segment(self, seq: int, path: str | os.PathLike[str]) -> int: ...
+    def purge_spool(
+        self,
+        older_than: datetime.datetime | None = None,
+        external_id: str | None = None,
+        id: int | None = None,
+    ) -> int: ...
+    def spool_dropped(self) -> SpoolDropStats: ...
 
 
 # ====================== Events ======================
@@ -736,6 +798,12 @@ class EventsServiceSync:
     def search_statuses(self, query: str, limit: int | None = None) -> list[str]: ...
     def list_sources(self, limit: int | None = None) -> list[str]: ...
     def search_sources(self, query: str, limit: int | None = None) -> list[str]: ...
+    # Durable spool administration; local disk I/O, synchronous on both clients.
+    def spool_segments(self) -> list[SpoolSegment]: ...
+    def peek_spool(self, seq: int, limit: int = 10) -> list[Event]: ...
+    def export_spool_segment(self, seq: int, path: str | os.PathLike[str]) -> int: ...
+    def purge_spool(self, older_than: datetime.datetime) -> int: ...
+    def spool_dropped(self) -> SpoolDropStats: ...
 
 
 class EventsServiceAsync:
@@ -761,6 +829,12 @@ class EventsServiceAsync:
     async def search_statuses(self, query: str, limit: int | None = None) -> list[str]: ...
     async def list_sources(self, limit: int | None = None) -> list[str]: ...
     async def search_sources(self, query: str, limit: int | None = None) -> list[str]: ...
+    # Durable spool administration; local disk I/O, synchronous on both clients.
+    def spool_segments(self) -> list[SpoolSegment]: ...
+    def peek_spool(self, seq: int, limit: int = 10) -> list[Event]: ...
+    def export_spool_segment(self, seq: int, path: str | os.PathLike[str]) -> int: ...
+    def purge_spool(self, older_than: datetime.datetime) -> int: ...
+    def spool_dropped(self) -> SpoolDropStats: ...
 
 
 # ====================== Datasets ======================
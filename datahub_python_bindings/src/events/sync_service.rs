@@ -246,3 +246,34 @@ impl PyEventsServiceSync {
         self.list_dimension(py, PyEventDimension::SOURCE, Some(query), limit)
     }
 }
+
+/// Durable spool inspection and administration. Local disk I/O, so these block (without the GIL)
+/// on the async service too.
+#[pymethods]
+impl PyEventsServiceSync {
+    fn spool_segments(&self, py: Python<'_>) -> Vec<crate::spool::PySpoolSegment> {
+        crate::spool::segments(py, &self.api_service.events.spool())
+    }
+
+    #[pyo3(signature = (seq, limit = 10))]
+    fn peek_spool(&self, py: Python<'_>, seq: u64, limit: usize) -> PyResult<Vec<PyEvent>> {
+        crate::spool::peek_events(py, &self.api_service, seq, limit)
+    }
+
+    fn export_spool_segment(
+        &self,
+        py: Python<'_>,
+        seq: u64,
+        path: std::path::PathBuf,
+    ) -> PyResult<u64> {
+        crate::spool::export_segment(py, &self.api_service.events.spool(), seq, path)
+    }
+
+    fn purge_spool(&self, py: Python<'_>, older_than: Bound<'_, PyAny>) -> PyResult<u64> {
+        crate::spool::purge_events(py, &self.api_service, &older_than)
+    }
+
+    fn spool_dropped(&self) -> crate::spool::PySpoolDropStats {
+        self.api_service.events.spool().dropped().into()
+    }
+}
@@ -5,6 +5,7 @@ mod files;
 mod labels;
 mod relations;
 mod resources;
+mod spool;
 mod subscriptions;
 pub mod timeseries;
 pub mod units;
@@ -865,6 +866,7 @@ fn datahub_sdk(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PyTimeSeriesFilterForm>()?;
     timeseries::register(m)?;
     events::register(m)?;
+    spool::register(m)?;
     datasets::register(m)?;
     files::register(m)?;
     subscriptions::register(m)?;
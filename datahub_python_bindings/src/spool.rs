@@ -0,0 +1,188 @@
+//! Inspection and administration of the durable ingest spools, shared by the sync and async
+//! time series and events services. Everything here is local disk I/O, so the async services
+//! expose these as plain (blocking) methods too; each releases the GIL while it runs.
+
+use crate::datetime::opt_py_datetime_to_utc;
+use crate::events::PyEvent;
+use chrono::{DateTime, Utc};
+use dataplatform_rust_sdk::ApiService;
+use dataplatform_rust_sdk::buffer::{DropStats, SegmentInfo, SpoolAdmin};
+use dataplatform_rust_sdk::generic::IdAndExtId;
+use dataplatform_rust_sdk::timeseries::SpoolDatapoint;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// One segment of a spool. Timestamps are the earliest and latest record in it; `None` when empty.
+#[pyclass(module = "datahub_sdk", name = "SpoolSegment", frozen)]
+pub struct PySpoolSegment {
+    #[pyo3(get)]
+    seq: u64,
+    #[pyo3(get)]
+    sealed: bool,
+    #[pyo3(get)]
+    bytes: u64,
+    #[pyo3(get)]
+    records: u64,
+    #[pyo3(get)]
+    min_timestamp: Option<DateTime<Utc>>,
+    #[pyo3(get)]
+    max_timestamp: Option<DateTime<Utc>>,
+}
+
+impl From<SegmentInfo> for PySpoolSegment {
+    fn from(s: SegmentInfo) -> Self {
+        Self {
+            seq: s.seq,
+            sealed: s.sealed,
+            bytes: s.bytes,
+            records: s.records,
+            min_timestamp: s.min_timestamp.and_then(DateTime::from_timestamp_millis),
+            max_timestamp: s.max_timestamp.and_then(DateTime::from_timestamp_millis),
+        }
+    }
+}
+
+#[pymethods]
+impl PySpoolSegment {
+    fn __repr__(&self) -> String {
+        format!(
+            "SpoolSegment(seq={}, sealed={}, bytes={}, records={})",
+            self.seq, self.sealed, self.bytes, self.records
+        )
+    }
+}
+
+/// Records and bytes a spool has dropped to its retention window and size cap since startup.
+#[pyclass(module = "datahub_sdk", name = "SpoolDropStats", frozen)]
+pub struct PySpoolDropStats {
+    #[pyo3(get)]
+    retention_records: u64,
+    #[pyo3(get)]
+    retention_bytes: u64,
+    #[pyo3(get)]
+    size_cap_records: u64,
+    #[pyo3(get)]
+    size_cap_bytes: u64,
+}
+
+impl From<DropStats> for PySpoolDropStats {
+    fn from(d: DropStats) -> Self {
+        Self {
+            retention_records: d.retention_records,
+            retention_bytes: d.retention_bytes,
+            size_cap_records: d.size_cap_records,
+            size_cap_bytes: d.size_cap_bytes,
+        }
+    }
+}
+
+/// A datapoint waiting in the time series spool.
+#[pyclass(module = "datahub_sdk", name = "SpooledDatapoint", frozen)]
+pub struct PySpooledDatapoint {
+    #[pyo3(get)]
+    id: Option<u64>,
+    #[pyo3(get)]
+    external_id: Option<String>,
+    #[pyo3(get)]
+    timestamp: String,
+    #[pyo3(get)]
+    value: String,
+}
+
+impl From<SpoolDatapoint> for PySpooledDatapoint {
+    fn from(dp: SpoolDatapoint) -> Self {
+        Self {
+            id: dp.id,
+            external_id: dp.external_id,
+            timestamp: dp.timestamp,
+            value: dp.value,
+        }
+    }
+}
+
+pub(crate) fn segments<T: DeserializeOwned>(
+    py: Python<'_>,
+    spool: &SpoolAdmin<'_, T>,
+) -> Vec<PySpoolSegment> {
+    py.detach(|| spool.segments())
+        .into_iter()
+        .map(Into::into)
+        .collect()
+}
+
+pub(crate) fn export_segment<T: DeserializeOwned>(
+    py: Python<'_>,
+    spool: &SpoolAdmin<'_, T>,
+    seq: u64,
+    path: PathBuf,
+) -> PyResult<u64> {
+    Ok(py.detach(|| spool.export_segment(seq, path))?)
+}
+
+pub(crate) fn peek_datapoints(
+    py: Python<'_>,
+    api: &Arc<ApiService>,
+    seq: u64,
+    limit: usize,
+) -> PyResult<Vec<PySpooledDatapoint>> {
+    let points = py.detach(|| api.time_series.spool().peek(seq, limit))?;
+    Ok(points.into_iter().map(Into::into).collect())
+}
+
+pub(crate) fn peek_events(
+    py: Python<'_>,
+    api: &Arc<ApiService>,
+    seq: u64,
+    limit: usize,
+) -> PyResult<Vec<PyEvent>> {
+    let events = py.detach(|| api.events.spool().peek(seq, limit))?;
+    Ok(events.into_iter().map(PyEvent::from).collect())
+}
+
+/// Purge the time series spool by age and/or series. At least one criterion is required.
+pub(crate) fn purge_datapoints(
+    py: Python<'_>,
+    api: &Arc<ApiService>,
+    older_than: Option<&Bound<'_, PyAny>>,
+    external_id: Option<String>,
+    id: Option<u64>,
+) -> PyResult<u64> {
+    let older_than = opt_py_datetime_to_utc(older_than)?;
+    if older_than.is_none() && external_id.is_none() && id.is_none() {
+        return Err(PyValueError::new_err(
+            "purge_spool needs older_than, external_id or id",
+        ));
+    }
+    let series = (external_id.is_some() || id.is_some()).then(|| IdAndExtId { id, external_id });
+    Ok(py.detach(|| -> std::io::Result<u64> {
+        let spool = api.time_series.spool();
+        let mut removed = 0;
+        if let Some(cutoff) = older_than {
+            removed += spool.purge_older_than(cutoff)?;
+        }
+        if let Some(series) = &series {
+            removed += spool.purge_series(series)?;
+        }
+        Ok(removed)
+    })?)
+}
+
+/// Purge every spooled event whose event time is before `older_than`.
+pub(crate) fn purge_events(
+    py: Python<'_>,
+    api: &Arc<ApiService>,
+    older_than: &Bound<'_, PyAny>,
+) -> PyResult<u64> {
+    let cutoff = crate::datetime::py_datetime_to_utc(older_than)?;
+    Ok(py.detach(|| api.events.spool().purge_older_than(cutoff))?)
+}
+
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySpoolSegment>()?;
+    m.add_class::<PySpoolDropStats>()?;
+    m.add_class::<PySpooledDatapoint>()?;
+    Ok(())
+}
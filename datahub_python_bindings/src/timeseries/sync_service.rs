@@ -282,3 +282,52 @@ impl PyTimeSeriesServiceSync {
         Ok(res)
     }
 }
+
+/// Durable spool inspection and administration. Local disk I/O, so these block (without the GIL)
+/// on the async service too.
+#[pymethods]
+impl PyTimeSeriesServiceSync {
+    fn spool_segments(&self, py: Python<'_>) -> Vec<crate::spool::PySpoolSegment> {
+        crate::spool::segments(py, &self.api_service.time_series.spool())
+    }
+
+    #[pyo3(signature = (seq, limit = 10))]
+    fn peek_spool(
+        &self,
+        py: Python<'_>,
+        seq: u64,
+        limit: usize,
+    ) -> PyResult<Vec<crate::spool::PySpooledDatapoint>> {
+        crate::spool::peek_datapoints(py, &self.api_service, seq, limit)
+    }
+
+    fn export_spool_segment(
+        &self,
+        py: Python<'_>,
+        seq: u64,
+        path: std::path::PathBuf,
+    ) -> PyResult<u64> {
+        crate::spool::export_segment(py, &self.api_service.time_series.spool(), seq, path)
+    }
+
+    #[pyo3(signature = (older_than = None, external_id = None, id = None))]
+    fn purge_spool(
+        &self,
+        py: Python<'_>,
+        older_than: Option<Bound<'_, PyAny>>,
+        external_id: Option<String>,
+        id: Option<u64>,
+    ) -> PyResult<u64> {
+        crate::spool::purge_datapoints(
+            py,
+            &self.api_service,
+            older_than.as_ref(),
+            external_id,
+            id,
+        )
+    }
+
+    fn spool_dropped(&self) -> crate::spool::PySpoolDropStats {
+        self.api_service.time_series.spool().dropped().into()
+    }
+}
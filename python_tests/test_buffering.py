@@ -81,6 +81,32 @@ def test_datapoints_buffer_to_disk(tmp_path):
     assert (tmp_path / "datapoints").is_dir(), "datapoint spool directory should exist"
 
 
+def test_spool_can_be_inspected_exported_and_purged(tmp_path):
+    client = _unreachable_buffered_client(tmp_path)
+    keep, drop = unique_id("spool_keep"), unique_id("spool_drop")
+    now = datetime.now(timezone.utc)
+    client.timeseries.insert_from_lists(timestamps=[now], values=[1.0], ts=keep)
+    client.timeseries.insert_from_lists(timestamps=[now, now], values=[2.0, 3.0], ts=drop)
+
+    segments = client.timeseries.spool_segments()
+    assert sum(s.records for s in segments) == 3
+    assert segments[0].min_timestamp is not None
+
+    peeked = client.timeseries.peek_spool(segments[0].seq, limit=1)
+    assert [p.external_id for p in peeked] == [keep]
+
+    exported = tmp_path / "segment.ndjson"
+    written = client.timeseries.export_spool_segment(segments[0].seq, exported)
+    assert written == segments[0].records
+    assert len(exported.read_text().splitlines()) == written
+
+    assert client.timeseries.purge_spool(external_id=drop) == 2
+    assert sum(s.records for s in client.timeseries.spool_segments()) == 1
+    with pytest.raises(ValueError):
+        client.timeseries.purge_spool()
+    assert client.timeseries.spool_dropped().retention_records == 0
+
+
 @pytest.mark.skipif(
     not os.path.exists(ENV_FILE),
     reason="needs a live backend (.env with BASE_URL + auth)",
@@ -22,13 +22,18 @@
 //! an async context: building its runtime on a Tokio runtime thread panics. Use the
 //! async [`crate::ApiService`] there instead.
 
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use tokio::runtime::Runtime;
 
+use crate::buffer::SpoolAdmin;
 use crate::datahub::DataHubConfig;
 use crate::datasets::{Dataset, DatasetFilter, DatasetSearch};
+use crate::dead_letter::{DeadLetterRecord, ReplayReport};
 use crate::events::{Event, EventDimension, EventIdCollection};
 use crate::files::{FileDownload, FileUpdate, FileUpload};
 use crate::filters::EventFilter;
@@ -42,8 +47,15 @@ use crate::http::ResponseError;
 use crate::labels::Label;
 use crate::relations::{EdgeProxy, RelForm, RelTypeForm, RelationshipType};
 use crate::resources::{RelatedResourcesForm, Resource, ResourceNetwork, ResourceUpdate};
-use crate::timeseries::{TimeSeries, TimeSeriesUpdateCollection};
-use crate::unit::Unit;
+use crate::subscriptions::{
+    ConnectionState, FileDedupStore, ListenError, ListenOptions, ListenerStats, Subscription,
+    SubscriptionMessage, SubscriptionPage, SubscriptionRetriever,
+};
+use crate::timeseries::{
+    SpoolDatapoint, TimeSeries, TimeSeriesUpdateCollection, ValidationError, ValidationOptions,
+    ValidationReport,
+};
+use crate::unit::{Unit, UnitCatalog, UnitError};
 
 /// Generate blocking methods that delegate to the same-named async method on one of
 /// the inner [`crate::ApiService`] services. Signatures are stated once here and must
@@ -95,6 +107,7 @@ pub struct ApiService {
     pub functions: FunctionsService,
     pub labels: LabelsService,
     pub edges: EdgesService,
+    pub subscriptions: SubscriptionsService,
 }
 
 /// The blocking counterpart of [`crate::create_api_service`]: configuration from the
@@ -117,6 +130,11 @@ impl ApiService {
     fn wrap(api: Arc<crate::ApiService>) -> ApiService {
         let rt =
             Arc::new(Runtime::new().expect("failed to build the blocking client's Tokio runtime"));
+        // The async constructor had no runtime to start the background spool flush on.
+        {
+            let _runtime = rt.enter();
+            api.start_background_flush();
+        }
         macro_rules! service {
             ($name:ident) => {
                 $name {
@@ -135,12 +153,23 @@ impl ApiService {
             functions: service!(FunctionsService),
             labels: service!(LabelsService),
             edges: service!(EdgesService),
+            subscriptions: service!(SubscriptionsService),
             api,
         }
     }
 
-    /// Escape hatch: the async service this client wraps, for the few places (e.g.
-    /// subscriptions) that only exist on the async API.
+    /// The blocking counterpart of [`crate::ApiService::flush_buffers`].
+    pub fn flush_buffers(&self) -> Result<(), ResponseError> {
+        self.time_series.rt.block_on(self.api.flush_buffers())
+    }
+
+    /// The blocking counterpart of [`crate::ApiService::shutdown`].
+    pub fn shutdown(&self, timeout: std::time::Duration) -> Result<(), ResponseError> {
+        self.time_series.rt.block_on(self.api.shutdown(timeout))
+    }
+
+    /// Escape hatch: the async service this client wraps, for the few places (e.g. the
+    /// subscription consumer and backfill) that only exist on the async API.
     pub fn async_api(&self) -> Arc<crate::ApiService> {
         self.api.clone()
     }
@@ -171,12 +200,53 @@ impl TimeSeriesService {
         fn retrieve_datapoints(json: &DataWrapper<RetrieveFilter>) -> Result<DataWrapper<DatapointsCollection<Datapoint>>, ResponseError>;
         fn delete_datapoints(json: &DataWrapper<DeleteFilter>) -> Result<DataWrapper<String>, ResponseError>;
         fn retrieve_latest_datapoint(json: &DataWrapper<IdAndExtId>) -> Result<DataWrapper<DatapointsCollection<Datapoint>>, ResponseError>;
+        fn retrieve_datapoints_in_unit(json: &DataWrapper<RetrieveFilter>, target_unit: &str) -> Result<DataWrapper<DatapointsCollection<Datapoint>>, UnitError>;
+        fn insert_datapoints_in_unit(json: &mut DataWrapper<DatapointsCollection<DatapointString>>, target_unit: &str) -> Result<DataWrapper<String>, UnitError>;
+        fn validate_datapoints(json: &mut DataWrapper<DatapointsCollection<DatapointString>>, options: &ValidationOptions) -> ValidationReport;
+        fn insert_datapoints_validated(json: &mut DataWrapper<DatapointsCollection<DatapointString>>, options: &ValidationOptions) -> Result<(DataWrapper<String>, ValidationReport), ValidationError>;
     }
 
     /// Already synchronous on the async service; passed through directly.
     pub fn buffered_count(&self) -> u64 {
         self.api.time_series.buffered_count()
     }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn spool(&self) -> SpoolAdmin<'_, SpoolDatapoint> {
+        self.api.time_series.spool()
+    }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn quarantined_count(&self) -> u64 {
+        self.api.time_series.quarantined_count()
+    }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn dead_letter_count(&self) -> u64 {
+        self.api.time_series.dead_letter_count()
+    }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn dead_letters(&self) -> io::Result<Vec<DeadLetterRecord<SpoolDatapoint>>> {
+        self.api.time_series.dead_letters()
+    }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn export_dead_letters(&self, path: &Path) -> io::Result<usize> {
+        self.api.time_series.export_dead_letters(path)
+    }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn clear_dead_letters(&self) -> io::Result<()> {
+        self.api.time_series.clear_dead_letters()
+    }
+
+    pub fn replay_dead_letters<F>(&self, fix: F) -> Result<ReplayReport, ResponseError>
+    where
+        F: FnMut(SpoolDatapoint) -> Option<SpoolDatapoint>,
+    {
+        self.rt.block_on(self.api.time_series.replay_dead_letters(fix))
+    }
 }
 
 /// Blocking counterpart of [`crate::ResourceService`].
@@ -246,6 +316,38 @@ impl EventsService {
     pub fn buffered_count(&self) -> u64 {
         self.api.events.buffered_count()
     }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn spool(&self) -> SpoolAdmin<'_, Event> {
+        self.api.events.spool()
+    }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn dead_letter_count(&self) -> u64 {
+        self.api.events.dead_letter_count()
+    }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn dead_letters(&self) -> io::Result<Vec<DeadLetterRecord<Event>>> {
+        self.api.events.dead_letters()
+    }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn export_dead_letters(&self, path: &Path) -> io::Result<usize> {
+        self.api.events.export_dead_letters(path)
+    }
+
+    /// Already synchronous on the async service; passed through directly.
+    pub fn clear_dead_letters(&self) -> io::Result<()> {
+        self.api.events.clear_dead_letters()
+    }
+
+    pub fn replay_dead_letters<F>(&self, fix: F) -> Result<ReplayReport, ResponseError>
+    where
+        F: FnMut(Event) -> Option<Event>,
+    {
+        self.rt.block_on(self.api.events.replay_dead_letters(fix))
+    }
 }
 
 /// Blocking counterpart of [`crate::datasets::DatasetsService`].
@@ -280,6 +382,8 @@ impl UnitsService {
         fn list() -> Result<DataWrapper<Unit>, ResponseError>;
         fn by_external_id(value: &str) -> Result<DataWrapper<Unit>, ResponseError>;
         fn by_ids(json: &DataWrapper<IdAndExtId>) -> Result<DataWrapper<Unit>, ResponseError>;
+        fn catalog() -> Result<Arc<UnitCatalog>, ResponseError>;
+        fn refresh_catalog() -> Result<Arc<UnitCatalog>, ResponseError>;
     }
 }
 
@@ -375,3 +479,185 @@ impl EdgesService {
         fn create_types(data: Into<DataWrapper<RelTypeForm>>) -> Result<DataWrapper<RelationshipType>, ResponseError>;
     }
 }
+
+/// Blocking counterpart of [`crate::SubscriptionsService`].
+pub struct SubscriptionsService {
+    api: Arc<crate::ApiService>,
+    rt: Arc<Runtime>,
+}
+
+impl SubscriptionsService {
+    delegate! { subscriptions =>
+        fn list(retriever: &SubscriptionRetriever) -> Result<DataWrapper<Subscription>, ResponseError>;
+        fn list_page(retriever: &SubscriptionRetriever) -> Result<SubscriptionPage, ResponseError>;
+        fn list_all(retriever: &SubscriptionRetriever) -> Result<DataWrapper<Subscription>, ResponseError>;
+        fn by_ids(ids: &[IdAndExtId]) -> Result<DataWrapper<Subscription>, ResponseError>;
+        fn get(external_id: &str) -> Result<Subscription, ResponseError>;
+    }
+
+    delegate_into! { subscriptions =>
+        fn create(data: Into<DataWrapper<Subscription>>) -> Result<DataWrapper<Subscription>, ResponseError>;
+        fn delete(json: Into<DataWrapper<IdAndExtId>>) -> Result<DataWrapper<Subscription>, ResponseError>;
+    }
+
+    /// See `crate::SubscriptionsService::update`; needs the `unstable-subscription-update` feature.
+    #[cfg(feature = "unstable-subscription-update")]
+    pub fn update<I>(&self, input: &I) -> Result<DataWrapper<Subscription>, ResponseError>
+    where
+        for<'a> &'a I: Into<DataWrapper<crate::subscriptions::SubscriptionUpdate>>,
+    {
+        self.rt.block_on(self.api.subscriptions.update(input))
+    }
+
+    /// See [`crate::SubscriptionsService::dedup_store`].
+    pub fn dedup_store(&self, name: &str, capacity: usize) -> std::io::Result<FileDedupStore> {
+        self.api.subscriptions.dedup_store(name, capacity)
+    }
+
+    /// The blocking counterpart of [`crate::SubscriptionsService::listen`]. The socket is served
+    /// (pings answered, reconnects made) on the client's runtime in the background, whether or not
+    /// the listener is being read.
+    pub fn listen<S: AsRef<str>>(
+        &self,
+        subscription_external_ids: &[S],
+    ) -> Result<SubscriptionListener, ListenError> {
+        self.listen_with(subscription_external_ids, ListenOptions::default())
+    }
+
+    /// The blocking counterpart of [`crate::SubscriptionsService::listen_with`].
+    pub fn listen_with<S: AsRef<str>>(
+        &self,
+        subscription_external_ids: &[S],
+        options: ListenOptions,
+    ) -> Result<SubscriptionListener, ListenError> {
+        let inner = self.rt.block_on(
+            self.api
+                .subscriptions
+                .listen_with(subscription_external_ids, options),
+        )?;
+        Ok(SubscriptionListener {
+            handle: ListenerHandle {
+                inner: inner.handle(),
+                rt: self.rt.clone(),
+            },
+            inner,
+        })
+    }
+}
+
+/// Blocking counterpart of [`crate::SubscriptionListener`]: an [`Iterator`] of messages, ending
+/// when the listener stops. [`next_timeout`](Self::next_timeout) waits for a bounded time.
+pub struct SubscriptionListener {
+    inner: crate::SubscriptionListener,
+    handle: ListenerHandle,
+}
+
+impl SubscriptionListener {
+    /// The next message, or `Err(ListenError::Timeout)` if none arrives within `timeout`.
+    /// `None` once the listener has stopped.
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Option<Result<SubscriptionMessage, ListenError>> {
+        let rt = self.handle.rt.clone();
+        rt.block_on(async {
+            tokio::time::timeout(timeout, self.inner.next())
+                .await
+                .unwrap_or(Some(Err(ListenError::Timeout(timeout))))
+        })
+    }
+
+    /// A cloneable handle, to ack, nack and change subscriptions from other threads.
+    pub fn handle(&self) -> ListenerHandle {
+        self.handle.clone()
+    }
+
+    pub fn ack<S: AsRef<str>>(&self, message_ids: &[S]) -> Result<(), ListenError> {
+        self.handle.ack(message_ids)
+    }
+
+    pub fn nack<S: AsRef<str>>(&self, message_ids: &[S]) -> Result<(), ListenError> {
+        self.handle.nack(message_ids)
+    }
+
+    pub fn subscribe<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        self.handle.subscribe(external_ids)
+    }
+
+    pub fn unsubscribe<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        self.handle.unsubscribe(external_ids)
+    }
+
+    pub fn set_subscriptions<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        self.handle.set_subscriptions(external_ids)
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.handle.connection_state()
+    }
+
+    pub fn stats(&self) -> ListenerStats {
+        self.inner.stats()
+    }
+
+    /// Close the connection gracefully.
+    pub fn close(self) -> Result<(), ListenError> {
+        let rt = self.handle.rt.clone();
+        rt.block_on(self.inner.close())
+    }
+}
+
+impl Iterator for SubscriptionListener {
+    type Item = Result<SubscriptionMessage, ListenError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rt = self.handle.rt.clone();
+        rt.block_on(self.inner.next())
+    }
+}
+
+/// Blocking counterpart of [`crate::ListenerHandle`].
+#[derive(Clone)]
+pub struct ListenerHandle {
+    inner: crate::ListenerHandle,
+    rt: Arc<Runtime>,
+}
+
+impl ListenerHandle {
+    pub fn ack<S: AsRef<str>>(&self, message_ids: &[S]) -> Result<(), ListenError> {
+        self.rt.block_on(self.inner.ack(message_ids))
+    }
+
+    pub fn nack<S: AsRef<str>>(&self, message_ids: &[S]) -> Result<(), ListenError> {
+        self.rt.block_on(self.inner.nack(message_ids))
+    }
+
+    pub fn subscribe<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        self.rt.block_on(self.inner.subscribe(external_ids))
+    }
+
+    pub fn unsubscribe<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        self.rt.block_on(self.inner.unsubscribe(external_ids))
+    }
+
+    pub fn set_subscriptions<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        self.rt.block_on(self.inner.set_subscriptions(external_ids))
+    }
+
+    /// Close the listener from any thread; its iterator then ends.
+    pub fn close(&self) -> Result<(), ListenError> {
+        self.rt.block_on(self.inner.close())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.inner.connection_state().borrow().clone()
+    }
+
+    pub fn stats(&self) -> ListenerStats {
+        self.inner.stats()
+    }
+}
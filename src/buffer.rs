@@ -13,12 +13,47 @@
 //! - Time retention (`retention_ms`) drops whole segments past the window and expired records on
 //!   read; the size cap (`max_bytes`) bounds total on-disk bytes by deleting the oldest segment.
 //!
+//! How much survives a power loss is set by [`Durability`]: by default sealed segments are
+//! fsynced (file, then directory after the rename), and the active segment can additionally be
+//! fsynced on every append or by a group commit every few milliseconds. On recovery a torn
+//! trailing line in the active segment is cut off, so later appends start on a clean line.
+//!
+//! A spool directory belongs to one process at a time: [`DurableSpool::open`] takes an advisory
+//! lock on `<dir>/.lock` (released when the spool is dropped or the process dies), and
+//! [`SpoolLockMode`] decides what a second process does when the directory is taken.
+//!
 //! Each on-disk line is `<epoch_millis>\t<json>`: the timestamp (for retention) followed by the
 //! serialized item to resend. The spool is content-agnostic; callers serialize their own items.
+//! With [`DurableSpool::with_encryption`] the item is stored sealed instead (see
+//! [`crate::encryption`]); the timestamp stays readable.
+//!
+//! [`SpoolAdmin`] is the typed view the services hand out for inspecting and administering a
+//! spool: listing segments, peeking at or exporting records, and purging them.
+//!
+//! What happens to the ingest spools is reported as [`BufferEvent`]s to the observer set with
+//! `DataHubConfig::set_buffer_observer`, and, with the `metrics` feature, as counters and gauges
+//! through the [`metrics`](https://docs.rs/metrics) facade:
+//!
+//! | metric | kind | labels |
+//! |---|---|---|
+//! | `datahub_buffer_spooled_records_total` | counter | `spool` |
+//! | `datahub_buffer_drained_records_total`, `datahub_buffer_drained_segments_total` | counter | `spool` |
+//! | `datahub_buffer_dropped_records_total`, `datahub_buffer_dropped_bytes_total` | counter | `spool`, `reason` (`retention`, `size_cap`) |
+//! | `datahub_buffer_rejected_records_total` | counter | `spool` |
+//! | `datahub_buffer_io_errors_total` | counter | `spool`, `operation` |
+//! | `datahub_buffer_records`, `datahub_buffer_bytes`, `datahub_buffer_max_bytes` | gauge | `spool` |
 
+use crate::encryption::{self, KeyProvider, SpoolCipher};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const ACTIVE_SUFFIX: &str = ".ndjson";
 const SEALED_SUFFIX: &str = ".ndjson.zst";
@@ -26,17 +61,264 @@ const TEMP_SUFFIX: &str = ".ndjson.zst.tmp";
 const DEFAULT_ROLLOVER_BYTES: u64 = 50 * 1024 * 1024; // 50 MiB of plain NDJSON
 const MIN_ROLLOVER_BYTES: u64 = 64 * 1024;
 const ZSTD_LEVEL: i32 = 9;
+const LOCK_FILE: &str = ".lock";
+const INSTANCE_PREFIX: &str = "instance-";
+const MAX_INSTANCES: u32 = 64;
+const HAND_OVER_POLL: Duration = Duration::from_millis(50);
+/// How long a [`LazySpool`] that failed to open goes without trying again.
+const REOPEN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When spooled data is forced to stable storage (fsync). Stronger levels survive a power loss
+/// with less data lost, at the cost of append throughput.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Never fsync; the OS writes data back when it chooses. A power loss can lose any record
+    /// written in the last few seconds, including whole sealed segments.
+    None,
+    /// Fsync each segment when it is sealed, and the directory after the rename. The active
+    /// segment (up to `rollover_bytes`) is still at the OS's mercy.
+    #[default]
+    OnSeal,
+    /// As `OnSeal`, and fsync the active segment before every `append` returns.
+    PerAppend,
+    /// As `OnSeal`, and fsync the active segment at most this long after an append, from a
+    /// background thread, so concurrent appends share one fsync.
+    GroupCommit(Duration),
+}
+
+/// The group-commit thread of a spool: fsyncs the last-written segment every interval if it was
+/// written since. Stops (after a final sync) when dropped.
+struct GroupCommit {
+    dirty: Arc<Mutex<Option<PathBuf>>>,
+    _stop: mpsc::Sender<()>,
+}
+
+impl GroupCommit {
+    fn start(interval: Duration) -> io::Result<Self> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let dirty = Arc::new(Mutex::new(None::<PathBuf>));
+        let pending = dirty.clone();
+        std::thread::Builder::new()
+            .name("datahub-spool-commit".into())
+            .spawn(move || loop {
+                let done = matches!(
+                    stopped.recv_timeout(interval),
+                    Err(RecvTimeoutError::Disconnected)
+                );
+                if let Some(path) = pending.lock().unwrap().take() {
+                    // The segment may have been sealed (and so synced) or deleted meanwhile.
+                    if let Ok(file) = OpenOptions::new().write(true).open(&path) {
+                        let _ = file.sync_data();
+                    }
+                }
+                if done {
+                    return;
+                }
+            })?;
+        Ok(GroupCommit { dirty, _stop: stop })
+    }
+
+    fn mark(&self, path: &Path) {
+        *self.dirty.lock().unwrap() = Some(path.to_path_buf());
+    }
+}
+
+/// What opening a spool directory that another process holds does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpoolLockMode {
+    /// Fail with an error naming the directory and the holder's pid.
+    #[default]
+    Exclusive,
+    /// Use the first free sub-spool `<dir>/instance-<n>` instead. Whichever process next holds
+    /// `<dir>` itself adopts the segments of sub-spools whose process has gone.
+    PerProcess,
+    /// Wait up to the given time for the holder to let go (an overlapping restart), then take the
+    /// directory over with its backlog. [`DurableSpool::open_with_lock`] blocks the opening thread
+    /// while it waits; the services wait asynchronously, on their first ingest call only.
+    HandOver(Duration),
+}
 
 /// One on-disk segment file plus the stats kept in memory for retention.
 struct Segment {
     path: PathBuf,
     compressed: bool,
     seq: u64,
+    min_ts: i64,
     max_ts: i64,
     records: u64,
     bytes: u64,
 }
 
+/// One segment of a spool, as reported by [`DurableSpool::segments`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentInfo {
+    pub seq: u64,
+    /// Sealed segments are compressed and immutable; the unsealed one is still being appended to.
+    pub sealed: bool,
+    /// Size on disk.
+    pub bytes: u64,
+    pub records: u64,
+    /// Earliest and latest record timestamp (epoch millis); `None` for an empty segment.
+    pub min_timestamp: Option<i64>,
+    pub max_timestamp: Option<i64>,
+}
+
+/// Records and on-disk bytes a spool has dropped to stay within its bounds since it was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DropStats {
+    pub retention_records: u64,
+    pub retention_bytes: u64,
+    pub size_cap_records: u64,
+    pub size_cap_bytes: u64,
+}
+
+/// Something that happened to an ingest spool (`"datapoints"` or `"events"`).
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum BufferEvent {
+    /// Records were written to disk because the backend couldn't take them.
+    Spooled { spool: &'static str, records: u64 },
+    /// A segment was delivered and deleted.
+    SegmentDrained {
+        spool: &'static str,
+        seq: u64,
+        records: u64,
+    },
+    /// A segment fell out of the retention window and was deleted unsent.
+    DroppedByRetention {
+        spool: &'static str,
+        records: u64,
+        bytes: u64,
+    },
+    /// The oldest segment was deleted unsent to stay under the size cap.
+    DroppedBySizeCap {
+        spool: &'static str,
+        records: u64,
+        bytes: u64,
+    },
+    /// The backend rejected spooled records outright; they were moved to the dead-letter spool.
+    Rejected {
+        spool: &'static str,
+        records: u64,
+        status: u16,
+        error: String,
+    },
+    /// Reading or writing the spool failed. Records being spooled at the time are lost.
+    IoError {
+        spool: &'static str,
+        operation: &'static str,
+        error: String,
+    },
+}
+
+impl BufferEvent {
+    /// The spool the event is about.
+    pub fn spool(&self) -> &'static str {
+        match self {
+            BufferEvent::Spooled { spool, .. }
+            | BufferEvent::SegmentDrained { spool, .. }
+            | BufferEvent::DroppedByRetention { spool, .. }
+            | BufferEvent::DroppedBySizeCap { spool, .. }
+            | BufferEvent::Rejected { spool, .. }
+            | BufferEvent::IoError { spool, .. } => spool,
+        }
+    }
+}
+
+/// Receives [`BufferEvent`]s. Called synchronously, sometimes while a spool's lock is held: keep it
+/// quick and don't call back into the spool. Implemented for `Fn(&BufferEvent)` closures.
+pub trait BufferObserver: Send + Sync {
+    fn on_event(&self, event: &BufferEvent);
+}
+
+impl<F: Fn(&BufferEvent) + Send + Sync> BufferObserver for F {
+    fn on_event(&self, event: &BufferEvent) {
+        self(event)
+    }
+}
+
+impl fmt::Debug for dyn BufferObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BufferObserver")
+    }
+}
+
+/// Reports one spool's events to the configured observer and the metrics facade.
+#[derive(Clone)]
+pub(crate) struct SpoolNotifier {
+    spool: &'static str,
+    observer: Option<Arc<dyn BufferObserver>>,
+}
+
+impl SpoolNotifier {
+    pub(crate) fn new(spool: &'static str, observer: Option<Arc<dyn BufferObserver>>) -> Self {
+        SpoolNotifier { spool, observer }
+    }
+
+    pub(crate) fn spool(&self) -> &'static str {
+        self.spool
+    }
+
+    pub(crate) fn emit(&self, event: BufferEvent) {
+        #[cfg(feature = "metrics")]
+        record_metrics(&event);
+        if let Some(observer) = &self.observer {
+            observer.on_event(&event);
+        }
+    }
+
+    pub(crate) fn io_error(&self, operation: &'static str, error: &io::Error) {
+        self.emit(BufferEvent::IoError {
+            spool: self.spool,
+            operation,
+            error: error.to_string(),
+        });
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn gauges(&self, records: u64, bytes: u64, max_bytes: Option<u64>) {
+        #[cfg(feature = "metrics")]
+        {
+            metrics::gauge!("datahub_buffer_records", "spool" => self.spool).set(records as f64);
+            metrics::gauge!("datahub_buffer_bytes", "spool" => self.spool).set(bytes as f64);
+            if let Some(cap) = max_bytes {
+                metrics::gauge!("datahub_buffer_max_bytes", "spool" => self.spool).set(cap as f64);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn record_metrics(event: &BufferEvent) {
+    use metrics::counter;
+    match event {
+        BufferEvent::Spooled { spool, records } => {
+            counter!("datahub_buffer_spooled_records_total", "spool" => *spool).increment(*records)
+        }
+        BufferEvent::SegmentDrained { spool, records, .. } => {
+            counter!("datahub_buffer_drained_records_total", "spool" => *spool).increment(*records);
+            counter!("datahub_buffer_drained_segments_total", "spool" => *spool).increment(1);
+        }
+        BufferEvent::DroppedByRetention { spool, records, bytes } => {
+            let labels = [("spool", *spool), ("reason", "retention")];
+            counter!("datahub_buffer_dropped_records_total", &labels).increment(*records);
+            counter!("datahub_buffer_dropped_bytes_total", &labels).increment(*bytes);
+        }
+        BufferEvent::DroppedBySizeCap { spool, records, bytes } => {
+            let labels = [("spool", *spool), ("reason", "size_cap")];
+            counter!("datahub_buffer_dropped_records_total", &labels).increment(*records);
+            counter!("datahub_buffer_dropped_bytes_total", &labels).increment(*bytes);
+        }
+        BufferEvent::Rejected { spool, records, .. } => {
+            counter!("datahub_buffer_rejected_records_total", "spool" => *spool).increment(*records)
+        }
+        BufferEvent::IoError { spool, operation, .. } => {
+            counter!("datahub_buffer_io_errors_total", "spool" => *spool, "operation" => *operation)
+                .increment(1)
+        }
+    }
+}
+
 /// A durable spool of `(timestamp_millis, json_line)` records. Not thread-safe on its own; wrap it
 /// in a `Mutex`. File I/O is synchronous, so never hold that lock across an `.await`.
 pub struct DurableSpool {
@@ -46,12 +328,45 @@ pub struct DurableSpool {
     rollover_bytes: u64,
     segments: Vec<Segment>, // ordered by seq (oldest first)
     next_seq: u64,
+    dropped: DropStats,
+    notifier: Option<SpoolNotifier>,
+    durability: Durability,
+    group_commit: Option<GroupCommit>,
+    cipher: Option<SpoolCipher>,
+    // Held (and so the directory locked) for as long as the spool is open.
+    _lock: File,
 }
 
 impl DurableSpool {
-    /// Open (and recover) a spool in `dir`. At least one of `retention_ms` / `max_bytes` should be
-    /// set, otherwise the spool is unbounded.
+    /// Open (and recover) a spool in `dir`, failing if another process holds it. At least one of
+    /// `retention_ms` / `max_bytes` should be set, otherwise the spool is unbounded.
     pub fn open(dir: PathBuf, retention_ms: Option<i64>, max_bytes: Option<u64>) -> io::Result<Self> {
+        Self::open_with_lock(dir, retention_ms, max_bytes, SpoolLockMode::Exclusive)
+    }
+
+    /// [`open`](Self::open), with `mode` deciding what happens if another process holds `dir`.
+    /// The spool may then live in a sub-directory; see [`dir`](Self::dir).
+    pub fn open_with_lock(
+        dir: PathBuf,
+        retention_ms: Option<i64>,
+        max_bytes: Option<u64>,
+        mode: SpoolLockMode,
+    ) -> io::Result<Self> {
+        let (locked_dir, lock) = acquire(&dir, mode)?;
+        let owns_root = locked_dir == dir;
+        let mut spool = Self::open_locked(locked_dir, lock, retention_ms, max_bytes)?;
+        if owns_root {
+            spool.adopt_orphans()?;
+        }
+        Ok(spool)
+    }
+
+    fn open_locked(
+        dir: PathBuf,
+        lock: File,
+        retention_ms: Option<i64>,
+        max_bytes: Option<u64>,
+    ) -> io::Result<Self> {
         let rollover_bytes = match max_bytes {
             Some(cap) => (cap / 4).clamp(MIN_ROLLOVER_BYTES, DEFAULT_ROLLOVER_BYTES),
             None => DEFAULT_ROLLOVER_BYTES,
@@ -63,11 +378,64 @@ impl DurableSpool {
             rollover_bytes,
             segments: Vec::new(),
             next_seq: 0,
+            dropped: DropStats::default(),
+            notifier: None,
+            durability: Durability::default(),
+            group_commit: None,
+            cipher: None,
+            _lock: lock,
         };
         spool.recover()?;
         Ok(spool)
     }
 
+    /// Set when data is fsynced (see [`Durability`]); `OnSeal` unless changed.
+    pub fn with_durability(mut self, durability: Durability) -> io::Result<Self> {
+        self.group_commit = match durability {
+            Durability::GroupCommit(interval) => Some(GroupCommit::start(interval)?),
+            _ => None,
+        };
+        self.durability = durability;
+        Ok(self)
+    }
+
+    /// Encrypt records appended from now on with `keys` (see [`crate::encryption`]), and decrypt
+    /// encrypted records when reading.
+    pub fn with_encryption(mut self, keys: Arc<dyn KeyProvider>) -> Self {
+        self.cipher = Some(SpoolCipher::new(keys));
+        self
+    }
+
+    /// Report this spool's events through `notifier`.
+    pub(crate) fn with_notifier(mut self, notifier: SpoolNotifier) -> Self {
+        self.notifier = Some(notifier);
+        self.update_gauges();
+        self
+    }
+
+    /// Report that segment `seq` was delivered; `records` is how many the backend accepted.
+    pub(crate) fn report_drained(&self, seq: u64, records: u64) {
+        if let Some(notifier) = &self.notifier {
+            notifier.emit(BufferEvent::SegmentDrained {
+                spool: notifier.spool(),
+                seq,
+                records,
+            });
+        }
+    }
+
+    /// Report an I/O error the caller hit while using this spool.
+    pub(crate) fn report_io_error(&self, operation: &'static str, error: &io::Error) {
+        if let Some(notifier) = &self.notifier {
+            notifier.io_error(operation, error);
+        }
+    }
+
+    /// The directory the spool lives in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
     /// Total records currently spooled across all segments.
     pub fn size(&self) -> u64 {
         self.segments.iter().map(|s| s.records).sum()
@@ -84,22 +452,49 @@ impl DurableSpool {
         let active_idx = self.active_for_write();
         {
             let path = self.segments[active_idx].path.clone();
+            let created = !path.exists();
             let file = OpenOptions::new().create(true).append(true).open(&path)?;
             let mut writer = BufWriter::new(file);
             for (ts, json) in records {
-                writeln!(writer, "{}\t{}", ts, json)?;
+                match &self.cipher {
+                    Some(cipher) => writeln!(writer, "{}\t{}", ts, cipher.seal(*ts, json)?)?,
+                    None => writeln!(writer, "{}\t{}", ts, json)?,
+                }
                 let seg = &mut self.segments[active_idx];
+                seg.min_ts = seg.min_ts.min(*ts);
                 seg.max_ts = seg.max_ts.max(*ts);
                 seg.records += 1;
             }
             writer.flush()?;
+            let synced = match (&self.durability, &self.group_commit) {
+                (Durability::PerAppend, _) => {
+                    writer.get_ref().sync_data()?;
+                    true
+                }
+                (_, Some(group_commit)) => {
+                    group_commit.mark(&path);
+                    true
+                }
+                _ => false,
+            };
+            // A new segment's directory entry must be durable too, or its synced data is orphaned.
+            if created && synced {
+                sync_dir(&self.dir)?;
+            }
         }
         let seg = &mut self.segments[active_idx];
         seg.bytes = fs::metadata(&seg.path)?.len();
         if seg.bytes >= self.rollover_bytes {
             self.seal(active_idx)?;
         }
+        if let Some(notifier) = &self.notifier {
+            notifier.emit(BufferEvent::Spooled {
+                spool: notifier.spool(),
+                records: records.len() as u64,
+            });
+        }
         self.prune(now_ms)?;
+        self.update_gauges();
         Ok(())
     }
 
@@ -117,6 +512,7 @@ impl DurableSpool {
                 self.segments.remove(idx);
             }
         }
+        self.update_gauges();
         Ok(())
     }
 
@@ -130,6 +526,11 @@ impl DurableSpool {
             .min()
     }
 
+    /// Sequence numbers of every segment, sealed or active, oldest first.
+    pub fn segment_seqs(&self) -> Vec<u64> {
+        self.segments.iter().map(|s| s.seq).collect()
+    }
+
     /// Read a segment's still-valid records (json lines), dropping any past the time window.
     pub fn read_segment(&self, seq: u64, now_ms: i64) -> io::Result<Vec<String>> {
         let Some(seg) = self.segments.iter().find(|s| s.seq == seq) else {
@@ -137,14 +538,11 @@ impl DurableSpool {
         };
         let cutoff = self.retention_ms.map(|r| now_ms - r);
         let mut out = Vec::new();
-        for_each_line(&seg.path, seg.compressed, |line| {
-            if let Some((ts_str, json)) = line.split_once('\t') {
-                if let Ok(ts) = ts_str.parse::<i64>() {
-                    if cutoff.map_or(true, |c| ts >= c) {
-                        out.push(json.to_string());
-                    }
-                }
+        self.for_each_decoded(seg, |ts, _, json| {
+            if cutoff.map_or(true, |c| ts >= c) {
+                out.push(json.to_string());
             }
+            true
         })?;
         Ok(out)
     }
@@ -155,6 +553,7 @@ impl DurableSpool {
             let _ = fs::remove_file(&self.segments[pos].path);
             self.segments.remove(pos);
         }
+        self.update_gauges();
         Ok(())
     }
 
@@ -163,19 +562,194 @@ impl DurableSpool {
         for seg in self.segments.drain(..) {
             let _ = fs::remove_file(&seg.path);
         }
+        self.update_gauges();
         Ok(())
     }
 
+    /// Every segment, oldest first.
+    pub fn segments(&self) -> Vec<SegmentInfo> {
+        self.segments
+            .iter()
+            .map(|s| SegmentInfo {
+                seq: s.seq,
+                sealed: s.compressed,
+                bytes: s.bytes,
+                records: s.records,
+                min_timestamp: (s.records > 0).then_some(s.min_ts),
+                max_timestamp: (s.records > 0).then_some(s.max_ts),
+            })
+            .collect()
+    }
+
+    /// Stream a segment's `(ts_millis, json)` records, including any past the time window, until
+    /// `f` returns `false`. An unknown `seq` yields nothing.
+    pub fn for_each_record(&self, seq: u64, mut f: impl FnMut(i64, &str) -> bool) -> io::Result<()> {
+        let Some(seg) = self.segments.iter().find(|s| s.seq == seq) else {
+            return Ok(());
+        };
+        self.for_each_decoded(seg, |ts, _, json| f(ts, json))
+    }
+
+    /// Write a segment's records to `path` as plain NDJSON (one item per line, without the
+    /// timestamp prefix). Returns the number of records written; fails with `NotFound` if there is
+    /// no such segment.
+    pub fn export_segment(&self, seq: u64, path: &Path) -> io::Result<u64> {
+        if !self.segments.iter().any(|s| s.seq == seq) {
+            return Err(no_segment(seq));
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut written = 0u64;
+        let mut result = Ok(());
+        self.for_each_record(seq, |_, json| {
+            result = writeln!(writer, "{}", json);
+            written += 1;
+            result.is_ok()
+        })?;
+        result?;
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Remove every record `keep` returns `false` for, rewriting the segments that change. The
+    /// active segment is sealed first. Returns the number of records removed.
+    pub fn retain(&mut self, mut keep: impl FnMut(i64, &str) -> bool, now_ms: i64) -> io::Result<u64> {
+        self.roll(now_ms)?;
+        let mut removed = 0u64;
+        let mut idx = 0;
+        while idx < self.segments.len() {
+            match self.rewrite(idx, &mut keep)? {
+                Some(n) if self.segments[idx].records == 0 => {
+                    removed += n;
+                    let seg = self.segments.remove(idx);
+                    let _ = fs::remove_file(&seg.path);
+                }
+                Some(n) => {
+                    removed += n;
+                    idx += 1;
+                }
+                None => idx += 1,
+            }
+        }
+        self.update_gauges();
+        Ok(removed)
+    }
+
+    /// [`retain`](Self::retain) within segment `seq` only, deleting it if nothing is kept.
+    pub(crate) fn retain_in(
+        &mut self,
+        seq: u64,
+        mut keep: impl FnMut(i64, &str) -> bool,
+        now_ms: i64,
+    ) -> io::Result<u64> {
+        if self.active_index().is_some_and(|i| self.segments[i].seq == seq) {
+            self.roll(now_ms)?;
+        }
+        let Some(idx) = self.segments.iter().position(|s| s.seq == seq) else {
+            return Err(no_segment(seq));
+        };
+        let removed = self.rewrite(idx, &mut keep)?.unwrap_or(0);
+        if removed > 0 && self.segments[idx].records == 0 {
+            let seg = self.segments.remove(idx);
+            let _ = fs::remove_file(&seg.path);
+        }
+        self.update_gauges();
+        Ok(removed)
+    }
+
+    /// Remove every record timestamped before `cutoff_ms`. Segments entirely older than the cutoff
+    /// are deleted without being read. Returns the number of records removed.
+    pub fn purge_older_than(&mut self, cutoff_ms: i64, now_ms: i64) -> io::Result<u64> {
+        let mut removed = 0u64;
+        self.segments.retain(|s| {
+            if s.records > 0 && s.max_ts < cutoff_ms {
+                let _ = fs::remove_file(&s.path);
+                removed += s.records;
+                false
+            } else {
+                true
+            }
+        });
+        let straddling = self
+            .segments
+            .iter()
+            .any(|s| s.records > 0 && s.min_ts < cutoff_ms);
+        if straddling {
+            removed += self.retain(|ts, _| ts >= cutoff_ms, now_ms)?;
+        }
+        self.update_gauges();
+        Ok(removed)
+    }
+
+    /// What retention and the size cap have dropped since the spool was opened.
+    pub fn dropped(&self) -> DropStats {
+        self.dropped
+    }
+
     // --- internals -----------------------------------------------------------------------------
 
+    /// Move the segments of every `instance-<n>` sub-spool no process holds any more into this
+    /// spool, after its own, and delete the sub-spool.
+    fn adopt_orphans(&mut self) -> io::Result<()> {
+        let mut orphans = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let is_instance = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(INSTANCE_PREFIX));
+            if is_instance && path.is_dir() {
+                if let Some(lock) = try_lock_dir(&path)? {
+                    orphans.push((path, lock));
+                }
+            }
+        }
+        if orphans.is_empty() {
+            return Ok(());
+        }
+        orphans.sort_by(|a, b| a.0.cmp(&b.0));
+        let now = chrono::Utc::now().timestamp_millis();
+        // Seal our own active segment so the adopted ones can follow it.
+        self.roll(now)?;
+        for (path, lock) in orphans {
+            let mut orphan = Self::open_locked(path.clone(), lock, None, None)?;
+            orphan.roll(now)?;
+            for seg in orphan.segments.drain(..) {
+                let seq = self.next_seq;
+                self.next_seq += 1;
+                let target = self.dir.join(segment_name(seq, SEALED_SUFFIX));
+                fs::rename(&seg.path, &target)?;
+                self.segments.push(Segment {
+                    path: target,
+                    seq,
+                    ..seg
+                });
+            }
+            self.sync_dir()?;
+            // Delete while still holding its lock, so no process picks the sub-spool up meanwhile.
+            let _ = fs::remove_dir_all(&path);
+            drop(orphan);
+        }
+        self.prune(now)?;
+        self.update_gauges();
+        Ok(())
+    }
+
     fn prune(&mut self, now_ms: i64) -> io::Result<()> {
         if let Some(retention) = self.retention_ms {
             let cutoff = now_ms - retention;
             while let Some(first) = self.segments.first() {
                 if first.max_ts < cutoff {
-                    let path = first.path.clone();
-                    let _ = fs::remove_file(path);
-                    self.segments.remove(0);
+                    let seg = self.segments.remove(0);
+                    let _ = fs::remove_file(&seg.path);
+                    self.dropped.retention_records += seg.records;
+                    self.dropped.retention_bytes += seg.bytes;
+                    if let Some(notifier) = &self.notifier {
+                        notifier.emit(BufferEvent::DroppedByRetention {
+                            spool: notifier.spool(),
+                            records: seg.records,
+                            bytes: seg.bytes,
+                        });
+                    }
                 } else {
                     break;
                 }
@@ -183,14 +757,111 @@ impl DurableSpool {
         }
         if let Some(cap) = self.max_bytes {
             while self.total_bytes() > cap && !self.segments.is_empty() {
-                let path = self.segments[0].path.clone();
-                let _ = fs::remove_file(path);
-                self.segments.remove(0);
+                let seg = self.segments.remove(0);
+                let _ = fs::remove_file(&seg.path);
+                self.dropped.size_cap_records += seg.records;
+                self.dropped.size_cap_bytes += seg.bytes;
+                if let Some(notifier) = &self.notifier {
+                    notifier.emit(BufferEvent::DroppedBySizeCap {
+                        spool: notifier.spool(),
+                        records: seg.records,
+                        bytes: seg.bytes,
+                    });
+                }
             }
         }
         Ok(())
     }
 
+    /// Rewrite sealed segment `idx` without the records `keep` rejects. Returns how many were
+    /// removed, or `None` if the segment was left untouched.
+    fn rewrite(&mut self, idx: usize, keep: &mut impl FnMut(i64, &str) -> bool) -> io::Result<Option<u64>> {
+        let seq = self.segments[idx].seq;
+        let tmp = self.dir.join(segment_name(seq, TEMP_SUFFIX));
+        let (mut min_ts, mut max_ts, mut kept, mut removed) = (i64::MAX, i64::MIN, 0u64, 0u64);
+        {
+            let seg = &self.segments[idx];
+            let mut encoder = zstd::stream::write::Encoder::new(File::create(&tmp)?, ZSTD_LEVEL)?;
+            let mut result = Ok(());
+            // Kept records are copied as stored, so they stay sealed with the key they had.
+            self.for_each_decoded(seg, |ts, line, json| {
+                if !keep(ts, json) {
+                    removed += 1;
+                } else {
+                    result = writeln!(encoder, "{}", line);
+                    min_ts = min_ts.min(ts);
+                    max_ts = max_ts.max(ts);
+                    kept += 1;
+                }
+                result.is_ok()
+            })?;
+            result?;
+            let out = encoder.finish()?;
+            if removed > 0 {
+                self.sync_file(&out)?;
+            }
+        }
+        if removed == 0 {
+            let _ = fs::remove_file(&tmp);
+            return Ok(None);
+        }
+        fs::rename(&tmp, &self.segments[idx].path)?;
+        self.sync_dir()?;
+        let seg = &mut self.segments[idx];
+        seg.min_ts = min_ts;
+        seg.max_ts = max_ts;
+        seg.records = kept;
+        seg.bytes = fs::metadata(&seg.path)?.len();
+        Ok(Some(removed))
+    }
+
+    /// Stream a segment's records as `(ts, stored line, json)`, decrypting as needed, until `f`
+    /// returns `false`. Fails on the first record that can't be decrypted.
+    fn for_each_decoded(
+        &self,
+        seg: &Segment,
+        mut f: impl FnMut(i64, &str, &str) -> bool,
+    ) -> io::Result<()> {
+        let mut result = Ok(());
+        let mut done = false;
+        for_each_line(&seg.path, seg.compressed, |line| {
+            if done {
+                return;
+            }
+            let Some((ts, payload)) = parse_line(line) else {
+                return;
+            };
+            match encryption::open(self.cipher.as_ref(), ts, payload) {
+                Ok(json) => done = !f(ts, line, &json),
+                Err(e) => {
+                    result = Err(e);
+                    done = true;
+                }
+            }
+        })?;
+        result
+    }
+
+    fn sync_file(&self, file: &File) -> io::Result<()> {
+        match self.durability {
+            Durability::None => Ok(()),
+            _ => file.sync_all(),
+        }
+    }
+
+    fn sync_dir(&self) -> io::Result<()> {
+        match self.durability {
+            Durability::None => Ok(()),
+            _ => sync_dir(&self.dir),
+        }
+    }
+
+    fn update_gauges(&self) {
+        if let Some(notifier) = &self.notifier {
+            notifier.gauges(self.size(), self.total_bytes(), self.max_bytes);
+        }
+    }
+
     fn total_bytes(&self) -> u64 {
         self.segments.iter().map(|s| s.bytes).sum()
     }
@@ -211,6 +882,7 @@ impl DurableSpool {
             path: self.dir.join(segment_name(seq, ACTIVE_SUFFIX)),
             compressed: false,
             seq,
+            min_ts: i64::MAX,
             max_ts: i64::MIN,
             records: 0,
             bytes: 0,
@@ -229,9 +901,11 @@ impl DurableSpool {
             let out = File::create(&tmp)?;
             let mut encoder = zstd::stream::write::Encoder::new(out, ZSTD_LEVEL)?;
             io::copy(&mut input, &mut encoder)?;
-            encoder.finish()?;
+            let out = encoder.finish()?;
+            self.sync_file(&out)?;
         }
         fs::rename(&tmp, &sealed)?;
+        self.sync_dir()?;
         let _ = fs::remove_file(&plain);
         let seg = &mut self.segments[idx];
         seg.path = sealed.clone();
@@ -271,10 +945,14 @@ impl DurableSpool {
             } else {
                 (plain.get(&seq).unwrap().clone(), false)
             };
+            if !compressed {
+                truncate_torn_tail(&path)?;
+            }
             let mut seg = Segment {
                 path,
                 compressed,
                 seq,
+                min_ts: i64::MAX,
                 max_ts: i64::MIN,
                 records: 0,
                 bytes: 0,
@@ -287,16 +965,19 @@ impl DurableSpool {
     }
 
     fn scan(&self, seg: &mut Segment) -> io::Result<()> {
+        let mut min_ts = i64::MAX;
         let mut max_ts = i64::MIN;
         let mut records = 0u64;
         for_each_line(&seg.path, seg.compressed, |line| {
             if let Some((ts_str, _)) = line.split_once('\t') {
                 if let Ok(ts) = ts_str.parse::<i64>() {
+                    min_ts = min_ts.min(ts);
                     max_ts = max_ts.max(ts);
                     records += 1;
                 }
             }
         })?;
+        seg.min_ts = min_ts;
         seg.max_ts = max_ts;
         seg.records = records;
         seg.bytes = fs::metadata(&seg.path).map(|m| m.len()).unwrap_or(0);
@@ -304,6 +985,183 @@ impl DurableSpool {
     }
 }
 
+/// A service's spool, opened on first use. A failed open is remembered: for
+/// [`REOPEN_INTERVAL`] callers go on without the spool instead of each trying again. With
+/// [`SpoolLockMode::HandOver`] the wait for the directory happens at most once, asynchronously,
+/// in [`hand_over`](Self::hand_over); opening itself never blocks on another process.
+#[derive(Default)]
+pub(crate) struct LazySpool {
+    spool: Mutex<Option<DurableSpool>>,
+    failed_at: Mutex<Option<Instant>>,
+    // Serializes hand-overs; true once one has been waited for.
+    handed_over: tokio::sync::Mutex<bool>,
+}
+
+impl std::ops::Deref for LazySpool {
+    type Target = Mutex<Option<DurableSpool>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.spool
+    }
+}
+
+impl LazySpool {
+    /// Open the spool with `open` unless it is open or an open failed within the last
+    /// [`REOPEN_INTERVAL`]. Returns `open`'s error, once per failed attempt, for the caller to
+    /// report.
+    pub(crate) fn ensure(
+        &self,
+        mode: SpoolLockMode,
+        open: impl FnOnce() -> io::Result<DurableSpool>,
+    ) -> io::Result<()> {
+        let mut guard = self.spool.lock().unwrap();
+        if guard.is_some() {
+            return Ok(());
+        }
+        let mut failed_at = self.failed_at.lock().unwrap();
+        if failed_at.is_some_and(|at| at.elapsed() < REOPEN_INTERVAL) {
+            return Ok(());
+        }
+        match open() {
+            Ok(spool) => {
+                *guard = Some(spool);
+                *failed_at = None;
+                Ok(())
+            }
+            Err(e) => {
+                // A directory still held before the hand-over was waited for isn't a failure yet.
+                let awaiting_hand_over = matches!(mode, SpoolLockMode::HandOver(_))
+                    && e.kind() == io::ErrorKind::WouldBlock
+                    && !self.handed_over.try_lock().is_ok_and(|done| *done);
+                if !awaiting_hand_over {
+                    *failed_at = Some(Instant::now());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// With [`SpoolLockMode::HandOver`], wait (without blocking the thread) until no other
+    /// process holds `dir` or the timeout passes, so a following [`ensure`](Self::ensure) can
+    /// take it over. Only the first call waits; a no-op in other modes or once the spool is open.
+    pub(crate) async fn hand_over(&self, dir: &Path, mode: SpoolLockMode) {
+        let SpoolLockMode::HandOver(timeout) = mode else {
+            return;
+        };
+        if self.spool.lock().unwrap().is_some() {
+            return;
+        }
+        let mut handed_over = self.handed_over.lock().await;
+        if *handed_over {
+            return;
+        }
+        let deadline = Instant::now() + timeout;
+        // The probe's lock is dropped at once; a directory that can't be locked at all is left
+        // for `ensure` to report.
+        while let Ok(None) = try_lock_dir(dir) {
+            if Instant::now() >= deadline {
+                break;
+            }
+            tokio::time::sleep(HAND_OVER_POLL).await;
+        }
+        *handed_over = true;
+    }
+}
+
+/// Inspection and administration of a service's ingest spool, with records decoded as `T`.
+/// Returned by `time_series.spool()` and `events.spool()`. Each call takes the spool's lock for its
+/// duration, so an ingest or drain waits behind a long export or purge. Records that don't decode
+/// as `T` are skipped by [`peek`](Self::peek) and [`for_each`](Self::for_each) and kept by
+/// [`purge_where`](Self::purge_where).
+pub struct SpoolAdmin<'a, T> {
+    spool: &'a Mutex<Option<DurableSpool>>,
+    record: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: DeserializeOwned> SpoolAdmin<'a, T> {
+    pub(crate) fn new(spool: &'a Mutex<Option<DurableSpool>>) -> Self {
+        SpoolAdmin {
+            spool,
+            record: PhantomData,
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut DurableSpool) -> R) -> Option<R> {
+        self.spool.lock().unwrap().as_mut().map(f)
+    }
+
+    /// Every segment, oldest first. Empty when buffering is off.
+    pub fn segments(&self) -> Vec<SegmentInfo> {
+        self.with(|spool| spool.segments()).unwrap_or_default()
+    }
+
+    /// Total records spooled.
+    pub fn records(&self) -> u64 {
+        self.with(|spool| spool.size()).unwrap_or(0)
+    }
+
+    /// Total bytes on disk.
+    pub fn bytes(&self) -> u64 {
+        self.with(|spool| spool.total_bytes()).unwrap_or(0)
+    }
+
+    /// Up to `limit` records from the start of segment `seq`.
+    pub fn peek(&self, seq: u64, limit: usize) -> io::Result<Vec<T>> {
+        let mut out = Vec::new();
+        if limit == 0 {
+            return Ok(out);
+        }
+        self.for_each(seq, |record| {
+            out.push(record);
+            out.len() < limit
+        })?;
+        Ok(out)
+    }
+
+    /// Stream the records of segment `seq` until `f` returns `false`, without loading the segment
+    /// into memory.
+    pub fn for_each(&self, seq: u64, mut f: impl FnMut(T) -> bool) -> io::Result<()> {
+        self.with(|spool| {
+            spool.for_each_record(seq, |_, json| match serde_json::from_str(json) {
+                Ok(record) => f(record),
+                Err(_) => true,
+            })
+        })
+        .unwrap_or(Ok(()))
+    }
+
+    /// Write segment `seq` to `path` as NDJSON. Returns the number of records written; fails with
+    /// `NotFound` if there is no such segment.
+    pub fn export_segment(&self, seq: u64, path: impl AsRef<Path>) -> io::Result<u64> {
+        self.with(|spool| spool.export_segment(seq, path.as_ref()))
+            .unwrap_or_else(|| Err(no_segment(seq)))
+    }
+
+    /// Remove every record timestamped before `cutoff`. Returns the number removed.
+    pub fn purge_older_than(&self, cutoff: DateTime<Utc>) -> io::Result<u64> {
+        let now = Utc::now().timestamp_millis();
+        self.with(|spool| spool.purge_older_than(cutoff.timestamp_millis(), now))
+            .unwrap_or(Ok(0))
+    }
+
+    /// Remove every record `matches` returns `true` for. Returns the number removed.
+    pub fn purge_where(&self, mut matches: impl FnMut(&T) -> bool) -> io::Result<u64> {
+        let now = Utc::now().timestamp_millis();
+        self.with(|spool| {
+            spool.retain(
+                |_, json| serde_json::from_str(json).map_or(true, |record| !matches(&record)),
+                now,
+            )
+        })
+        .unwrap_or(Ok(0))
+    }
+
+    /// Records and bytes dropped by the retention window and the size cap since startup.
+    pub fn dropped(&self) -> DropStats {
+        self.with(|spool| spool.dropped()).unwrap_or_default()
+    }
+}
+
 /// Stream a segment's lines, decompressing if sealed, tolerating a torn trailing line.
 fn for_each_line(path: &Path, compressed: bool, mut f: impl FnMut(&str)) -> io::Result<()> {
     let file = match File::open(path) {
@@ -329,6 +1187,113 @@ fn for_each_line(path: &Path, compressed: bool, mut f: impl FnMut(&str)) -> io::
     Ok(())
 }
 
+/// Cut a plain segment back to its last complete line: whatever follows the last newline is a
+/// write torn by a crash (possibly zero-filled by the file system).
+fn truncate_torn_tail(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut end = len;
+    let mut chunk = vec![0u8; 64 * 1024];
+    let keep = loop {
+        if end == 0 {
+            break 0;
+        }
+        let start = end.saturating_sub(chunk.len() as u64);
+        let buf = &mut chunk[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(buf)?;
+        if let Some(i) = buf.iter().rposition(|b| *b == b'\n') {
+            break start + i as u64 + 1;
+        }
+        end = start;
+    };
+    if keep < len {
+        file.set_len(keep)?;
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Make a directory's entries (created, renamed files) durable. Not needed on Windows, where
+/// directories can't be opened as files.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Lock `dir` (creating it), or find the directory `mode` falls back to.
+fn acquire(dir: &Path, mode: SpoolLockMode) -> io::Result<(PathBuf, File)> {
+    if let Some(lock) = try_lock_dir(dir)? {
+        return Ok((dir.to_path_buf(), lock));
+    }
+    match mode {
+        SpoolLockMode::Exclusive => {}
+        SpoolLockMode::PerProcess => {
+            for n in 1..=MAX_INSTANCES {
+                let sub = dir.join(format!("{}{}", INSTANCE_PREFIX, n));
+                if let Some(lock) = try_lock_dir(&sub)? {
+                    return Ok((sub, lock));
+                }
+            }
+        }
+        SpoolLockMode::HandOver(timeout) => {
+            let deadline = Instant::now() + timeout;
+            while Instant::now() < deadline {
+                std::thread::sleep(HAND_OVER_POLL);
+                if let Some(lock) = try_lock_dir(dir)? {
+                    return Ok((dir.to_path_buf(), lock));
+                }
+            }
+        }
+    }
+    let holder = fs::read_to_string(dir.join(LOCK_FILE))
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .map_or(String::new(), |pid| format!(" (pid {})", pid));
+    Err(io::Error::new(
+        io::ErrorKind::WouldBlock,
+        format!(
+            "spool directory {} is in use by another process{}; use another buffer directory or a \
+             SpoolLockMode other than Exclusive",
+            dir.display(),
+            holder
+        ),
+    ))
+}
+
+/// Take the advisory lock on `dir`, recording our pid in the lock file. `None` if it is held.
+fn try_lock_dir(dir: &Path) -> io::Result<Option<File>> {
+    fs::create_dir_all(dir)?;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => {
+            file.set_len(0)?;
+            write!(file, "{}", std::process::id())?;
+            file.flush()?;
+            Ok(Some(file))
+        }
+        Err(fs::TryLockError::WouldBlock) => Ok(None),
+        Err(fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+fn no_segment(seq: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no spool segment {}", seq))
+}
+
+fn parse_line(line: &str) -> Option<(i64, &str)> {
+    let (ts, json) = line.split_once('\t')?;
+    Some((ts.parse().ok()?, json))
+}
+
 fn segment_name(seq: u64, suffix: &str) -> String {
     format!("{:019}{}", seq, suffix)
 }
@@ -439,4 +1404,416 @@ mod tests {
         assert_eq!(reopened.size(), 2);
         let _ = fs::remove_dir_all(dir);
     }
+
+    #[test]
+    fn encrypted_spool_keeps_no_plaintext_and_survives_key_rotation() {
+        use crate::encryption::StaticKeys;
+        let on_disk = |dir: &Path| -> String {
+            let mut text = String::new();
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                let bytes = fs::read(&path).unwrap();
+                if path.to_string_lossy().ends_with(SEALED_SUFFIX) {
+                    text.push_str(&String::from_utf8(zstd::decode_all(&bytes[..]).unwrap()).unwrap());
+                } else {
+                    text.push_str(&String::from_utf8_lossy(&bytes));
+                }
+            }
+            text
+        };
+        let dir = temp_dir();
+        let now = 1_000_000_000_000;
+        {
+            // A record spooled before encryption was turned on stays readable.
+            let mut spool = DurableSpool::open(dir.clone(), None, None).unwrap();
+            spool.append(&[(now, "{\"n\":\"plain\"}".into())], now).unwrap();
+        }
+        {
+            let keys = Arc::new(StaticKeys::new("k1", [1; 32]));
+            let mut spool = DurableSpool::open(dir.clone(), None, None).unwrap().with_encryption(keys);
+            spool.append(&[(now, "{\"n\":\"secret-1\"}".into())], now).unwrap();
+            spool.roll(now).unwrap();
+            spool.append(&[(now, "{\"n\":\"secret-2\"}".into())], now).unwrap();
+        }
+        assert!(!on_disk(&dir).contains("secret"));
+
+        // Without the key, the encrypted records can't be read, and nothing is skipped silently.
+        {
+            let spool = DurableSpool::open(dir.clone(), None, None).unwrap();
+            let seq = spool.oldest_sealed_seq().unwrap();
+            let err = spool.read_segment(seq, now).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let rotated = Arc::new(StaticKeys::new("k2", [2; 32]).with_retired_key("k1", [1; 32]));
+        let mut spool = DurableSpool::open(dir.clone(), None, None).unwrap().with_encryption(rotated);
+        spool.append(&[(now, "{\"n\":\"secret-3\"}".into())], now).unwrap();
+        assert!(on_disk(&dir).contains("\t!k2:"));
+        assert_eq!(spool.retain(|_, json| !json.contains("secret-2"), now).unwrap(), 1);
+        assert_eq!(
+            drain_all(&mut spool, now),
+            vec!["{\"n\":\"plain\"}", "{\"n\":\"secret-1\"}", "{\"n\":\"secret-3\"}"]
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn lists_exports_and_purges_segments() {
+        let dir = temp_dir();
+        let mut spool = DurableSpool::open(dir.clone(), Some(3_600_000), None).unwrap();
+        // `SpoolAdmin` prunes against the wall clock, so keep the records inside the window.
+        let now = Utc::now().timestamp_millis();
+        spool
+            .append(&[(now - 3000, "{\"n\":1}".into()), (now - 2000, "{\"n\":2}".into())], now)
+            .unwrap();
+        spool.roll(now).unwrap();
+        spool
+            .append(&[(now - 1000, "{\"n\":3}".into()), (now, "{\"n\":4}".into())], now)
+            .unwrap();
+
+        let segments = spool.segments();
+        assert_eq!(segments.len(), 2);
+        assert!(segments[0].sealed && !segments[1].sealed);
+        assert_eq!(segments[0].records, 2);
+        assert_eq!(segments[0].min_timestamp, Some(now - 3000));
+        assert_eq!(segments[1].max_timestamp, Some(now));
+
+        let out = dir.join("export.ndjson");
+        assert_eq!(spool.export_segment(segments[0].seq, &out).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+        assert!(spool.export_segment(99, &out).is_err());
+
+        // The first segment goes whole; the second is rewritten without its older record.
+        assert_eq!(spool.purge_older_than(now - 500, now).unwrap(), 3);
+        let segments = spool.segments();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].records, 1);
+        assert_eq!(segments[0].min_timestamp, Some(now));
+
+        let admin_spool = Mutex::new(Some(spool));
+        let admin = SpoolAdmin::<serde_json::Value>::new(&admin_spool);
+        assert_eq!(admin.peek(segments[0].seq, 10).unwrap(), vec![serde_json::json!({"n": 4})]);
+        assert_eq!(admin.purge_where(|v| v["n"] == 4).unwrap(), 1);
+        assert_eq!(admin.records(), 0);
+        assert!(admin.segments().is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn counts_records_dropped_by_retention_and_size_cap() {
+        let dir = temp_dir();
+        let mut spool = DurableSpool::open(dir.clone(), Some(60_000), None).unwrap();
+        let now = 1_000_000_000_000;
+        spool.append(&[(now - 120_000, "old".into())], now).unwrap();
+        spool.roll(now).unwrap();
+        spool.append(&[(now, "fresh".into())], now).unwrap();
+        let dropped = spool.dropped();
+        assert_eq!(dropped.retention_records, 1);
+        assert!(dropped.retention_bytes > 0);
+        assert_eq!(dropped.size_cap_records, 0);
+
+        let capped_dir = temp_dir();
+        let mut capped = DurableSpool::open(capped_dir.clone(), None, Some(1)).unwrap();
+        capped.append(&[(now, "x".into())], now).unwrap();
+        assert_eq!(capped.size(), 0);
+        assert_eq!(capped.dropped().size_cap_records, 1);
+        let _ = fs::remove_dir_all(dir);
+        let _ = fs::remove_dir_all(capped_dir);
+    }
+
+    #[tokio::test]
+    async fn time_series_spool_can_be_purged_by_series() {
+        use crate::generic::{DataWrapper, DatapointString, DatapointsCollection, IdAndExtId};
+        use crate::tests::mock_backend::MockBackend;
+
+        let backend = MockBackend::start(|_| (503, String::new())).await;
+        let dir = temp_dir();
+        let mut config = backend.config();
+        config.set_buffer_dir(&dir).enable_buffering();
+        let api = crate::ApiService::new(config);
+
+        let now = Utc::now().timestamp_millis();
+        let mut json = DataWrapper::new();
+        for (external_id, points) in [("keep", 2), ("drop", 3)] {
+            let mut collection = DatapointsCollection::from_external_id(external_id);
+            for i in 0..points {
+                collection
+                    .datapoints
+                    .push(DatapointString::new(&(now + i).to_string(), "1"));
+            }
+            json.add_item(collection);
+        }
+        api.time_series.insert_datapoints(&mut json).await.unwrap();
+
+        let spool = api.time_series.spool();
+        assert_eq!(spool.records(), 5);
+        let seq = spool.segments()[0].seq;
+        assert_eq!(spool.peek(seq, 1).unwrap()[0].external_id.as_deref(), Some("keep"));
+        let series = IdAndExtId::from_external_id("drop");
+        assert_eq!(spool.purge_series(&series).unwrap(), 3);
+        assert_eq!(api.time_series.buffered_count(), 2);
+        assert_eq!(api.events.spool().records(), 0);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    fn recording_notifier(spool: &'static str) -> (SpoolNotifier, Arc<Mutex<Vec<BufferEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let observer = move |e: &BufferEvent| seen.lock().unwrap().push(e.clone());
+        (SpoolNotifier::new(spool, Some(Arc::new(observer))), events)
+    }
+
+    #[test]
+    fn observer_is_told_about_size_cap_drops() {
+        let dir = temp_dir();
+        let (notifier, events) = recording_notifier("datapoints");
+        let mut spool = DurableSpool::open(dir.clone(), None, Some(1))
+            .unwrap()
+            .with_notifier(notifier);
+        let now = 1_000_000_000_000;
+        spool.append(&[(now, "x".into()), (now, "y".into())], now).unwrap();
+        let events = events.lock().unwrap();
+        assert_eq!(
+            events[0],
+            BufferEvent::Spooled {
+                spool: "datapoints",
+                records: 2
+            }
+        );
+        assert!(matches!(
+            events[1],
+            BufferEvent::DroppedBySizeCap { spool: "datapoints", records: 2, bytes } if bytes > 0
+        ));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn observer_sees_drains_and_rejections() {
+        use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+        use crate::tests::mock_backend::MockBackend;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let up = Arc::new(AtomicBool::new(false));
+        let backend_up = up.clone();
+        let backend = MockBackend::start(move |req| {
+            if !backend_up.load(Ordering::SeqCst) {
+                (503, String::new())
+            } else if req.body.contains("\"bad\"") {
+                (400, "invalid value".to_string())
+            } else {
+                (204, String::new())
+            }
+        })
+        .await;
+        let dir = temp_dir();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let mut config = backend.config();
+        config
+            .set_buffer_dir(&dir)
+            .enable_buffering()
+            .set_buffer_observer(move |e: &BufferEvent| seen.lock().unwrap().push(e.clone()));
+        let api = crate::ApiService::new(config);
+
+        let now = Utc::now().timestamp_millis();
+        let mut json = DataWrapper::new();
+        let mut collection = DatapointsCollection::from_external_id("temp");
+        for (i, value) in ["1", "bad", "3"].into_iter().enumerate() {
+            let ts = (now + i as i64).to_string();
+            collection.datapoints.push(DatapointString::new(&ts, value));
+        }
+        json.add_item(collection);
+        api.time_series.insert_datapoints(&mut json).await.unwrap();
+        up.store(true, Ordering::SeqCst);
+        api.flush_buffers().await.unwrap();
+
+        let events = events.lock().unwrap();
+        assert!(events.contains(&BufferEvent::Spooled {
+            spool: "datapoints",
+            records: 3
+        }));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            BufferEvent::Rejected { spool: "datapoints", records: 1, status: 400, .. }
+        )));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            BufferEvent::SegmentDrained { spool: "datapoints", records: 2, .. }
+        )));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn a_held_directory_is_not_opened_twice() {
+        let dir = temp_dir();
+        let _held = DurableSpool::open(dir.clone(), None, None).unwrap();
+        let err = DurableSpool::open(dir.clone(), None, None).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(err.to_string().contains(&format!("pid {}", std::process::id())));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn per_process_sub_spools_are_adopted_once_orphaned() {
+        let dir = temp_dir();
+        let now = Utc::now().timestamp_millis();
+        let main = DurableSpool::open(dir.clone(), Some(3_600_000), None).unwrap();
+        let mut second =
+            DurableSpool::open_with_lock(dir.clone(), Some(3_600_000), None, SpoolLockMode::PerProcess)
+                .unwrap();
+        assert_eq!(second.dir(), dir.join("instance-1"));
+        second.append(&[(now, "a".into()), (now, "b".into())], now).unwrap();
+        drop(second);
+        drop(main);
+
+        let mut adopted = DurableSpool::open(dir.clone(), Some(3_600_000), None).unwrap();
+        assert_eq!(adopted.size(), 2);
+        assert!(!dir.join("instance-1").exists());
+        assert_eq!(drain_all(&mut adopted, now), vec!["a", "b"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn hand_over_waits_for_the_holder() {
+        let dir = temp_dir();
+        let held = DurableSpool::open(dir.clone(), None, None).unwrap();
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(150));
+            drop(held);
+        });
+        let mode = SpoolLockMode::HandOver(Duration::from_secs(5));
+        assert!(DurableSpool::open_with_lock(dir.clone(), None, None, mode).is_ok());
+        release.join().unwrap();
+
+        let _held = DurableSpool::open(dir.clone(), None, None).unwrap();
+        let mode = SpoolLockMode::HandOver(Duration::from_millis(100));
+        assert!(DurableSpool::open_with_lock(dir.clone(), None, None, mode).is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn ingest_fails_instead_of_buffering_into_a_held_spool() {
+        use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+        use crate::tests::mock_backend::MockBackend;
+
+        let backend = MockBackend::start(|_| (503, String::new())).await;
+        let dir = temp_dir();
+        let _other_process = DurableSpool::open(dir.join("datapoints"), None, None).unwrap();
+        let mut config = backend.config();
+        config.set_buffer_dir(&dir).enable_buffering();
+        let api = crate::ApiService::new(config);
+
+        let mut json = DataWrapper::new();
+        let mut collection = DatapointsCollection::from_external_id("temp");
+        let ts = Utc::now().timestamp_millis().to_string();
+        collection.datapoints.push(DatapointString::new(&ts, "1"));
+        json.add_item(collection);
+        let err = api.time_series.insert_datapoints(&mut json).await.err().unwrap();
+        assert_eq!(err.get_status().as_u16(), 503);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn ingest_hands_over_once_without_blocking_the_runtime() {
+        use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+        use crate::tests::mock_backend::MockBackend;
+
+        let backend = MockBackend::start(|_| (503, String::new())).await;
+        let dir = temp_dir();
+        let held = DurableSpool::open(dir.join("datapoints"), None, None).unwrap();
+        let mut config = backend.config();
+        config
+            .set_buffer_dir(&dir)
+            .enable_buffering()
+            .set_buffer_lock_mode(SpoolLockMode::HandOver(Duration::from_secs(5)));
+        let api = crate::ApiService::new(config);
+        let insert = || async {
+            let mut json = DataWrapper::new();
+            let mut collection = DatapointsCollection::from_external_id("temp");
+            let ts = Utc::now().timestamp_millis().to_string();
+            collection.datapoints.push(DatapointString::new(&ts, "1"));
+            json.add_item(collection);
+            api.time_series.insert_datapoints(&mut json).await
+        };
+
+        // The holder lets go on this (single-threaded) runtime, so the wait must yield to it.
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            drop(held);
+        };
+        let (inserted, ()) = tokio::join!(insert(), release);
+        assert_eq!(inserted.unwrap().get_http_status_code(), Some(202));
+        assert_eq!(api.time_series.buffered_count(), 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn a_failed_hand_over_is_not_waited_for_again() {
+        use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+        use crate::tests::mock_backend::MockBackend;
+
+        let backend = MockBackend::start(|_| (503, String::new())).await;
+        let dir = temp_dir();
+        let _other_process = DurableSpool::open(dir.join("datapoints"), None, None).unwrap();
+        let mut config = backend.config();
+        config
+            .set_buffer_dir(&dir)
+            .enable_buffering()
+            .set_buffer_lock_mode(SpoolLockMode::HandOver(Duration::from_millis(300)));
+        let api = crate::ApiService::new(config);
+        let mut json = DataWrapper::new();
+        let mut collection = DatapointsCollection::from_external_id("temp");
+        let ts = Utc::now().timestamp_millis().to_string();
+        collection.datapoints.push(DatapointString::new(&ts, "1"));
+        json.add_item(collection);
+
+        let started = Instant::now();
+        assert!(api.time_series.insert_datapoints(&mut json).await.is_err());
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        let started = Instant::now();
+        assert!(api.time_series.insert_datapoints(&mut json).await.is_err());
+        assert!(started.elapsed() < Duration::from_millis(300));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn drain_keeps_a_segment_it_cannot_decrypt() {
+        use crate::encryption::StaticKeys;
+        use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+        use crate::tests::mock_backend::MockBackend;
+
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let dir = temp_dir();
+        let now = Utc::now().timestamp_millis();
+        {
+            let keys = Arc::new(StaticKeys::new("lost", [9; 32]));
+            let mut spool = DurableSpool::open(dir.join("datapoints"), None, None)
+                .unwrap()
+                .with_encryption(keys);
+            let dp = r#"{"externalId":"temp","timestamp":"1","value":"1"}"#;
+            spool.append(&[(now, dp.into())], now).unwrap();
+        }
+        let mut config = backend.config();
+        config
+            .set_buffer_dir(&dir)
+            .enable_buffering()
+            .set_buffer_encryption(StaticKeys::new("current", [1; 32]));
+        let api = crate::ApiService::new(config);
+
+        let err = api.flush_buffers().await.err().unwrap();
+        assert!(err.is_backlog_blocked());
+        assert_eq!(api.time_series.buffered_count(), 1);
+
+        // New points queue up behind the stuck segment rather than overtaking it.
+        let mut json = DataWrapper::new();
+        let mut collection = DatapointsCollection::from_external_id("temp");
+        let ts = now.to_string();
+        collection.datapoints.push(DatapointString::new(&ts, "2"));
+        json.add_item(collection);
+        let inserted = api.time_series.insert_datapoints(&mut json).await.unwrap();
+        assert_eq!(inserted.get_http_status_code(), Some(202));
+        assert_eq!(api.time_series.buffered_count(), 2);
+        assert!(backend.requests_to("/timeseries/data").is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
 }
@@ -1,4 +1,7 @@
+use crate::buffer::{BufferObserver, Durability, DurableSpool, SpoolLockMode, SpoolNotifier};
+use crate::encryption::{KeyProvider, StaticKeys};
 use crate::errors::DataHubError;
+use crate::timeseries::validation::ValidationOptions;
 use chrono::{DateTime, Duration, Utc};
 use dotenv::from_path;
 use maplit::hashmap;
@@ -21,6 +24,14 @@ pub const DEFAULT_BUFFER_RETENTION_MS: i64 = 72 * 3600 * 1000; // 72 hours
 pub const DEFAULT_BUFFER_MAX_BYTES: u64 = 5 * 1024 * 1024 * 1024;
 /// Default directory for the on-disk ingest spools.
 pub const DEFAULT_BUFFER_DIR: &str = ".datahub-spool";
+/// Default upper bound on the background flush task's retry backoff.
+pub const DEFAULT_BUFFER_FLUSH_MAX_BACKOFF: std::time::Duration =
+    std::time::Duration::from_secs(300);
+/// Default wait for `BUFFER_LOCK_MODE=hand_over`.
+pub const DEFAULT_BUFFER_HAND_OVER_TIMEOUT: std::time::Duration =
+    std::time::Duration::from_secs(30);
+/// Default group-commit interval for `BUFFER_DURABILITY=group_commit`.
+pub const DEFAULT_BUFFER_GROUP_COMMIT: std::time::Duration = std::time::Duration::from_millis(100);
 /// RFC 7523 grant type: exchange an externally-issued JWT assertion for a token.
 const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
 /// RFC 7523 client-authentication type: authenticate the client itself with a JWT assertion
@@ -121,6 +132,19 @@ pub struct DataHubConfig {
     pub(crate) buffer_retention_ms: Option<i64>,
     pub(crate) buffer_max_bytes: Option<u64>,
     pub(crate) buffer_dir: Option<PathBuf>,
+    // Also log metadata writes that can't be sent (see `crate::mutations`).
+    pub(crate) buffer_metadata: bool,
+    pub(crate) buffer_lock_mode: SpoolLockMode,
+    pub(crate) buffer_durability: Durability,
+    // Encrypts everything the spools write (off unless set).
+    pub(crate) buffer_encryption: Option<Arc<dyn KeyProvider>>,
+    // Background spool flushing (off unless an interval is set).
+    pub(crate) buffer_flush_interval: Option<std::time::Duration>,
+    pub(crate) buffer_flush_max_backoff: std::time::Duration,
+    // Receives spool lifecycle events (spooled, drained, dropped, rejected, I/O errors).
+    pub(crate) buffer_observer: Option<Arc<dyn BufferObserver>>,
+    // Validation applied to every `insert_datapoints` call (off unless set).
+    pub(crate) datapoint_validation: Option<ValidationOptions>,
 }
 impl AuthState {
     pub fn is_expired(&self) -> bool {
@@ -206,6 +230,14 @@ impl DataHubConfig {
             buffer_retention_ms: None,
             buffer_max_bytes: None,
             buffer_dir: None,
+            buffer_metadata: false,
+            buffer_lock_mode: SpoolLockMode::Exclusive,
+            buffer_durability: Durability::OnSeal,
+            buffer_encryption: None,
+            buffer_flush_interval: None,
+            buffer_flush_max_backoff: DEFAULT_BUFFER_FLUSH_MAX_BACKOFF,
+            buffer_observer: None,
+            datapoint_validation: None,
         }
     }
 
@@ -239,7 +271,12 @@ impl DataHubConfig {
             Arc::new(RwLock::new(AuthState::default()))
         };
         // Durable buffering env config (all optional): ENABLE_BUFFERING, BUFFER_RETENTION_SECS,
-        // BUFFER_MAX_BYTES, BUFFER_DIR. Setting any retention/size bound also enables buffering.
+        // BUFFER_MAX_BYTES, BUFFER_DIR, BUFFER_METADATA, BUFFER_FLUSH_INTERVAL_SECS, BUFFER_LOCK_MODE
+        // (exclusive | per_process | hand_over, waiting BUFFER_LOCK_TIMEOUT_SECS),
+        // BUFFER_DURABILITY (none | on_seal | per_append | group_commit, every
+        // BUFFER_GROUP_COMMIT_MS), BUFFER_ENCRYPTION_KEYS (id:base64key[,...], first is current).
+        // Setting any
+        // retention/size bound, metadata buffering or a flush interval also enables buffering.
         let buffering_requested = map
             .get("ENABLE_BUFFERING")
             .map(|v| v == "true" || v == "1")
@@ -250,6 +287,60 @@ impl DataHubConfig {
             .map(|secs| secs * 1000);
         let buffer_max_bytes = map.get("BUFFER_MAX_BYTES").and_then(|v| v.parse::<u64>().ok());
         let buffer_dir = map.get("BUFFER_DIR").map(PathBuf::from);
+        let buffer_metadata = map
+            .get("BUFFER_METADATA")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        let buffer_flush_interval = map
+            .get("BUFFER_FLUSH_INTERVAL_SECS")
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(std::time::Duration::from_secs);
+        let buffer_lock_mode = match map.get("BUFFER_LOCK_MODE").map(|v| v.to_lowercase()) {
+            None => SpoolLockMode::Exclusive,
+            Some(mode) => match mode.as_str() {
+                "exclusive" => SpoolLockMode::Exclusive,
+                "per_process" => SpoolLockMode::PerProcess,
+                "hand_over" => SpoolLockMode::HandOver(
+                    map.get("BUFFER_LOCK_TIMEOUT_SECS")
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .map_or(DEFAULT_BUFFER_HAND_OVER_TIMEOUT, std::time::Duration::from_secs),
+                ),
+                _ => {
+                    return Err(DataHubError::ConfigError(format!(
+                        "BUFFER_LOCK_MODE must be exclusive, per_process or hand_over, not {}",
+                        mode
+                    )))
+                }
+            },
+        };
+        let buffer_durability = match map.get("BUFFER_DURABILITY").map(|v| v.to_lowercase()) {
+            None => Durability::OnSeal,
+            Some(level) => match level.as_str() {
+                "none" => Durability::None,
+                "on_seal" => Durability::OnSeal,
+                "per_append" => Durability::PerAppend,
+                "group_commit" => Durability::GroupCommit(
+                    map.get("BUFFER_GROUP_COMMIT_MS")
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .filter(|ms| *ms > 0)
+                        .map_or(DEFAULT_BUFFER_GROUP_COMMIT, std::time::Duration::from_millis),
+                ),
+                _ => {
+                    return Err(DataHubError::ConfigError(format!(
+                        "BUFFER_DURABILITY must be none, on_seal, per_append or group_commit, not {}",
+                        level
+                    )))
+                }
+            },
+        };
+        let buffer_encryption = match map.get("BUFFER_ENCRYPTION_KEYS") {
+            None => None,
+            Some(spec) => Some(Arc::new(
+                StaticKeys::parse(spec)
+                    .map_err(|e| DataHubError::ConfigError(format!("BUFFER_ENCRYPTION_KEYS: {}", e)))?,
+            ) as Arc<dyn KeyProvider>),
+        };
 
         Ok(Self {
             config: Arc::new(oauthconfig),
@@ -257,10 +348,20 @@ impl DataHubConfig {
             oauth2_client: client,
             http_client: reqwest::Client::new(),
             auth_state,
-            buffering_requested,
+            buffering_requested: buffering_requested
+                || buffer_metadata
+                || buffer_flush_interval.is_some(),
             buffer_retention_ms,
             buffer_max_bytes,
             buffer_dir,
+            buffer_metadata,
+            buffer_lock_mode,
+            buffer_durability,
+            buffer_encryption,
+            buffer_flush_interval,
+            buffer_flush_max_backoff: DEFAULT_BUFFER_FLUSH_MAX_BACKOFF,
+            buffer_observer: None,
+            datapoint_validation: None,
         })
     }
 
@@ -333,6 +434,16 @@ impl DataHubConfig {
         self
     }
 
+    /// Also buffer metadata writes — `time_series.create`, `resources.create` / `update`,
+    /// `edges.create`, `labels.create` — that can't reach the backend, replaying them in order
+    /// before any buffered datapoints or events (see [`crate::mutations`]); also enables
+    /// buffering. Off by default, as a buffered create answers 202 without the created items.
+    pub fn enable_metadata_buffering(&mut self) -> &mut Self {
+        self.buffer_metadata = true;
+        self.buffering_requested = true;
+        self
+    }
+
     /// Set the buffer time window (seconds); also enables buffering.
     pub fn set_buffer_retention_secs(&mut self, secs: i64) -> &mut Self {
         self.buffer_retention_ms = Some(secs * 1000);
@@ -353,6 +464,61 @@ impl DataHubConfig {
         self
     }
 
+    /// What happens when another process already holds a spool directory (see
+    /// [`SpoolLockMode`]). `Exclusive` by default: this client's ingest then fails instead of
+    /// buffering.
+    pub fn set_buffer_lock_mode(&mut self, mode: SpoolLockMode) -> &mut Self {
+        self.buffer_lock_mode = mode;
+        self
+    }
+
+    /// When spooled data is fsynced (see [`Durability`]). `OnSeal` by default; `PerAppend` or
+    /// `GroupCommit` also protect the active segment against power loss, at some throughput cost.
+    pub fn set_buffer_durability(&mut self, durability: Durability) -> &mut Self {
+        self.buffer_durability = durability;
+        self
+    }
+
+    /// Encrypt everything the spools write to disk with `keys` — the ingest, dead-letter and
+    /// quarantine spools — using AES-256-GCM (see [`crate::encryption`]). Use [`StaticKeys`] for
+    /// keys from configuration, or implement [`KeyProvider`] to fetch them from a key store.
+    pub fn set_buffer_encryption(&mut self, keys: impl KeyProvider + 'static) -> &mut Self {
+        self.buffer_encryption = Some(Arc::new(keys));
+        self
+    }
+
+    /// Drain the spools from a background task every `interval`, so a backlog is flushed even when
+    /// nothing new is ingested; also enables buffering. While the backend stays unreachable the
+    /// wait doubles up to [`set_buffer_flush_max_backoff`](Self::set_buffer_flush_max_backoff)
+    /// (5 minutes by default). The task starts with the `ApiService`, on the Tokio runtime it is
+    /// created in; see [`crate::ApiService::flush_buffers`] and [`crate::ApiService::shutdown`].
+    pub fn set_buffer_flush_interval(&mut self, interval: std::time::Duration) -> &mut Self {
+        self.buffer_flush_interval = Some(interval);
+        self.buffering_requested = true;
+        self
+    }
+
+    pub fn set_buffer_flush_max_backoff(&mut self, max_backoff: std::time::Duration) -> &mut Self {
+        self.buffer_flush_max_backoff = max_backoff;
+        self
+    }
+
+    /// Be told what happens to the ingest spools: records spooled and drained, segments dropped by
+    /// the retention window or size cap, records the backend rejected, and spool I/O errors (see
+    /// [`BufferEvent`](crate::buffer::BufferEvent)). Alerting can use the drop events to page before
+    /// data is discarded, or the metrics the `metrics` feature exports.
+    pub fn set_buffer_observer(&mut self, observer: impl BufferObserver + 'static) -> &mut Self {
+        self.buffer_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Validate every `insert_datapoints` request before it is sent (see
+    /// [`crate::timeseries::validation`]). Off by default.
+    pub fn set_datapoint_validation(&mut self, options: ValidationOptions) -> &mut Self {
+        self.datapoint_validation = Some(options);
+        self
+    }
+
     /// Whether durable ingest buffering is enabled (a bound was set or it was explicitly enabled).
     pub fn buffering_enabled(&self) -> bool {
         self.buffering_requested
@@ -360,6 +526,12 @@ impl DataHubConfig {
             || self.buffer_max_bytes.is_some()
     }
 
+    /// Whether metadata writes are buffered too (see
+    /// [`enable_metadata_buffering`](Self::enable_metadata_buffering)).
+    pub fn metadata_buffering_enabled(&self) -> bool {
+        self.buffer_metadata && self.buffering_enabled()
+    }
+
     /// Effective time window (applies the default when enabled but unset), else `None`.
     pub(crate) fn effective_buffer_retention_ms(&self) -> Option<i64> {
         self.buffering_enabled()
@@ -372,6 +544,31 @@ impl DataHubConfig {
             .then(|| self.buffer_max_bytes.unwrap_or(DEFAULT_BUFFER_MAX_BYTES))
     }
 
+    /// Open a spool in `dir` with the configured lock mode, durability and encryption. Never
+    /// waits for a hand-over; services do that beforehand with [`crate::buffer::LazySpool::hand_over`].
+    pub(crate) fn open_spool(
+        &self,
+        dir: PathBuf,
+        retention_ms: Option<i64>,
+        max_bytes: Option<u64>,
+    ) -> std::io::Result<DurableSpool> {
+        let mode = match self.buffer_lock_mode {
+            SpoolLockMode::HandOver(_) => SpoolLockMode::Exclusive,
+            mode => mode,
+        };
+        let spool = DurableSpool::open_with_lock(dir, retention_ms, max_bytes, mode)?
+            .with_durability(self.buffer_durability)?;
+        Ok(match &self.buffer_encryption {
+            Some(keys) => spool.with_encryption(keys.clone()),
+            None => spool,
+        })
+    }
+
+    /// The notifier for the ingest spool `name`.
+    pub(crate) fn spool_notifier(&self, name: &'static str) -> SpoolNotifier {
+        SpoolNotifier::new(name, self.buffer_observer.clone())
+    }
+
     /// Directory the on-disk spools live in.
     pub(crate) fn buffer_directory(&self) -> PathBuf {
         self.buffer_dir
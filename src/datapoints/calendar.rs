@@ -0,0 +1,657 @@
+//! Calendar aggregation in a local time zone.
+//!
+//! `RetrieveFilter::granularity` asks the backend for fixed-length UTC buckets. Reports usually
+//! need plant-local days, weeks, months or shifts instead. Those buckets vary in length: a day
+//! is 23 or 25 hours when daylight saving time starts or ends. A [`CalendarAggregator`] rolls
+//! the output of `retrieve_datapoints` into such buckets on the client:
+//!
+//! - [`aggregate`](CalendarAggregator::aggregate) takes raw datapoints (`value` set) and computes
+//!   count, min, max, average, sum and a time-weighted average per bucket.
+//! - [`rollup`](CalendarAggregator::rollup) takes finer aggregates (`min`/`max`/`average`/`sum`
+//!   over a known `granularity`, e.g. hourly) and combines them per bucket.
+//!
+//! Both take the requested `[start, end)` range. A bucket only partly inside it is marked
+//! [`partial`](CalendarAggregate::partial), and its statistics cover just the part inside. For
+//! the time-weighted average, the last value before a bucket carries into it, so a bucket with
+//! no points of its own still gets a value.
+//!
+//! ```no_run
+//! use dataplatform_rust_sdk::datapoints::calendar::{CalendarAggregator, CalendarPeriod};
+//! # fn run(points: Vec<dataplatform_rust_sdk::generic::Datapoint>,
+//! #        start: chrono::DateTime<chrono::Utc>, end: chrono::DateTime<chrono::Utc>) {
+//! let daily = CalendarAggregator::for_time_zone("Europe/Oslo", CalendarPeriod::Day).unwrap();
+//! for day in daily.aggregate(&points, start, end) {
+//!     println!("{}: {:?}", day.bucket.label, day.time_weighted_average);
+//! }
+//! # }
+//! ```
+
+use crate::generic::Datapoint;
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset,
+    TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CalendarError {
+    #[error("unknown time zone '{0}'")]
+    UnknownTimeZone(String),
+    #[error("a shift pattern needs at least one shift")]
+    NoShifts,
+    #[error("more than one shift starts at {0}")]
+    DuplicateShiftStart(NaiveTime),
+}
+
+/// A named shift, running from `start` (local time) until the next shift in its pattern starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shift {
+    pub name: String,
+    pub start: NaiveTime,
+}
+
+impl Shift {
+    pub fn new(name: &str, start: NaiveTime) -> Self {
+        Shift {
+            name: name.to_string(),
+            start,
+        }
+    }
+}
+
+/// The shifts that make up each day. Together they always cover the whole day; the last one
+/// runs past midnight into the first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShiftPattern {
+    shifts: Vec<Shift>,
+}
+
+impl ShiftPattern {
+    pub fn new(mut shifts: Vec<Shift>) -> Result<Self, CalendarError> {
+        if shifts.is_empty() {
+            return Err(CalendarError::NoShifts);
+        }
+        shifts.sort_by_key(|s| s.start);
+        if let Some(pair) = shifts.windows(2).find(|w| w[0].start == w[1].start) {
+            return Err(CalendarError::DuplicateShiftStart(pair[0].start));
+        }
+        Ok(ShiftPattern { shifts })
+    }
+
+    /// Shifts in order of their start time.
+    pub fn shifts(&self) -> &[Shift] {
+        &self.shifts
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CalendarPeriod {
+    Hour,
+    Day,
+    /// Weeks starting on the given day.
+    Week(Weekday),
+    Month,
+    Quarter,
+    Year,
+    Shifts(ShiftPattern),
+}
+
+/// How the time-weighted average fills the time between datapoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Each value holds until the next datapoint.
+    #[default]
+    Step,
+    /// Straight lines between datapoints. The last value holds.
+    Linear,
+}
+
+/// One calendar bucket, `[start, end)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalendarBucket {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Local description: `2024-03-31`, `2024-03`, `2024-Q1`, `2024-03-31 Night`, …
+    pub label: String,
+}
+
+impl CalendarBucket {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarAggregate {
+    pub bucket: CalendarBucket,
+    /// Raw datapoints in the bucket or, for [`rollup`](CalendarAggregator::rollup), finer
+    /// aggregates.
+    pub count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Mean of the values. In a rollup, weighted by each finer aggregate's duration.
+    pub average: Option<f64>,
+    pub sum: Option<f64>,
+    pub time_weighted_average: Option<f64>,
+    /// The requested range covers only part of the bucket.
+    pub partial: bool,
+    /// Fraction of the bucket's in-range time that has a value, between 0 and 1.
+    pub coverage: f64,
+}
+
+/// Rolls datapoints into calendar buckets in one time zone.
+#[derive(Debug, Clone)]
+pub struct CalendarAggregator {
+    tz: Tz,
+    period: CalendarPeriod,
+    interpolation: Interpolation,
+}
+
+impl CalendarAggregator {
+    pub fn new(tz: Tz, period: CalendarPeriod) -> Self {
+        CalendarAggregator {
+            tz,
+            period,
+            interpolation: Interpolation::default(),
+        }
+    }
+
+    /// Use an IANA time zone name, e.g. `Europe/Oslo`.
+    pub fn for_time_zone(name: &str, period: CalendarPeriod) -> Result<Self, CalendarError> {
+        let tz = name
+            .parse::<Tz>()
+            .map_err(|_| CalendarError::UnknownTimeZone(name.to_string()))?;
+        Ok(Self::new(tz, period))
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Every bucket that overlaps `[start, end)`, in order.
+    pub fn buckets(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<CalendarBucket> {
+        let mut out = Vec::new();
+        if start >= end {
+            return out;
+        }
+        let mut bucket = self.bucket_at(start);
+        loop {
+            let bucket_end = bucket.end;
+            out.push(bucket);
+            if bucket_end >= end {
+                return out;
+            }
+            let next = self.bucket_at(bucket_end);
+            if next.end <= bucket_end {
+                // Can't happen for a well-formed zone; never loop forever if it does.
+                return out;
+            }
+            bucket = next;
+        }
+    }
+
+    /// Aggregate raw datapoints (their `value`) over `[start, end)`. Points outside the range
+    /// only contribute the value carried into its first bucket.
+    pub fn aggregate(
+        &self,
+        points: &[Datapoint],
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Vec<CalendarAggregate> {
+        let mut samples: Vec<(i64, f64)> = points
+            .iter()
+            .filter_map(|dp| {
+                dp.value
+                    .filter(|v| v.is_finite())
+                    .map(|v| (dp.timestamp.timestamp_millis(), v))
+            })
+            .collect();
+        samples.sort_by_key(|(ts, _)| *ts);
+
+        self.buckets(start, end)
+            .into_iter()
+            .map(|bucket| {
+                let (ws, we, partial) = window(&bucket, start, end);
+                let lo = samples.partition_point(|(ts, _)| *ts < ws);
+                let hi = samples.partition_point(|(ts, _)| *ts < we);
+                let values: Vec<f64> = samples[lo..hi].iter().map(|(_, v)| *v).collect();
+                let (area, covered) =
+                    integrate(&samples, ws, we, end.timestamp_millis(), self.interpolation);
+                let sum: f64 = values.iter().sum();
+                CalendarAggregate {
+                    bucket,
+                    count: values.len() as u64,
+                    min: values.iter().copied().reduce(f64::min),
+                    max: values.iter().copied().reduce(f64::max),
+                    average: (!values.is_empty()).then(|| sum / values.len() as f64),
+                    sum: (!values.is_empty()).then_some(sum),
+                    time_weighted_average: (covered > 0.0).then(|| area / covered),
+                    partial,
+                    coverage: covered / (we - ws) as f64,
+                }
+            })
+            .collect()
+    }
+
+    /// Combine finer aggregates, each covering `granularity` from its timestamp, over
+    /// `[start, end)`. A finer aggregate belongs to the bucket its timestamp falls in. `min`,
+    /// `max` and `average` fall back to `value` when unset.
+    pub fn rollup(
+        &self,
+        aggregates: &[Datapoint],
+        granularity: Duration,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Vec<CalendarAggregate> {
+        let mut sorted: Vec<&Datapoint> = aggregates.iter().collect();
+        sorted.sort_by_key(|dp| dp.timestamp);
+        let step = granularity.num_milliseconds().max(1);
+
+        self.buckets(start, end)
+            .into_iter()
+            .map(|bucket| {
+                let (ws, we, partial) = window(&bucket, start, end);
+                let lo = sorted.partition_point(|dp| dp.timestamp.timestamp_millis() < ws);
+                let hi = sorted.partition_point(|dp| dp.timestamp.timestamp_millis() < we);
+                let mut agg = CalendarAggregate {
+                    bucket,
+                    count: 0,
+                    min: None,
+                    max: None,
+                    average: None,
+                    sum: None,
+                    time_weighted_average: None,
+                    partial,
+                    coverage: 0.0,
+                };
+                let (mut weighted, mut weight) = (0.0, 0.0);
+                for dp in &sorted[lo..hi] {
+                    agg.count += 1;
+                    if let Some(min) = dp.min.or(dp.value) {
+                        agg.min = Some(agg.min.map_or(min, |m| m.min(min)));
+                    }
+                    if let Some(max) = dp.max.or(dp.value) {
+                        agg.max = Some(agg.max.map_or(max, |m| m.max(max)));
+                    }
+                    if let Some(sum) = dp.sum {
+                        agg.sum = Some(agg.sum.unwrap_or(0.0) + sum);
+                    }
+                    if let Some(avg) = dp.average.or(dp.value) {
+                        let ts = dp.timestamp.timestamp_millis();
+                        let w = ((ts + step).min(we) - ts) as f64;
+                        weighted += avg * w;
+                        weight += w;
+                    }
+                }
+                if weight > 0.0 {
+                    agg.average = Some(weighted / weight);
+                    agg.time_weighted_average = agg.average;
+                }
+                agg.coverage = (weight / (we - ws) as f64).min(1.0);
+                agg
+            })
+            .collect()
+    }
+
+    /// The bucket containing `t`.
+    fn bucket_at(&self, t: DateTime<Utc>) -> CalendarBucket {
+        if let CalendarPeriod::Hour = self.period {
+            // Hours are fixed-length; flooring by the local offset keeps them aligned to local
+            // hours in zones offset by a fraction of an hour.
+            let offset = self.tz.offset_from_utc_datetime(&t.naive_utc()).fix();
+            let local = t.timestamp() + offset.local_minus_utc() as i64;
+            let start = DateTime::from_timestamp(local - local.rem_euclid(3600), 0).unwrap()
+                - Duration::seconds(offset.local_minus_utc() as i64);
+            return CalendarBucket {
+                start,
+                end: start + Duration::hours(1),
+                label: start
+                    .with_timezone(&self.tz)
+                    .format("%Y-%m-%dT%H:%M%:z")
+                    .to_string(),
+            };
+        }
+        let local = t.with_timezone(&self.tz).naive_local();
+        let (anchor, label) = self.floor(local);
+        let mut start = self.resolve(anchor);
+        if start > t {
+            // `t` falls before its local anchor resolves: a boundary inside a DST gap.
+            start = t;
+        }
+        CalendarBucket {
+            start,
+            end: self.resolve(self.next(anchor)),
+            label,
+        }
+    }
+
+    /// Local start of the bucket containing `local`, and its label.
+    fn floor(&self, local: NaiveDateTime) -> (NaiveDateTime, String) {
+        let date = local.date();
+        let midnight = |d: NaiveDate| d.and_time(NaiveTime::MIN);
+        match &self.period {
+            CalendarPeriod::Hour | CalendarPeriod::Day => {
+                (midnight(date), date.format("%Y-%m-%d").to_string())
+            }
+            CalendarPeriod::Week(first) => {
+                let back =
+                    (7 + date.weekday().num_days_from_monday() - first.num_days_from_monday()) % 7;
+                let start = date - Duration::days(back as i64);
+                (midnight(start), start.format("%Y-%m-%d").to_string())
+            }
+            CalendarPeriod::Month => (
+                midnight(first_of_month(date.year(), date.month())),
+                date.format("%Y-%m").to_string(),
+            ),
+            CalendarPeriod::Quarter => {
+                let quarter = (date.month() - 1) / 3;
+                (
+                    midnight(first_of_month(date.year(), quarter * 3 + 1)),
+                    format!("{}-Q{}", date.year(), quarter + 1),
+                )
+            }
+            CalendarPeriod::Year => (
+                midnight(first_of_month(date.year(), 1)),
+                date.year().to_string(),
+            ),
+            CalendarPeriod::Shifts(pattern) => {
+                let shifts = pattern.shifts();
+                let (day, shift) = match shifts.iter().rposition(|s| s.start <= local.time()) {
+                    Some(i) => (date, &shifts[i]),
+                    // Before the first shift of the day: still the previous day's last shift.
+                    None => (date - Duration::days(1), shifts.last().unwrap()),
+                };
+                (
+                    day.and_time(shift.start),
+                    format!("{} {}", day.format("%Y-%m-%d"), shift.name),
+                )
+            }
+        }
+    }
+
+    /// Local start of the bucket after the one starting at `anchor`.
+    fn next(&self, anchor: NaiveDateTime) -> NaiveDateTime {
+        let date = anchor.date();
+        let midnight = |d: NaiveDate| d.and_time(NaiveTime::MIN);
+        let months_later = |n: u32| {
+            let index = date.year() * 12 + date.month0() as i32 + n as i32;
+            midnight(first_of_month(
+                index.div_euclid(12),
+                index.rem_euclid(12) as u32 + 1,
+            ))
+        };
+        match &self.period {
+            CalendarPeriod::Hour => anchor + Duration::hours(1),
+            CalendarPeriod::Day => midnight(date + Duration::days(1)),
+            CalendarPeriod::Week(_) => midnight(date + Duration::days(7)),
+            CalendarPeriod::Month => months_later(1),
+            CalendarPeriod::Quarter => months_later(3),
+            CalendarPeriod::Year => months_later(12),
+            CalendarPeriod::Shifts(pattern) => {
+                let shifts = pattern.shifts();
+                match shifts.iter().position(|s| s.start > anchor.time()) {
+                    Some(i) => date.and_time(shifts[i].start),
+                    None => (date + Duration::days(1)).and_time(shifts[0].start),
+                }
+            }
+        }
+    }
+
+    /// The instant a local time names. A time skipped by a DST change resolves to the first
+    /// valid time after it; a repeated one to its first occurrence.
+    fn resolve(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        let mut candidate = local;
+        // DST gaps are at most a couple of hours.
+        for _ in 0..16 {
+            match self.tz.from_local_datetime(&candidate) {
+                LocalResult::Single(t) => return t.with_timezone(&Utc),
+                LocalResult::Ambiguous(earliest, _) => return earliest.with_timezone(&Utc),
+                LocalResult::None => candidate += Duration::minutes(15),
+            }
+        }
+        Utc.from_utc_datetime(&local)
+    }
+}
+
+fn first_of_month(year: i32, month: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, 1).expect("month is always 1..=12")
+}
+
+/// The part of `bucket` inside `[start, end)` in epoch millis, and whether that's only part of it.
+fn window(bucket: &CalendarBucket, start: DateTime<Utc>, end: DateTime<Utc>) -> (i64, i64, bool) {
+    let ws = bucket.start.max(start);
+    let we = bucket.end.min(end);
+    (
+        ws.timestamp_millis(),
+        we.timestamp_millis(),
+        ws > bucket.start || we < bucket.end,
+    )
+}
+
+/// Integrate the interpolated signal over `[a, b)`. Returns the area and the time with a value.
+/// The signal starts at the first sample and, after the last, holds until `hold_until`.
+fn integrate(
+    samples: &[(i64, f64)],
+    a: i64,
+    b: i64,
+    hold_until: i64,
+    interpolation: Interpolation,
+) -> (f64, f64) {
+    let (mut area, mut covered) = (0.0, 0.0);
+    // The segment in effect at `a` starts at the last sample at or before it.
+    let first = samples
+        .partition_point(|(ts, _)| *ts <= a)
+        .saturating_sub(1);
+    for i in first..samples.len() {
+        let (t0, v0) = samples[i];
+        if t0 >= b {
+            break;
+        }
+        let next = samples.get(i + 1).copied();
+        let t1 = next.map_or(hold_until, |(t, _)| t);
+        let (lo, hi) = (t0.max(a), t1.min(b));
+        if hi <= lo {
+            continue;
+        }
+        let len = (hi - lo) as f64;
+        let mean = match (interpolation, next) {
+            (Interpolation::Linear, Some((t1, v1))) => {
+                let at = |t: i64| v0 + (v1 - v0) * (t - t0) as f64 / (t1 - t0) as f64;
+                (at(lo) + at(hi)) / 2.0
+            }
+            _ => v0,
+        };
+        area += mean * len;
+        covered += len;
+    }
+    (area, covered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oslo(period: CalendarPeriod) -> CalendarAggregator {
+        CalendarAggregator::for_time_zone("Europe/Oslo", period).unwrap()
+    }
+
+    fn local(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        chrono_tz::Europe::Oslo
+            .with_ymd_and_hms(y, m, d, h, min, 0)
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn point(t: DateTime<Utc>, value: f64) -> Datapoint {
+        Datapoint {
+            timestamp: t,
+            value: Some(value),
+            min: None,
+            max: None,
+            average: None,
+            sum: None,
+        }
+    }
+
+    #[test]
+    fn days_follow_daylight_saving_time() {
+        let days =
+            oslo(CalendarPeriod::Day).buckets(local(2024, 3, 30, 0, 0), local(2024, 4, 1, 0, 0));
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].duration(), Duration::hours(24));
+        assert_eq!(days[1].label, "2024-03-31");
+        assert_eq!(days[1].duration(), Duration::hours(23));
+
+        let autumn = oslo(CalendarPeriod::Day)
+            .buckets(local(2024, 10, 27, 12, 0), local(2024, 10, 27, 13, 0));
+        assert_eq!(autumn[0].duration(), Duration::hours(25));
+    }
+
+    #[test]
+    fn weeks_months_quarters_and_years() {
+        let weeks = oslo(CalendarPeriod::Week(Weekday::Mon))
+            .buckets(local(2024, 3, 27, 10, 0), local(2024, 3, 27, 11, 0));
+        assert_eq!(weeks[0].start, local(2024, 3, 25, 0, 0));
+        assert_eq!(weeks[0].duration(), Duration::hours(7 * 24 - 1));
+
+        let months =
+            oslo(CalendarPeriod::Month).buckets(local(2024, 11, 15, 0, 0), local(2025, 1, 2, 0, 0));
+        let labels: Vec<&str> = months.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(labels, vec!["2024-11", "2024-12", "2025-01"]);
+        assert_eq!(months[1].end, local(2025, 1, 1, 0, 0));
+
+        let quarters =
+            oslo(CalendarPeriod::Quarter).buckets(local(2024, 5, 1, 0, 0), local(2024, 5, 2, 0, 0));
+        assert_eq!(quarters[0].label, "2024-Q2");
+        assert_eq!(quarters[0].start, local(2024, 4, 1, 0, 0));
+        assert_eq!(quarters[0].end, local(2024, 7, 1, 0, 0));
+
+        let years =
+            oslo(CalendarPeriod::Year).buckets(local(2024, 5, 1, 0, 0), local(2024, 5, 2, 0, 0));
+        assert_eq!(years[0].label, "2024");
+    }
+
+    #[test]
+    fn hours_in_a_half_hour_zone() {
+        let kolkata =
+            CalendarAggregator::for_time_zone("Asia/Kolkata", CalendarPeriod::Hour).unwrap();
+        let t = DateTime::parse_from_rfc3339("2024-01-01T00:10:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let hour = &kolkata.buckets(t, t + Duration::minutes(1))[0];
+        // 00:10 UTC is 05:40 local; the local hour 05:00-06:00 is 23:30-00:30 UTC.
+        assert_eq!(hour.start.to_rfc3339(), "2023-12-31T23:30:00+00:00");
+        assert_eq!(hour.label, "2024-01-01T05:00+05:30");
+    }
+
+    #[test]
+    fn shifts_wrap_past_midnight_and_shrink_with_dst() {
+        let pattern = ShiftPattern::new(vec![
+            Shift::new("Night", NaiveTime::from_hms_opt(22, 0, 0).unwrap()),
+            Shift::new("Day", NaiveTime::from_hms_opt(6, 0, 0).unwrap()),
+            Shift::new("Evening", NaiveTime::from_hms_opt(14, 0, 0).unwrap()),
+        ])
+        .unwrap();
+        let shifts = oslo(CalendarPeriod::Shifts(pattern));
+        let buckets = shifts.buckets(local(2024, 3, 30, 15, 0), local(2024, 3, 31, 7, 0));
+        let labels: Vec<&str> = buckets.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec!["2024-03-30 Evening", "2024-03-30 Night", "2024-03-31 Day"]
+        );
+        assert_eq!(buckets[1].duration(), Duration::hours(7));
+
+        // 03:00 belongs to the previous evening's night shift.
+        let early = shifts.buckets(local(2024, 4, 2, 3, 0), local(2024, 4, 2, 3, 1));
+        assert_eq!(early[0].label, "2024-04-01 Night");
+
+        assert_eq!(ShiftPattern::new(vec![]), Err(CalendarError::NoShifts));
+    }
+
+    #[test]
+    fn raw_aggregates_with_partial_buckets() {
+        let day = oslo(CalendarPeriod::Day);
+        let points = vec![
+            point(local(2024, 6, 1, 0, 0), 0.0),
+            point(local(2024, 6, 1, 18, 0), 10.0),
+        ];
+        let full = day.aggregate(&points, local(2024, 6, 1, 0, 0), local(2024, 6, 2, 0, 0));
+        assert_eq!(full.len(), 1);
+        let agg = &full[0];
+        assert_eq!(agg.count, 2);
+        assert_eq!(agg.average, Some(5.0));
+        assert_eq!(agg.sum, Some(10.0));
+        assert_eq!(agg.time_weighted_average, Some(2.5));
+        assert!(!agg.partial);
+        assert_eq!(agg.coverage, 1.0);
+
+        // From noon: the 0 from midnight carries in; only the point at 18:00 is counted.
+        let half = day.aggregate(&points, local(2024, 6, 1, 12, 0), local(2024, 6, 2, 0, 0));
+        let agg = &half[0];
+        assert!(agg.partial);
+        assert_eq!(agg.count, 1);
+        assert_eq!(agg.time_weighted_average, Some(5.0));
+
+        let linear = day
+            .clone()
+            .with_interpolation(Interpolation::Linear)
+            .aggregate(&points, local(2024, 6, 1, 0, 0), local(2024, 6, 2, 0, 0));
+        // Ramp 0 -> 10 over 18 h (mean 5), then 10 for 6 h.
+        assert_eq!(
+            linear[0].time_weighted_average,
+            Some((5.0 * 18.0 + 10.0 * 6.0) / 24.0)
+        );
+    }
+
+    #[test]
+    fn empty_buckets_before_the_first_point_have_no_coverage() {
+        let day = oslo(CalendarPeriod::Day);
+        let points = vec![point(local(2024, 6, 2, 12, 0), 4.0)];
+        let out = day.aggregate(&points, local(2024, 6, 1, 0, 0), local(2024, 6, 3, 0, 0));
+        assert_eq!(out[0].count, 0);
+        assert_eq!(out[0].time_weighted_average, None);
+        assert_eq!(out[0].coverage, 0.0);
+        assert_eq!(out[1].time_weighted_average, Some(4.0));
+        assert_eq!(out[1].coverage, 0.5);
+    }
+
+    #[test]
+    fn rollup_of_hourly_aggregates() {
+        let day = oslo(CalendarPeriod::Day);
+        let start = local(2024, 6, 1, 0, 0);
+        let hourly: Vec<Datapoint> = (0..24)
+            .map(|h| Datapoint {
+                timestamp: start + Duration::hours(h),
+                value: None,
+                min: Some(h as f64),
+                max: Some(h as f64 + 1.0),
+                average: Some(h as f64 + 0.5),
+                sum: Some(10.0),
+            })
+            .collect();
+        let out = day.rollup(
+            &hourly,
+            Duration::hours(1),
+            start,
+            start + Duration::days(1),
+        );
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].count, 24);
+        assert_eq!(out[0].min, Some(0.0));
+        assert_eq!(out[0].max, Some(24.0));
+        assert_eq!(out[0].sum, Some(240.0));
+        assert_eq!(out[0].average, Some(12.0));
+        assert_eq!(out[0].coverage, 1.0);
+    }
+
+    #[test]
+    fn unknown_time_zone() {
+        assert_eq!(
+            CalendarAggregator::for_time_zone("Mars/Olympus", CalendarPeriod::Day).unwrap_err(),
+            CalendarError::UnknownTimeZone("Mars/Olympus".to_string())
+        );
+    }
+}
@@ -0,0 +1,1043 @@
+//! Calculated series: derive a signal from other series with an expression such as
+//! `flow_in - flow_out`, `power / 1000` or `rolling_mean(temp, 10m)`.
+//!
+//! # Language
+//!
+//! - Numbers (`2.5`, `1e3`) and series references. A bare name (`flow_in`, `plant.area:temp`)
+//!   refers to the series with that external id; wrap ids with other characters in braces:
+//!   `{flow-in}`.
+//! - Arithmetic `+ - * / % ^`, comparisons `< <= > >= == !=`, logic `&& || !`. Comparisons
+//!   and logic yield 1 or 0.
+//! - Functions: `abs sqrt exp ln log10 floor ceil round`, `min(a, b, …)`, `max(a, b, …)`,
+//!   `coalesce(a, b, …)` (first value present), `if(cond, then, else)`, `diff(x)` (change since
+//!   the previous row), `rate(x)` (change per second), and `rolling_mean`, `rolling_sum`,
+//!   `rolling_min`, `rolling_max`. The rolling functions take a window as a duration (`30s`,
+//!   `10m`, `1h`, `1d`, `500ms`, at least 1ms) or a number of rows (at least 1).
+//!
+//! A row where an input is missing, or the result isn't a finite number (e.g. division by
+//! zero), produces no output point.
+//!
+//! # Evaluation
+//!
+//! The referenced series are aligned on one time index first (see [`Alignment`]), then the
+//! expression is evaluated row by row. [`Expression::evaluate`] works on datapoints you already
+//! have; [`TimeSeriesService::calculate`](crate::TimeSeriesService::calculate) retrieves them.
+//! [`CalculatedSeries`] writes the result into a target series, once, incrementally, or on a
+//! schedule.
+
+use crate::datapoints::ops::{align, AlignedSeries, Join, ResampleMethod};
+use crate::generic::{DataWrapper, Datapoint, DatapointString, DatapointsCollection};
+use crate::http::ResponseError;
+use crate::ApiService;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Weak;
+use thiserror::Error;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Error)]
+pub enum ExpressionError {
+    #[error("syntax error at {position}: {message}")]
+    Syntax { position: usize, message: String },
+    #[error("unknown function '{0}'")]
+    UnknownFunction(String),
+    #[error("{name}() takes {expected} argument(s), got {got}")]
+    Arity {
+        name: String,
+        expected: &'static str,
+        got: usize,
+    },
+    #[error("a duration is only allowed as the window of a rolling function")]
+    MisplacedDuration,
+    #[error("the expression references no series")]
+    NoSeries,
+    #[error("no datapoints were given for series '{0}'")]
+    MissingSeries(String),
+    #[error(transparent)]
+    Request(#[from] ResponseError),
+}
+
+// ---------------------------------------------------------------------------------------------
+// Syntax
+// ---------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Duration(i64),
+    Name(String),
+    Series(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+const OPERATORS: [&str; 15] = [
+    "<=", ">=", "==", "!=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "^", "!",
+];
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, ExpressionError> {
+    let chars: Vec<char> = src.chars().collect();
+    let syntax = |position: usize, message: &str| ExpressionError::Syntax {
+        position,
+        message: message.to_string(),
+    };
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit()))
+        {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let sign = matches!(chars.get(i + 1), Some('+' | '-')) as usize;
+                if chars.get(i + 1 + sign).is_some_and(|d| d.is_ascii_digit()) {
+                    i += 1 + sign;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value: f64 = text.parse().map_err(|_| syntax(start, "invalid number"))?;
+            let mut unit_end = i;
+            while unit_end < chars.len() && chars[unit_end].is_ascii_alphabetic() {
+                unit_end += 1;
+            }
+            let unit: String = chars[i..unit_end].iter().collect();
+            if unit.is_empty() {
+                tokens.push((start, Token::Number(value)));
+            } else {
+                let ms = match unit.as_str() {
+                    "ms" => 1.0,
+                    "s" => 1_000.0,
+                    "m" => 60_000.0,
+                    "h" => 3_600_000.0,
+                    "d" => 86_400_000.0,
+                    "w" => 604_800_000.0,
+                    _ => return Err(syntax(i, "unknown duration unit")),
+                };
+                tokens.push((start, Token::Duration((value * ms) as i64)));
+                i = unit_end;
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.' | ':'))
+            {
+                i += 1;
+            }
+            tokens.push((start, Token::Name(chars[start..i].iter().collect())));
+        } else if c == '{' {
+            let close = chars[i..]
+                .iter()
+                .position(|c| *c == '}')
+                .ok_or_else(|| syntax(start, "unclosed '{'"))?;
+            let name: String = chars[i + 1..i + close].iter().collect();
+            if name.trim().is_empty() {
+                return Err(syntax(start, "empty series reference"));
+            }
+            tokens.push((start, Token::Series(name.trim().to_string())));
+            i += close + 1;
+        } else if c == '(' {
+            tokens.push((start, Token::LParen));
+            i += 1;
+        } else if c == ')' {
+            tokens.push((start, Token::RParen));
+            i += 1;
+        } else if c == ',' {
+            tokens.push((start, Token::Comma));
+            i += 1;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| syntax(start, &format!("unexpected '{c}'")))?;
+            tokens.push((start, Token::Op(op)));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Duration(i64),
+    Series(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+/// Binding power of a binary operator, and whether it's right-associative.
+fn binding(op: &str) -> Option<(u8, bool)> {
+    Some(match op {
+        "||" => (1, false),
+        "&&" => (2, false),
+        "==" | "!=" => (3, false),
+        "<" | "<=" | ">" | ">=" => (4, false),
+        "+" | "-" => (5, false),
+        "*" | "/" | "%" => (6, false),
+        "^" => (8, true),
+        _ => return None,
+    })
+}
+const UNARY_BINDING: u8 = 7;
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn error(&self, message: &str) -> ExpressionError {
+        ExpressionError::Syntax {
+            position: self.position(),
+            message: message.to_string(),
+        }
+    }
+
+    fn expression(&mut self, min_binding: u8) -> Result<Expr, ExpressionError> {
+        let mut lhs = self.primary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            let Some((power, right)) = binding(op) else {
+                break;
+            };
+            if power < min_binding {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expression(if right { power } else { power + 1 })?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExpressionError> {
+        let Some((_, token)) = self.tokens.get(self.pos).cloned() else {
+            return Err(self.error("unexpected end of expression"));
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(v) => Ok(Expr::Number(v)),
+            Token::Duration(ms) => Ok(Expr::Duration(ms)),
+            Token::Series(name) => Ok(Expr::Series(name)),
+            Token::Op(op @ ("-" | "!" | "+")) => {
+                let operand = self.expression(UNARY_BINDING)?;
+                Ok(match op {
+                    "+" => operand,
+                    _ => Expr::Unary(op, Box::new(operand)),
+                })
+            }
+            Token::LParen => {
+                let inner = self.expression(0)?;
+                self.expect(Token::RParen, "expected ')'")?;
+                Ok(inner)
+            }
+            Token::Name(name) if self.peek() == Some(&Token::LParen) => {
+                self.pos += 1;
+                let mut args = vec![];
+                if self.peek() != Some(&Token::RParen) {
+                    loop {
+                        args.push(self.expression(0)?);
+                        if self.peek() == Some(&Token::Comma) {
+                            self.pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                self.expect(Token::RParen, "expected ',' or ')'")?;
+                Ok(Expr::Call(name, args))
+            }
+            Token::Name(name) => Ok(Expr::Series(name)),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected a number, series or function"))
+            }
+        }
+    }
+
+    fn expect(&mut self, token: Token, message: &str) -> Result<(), ExpressionError> {
+        if self.peek() == Some(&token) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+}
+
+/// Check function names, argument counts and where durations appear.
+fn validate(expr: &Expr) -> Result<(), ExpressionError> {
+    match expr {
+        Expr::Number(_) | Expr::Series(_) => Ok(()),
+        Expr::Duration(_) => Err(ExpressionError::MisplacedDuration),
+        Expr::Unary(_, e) => validate(e),
+        Expr::Binary(_, l, r) => validate(l).and(validate(r)),
+        Expr::Call(name, args) => {
+            let arity = |expected: &'static str, ok: bool| {
+                if ok {
+                    Ok(())
+                } else {
+                    Err(ExpressionError::Arity {
+                        name: name.clone(),
+                        expected,
+                        got: args.len(),
+                    })
+                }
+            };
+            match name.as_str() {
+                "abs" | "sqrt" | "exp" | "ln" | "log10" | "floor" | "ceil" | "round" | "diff"
+                | "rate" => arity("1", args.len() == 1)?,
+                "min" | "max" | "coalesce" => arity("at least 1", !args.is_empty())?,
+                "if" => arity("3", args.len() == 3)?,
+                "rolling_mean" | "rolling_sum" | "rolling_min" | "rolling_max" => {
+                    arity("2", args.len() == 2)?;
+                    return match &args[1] {
+                        Expr::Duration(ms) if *ms <= 0 => Err(ExpressionError::Syntax {
+                            position: 0,
+                            message: format!("the window of {name}() must be at least 1ms"),
+                        }),
+                        Expr::Number(k) if *k < 1.0 => Err(ExpressionError::Syntax {
+                            position: 0,
+                            message: format!("the window of {name}() must be at least 1 row"),
+                        }),
+                        Expr::Duration(_) | Expr::Number(_) => validate(&args[0]),
+                        _ => Err(ExpressionError::Syntax {
+                            position: 0,
+                            message: format!(
+                                "the window of {name}() must be a duration or a row count"
+                            ),
+                        }),
+                    };
+                }
+                _ => return Err(ExpressionError::UnknownFunction(name.clone())),
+            }
+            args.iter().try_for_each(validate)
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Evaluation
+// ---------------------------------------------------------------------------------------------
+
+/// How the input series are put on one time index before evaluation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alignment {
+    pub join: Join,
+    /// Fill a series at timestamps where it has no point of its own. Ignored by
+    /// [`Join::Grid`], which fills with its own method.
+    pub fill: Option<ResampleMethod>,
+}
+
+impl Default for Alignment {
+    /// Every input timestamp, with other series linearly interpolated there.
+    fn default() -> Self {
+        Alignment {
+            join: Join::Outer,
+            fill: Some(ResampleMethod::Linear),
+        }
+    }
+}
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    source: String,
+    ast: Expr,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, ExpressionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: source.chars().count(),
+        };
+        let ast = parser.expression(0)?;
+        if parser.pos < parser.tokens.len() {
+            return Err(parser.error("unexpected input after expression"));
+        }
+        validate(&ast)?;
+        Ok(Expression {
+            source: source.to_string(),
+            ast,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// External ids of the series the expression reads, sorted.
+    pub fn series(&self) -> Vec<String> {
+        fn walk(e: &Expr, out: &mut BTreeSet<String>) {
+            match e {
+                Expr::Series(name) => {
+                    out.insert(name.clone());
+                }
+                Expr::Unary(_, e) => walk(e, out),
+                Expr::Binary(_, l, r) => {
+                    walk(l, out);
+                    walk(r, out);
+                }
+                Expr::Call(_, args) => args.iter().for_each(|a| walk(a, out)),
+                Expr::Number(_) | Expr::Duration(_) => {}
+            }
+        }
+        let mut out = BTreeSet::new();
+        walk(&self.ast, &mut out);
+        out.into_iter().collect()
+    }
+
+    /// How much earlier data the time-based rolling windows need: retrieve from
+    /// `start - lookback()` for the first outputs at `start` to see full windows. Row-count
+    /// windows can't be expressed in time and aren't included.
+    pub fn lookback(&self) -> Duration {
+        fn walk(e: &Expr) -> i64 {
+            match e {
+                Expr::Duration(ms) => *ms,
+                Expr::Unary(_, e) => walk(e),
+                Expr::Binary(_, l, r) => walk(l).max(walk(r)),
+                Expr::Call(_, args) => args.iter().map(walk).max().unwrap_or(0),
+                Expr::Number(_) | Expr::Series(_) => 0,
+            }
+        }
+        Duration::milliseconds(walk(&self.ast))
+    }
+
+    /// Evaluate over `inputs`, keyed by external id. Every referenced series must be present.
+    pub fn evaluate(
+        &self,
+        inputs: &HashMap<String, Vec<Datapoint>>,
+        alignment: &Alignment,
+    ) -> Result<Vec<Datapoint>, ExpressionError> {
+        let names = self.series();
+        if names.is_empty() {
+            return Err(ExpressionError::NoSeries);
+        }
+        let mut series = vec![];
+        for name in &names {
+            let points = inputs
+                .get(name)
+                .ok_or_else(|| ExpressionError::MissingSeries(name.clone()))?;
+            series.push((name.as_str(), points.as_slice()));
+        }
+        let table = align(&series, &alignment.join, alignment.fill);
+        let values = eval(&self.ast, &table);
+        Ok(table
+            .timestamps
+            .iter()
+            .zip(values)
+            .filter_map(|(t, v)| {
+                v.map(|value| Datapoint {
+                    timestamp: *t,
+                    value: Some(value),
+                    min: None,
+                    max: None,
+                    average: None,
+                    sum: None,
+                })
+            })
+            .collect())
+    }
+}
+
+type Column = Vec<Option<f64>>;
+
+fn finite(v: f64) -> Option<f64> {
+    v.is_finite().then_some(v)
+}
+
+fn truth(v: bool) -> f64 {
+    if v {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn eval(expr: &Expr, table: &AlignedSeries) -> Column {
+    let n = table.len();
+    match expr {
+        Expr::Number(v) => vec![Some(*v); n],
+        // Rejected by `validate` outside a rolling window.
+        Expr::Duration(_) => vec![None; n],
+        Expr::Series(name) => table
+            .column(name)
+            .map(<[_]>::to_vec)
+            .unwrap_or(vec![None; n]),
+        Expr::Unary(op, e) => eval(e, table)
+            .into_iter()
+            .map(|v| {
+                v.map(|v| match *op {
+                    "-" => -v,
+                    _ => truth(v == 0.0),
+                })
+            })
+            .collect(),
+        Expr::Binary(op, l, r) => {
+            let (l, r) = (eval(l, table), eval(r, table));
+            l.into_iter()
+                .zip(r)
+                .map(|(a, b)| {
+                    let (a, b) = (a?, b?);
+                    finite(match *op {
+                        "+" => a + b,
+                        "-" => a - b,
+                        "*" => a * b,
+                        "/" => a / b,
+                        "%" => a % b,
+                        "^" => a.powf(b),
+                        "<" => truth(a < b),
+                        "<=" => truth(a <= b),
+                        ">" => truth(a > b),
+                        ">=" => truth(a >= b),
+                        "==" => truth(a == b),
+                        "!=" => truth(a != b),
+                        "&&" => truth(a != 0.0 && b != 0.0),
+                        _ => truth(a != 0.0 || b != 0.0),
+                    })
+                })
+                .collect()
+        }
+        Expr::Call(name, args) => call(name, args, table),
+    }
+}
+
+fn call(name: &str, args: &[Expr], table: &AlignedSeries) -> Column {
+    let map = |f: fn(f64) -> f64| -> Column {
+        eval(&args[0], table)
+            .into_iter()
+            .map(|v| v.and_then(|v| finite(f(v))))
+            .collect()
+    };
+    let columns = || args.iter().map(|a| eval(a, table)).collect::<Vec<_>>();
+    let n = table.len();
+    match name {
+        "abs" => map(f64::abs),
+        "sqrt" => map(f64::sqrt),
+        "exp" => map(f64::exp),
+        "ln" => map(f64::ln),
+        "log10" => map(f64::log10),
+        "floor" => map(f64::floor),
+        "ceil" => map(f64::ceil),
+        "round" => map(f64::round),
+        "min" | "max" | "coalesce" => {
+            let cols = columns();
+            (0..n)
+                .map(|row| {
+                    let mut present = cols.iter().map(|c| c[row]);
+                    match name {
+                        "coalesce" => present.find_map(|v| v),
+                        // Any missing input makes the row missing, as with operators.
+                        "min" => present.try_fold(f64::INFINITY, |m, v| v.map(|v| m.min(v))),
+                        _ => present.try_fold(f64::NEG_INFINITY, |m, v| v.map(|v| m.max(v))),
+                    }
+                })
+                .collect()
+        }
+        "if" => {
+            let cols = columns();
+            (0..n)
+                .map(|row| match cols[0][row]? {
+                    c if c != 0.0 => cols[1][row],
+                    _ => cols[2][row],
+                })
+                .collect()
+        }
+        "diff" | "rate" => {
+            let x = eval(&args[0], table);
+            let mut out = vec![None; n];
+            let mut prev: Option<(usize, f64)> = None;
+            for (row, v) in x.iter().enumerate() {
+                let Some(v) = *v else { continue };
+                if let Some((p, pv)) = prev {
+                    out[row] = if name == "diff" {
+                        Some(v - pv)
+                    } else {
+                        let secs = (table.timestamps[row] - table.timestamps[p]).num_milliseconds()
+                            as f64
+                            / 1000.0;
+                        finite((v - pv) / secs)
+                    };
+                }
+                prev = Some((row, v));
+            }
+            out
+        }
+        _ => rolling(name, &eval(&args[0], table), &args[1], &table.timestamps),
+    }
+}
+
+/// A rolling aggregate over the present values in each row's window: rows within the duration
+/// before it (exclusive), or the last `k` rows.
+fn rolling(name: &str, x: &[Option<f64>], window: &Expr, timestamps: &[DateTime<Utc>]) -> Column {
+    let in_window: Box<dyn Fn(usize, usize) -> bool> = match window {
+        Expr::Duration(ms) => {
+            let ms = *ms;
+            Box::new(move |first, row| {
+                (timestamps[row] - timestamps[first]).num_milliseconds() < ms
+            })
+        }
+        Expr::Number(k) => {
+            let k = k.max(1.0) as usize;
+            Box::new(move |first, row| row - first < k)
+        }
+        _ => return vec![None; x.len()],
+    };
+    let mut out = Vec::with_capacity(x.len());
+    let (mut first, mut sum, mut count) = (0, 0.0, 0usize);
+    // Monotonic queues of row indices for the window's min and max.
+    let (mut mins, mut maxs): (VecDeque<usize>, VecDeque<usize>) =
+        (VecDeque::new(), VecDeque::new());
+    for row in 0..x.len() {
+        if let Some(v) = x[row] {
+            sum += v;
+            count += 1;
+            while mins.back().is_some_and(|&i| x[i].unwrap() >= v) {
+                mins.pop_back();
+            }
+            mins.push_back(row);
+            while maxs.back().is_some_and(|&i| x[i].unwrap() <= v) {
+                maxs.pop_back();
+            }
+            maxs.push_back(row);
+        }
+        while first <= row && !in_window(first, row) {
+            if let Some(v) = x[first] {
+                sum -= v;
+                count -= 1;
+            }
+            if mins.front() == Some(&first) {
+                mins.pop_front();
+            }
+            if maxs.front() == Some(&first) {
+                maxs.pop_front();
+            }
+            first += 1;
+        }
+        out.push(if count == 0 {
+            None
+        } else {
+            match name {
+                "rolling_mean" => Some(sum / count as f64),
+                "rolling_sum" => Some(sum),
+                "rolling_min" => mins.front().and_then(|&i| x[i]),
+                _ => maxs.front().and_then(|&i| x[i]),
+            }
+        });
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------------------------
+// Writing back
+// ---------------------------------------------------------------------------------------------
+
+/// Progress of a [`CalculatedSeries`] running on a schedule.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CalculationStatus {
+    pub runs: u64,
+    pub points_written: u64,
+    /// Timestamp of the last point written.
+    pub watermark: Option<DateTime<Utc>>,
+    /// The last run's error, cleared by a successful run.
+    pub last_error: Option<String>,
+}
+
+/// An expression whose result is written into a target series. Create one with
+/// [`TimeSeriesService::calculated_series`](crate::TimeSeriesService::calculated_series).
+///
+/// [`backfill`](Self::backfill) computes a fixed range; [`update`](Self::update) continues
+/// after the last point written, up to now minus the settle time (late data arriving within
+/// it is still picked up). Rewriting a point is harmless: datapoints are keyed by
+/// `(series, timestamp)`.
+pub struct CalculatedSeries {
+    api_service: Weak<ApiService>,
+    expression: Expression,
+    target_external_id: String,
+    alignment: Alignment,
+    settle: Duration,
+    watermark: Option<DateTime<Utc>>,
+}
+
+impl CalculatedSeries {
+    pub(crate) fn new(
+        api_service: Weak<ApiService>,
+        expression: Expression,
+        target_external_id: &str,
+    ) -> Self {
+        CalculatedSeries {
+            api_service,
+            expression,
+            target_external_id: target_external_id.to_string(),
+            alignment: Alignment::default(),
+            settle: Duration::zero(),
+            watermark: None,
+        }
+    }
+
+    pub fn with_alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Leave the most recent `settle` unwritten by `update`, so inputs that arrive a little late
+    /// are in place before a row is computed.
+    pub fn with_settle_time(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// Resume `update` after this timestamp.
+    pub fn with_watermark(mut self, watermark: DateTime<Utc>) -> Self {
+        self.watermark = Some(watermark);
+        self
+    }
+
+    pub fn expression(&self) -> &Expression {
+        &self.expression
+    }
+
+    pub fn watermark(&self) -> Option<DateTime<Utc>> {
+        self.watermark
+    }
+
+    /// Compute `[start, end)` and write it. Returns the number of points written.
+    pub async fn backfill(
+        &mut self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<usize, ExpressionError> {
+        let api = self
+            .api_service
+            .upgrade()
+            .ok_or_else(|| ResponseError::from("api service has been dropped".to_string()))?;
+        let points: Vec<Datapoint> = api
+            .time_series
+            .calculate(&self.expression, start, end, &self.alignment)
+            .await?
+            .into_iter()
+            .filter(|p| p.timestamp >= start && p.timestamp < end)
+            .collect();
+        let Some(last) = points.last().map(|p| p.timestamp) else {
+            return Ok(0);
+        };
+        let mut collection = DatapointsCollection::from_external_id(&self.target_external_id);
+        collection.datapoints = points
+            .iter()
+            .map(|p| DatapointString::from_datetime(p.timestamp, &p.value.unwrap().to_string()))
+            .collect();
+        let mut request = DataWrapper::new();
+        request.add_item(collection);
+        api.time_series.insert_datapoints(&mut request).await?;
+        self.watermark = Some(self.watermark.map_or(last, |w| w.max(last)));
+        Ok(points.len())
+    }
+
+    /// Compute and write everything after the watermark up to now minus the settle time. The
+    /// first call without a watermark starts `initial` before that.
+    pub async fn update(&mut self, initial: Duration) -> Result<usize, ExpressionError> {
+        let end = Utc::now() - self.settle;
+        let start = match self.watermark {
+            Some(w) => w + Duration::milliseconds(1),
+            None => end - initial,
+        };
+        if start >= end {
+            return Ok(0);
+        }
+        self.backfill(start, end).await
+    }
+
+    /// Run [`update`](Self::update) every `every` on the current Tokio runtime until the returned
+    /// handle is stopped. The first run starts `initial` back.
+    pub fn spawn(mut self, every: std::time::Duration, initial: Duration) -> ScheduledCalculation {
+        let (tx, status) = watch::channel(CalculationStatus {
+            watermark: self.watermark,
+            ..Default::default()
+        });
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(every);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let result = self.update(initial).await;
+                tx.send_modify(|s| {
+                    s.runs += 1;
+                    s.watermark = self.watermark;
+                    match result {
+                        Ok(n) => {
+                            s.points_written += n as u64;
+                            s.last_error = None;
+                        }
+                        Err(e) => s.last_error = Some(e.to_string()),
+                    }
+                });
+                if self.api_service.strong_count() == 0 {
+                    return;
+                }
+            }
+        });
+        ScheduledCalculation { handle, status }
+    }
+}
+
+/// A [`CalculatedSeries`] running on a schedule. Dropping this does not stop it; call
+/// [`stop`](Self::stop).
+pub struct ScheduledCalculation {
+    handle: JoinHandle<()>,
+    status: watch::Receiver<CalculationStatus>,
+}
+
+impl ScheduledCalculation {
+    pub fn status(&self) -> CalculationStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Wait for the next run to finish.
+    pub async fn changed(&mut self) -> Option<CalculationStatus> {
+        self.status.changed().await.ok()?;
+        Some(self.status())
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::mock_backend::MockBackend;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    fn series(points: &[(i64, f64)]) -> Vec<Datapoint> {
+        points
+            .iter()
+            .map(|(t, v)| Datapoint {
+                timestamp: at(*t),
+                value: Some(*v),
+                min: None,
+                max: None,
+                average: None,
+                sum: None,
+            })
+            .collect()
+    }
+
+    fn values(points: &[Datapoint]) -> Vec<(i64, f64)> {
+        points
+            .iter()
+            .map(|p| (p.timestamp.timestamp(), p.value.unwrap()))
+            .collect()
+    }
+
+    fn eval_with(src: &str, inputs: &[(&str, Vec<Datapoint>)]) -> Vec<(i64, f64)> {
+        let inputs: HashMap<String, Vec<Datapoint>> = inputs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect();
+        values(
+            &Expression::parse(src)
+                .unwrap()
+                .evaluate(&inputs, &Alignment::default())
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn parses_precedence_and_references() {
+        let e = Expression::parse("-a + b * 2 ^ 2 ^ 0.5 >= {flow-in} && !c").unwrap();
+        assert_eq!(e.series(), vec!["a", "b", "c", "flow-in"]);
+        let e = Expression::parse("rolling_mean(plant.area:temp, 10m) / 1e3").unwrap();
+        assert_eq!(e.lookback(), Duration::minutes(10));
+    }
+
+    #[test]
+    fn reports_errors() {
+        let err = |src| Expression::parse(src).unwrap_err();
+        assert!(matches!(err("a +"), ExpressionError::Syntax { .. }));
+        assert!(matches!(err("(a"), ExpressionError::Syntax { .. }));
+        assert!(matches!(
+            err("a # b"),
+            ExpressionError::Syntax { position: 2, .. }
+        ));
+        assert!(matches!(err("sin(a)"), ExpressionError::UnknownFunction(f) if f == "sin"));
+        assert!(matches!(
+            err("if(a, b)"),
+            ExpressionError::Arity { got: 2, .. }
+        ));
+        assert!(matches!(err("a + 5m"), ExpressionError::MisplacedDuration));
+        assert!(matches!(
+            err("rolling_mean(a, b)"),
+            ExpressionError::Syntax { .. }
+        ));
+        assert!(matches!(err("3m5"), ExpressionError::Syntax { .. }));
+    }
+
+    #[test]
+    fn a_zero_length_window_is_rejected() {
+        for src in [
+            "rolling_mean(a, 0s)",
+            "rolling_sum(a, 0.0001s)",
+            "rolling_max(a, 0)",
+        ] {
+            assert!(matches!(
+                Expression::parse(src).unwrap_err(),
+                ExpressionError::Syntax { .. }
+            ));
+        }
+        // Even unvalidated, an empty window leaves every row out rather than panicking.
+        let x = [Some(1.0), Some(2.0), Some(3.0)];
+        let timestamps = [at(0), at(10), at(20)];
+        assert_eq!(
+            rolling("rolling_mean", &x, &Expr::Duration(0), &timestamps),
+            vec![None; 3]
+        );
+    }
+
+    #[test]
+    fn arithmetic_on_aligned_series() {
+        let flow_in = series(&[(0, 10.0), (10, 20.0), (20, 30.0)]);
+        let flow_out = series(&[(0, 4.0), (20, 14.0)]);
+        // At t=10 flow_out is interpolated to 9.
+        assert_eq!(
+            eval_with(
+                "flow_in - flow_out",
+                &[("flow_in", flow_in.clone()), ("flow_out", flow_out)]
+            ),
+            vec![(0, 6.0), (10, 11.0), (20, 16.0)]
+        );
+        assert_eq!(
+            eval_with(
+                "if(flow_in > 15, flow_in / 10, 0)",
+                &[("flow_in", flow_in.clone())]
+            ),
+            vec![(0, 0.0), (10, 2.0), (20, 3.0)]
+        );
+        // Division by zero leaves the row out.
+        assert_eq!(
+            eval_with("1 / (flow_in - 20)", &[("flow_in", flow_in)]),
+            vec![(0, -0.1), (20, 0.1)]
+        );
+    }
+
+    #[test]
+    fn rolling_and_difference_functions() {
+        let x = series(&[(0, 1.0), (10, 3.0), (20, 5.0), (30, 1.0), (40, 2.0)]);
+        let input = [("x", x)];
+        assert_eq!(
+            eval_with("rolling_mean(x, 2)", &input),
+            vec![(0, 1.0), (10, 2.0), (20, 4.0), (30, 3.0), (40, 1.5)]
+        );
+        // 25 s covers the current row and the two before it.
+        assert_eq!(
+            eval_with("rolling_max(x, 25s)", &input),
+            vec![(0, 1.0), (10, 3.0), (20, 5.0), (30, 5.0), (40, 5.0)]
+        );
+        assert_eq!(
+            eval_with("rolling_min(x, 25s)", &input),
+            vec![(0, 1.0), (10, 1.0), (20, 1.0), (30, 1.0), (40, 1.0)]
+        );
+        assert_eq!(eval_with("rolling_sum(x, 3)", &input)[4], (40, 8.0));
+        assert_eq!(
+            eval_with("diff(x)", &input),
+            vec![(10, 2.0), (20, 2.0), (30, -4.0), (40, 1.0)]
+        );
+        assert_eq!(eval_with("rate(x)", &input)[0], (10, 0.2));
+        assert_eq!(eval_with("max(x, 2.5)", &input)[0], (0, 2.5));
+    }
+
+    #[test]
+    fn missing_inputs_are_reported() {
+        let e = Expression::parse("a + b").unwrap();
+        let inputs = HashMap::from([("a".to_string(), series(&[(0, 1.0)]))]);
+        assert!(matches!(
+            e.evaluate(&inputs, &Alignment::default()),
+            Err(ExpressionError::MissingSeries(s)) if s == "b"
+        ));
+        assert!(matches!(
+            Expression::parse("1 + 2")
+                .unwrap()
+                .evaluate(&inputs, &Alignment::default()),
+            Err(ExpressionError::NoSeries)
+        ));
+    }
+
+    #[tokio::test]
+    async fn writes_the_result_into_the_target_series() {
+        let backend = MockBackend::start(|req| {
+            if req.path != "/timeseries/data/list" {
+                return (204, String::new());
+            }
+            let ext = req.json()["items"][0]["externalId"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            let (points, cursor) = match (ext.as_str(), req.json()["items"][0]["cursor"].as_str()) {
+                ("power", None) => (vec![(0, 1000.0), (10, 2000.0)], Some("next")),
+                ("power", Some(_)) => (vec![(20, 3000.0)], None),
+                _ => (vec![], None),
+            };
+            let datapoints: Vec<serde_json::Value> = points
+                .iter()
+                .map(|(t, v)| serde_json::json!({ "timestamp": at(*t).to_rfc3339(), "value": v }))
+                .collect();
+            (
+                200,
+                serde_json::json!({ "items": [{
+                    "externalId": ext, "datapoints": datapoints, "nextCursor": cursor,
+                    "unit": null, "unitExternalId": null
+                }]})
+                .to_string(),
+            )
+        })
+        .await;
+        let api = backend.service();
+        let mut calc = api
+            .time_series
+            .calculated_series(Expression::parse("power / 1000").unwrap(), "power_kw");
+
+        let written = calc.backfill(at(0), at(60)).await.unwrap();
+        assert_eq!(written, 3);
+        assert_eq!(calc.watermark(), Some(at(20)));
+        let sent = backend.requests_to("/timeseries/data");
+        let body = sent[0].json();
+        assert_eq!(body["items"][0]["externalId"], "power_kw");
+        let sent_values: Vec<&str> = body["items"][0]["datapoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["value"].as_str().unwrap())
+            .collect();
+        assert_eq!(sent_values, vec!["1", "2", "3"]);
+        // Two pages were followed for the one input series.
+        assert_eq!(backend.requests_to("/timeseries/data/list").len(), 2);
+    }
+}
@@ -0,0 +1,25 @@
+//! Client-side processing of datapoints returned by
+//! [`TimeSeriesService::retrieve_datapoints`](crate::TimeSeriesService::retrieve_datapoints).
+//!
+//! - [`calendar`]: roll datapoints into calendar buckets (days, weeks, months, shifts) in a
+//!   given time zone.
+//! - [`ops`]: gap detection, resampling and interpolation, outlier flags, and alignment of
+//!   several series on one time index.
+//! - [`expression`]: calculated series from expressions over other series, optionally written
+//!   back on a schedule.
+
+pub mod calendar;
+pub mod expression;
+pub mod ops;
+
+use chrono::{DateTime, Utc};
+
+/// Parse a datapoint timestamp as sent on the wire: epoch milliseconds or RFC 3339.
+pub(crate) fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    match timestamp.trim().parse::<i64>() {
+        Ok(ms) => DateTime::from_timestamp_millis(ms),
+        Err(_) => DateTime::parse_from_rfc3339(timestamp.trim())
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+    }
+}
@@ -0,0 +1,661 @@
+//! Gap detection, resampling, interpolation, outlier flags and alignment of datapoint series.
+//!
+//! Everything here works on a slice of [`Datapoint`] — the `datapoints` of a collection returned
+//! by `retrieve_datapoints` — and reads each point's `value`. Input doesn't have to be sorted.
+//! Subscription streams deliver string datapoints; [`from_stream`] converts them, and a
+//! [`GapDetector`] watches a live stream one point at a time.
+
+use crate::datapoints::parse_timestamp;
+use crate::generic::{Datapoint, DatapointString};
+use crate::subscriptions::WsDatapoint;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeSet;
+
+/// Convert datapoints from a subscription stream, skipping non-numeric ones.
+pub fn from_stream(points: &[WsDatapoint]) -> Vec<Datapoint> {
+    points
+        .iter()
+        .filter_map(|p| numeric(&p.timestamp, &p.value))
+        .collect()
+}
+
+/// Convert string datapoints (as ingested), skipping non-numeric ones.
+pub fn from_strings(points: &[DatapointString]) -> Vec<Datapoint> {
+    points
+        .iter()
+        .filter_map(|p| numeric(&p.timestamp, &p.value))
+        .collect()
+}
+
+fn numeric(timestamp: &str, value: &str) -> Option<Datapoint> {
+    let value = value.trim().parse::<f64>().ok().filter(|v| v.is_finite())?;
+    Some(point(parse_timestamp(timestamp)?, Some(value)))
+}
+
+fn point(timestamp: DateTime<Utc>, value: Option<f64>) -> Datapoint {
+    Datapoint {
+        timestamp,
+        value,
+        min: None,
+        max: None,
+        average: None,
+        sum: None,
+    }
+}
+
+/// `(millis, value)` for every point with a value, sorted by time; the last wins on duplicates.
+fn samples(points: &[Datapoint]) -> Vec<(i64, f64)> {
+    let mut out: Vec<(i64, f64)> = points
+        .iter()
+        .filter_map(|dp| dp.value.map(|v| (dp.timestamp.timestamp_millis(), v)))
+        .collect();
+    out.sort_by_key(|(ts, _)| *ts);
+    out.dedup_by(|later, earlier| {
+        if later.0 == earlier.0 {
+            earlier.1 = later.1;
+            true
+        } else {
+            false
+        }
+    });
+    out
+}
+
+fn millis(ms: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(ms).expect("timestamp in range")
+}
+
+// ---------------------------------------------------------------------------------------------
+// Gaps
+// ---------------------------------------------------------------------------------------------
+
+/// A stretch with no datapoints between two consecutive points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gap {
+    /// The last point before the gap.
+    pub start: DateTime<Utc>,
+    /// The first point after it.
+    pub end: DateTime<Utc>,
+    /// Points the expected interval says are missing.
+    pub missing: u64,
+}
+
+impl Gap {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    fn between(start: i64, end: i64, expected: i64) -> Gap {
+        let missing = ((end - start) as f64 / expected as f64).round() as u64;
+        Gap {
+            start: millis(start),
+            end: millis(end),
+            missing: missing.saturating_sub(1),
+        }
+    }
+}
+
+/// The series' sampling interval: the median spacing between consecutive points. `None` with
+/// fewer than two distinct timestamps.
+pub fn infer_interval(points: &[Datapoint]) -> Option<Duration> {
+    let ts: Vec<i64> = samples(points).iter().map(|(t, _)| *t).collect();
+    median_interval(&ts).map(Duration::milliseconds)
+}
+
+fn median_interval(ts: &[i64]) -> Option<i64> {
+    let mut diffs: Vec<i64> = ts
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .filter(|d| *d > 0)
+        .collect();
+    if diffs.is_empty() {
+        return None;
+    }
+    let mid = diffs.len() / 2;
+    Some(*diffs.select_nth_unstable(mid).1)
+}
+
+/// Every spacing longer than `tolerance` times the expected interval (inferred when `None`).
+/// A `tolerance` of 1.5 tolerates jitter of half an interval.
+pub fn find_gaps(points: &[Datapoint], expected: Option<Duration>, tolerance: f64) -> Vec<Gap> {
+    let ts: Vec<i64> = samples(points).iter().map(|(t, _)| *t).collect();
+    let Some(expected) = expected
+        .map(|d| d.num_milliseconds())
+        .or_else(|| median_interval(&ts))
+        .filter(|e| *e > 0)
+    else {
+        return vec![];
+    };
+    let limit = expected as f64 * tolerance.max(1.0);
+    ts.windows(2)
+        .filter(|w| (w[1] - w[0]) as f64 > limit)
+        .map(|w| Gap::between(w[0], w[1], expected))
+        .collect()
+}
+
+/// Gap detection for a live stream, one timestamp at a time. Without an expected interval it
+/// learns one from the first `learn` spacings and reports nothing until then.
+#[derive(Debug, Clone)]
+pub struct GapDetector {
+    expected: Option<i64>,
+    tolerance: f64,
+    learn: usize,
+    seen: Vec<i64>,
+    last: Option<i64>,
+}
+
+impl GapDetector {
+    pub fn new(expected: Duration, tolerance: f64) -> Self {
+        GapDetector {
+            expected: Some(expected.num_milliseconds().max(1)),
+            tolerance: tolerance.max(1.0),
+            learn: 0,
+            seen: vec![],
+            last: None,
+        }
+    }
+
+    /// Infer the interval from the first `learn` spacings (at least one).
+    pub fn learning(learn: usize, tolerance: f64) -> Self {
+        GapDetector {
+            expected: None,
+            tolerance: tolerance.max(1.0),
+            learn: learn.max(1),
+            seen: vec![],
+            last: None,
+        }
+    }
+
+    pub fn expected_interval(&self) -> Option<Duration> {
+        self.expected.map(Duration::milliseconds)
+    }
+
+    /// Feed the next timestamp; returns the gap it closes, if any. Out-of-order and duplicate
+    /// timestamps are ignored.
+    pub fn push(&mut self, timestamp: DateTime<Utc>) -> Option<Gap> {
+        let ts = timestamp.timestamp_millis();
+        if self.last.is_some_and(|last| ts <= last) {
+            return None;
+        }
+        let last = self.last.replace(ts);
+        let Some(expected) = self.expected else {
+            self.seen.push(ts);
+            if self.seen.len() > self.learn {
+                self.expected = median_interval(&self.seen);
+                self.seen = vec![];
+            }
+            return None;
+        };
+        let last = last?;
+        ((ts - last) as f64 > expected as f64 * self.tolerance)
+            .then(|| Gap::between(last, ts, expected))
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Resampling
+// ---------------------------------------------------------------------------------------------
+
+/// How to fill a timestamp that falls between datapoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResampleMethod {
+    /// Straight line between the surrounding points. Nothing after the last point.
+    #[default]
+    Linear,
+    /// The preceding point's value. Nothing after the last point.
+    Step,
+    /// The last value seen, also after the last point (forward fill).
+    Previous,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResampleOptions {
+    pub step: Duration,
+    /// First grid timestamp. Defaults to the first point, rounded down to a multiple of `step`
+    /// since the epoch.
+    pub start: Option<DateTime<Utc>>,
+    /// Last grid timestamp (inclusive). Defaults to the last point.
+    pub end: Option<DateTime<Utc>>,
+    pub method: ResampleMethod,
+    /// Only fill from a point at most this old; further out the value is missing. Limits how far
+    /// values are carried across a gap.
+    pub max_fill: Option<Duration>,
+}
+
+impl ResampleOptions {
+    pub fn new(step: Duration) -> Self {
+        ResampleOptions {
+            step,
+            start: None,
+            end: None,
+            method: ResampleMethod::default(),
+            max_fill: None,
+        }
+    }
+
+    pub fn with_range(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        self.start = Some(start);
+        self.end = Some(end);
+        self
+    }
+
+    pub fn with_method(mut self, method: ResampleMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    pub fn with_max_fill(mut self, max_fill: Duration) -> Self {
+        self.max_fill = Some(max_fill);
+        self
+    }
+
+    fn grid(&self, first: i64, last: i64) -> Vec<i64> {
+        let step = self.step.num_milliseconds().max(1);
+        let start = self
+            .start
+            .map_or(first - first.rem_euclid(step), |s| s.timestamp_millis());
+        let end = self.end.map_or(last, |e| e.timestamp_millis());
+        (0..)
+            .map(|i| start + i * step)
+            .take_while(|t| *t <= end)
+            .collect()
+    }
+}
+
+/// Resample onto a regular grid. Grid timestamps that can't be filled get `value: None`.
+pub fn resample(points: &[Datapoint], options: &ResampleOptions) -> Vec<Datapoint> {
+    let samples = samples(points);
+    let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+        return vec![];
+    };
+    let max_fill = options.max_fill.map(|d| d.num_milliseconds());
+    options
+        .grid(first.0, last.0)
+        .into_iter()
+        .map(|t| point(millis(t), value_at(&samples, t, options.method, max_fill)))
+        .collect()
+}
+
+/// The series' value at `t`, exact or filled by `method`.
+fn value_at(
+    samples: &[(i64, f64)],
+    t: i64,
+    method: ResampleMethod,
+    max_fill: Option<i64>,
+) -> Option<f64> {
+    let i = samples.partition_point(|(ts, _)| *ts <= t);
+    let (prev_ts, prev) = *samples.get(i.checked_sub(1)?)?;
+    if prev_ts == t {
+        return Some(prev);
+    }
+    if max_fill.is_some_and(|limit| t - prev_ts > limit) {
+        return None;
+    }
+    match (method, samples.get(i)) {
+        (ResampleMethod::Linear, Some(&(next_ts, next))) => {
+            Some(prev + (next - prev) * (t - prev_ts) as f64 / (next_ts - prev_ts) as f64)
+        }
+        (ResampleMethod::Step, Some(_)) | (ResampleMethod::Previous, _) => Some(prev),
+        (_, None) => None,
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Outliers
+// ---------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutlierMethod {
+    /// More than this many standard deviations from the mean.
+    ZScore(f64),
+    /// Modified z-score (median absolute deviation) above this threshold; 3.5 is customary.
+    /// Robust to the outliers themselves.
+    Mad(f64),
+    /// Outside `[min, max]`.
+    Range { min: f64, max: f64 },
+}
+
+/// One flag per input point, in input order. Points without a value are never flagged.
+pub fn flag_outliers(points: &[Datapoint], method: OutlierMethod) -> Vec<bool> {
+    let values: Vec<f64> = points.iter().filter_map(|p| p.value).collect();
+    let is_outlier: Box<dyn Fn(f64) -> bool> = match method {
+        OutlierMethod::Range { min, max } => Box::new(move |v| v < min || v > max),
+        OutlierMethod::ZScore(threshold) => {
+            let n = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / n;
+            let sd = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+            Box::new(move |v| sd > 0.0 && ((v - mean) / sd).abs() > threshold)
+        }
+        OutlierMethod::Mad(threshold) => {
+            let mid = median(values.clone());
+            let mad = median(values.iter().map(|v| (v - mid).abs()).collect());
+            Box::new(move |v| {
+                if mad > 0.0 {
+                    (0.6745 * (v - mid) / mad).abs() > threshold
+                } else {
+                    // More than half the values are identical: anything else stands out.
+                    v != mid
+                }
+            })
+        }
+    };
+    points
+        .iter()
+        .map(|p| p.value.is_some_and(|v| !values.is_empty() && is_outlier(v)))
+        .collect()
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Alignment
+// ---------------------------------------------------------------------------------------------
+
+/// Which timestamps an aligned table has.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Join {
+    /// Every timestamp of any series.
+    Outer,
+    /// Only timestamps every series has.
+    Inner,
+    /// A regular grid; the range defaults to the union of all series.
+    Grid(ResampleOptions),
+}
+
+/// Several series on one time index: `columns[c][row]` is series `c` at `timestamps[row]`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AlignedSeries {
+    pub timestamps: Vec<DateTime<Utc>>,
+    pub names: Vec<String>,
+    pub columns: Vec<Vec<Option<f64>>>,
+}
+
+impl AlignedSeries {
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+
+    pub fn column(&self, name: &str) -> Option<&[Option<f64>]> {
+        let i = self.names.iter().position(|n| n == name)?;
+        Some(&self.columns[i])
+    }
+
+    /// The values of every series at `row`, in column order.
+    pub fn row(&self, row: usize) -> Vec<Option<f64>> {
+        self.columns.iter().map(|c| c[row]).collect()
+    }
+
+    /// Drop rows where any series is missing.
+    pub fn complete_rows(mut self) -> Self {
+        let keep: Vec<bool> = (0..self.len())
+            .map(|r| self.columns.iter().all(|c| c[r].is_some()))
+            .collect();
+        fn retain<T>(v: Vec<T>, keep: &[bool]) -> Vec<T> {
+            v.into_iter()
+                .zip(keep)
+                .filter_map(|(x, k)| k.then_some(x))
+                .collect()
+        }
+        self.timestamps = retain(self.timestamps, &keep);
+        self.columns = self.columns.into_iter().map(|c| retain(c, &keep)).collect();
+        self
+    }
+}
+
+/// Put named series on a common time index. With `fill`, a series without a point at a
+/// timestamp gets a value filled by that method; otherwise it's `None`. [`Join::Grid`] always
+/// fills with its own method.
+pub fn align(
+    series: &[(&str, &[Datapoint])],
+    join: &Join,
+    fill: Option<ResampleMethod>,
+) -> AlignedSeries {
+    let all: Vec<Vec<(i64, f64)>> = series.iter().map(|(_, points)| samples(points)).collect();
+    let names = series.iter().map(|(name, _)| name.to_string()).collect();
+    let (index, method, max_fill): (Vec<i64>, Option<ResampleMethod>, Option<i64>) = match join {
+        Join::Outer => {
+            let union: BTreeSet<i64> = all.iter().flatten().map(|(t, _)| *t).collect();
+            (union.into_iter().collect(), fill, None)
+        }
+        Join::Inner => {
+            let mut sets = all
+                .iter()
+                .map(|s| s.iter().map(|(t, _)| *t).collect::<BTreeSet<i64>>());
+            let first = sets.next().unwrap_or_default();
+            let common = sets.fold(first, |acc, s| acc.intersection(&s).copied().collect());
+            (common.into_iter().collect(), None, None)
+        }
+        Join::Grid(options) => {
+            let first = all.iter().filter_map(|s| s.first()).map(|(t, _)| *t).min();
+            let last = all.iter().filter_map(|s| s.last()).map(|(t, _)| *t).max();
+            let grid = match (first, last) {
+                (Some(first), Some(last)) => options.grid(first, last),
+                _ => vec![],
+            };
+            (
+                grid,
+                Some(options.method),
+                options.max_fill.map(|d| d.num_milliseconds()),
+            )
+        }
+    };
+    let columns = all
+        .iter()
+        .map(|samples| {
+            index
+                .iter()
+                .map(|t| match method {
+                    Some(method) => value_at(samples, *t, method, max_fill),
+                    None => samples
+                        .binary_search_by_key(t, |(ts, _)| *ts)
+                        .ok()
+                        .map(|i| samples[i].1),
+                })
+                .collect()
+        })
+        .collect();
+    AlignedSeries {
+        timestamps: index.into_iter().map(millis).collect(),
+        names,
+        columns,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(points: &[(i64, f64)]) -> Vec<Datapoint> {
+        points
+            .iter()
+            .map(|(t, v)| point(millis(*t * 1000), Some(*v)))
+            .collect()
+    }
+
+    fn secs(points: &[Datapoint]) -> Vec<i64> {
+        points.iter().map(|p| p.timestamp.timestamp()).collect()
+    }
+
+    fn values(points: &[Datapoint]) -> Vec<Option<f64>> {
+        points.iter().map(|p| p.value).collect()
+    }
+
+    #[test]
+    fn infers_the_interval_and_finds_gaps() {
+        let s = series(&[
+            (0, 1.0),
+            (10, 1.0),
+            (20, 1.0),
+            (31, 1.0),
+            (80, 1.0),
+            (90, 1.0),
+        ]);
+        assert_eq!(infer_interval(&s), Some(Duration::seconds(10)));
+        let gaps = find_gaps(&s, None, 1.5);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].start.timestamp(), 31);
+        assert_eq!(gaps[0].end.timestamp(), 80);
+        assert_eq!(gaps[0].missing, 4);
+        assert!(find_gaps(&s, Some(Duration::seconds(60)), 1.0).is_empty());
+    }
+
+    #[test]
+    fn gap_detector_learns_from_a_stream() {
+        let mut detector = GapDetector::learning(3, 1.5);
+        let mut gaps = vec![];
+        for t in [0, 5, 10, 15, 20, 40, 45, 45, 44, 50] {
+            gaps.extend(detector.push(millis(t * 1000)));
+        }
+        assert_eq!(detector.expected_interval(), Some(Duration::seconds(5)));
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(
+            (gaps[0].start.timestamp(), gaps[0].end.timestamp()),
+            (20, 40)
+        );
+        assert_eq!(gaps[0].missing, 3);
+    }
+
+    #[test]
+    fn resamples_with_each_method() {
+        let s = series(&[(0, 0.0), (10, 10.0), (20, 0.0)]);
+        let linear = resample(&s, &ResampleOptions::new(Duration::seconds(5)));
+        assert_eq!(secs(&linear), vec![0, 5, 10, 15, 20]);
+        assert_eq!(
+            values(&linear),
+            vec![Some(0.0), Some(5.0), Some(10.0), Some(5.0), Some(0.0)]
+        );
+
+        let range = |m| {
+            ResampleOptions::new(Duration::seconds(5))
+                .with_range(millis(0), millis(30_000))
+                .with_method(m)
+        };
+        let step = resample(&s, &range(ResampleMethod::Step));
+        assert_eq!(
+            values(&step),
+            vec![
+                Some(0.0),
+                Some(0.0),
+                Some(10.0),
+                Some(10.0),
+                Some(0.0),
+                None,
+                None
+            ]
+        );
+        let previous = resample(&s, &range(ResampleMethod::Previous));
+        assert_eq!(previous[6].value, Some(0.0));
+        let limited = resample(
+            &s,
+            &range(ResampleMethod::Previous).with_max_fill(Duration::seconds(5)),
+        );
+        assert_eq!(limited[5].value, Some(0.0));
+        assert_eq!(limited[6].value, None);
+    }
+
+    #[test]
+    fn flags_outliers() {
+        let s = series(&[
+            (0, 10.0),
+            (1, 10.5),
+            (2, 9.5),
+            (3, 10.0),
+            (4, 100.0),
+            (5, 10.2),
+        ]);
+        assert_eq!(
+            flag_outliers(&s, OutlierMethod::Mad(3.5)),
+            vec![false, false, false, false, true, false]
+        );
+        assert_eq!(
+            flag_outliers(
+                &s,
+                OutlierMethod::Range {
+                    min: 0.0,
+                    max: 50.0
+                }
+            ),
+            vec![false, false, false, false, true, false]
+        );
+        // A single huge outlier inflates the standard deviation; z-score needs a low bar.
+        assert!(flag_outliers(&s, OutlierMethod::ZScore(2.0))[4]);
+    }
+
+    #[test]
+    fn aligns_series() {
+        let a = series(&[(0, 1.0), (10, 2.0), (20, 3.0)]);
+        let b = series(&[(10, 20.0), (15, 25.0), (20, 30.0)]);
+        let inputs = [("a", a.as_slice()), ("b", b.as_slice())];
+
+        let outer = align(&inputs, &Join::Outer, None);
+        assert_eq!(outer.len(), 4);
+        assert_eq!(
+            outer.column("a").unwrap(),
+            &[Some(1.0), Some(2.0), None, Some(3.0)]
+        );
+        assert_eq!(
+            outer.column("b").unwrap(),
+            &[None, Some(20.0), Some(25.0), Some(30.0)]
+        );
+        assert_eq!(outer.clone().complete_rows().len(), 2);
+
+        let filled = align(&inputs, &Join::Outer, Some(ResampleMethod::Linear));
+        assert_eq!(filled.column("a").unwrap()[2], Some(2.5));
+
+        let inner = align(&inputs, &Join::Inner, None);
+        assert_eq!(
+            inner
+                .timestamps
+                .iter()
+                .map(|t| t.timestamp())
+                .collect::<Vec<_>>(),
+            vec![10, 20]
+        );
+        assert_eq!(inner.row(1), vec![Some(3.0), Some(30.0)]);
+
+        let grid = align(
+            &inputs,
+            &Join::Grid(ResampleOptions::new(Duration::seconds(10))),
+            None,
+        );
+        assert_eq!(grid.len(), 3);
+        assert_eq!(grid.column("b").unwrap(), &[None, Some(20.0), Some(30.0)]);
+    }
+
+    #[test]
+    fn converts_stream_datapoints() {
+        let stream = vec![
+            WsDatapoint {
+                timestamp: "1000".to_string(),
+                value: "1.5".to_string(),
+            },
+            WsDatapoint {
+                timestamp: "2024-01-01T00:00:00Z".to_string(),
+                value: "2".to_string(),
+            },
+            WsDatapoint {
+                timestamp: "3000".to_string(),
+                value: "on".to_string(),
+            },
+        ];
+        let points = from_stream(&stream);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].timestamp.timestamp_millis(), 1000);
+        assert_eq!(points[1].value, Some(2.0));
+    }
+}
@@ -0,0 +1,443 @@
+//! Poison-record isolation for the durable ingest spools.
+//!
+//! A spooled batch the backend rejects with a terminal error (e.g. a 400 for one malformed
+//! datapoint) used to be dropped whole. Draining now [`bisect`]s a rejected batch: it resends
+//! each half, splitting again only the halves that are rejected, until the offending records are
+//! isolated. The good records get through, and each bad one goes to a dead-letter spool with the
+//! error that rejected it. Whatever is left once the request budget is spent is dead-lettered
+//! whole, so a segment the backend refuses entirely doesn't hold up the drain for one request
+//! per record.
+//!
+//! Dead letters stay on disk until they are handled (no time window; the buffer size cap still
+//! applies). Inspect them with `dead_letters()`, write them out with `export_dead_letters()`,
+//! resend them after fixing with `replay_dead_letters()`, or discard them with
+//! `clear_dead_letters()`, on both [`TimeSeriesService`](crate::TimeSeriesService) and
+//! [`EventsService`](crate::EventsService).
+
+use crate::buffer::{BufferEvent, DurableSpool, LazySpool};
+use crate::datahub::{DataHubConfig, DEFAULT_BUFFER_MAX_BYTES};
+use crate::http::ResponseError;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// A record the backend rejected, with the rejection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterRecord<T> {
+    pub record: T,
+    /// HTTP status of the rejection.
+    pub status: u16,
+    pub error: String,
+    pub rejected_at: DateTime<Utc>,
+}
+
+/// The outcome of replaying dead letters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Records the backend accepted.
+    pub accepted: usize,
+    /// Records rejected again; they are back in the dead-letter spool with the new error.
+    pub rejected: usize,
+    /// Records the fix function discarded.
+    pub discarded: usize,
+}
+
+/// Requests one [`bisect`] may make after the first; records still unresolved by then are
+/// rejected with the error of the batch they were in.
+const MAX_BISECT_REQUESTS: usize = 64;
+
+/// Send `records` with `send`, isolating the ones rejected with a terminal error by bisection.
+/// Returns each rejected record with its error. A rejected batch is split and both halves are
+/// sent, and each rejected half is split again. A transient (bufferable) error stops the bisection
+/// and is returned; records already accepted by then will be sent again on the next attempt,
+/// which the backend deduplicates. At most `1 + MAX_BISECT_REQUESTS` requests are made.
+pub(crate) async fn bisect<T, F, Fut>(
+    records: Vec<T>,
+    send: F,
+) -> Result<Vec<(T, ResponseError)>, ResponseError>
+where
+    F: Fn(Vec<T>) -> Fut,
+    Fut: Future<Output = Result<(), ResponseError>>,
+    T: Clone,
+{
+    if records.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut rejected = vec![];
+    // Rejected batches still to split, last in first out so records are sent in order.
+    let mut failed = match send(records.clone()).await {
+        Ok(()) => return Ok(rejected),
+        Err(e) if e.is_bufferable() => return Err(e),
+        Err(e) => vec![(records, e)],
+    };
+    let mut budget = MAX_BISECT_REQUESTS;
+    while let Some((mut batch, error)) = failed.pop() {
+        if batch.len() == 1 || budget < 2 {
+            rejected.extend(batch.into_iter().map(|r| (r, error.clone())));
+            continue;
+        }
+        let second = batch.split_off(batch.len() / 2);
+        let mut halves = vec![];
+        for half in [batch, second] {
+            budget -= 1;
+            match send(half.clone()).await {
+                Ok(()) => {}
+                Err(e) if e.is_bufferable() => return Err(e),
+                Err(e) => halves.push((half, e)),
+            }
+        }
+        failed.extend(halves.into_iter().rev());
+    }
+    Ok(rejected)
+}
+
+/// A dead-letter spool of `T` records under `<buffer dir>/dead-letter/<name>`, opened on first
+/// use.
+pub(crate) struct DeadLetterSpool<T> {
+    name: &'static str,
+    spool: LazySpool,
+    record: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> DeadLetterSpool<T> {
+    pub(crate) fn new(name: &'static str) -> Self {
+        DeadLetterSpool {
+            name,
+            spool: LazySpool::default(),
+            record: PhantomData,
+        }
+    }
+
+    fn with<R>(&self, config: &DataHubConfig, f: impl FnOnce(&mut DurableSpool) -> R) -> Option<R> {
+        let opened = self.spool.ensure(config.buffer_lock_mode, || {
+            let dir = config
+                .buffer_directory()
+                .join("dead-letter")
+                .join(self.name);
+            let max_bytes = config.buffer_max_bytes.unwrap_or(DEFAULT_BUFFER_MAX_BYTES);
+            config.open_spool(dir, None, Some(max_bytes))
+        });
+        if let Err(e) = opened {
+            config.spool_notifier(self.name).io_error("dead_letter", &e);
+        }
+        self.spool.lock().unwrap().as_mut().map(f)
+    }
+
+    pub(crate) fn append(&self, config: &DataHubConfig, rejected: Vec<(T, ResponseError)>) {
+        if rejected.is_empty() {
+            return;
+        }
+        let notifier = config.spool_notifier(self.name);
+        // One event per distinct rejection, not per record.
+        let mut reasons: Vec<(u16, String, u64)> = Vec::new();
+        for (_, e) in &rejected {
+            let (status, error) = (e.get_status().as_u16(), e.get_message());
+            match reasons.iter_mut().find(|r| r.0 == status && r.1 == error) {
+                Some(reason) => reason.2 += 1,
+                None => reasons.push((status, error, 1)),
+            }
+        }
+        for (status, error, records) in reasons {
+            notifier.emit(BufferEvent::Rejected {
+                spool: self.name,
+                records,
+                status,
+                error,
+            });
+        }
+        let now = Utc::now();
+        let records: Vec<(i64, String)> = rejected
+            .into_iter()
+            .filter_map(|(record, e)| {
+                let letter = DeadLetterRecord {
+                    record,
+                    status: e.get_status().as_u16(),
+                    error: e.get_message(),
+                    rejected_at: now,
+                };
+                serde_json::to_string(&letter)
+                    .ok()
+                    .map(|json| (now.timestamp_millis(), json))
+            })
+            .collect();
+        let written = self.with(config, |spool| spool.append(&records, now.timestamp_millis()));
+        if let Some(Err(e)) = written {
+            notifier.io_error("dead_letter", &e);
+        }
+    }
+
+    pub(crate) fn count(&self, config: &DataHubConfig) -> u64 {
+        self.with(config, |spool| spool.size()).unwrap_or(0)
+    }
+
+    /// Every dead letter with the sequence numbers of the segments read, oldest first. Seals
+    /// the active segment first, so letters added meanwhile land in later segments.
+    fn read_all(&self, config: &DataHubConfig) -> io::Result<(Vec<u64>, Vec<DeadLetterRecord<T>>)> {
+        let now = Utc::now().timestamp_millis();
+        self.with(config, |spool| {
+            spool.roll(now)?;
+            let seqs = spool.segment_seqs();
+            let mut letters = vec![];
+            for seq in &seqs {
+                letters.extend(
+                    spool
+                        .read_segment(*seq, now)?
+                        .iter()
+                        .filter_map(|line| serde_json::from_str(line).ok()),
+                );
+            }
+            Ok((seqs, letters))
+        })
+        .unwrap_or_else(|| Ok((vec![], vec![])))
+    }
+
+    pub(crate) fn records(&self, config: &DataHubConfig) -> io::Result<Vec<DeadLetterRecord<T>>> {
+        Ok(self.read_all(config)?.1)
+    }
+
+    /// Write every dead letter to `path` as newline-delimited JSON. Returns the number written.
+    pub(crate) fn export(&self, config: &DataHubConfig, path: &Path) -> io::Result<usize> {
+        let letters = self.records(config)?;
+        let mut out = BufWriter::new(File::create(path)?);
+        for letter in &letters {
+            serde_json::to_writer(&mut out, letter)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()?;
+        Ok(letters.len())
+    }
+
+    pub(crate) fn clear(&self, config: &DataHubConfig) -> io::Result<()> {
+        self.with(config, |spool| spool.clear()).unwrap_or(Ok(()))
+    }
+
+    /// Resend the dead letters, each passed through `fix` first (`None` discards it). Records
+    /// rejected again go back to the spool. On a transient failure every letter stays where it
+    /// was and the error is returned.
+    pub(crate) async fn replay<F, S, Fut>(
+        &self,
+        config: &DataHubConfig,
+        mut fix: F,
+        send: S,
+    ) -> Result<ReplayReport, ResponseError>
+    where
+        F: FnMut(T) -> Option<T>,
+        S: Fn(Vec<T>) -> Fut,
+        Fut: Future<Output = Result<(), ResponseError>>,
+    {
+        let (seqs, letters) = self
+            .read_all(config)
+            .map_err(|e| ResponseError::from(format!("reading dead letters: {e}")))?;
+        let mut report = ReplayReport::default();
+        let records: Vec<T> = letters
+            .into_iter()
+            .filter_map(|letter| {
+                let fixed = fix(letter.record);
+                if fixed.is_none() {
+                    report.discarded += 1;
+                }
+                fixed
+            })
+            .collect();
+        let total = records.len();
+        let rejected = bisect(records, send).await?;
+        report.rejected = rejected.len();
+        report.accepted = total - rejected.len();
+        self.with(config, |spool| {
+            for seq in seqs {
+                let _ = spool.delete_segment(seq);
+            }
+        });
+        self.append(config, rejected);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oauth2::http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    fn error(status: StatusCode) -> ResponseError {
+        ResponseError {
+            status,
+            message: status.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn bisection_isolates_the_rejected_records() {
+        let sent = Mutex::new(vec![]);
+        let requests = AtomicUsize::new(0);
+        let records: Vec<i32> = (0..20).collect();
+        let rejected = bisect(records, |batch: Vec<i32>| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            let ok = !batch.iter().any(|r| *r == 3 || *r == 17);
+            if ok {
+                sent.lock().unwrap().extend(batch);
+            }
+            async move {
+                if ok {
+                    Ok(())
+                } else {
+                    Err(error(StatusCode::BAD_REQUEST))
+                }
+            }
+        })
+        .await
+        .unwrap();
+        let bad: Vec<i32> = rejected.iter().map(|(r, _)| *r).collect();
+        assert_eq!(bad, vec![3, 17]);
+        assert_eq!(sent.lock().unwrap().len(), 18);
+        // Far fewer requests than one per record.
+        assert!(requests.load(Ordering::SeqCst) < 20);
+    }
+
+    #[tokio::test]
+    async fn a_batch_rejected_whole_is_not_split_record_by_record() {
+        let requests = AtomicUsize::new(0);
+        let records: Vec<i32> = (0..1000).collect();
+        let rejected = bisect(records, |_: Vec<i32>| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            async { Err(error(StatusCode::NOT_FOUND)) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(rejected.len(), 1000);
+        assert_eq!(requests.load(Ordering::SeqCst), 1 + MAX_BISECT_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn bisection_gives_up_after_its_request_budget() {
+        let requests = AtomicUsize::new(0);
+        let records: Vec<i32> = (0..1000).collect();
+        // Every other record is bad, so isolating them would take far more than the budget.
+        let rejected = bisect(records, |batch: Vec<i32>| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            let ok = batch.iter().all(|r| r % 2 == 1);
+            async move {
+                match ok {
+                    true => Ok(()),
+                    false => Err(error(StatusCode::BAD_REQUEST)),
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(requests.load(Ordering::SeqCst) <= 1 + MAX_BISECT_REQUESTS);
+        // Every bad record is rejected, along with good ones never sent alone.
+        assert_eq!(rejected.iter().filter(|(r, _)| r % 2 == 0).count(), 500);
+        assert!(rejected.len() < 1000);
+    }
+
+    #[tokio::test]
+    async fn bisection_stops_on_a_transient_error() {
+        let result = bisect(vec![1, 2, 3], |batch: Vec<i32>| async move {
+            match batch.len() {
+                3 => Err(error(StatusCode::BAD_REQUEST)),
+                _ => Err(error(StatusCode::SERVICE_UNAVAILABLE)),
+            }
+        })
+        .await;
+        assert!(matches!(result, Err(e) if e.is_transient()));
+    }
+
+    #[tokio::test]
+    async fn drain_dead_letters_only_the_poison_point() {
+        use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+        use crate::tests::mock_backend::MockBackend;
+        use crate::ApiService;
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+
+        let up = Arc::new(AtomicBool::new(false));
+        let backend_up = up.clone();
+        let backend = MockBackend::start(move |req| {
+            if !backend_up.load(Ordering::SeqCst) {
+                (503, String::new())
+            } else if req.body.contains("\"bad\"") {
+                (400, r#"{"message":"invalid value"}"#.to_string())
+            } else {
+                (204, String::new())
+            }
+        })
+        .await;
+        let dir =
+            std::env::temp_dir().join(format!("datahub_dead_letter_{}", uuid::Uuid::new_v4()));
+        let mut config = backend.config();
+        config.set_buffer_dir(&dir).enable_buffering();
+        let api = ApiService::new(config);
+
+        // Spool retention goes by datapoint time, so the points must be recent.
+        let start = Utc::now().timestamp_millis() - 60_000;
+        let mut collection = DatapointsCollection::from_external_id("temp");
+        for i in 0..16 {
+            let value = if i == 5 {
+                "bad".to_string()
+            } else {
+                i.to_string()
+            };
+            let ts = (start + i * 1000).to_string();
+            collection
+                .datapoints
+                .push(DatapointString::new(&ts, &value));
+        }
+        let mut json = DataWrapper::new();
+        json.add_item(collection);
+        api.time_series.insert_datapoints(&mut json).await.unwrap();
+        assert_eq!(api.time_series.buffered_count(), 16);
+
+        up.store(true, Ordering::SeqCst);
+        let mut next = DataWrapper::new();
+        let mut collection = DatapointsCollection::from_external_id("temp");
+        collection
+            .datapoints
+            .push(DatapointString::new(&(start + 30_000).to_string(), "1"));
+        next.add_item(collection);
+        api.time_series.insert_datapoints(&mut next).await.unwrap();
+
+        assert_eq!(api.time_series.buffered_count(), 0);
+        let letters = api.time_series.dead_letters().unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].record.value, "bad");
+        assert_eq!(letters[0].status, 400);
+        // The 15 good spooled points and the new one got through.
+        let accepted: usize = backend
+            .requests_to("/timeseries/data")
+            .iter()
+            .filter(|r| !r.body.contains("\"bad\""))
+            .map(|r| r.json()["items"][0]["datapoints"].as_array().unwrap().len())
+            .sum();
+        assert_eq!(accepted, 16);
+
+        let export = dir.join("export.ndjson");
+        assert_eq!(api.time_series.export_dead_letters(&export).unwrap(), 1);
+
+        let report = api
+            .time_series
+            .replay_dead_letters(|mut dp| {
+                dp.value = "5".to_string();
+                Some(dp)
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            ReplayReport {
+                accepted: 1,
+                rejected: 0,
+                discarded: 0
+            }
+        );
+        assert_eq!(api.time_series.dead_letter_count(), 0);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
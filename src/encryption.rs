@@ -0,0 +1,280 @@
+//! Encryption at rest for what the SDK writes to disk: the ingest spools, the dead-letter spool,
+//! the quarantine spool and subscription dedup stores opened with
+//! [`SubscriptionsService::dedup_store`](crate::SubscriptionsService::dedup_store).
+//!
+//! With a [`KeyProvider`] configured (`DataHubConfig::set_buffer_encryption`, or the
+//! `BUFFER_ENCRYPTION_KEYS` environment variable), every spooled record is sealed with AES-256-GCM
+//! under the provider's current key before it reaches the disk. Each record is encrypted on its own,
+//! so segments are still appended to, sealed and streamed a record at a time. A record's timestamp
+//! stays readable, so retention can drop old segments without a key, but it is authenticated along
+//! with the record: a record moved to another timestamp fails to decrypt.
+//!
+//! Every encrypted record names the key it was sealed with. Rotating is therefore a matter of
+//! making a new key current while the provider still returns the old ones; records sealed with an
+//! old key stay readable until they are drained. Records written before encryption was turned on
+//! are read as they are. A record that can't be decrypted — its key is gone, or it was tampered
+//! with — fails the read with [`io::ErrorKind::InvalidData`] rather than being sent or skipped, so
+//! it stays on disk until the key is restored or it is purged.
+//!
+//! Exports (`export_segment`, `export_dead_letters`) write plain NDJSON: they are for handing data
+//! to a person or tool, not for caching it.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Starts an encrypted record's payload; JSON never does.
+const ENCRYPTED_MARKER: char = '!';
+const NONCE_LEN: usize = 12;
+
+/// The keys spooled data is encrypted with. `current_key_id` is asked before each write, so a
+/// provider rotates by changing it; `key` must keep returning retired keys for as long as data
+/// sealed with them may still be on disk.
+///
+/// Key ids are stored next to each record, in plain text. They must be non-empty and may not
+/// contain `:` or whitespace.
+pub trait KeyProvider: Send + Sync {
+    /// The id of the key new records are sealed with.
+    fn current_key_id(&self) -> String;
+    /// The 256-bit key with this id, or `None` if it is unknown.
+    fn key(&self, id: &str) -> Option<[u8; 32]>;
+}
+
+impl fmt::Debug for dyn KeyProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyProvider")
+    }
+}
+
+/// A fixed set of keys, e.g. from configuration. The first key is current; keys added with
+/// [`with_retired_key`](Self::with_retired_key) only decrypt.
+#[derive(Clone)]
+pub struct StaticKeys {
+    current: String,
+    keys: HashMap<String, [u8; 32]>,
+}
+
+impl StaticKeys {
+    pub fn new(id: impl Into<String>, key: [u8; 32]) -> Self {
+        let current = id.into();
+        StaticKeys {
+            keys: HashMap::from([(current.clone(), key)]),
+            current,
+        }
+    }
+
+    /// Keep reading records sealed with an earlier key.
+    pub fn with_retired_key(mut self, id: impl Into<String>, key: [u8; 32]) -> Self {
+        self.keys.entry(id.into()).or_insert(key);
+        self
+    }
+
+    /// Parse `id:base64key[,id:base64key…]` (the `BUFFER_ENCRYPTION_KEYS` format); the first key
+    /// is current.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut keys: Option<StaticKeys> = None;
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (id, encoded) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("encryption key entry {:?} is not id:base64key", entry))?;
+            if !valid_key_id(id) {
+                return Err(format!("invalid encryption key id {:?}", id));
+            }
+            let key: [u8; 32] = STANDARD
+                .decode(encoded)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| format!("encryption key {:?} is not 32 bytes of base64", id))?;
+            keys = Some(match keys {
+                None => StaticKeys::new(id, key),
+                Some(keys) => keys.with_retired_key(id, key),
+            });
+        }
+        keys.ok_or_else(|| "no encryption keys given".to_string())
+    }
+}
+
+impl fmt::Debug for StaticKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ids: Vec<&String> = self.keys.keys().collect();
+        ids.sort();
+        f.debug_struct("StaticKeys")
+            .field("current", &self.current)
+            .field("ids", &ids)
+            .finish()
+    }
+}
+
+impl KeyProvider for StaticKeys {
+    fn current_key_id(&self) -> String {
+        self.current.clone()
+    }
+
+    fn key(&self, id: &str) -> Option<[u8; 32]> {
+        self.keys.get(id).copied()
+    }
+}
+
+fn valid_key_id(id: &str) -> bool {
+    !id.is_empty() && !id.contains(|c: char| c == ':' || c.is_whitespace())
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Seals and opens spool records with a [`KeyProvider`]'s keys, caching a cipher per key id.
+pub(crate) struct SpoolCipher {
+    keys: Arc<dyn KeyProvider>,
+    ciphers: Mutex<HashMap<String, Aes256Gcm>>,
+}
+
+impl SpoolCipher {
+    pub(crate) fn new(keys: Arc<dyn KeyProvider>) -> Self {
+        SpoolCipher {
+            keys,
+            ciphers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn cipher(&self, id: &str) -> io::Result<Aes256Gcm> {
+        let mut ciphers = self.ciphers.lock().unwrap();
+        if let Some(cipher) = ciphers.get(id) {
+            return Ok(cipher.clone());
+        }
+        let key = self.keys.key(id).ok_or_else(|| {
+            invalid_data(format!("spool encryption key {:?} is not available", id))
+        })?;
+        let cipher = Aes256Gcm::new(&Key::<Aes256Gcm>::from(key));
+        ciphers.insert(id.to_string(), cipher.clone());
+        Ok(cipher)
+    }
+
+    /// The payload to store for `json` recorded at `ts`: `!<key id>:<base64 nonce + ciphertext>`.
+    pub(crate) fn seal(&self, ts: i64, json: &str) -> io::Result<String> {
+        let id = self.keys.current_key_id();
+        if !valid_key_id(&id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid spool encryption key id {:?}", id),
+            ));
+        }
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let aad = ts.to_be_bytes();
+        let sealed = self
+            .cipher(&id)?
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: json.as_bytes(),
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| invalid_data("spool record encryption failed".to_string()))?;
+        let mut blob = nonce.to_vec();
+        blob.extend_from_slice(&sealed);
+        Ok(format!(
+            "{}{}:{}",
+            ENCRYPTED_MARKER,
+            id,
+            STANDARD.encode(blob)
+        ))
+    }
+}
+
+/// The JSON of a stored payload: decrypted if it was sealed, as is otherwise.
+pub(crate) fn open<'a>(
+    cipher: Option<&SpoolCipher>,
+    ts: i64,
+    payload: &'a str,
+) -> io::Result<Cow<'a, str>> {
+    let Some(sealed) = payload.strip_prefix(ENCRYPTED_MARKER) else {
+        return Ok(Cow::Borrowed(payload));
+    };
+    let cipher = cipher.ok_or_else(|| {
+        invalid_data("spool record is encrypted but no buffer encryption is configured".to_string())
+    })?;
+    let (id, encoded) = sealed
+        .split_once(':')
+        .ok_or_else(|| invalid_data("malformed encrypted spool record".to_string()))?;
+    let blob = STANDARD
+        .decode(encoded)
+        .ok()
+        .filter(|blob| blob.len() > NONCE_LEN)
+        .ok_or_else(|| invalid_data("malformed encrypted spool record".to_string()))?;
+    let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at the nonce length");
+    let aad = ts.to_be_bytes();
+    let plain = cipher
+        .cipher(id)?
+        .decrypt(
+            &Nonce::from(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| {
+            invalid_data(format!(
+                "spool record sealed with key {:?} failed authentication",
+                id
+            ))
+        })?;
+    String::from_utf8(plain)
+        .map(Cow::Owned)
+        .map_err(|_| invalid_data("decrypted spool record is not UTF-8".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(keys: StaticKeys) -> SpoolCipher {
+        SpoolCipher::new(Arc::new(keys))
+    }
+
+    #[test]
+    fn sealed_records_open_only_with_their_key_and_timestamp() {
+        let keys = cipher(StaticKeys::new("k1", [7; 32]));
+        let sealed = keys.seal(42, "{\"n\":1}").unwrap();
+        assert!(sealed.starts_with("!k1:") && !sealed.contains("\"n\""));
+        assert_eq!(open(Some(&keys), 42, &sealed).unwrap(), "{\"n\":1}");
+        assert_eq!(
+            open(Some(&keys), 43, &sealed).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(open(None, 42, &sealed).is_err());
+        assert!(open(Some(&cipher(StaticKeys::new("k1", [8; 32]))), 42, &sealed).is_err());
+        // Records written before encryption was enabled pass through.
+        assert_eq!(open(Some(&keys), 42, "{\"n\":2}").unwrap(), "{\"n\":2}");
+    }
+
+    #[test]
+    fn rotated_keys_still_open_older_records() {
+        let old = cipher(StaticKeys::new("k1", [1; 32]))
+            .seal(5, "old")
+            .unwrap();
+        let rotated = cipher(StaticKeys::new("k2", [2; 32]).with_retired_key("k1", [1; 32]));
+        assert!(rotated.seal(5, "new").unwrap().starts_with("!k2:"));
+        assert_eq!(open(Some(&rotated), 5, &old).unwrap(), "old");
+    }
+
+    #[test]
+    fn parses_the_environment_key_list() {
+        let k2 = STANDARD.encode([2u8; 32]);
+        let k1 = STANDARD.encode([1u8; 32]);
+        let keys = StaticKeys::parse(&format!("k2:{}, k1:{}", k2, k1)).unwrap();
+        assert_eq!(keys.current_key_id(), "k2");
+        assert_eq!(keys.key("k1"), Some([1; 32]));
+        assert!(!format!("{:?}", keys).contains(&k1));
+        assert!(StaticKeys::parse("k1:c2hvcnQ=").is_err());
+        assert!(StaticKeys::parse("no-separator").is_err());
+        assert!(StaticKeys::parse("").is_err());
+    }
+}
@@ -1,7 +1,8 @@
 #[cfg(test)]
 mod tests;
 
-use crate::buffer::DurableSpool;
+use crate::buffer::{LazySpool, SpoolAdmin};
+use crate::dead_letter::{bisect, DeadLetterRecord, DeadLetterSpool, ReplayReport};
 use crate::datahub::{to_snake_lower_cased_allow_start_with_digits, DataHubConfig};
 use crate::fields::{Field, ListField, MapField};
 use crate::filters::{BasicEventFilter, EventFilter};
@@ -14,14 +15,20 @@ use crate::ApiService;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::{Mutex, Weak};
+use std::io;
+use std::path::Path;
+use std::sync::Weak;
 use uuid::Uuid;
 
 pub struct EventsService {
     pub(crate) api_service: Weak<ApiService>,
     base_url: String,
     // Durable spool for event ingestion (lazily opened on first buffered send; None if buffering off).
-    spool: Mutex<Option<DurableSpool>>,
+    spool: LazySpool,
+    // Spooled events the backend rejected on drain.
+    dead_letter: DeadLetterSpool<Event>,
+    // Serializes drains (ingest calls, the background flush, `flush_buffers`).
+    drain_lock: tokio::sync::Mutex<()>,
 }
 
 impl EventsService {
@@ -30,7 +37,9 @@ impl EventsService {
         EventsService {
             api_service,
             base_url,
-            spool: Mutex::new(None),
+            spool: LazySpool::default(),
+            dead_letter: DeadLetterSpool::new("events"),
+            drain_lock: tokio::sync::Mutex::new(()),
         }
     }
 
@@ -56,24 +65,30 @@ impl EventsService {
                 .execute_post_request::<DataWrapper<Event>, _>(&path, &dw)
                 .await;
         }
-        self.ensure_spool(&svc.config);
+        let lock_mode = svc.config.buffer_lock_mode;
+        let dir = spool_dir(&svc.config);
         drop(svc); // don't hold the ApiService Arc across awaits
+        self.spool.hand_over(&dir, lock_mode).await;
+        self.ensure_spool(&self.get_api_service().config);
 
         let now = Utc::now().timestamp_millis();
         // Flush any on-disk backlog first; if it's still stuck, buffer the new events too.
-        if !self.drain_spool(&path, now).await {
-            self.append_to_spool(dw.get_items(), now);
-            return Ok(buffered_wrapper());
+        if let Err(e) = self.drain_spool(&path, now).await {
+            return match self.append_to_spool(dw.get_items(), now) {
+                true => Ok(buffered_wrapper()),
+                false => Err(e),
+            };
         }
         match self
             .execute_post_request::<DataWrapper<Event>, _>(&path, &dw)
             .await
         {
             Ok(r) => Ok(r),
-            Err(e) if e.is_bufferable() => {
-                self.append_to_spool(dw.get_items(), now);
-                Ok(buffered_wrapper())
-            }
+            Err(e) if e.is_bufferable() => match self.append_to_spool(dw.get_items(), now) {
+                true => Ok(buffered_wrapper()),
+                // Not spooled, e.g. another process holds the spool directory.
+                false => Err(e),
+            },
             Err(e) => Err(e), // terminal error: surface it
         }
     }
@@ -83,21 +98,48 @@ impl EventsService {
         self.spool.lock().unwrap().as_ref().map_or(0, |s| s.size())
     }
 
+    /// Inspect and administer the durable event spool. Opens the spool (finding any backlog left
+    /// on disk) if buffering is enabled; when it is off the spool reads as empty.
+    pub fn spool(&self) -> SpoolAdmin<'_, Event> {
+        let svc = self.get_api_service();
+        if svc.config.buffering_enabled() {
+            self.ensure_spool(&svc.config);
+        }
+        SpoolAdmin::new(&self.spool)
+    }
+
+    /// Records in the spool, opening it (and so finding a backlog left on disk, after a
+    /// hand-over if configured) if needed.
+    pub(crate) async fn pending_count(&self) -> u64 {
+        let svc = self.get_api_service();
+        if svc.config.buffering_enabled() {
+            let lock_mode = svc.config.buffer_lock_mode;
+            let dir = spool_dir(&svc.config);
+            drop(svc);
+            self.spool.hand_over(&dir, lock_mode).await;
+            self.ensure_spool(&self.get_api_service().config);
+        }
+        self.buffered_count()
+    }
+
     fn ensure_spool(&self, config: &DataHubConfig) {
-        let mut guard = self.spool.lock().unwrap();
-        if guard.is_none() {
-            let dir = config.buffer_directory().join("events");
-            if let Ok(spool) = DurableSpool::open(
-                dir,
-                config.effective_buffer_retention_ms(),
-                config.effective_buffer_max_bytes(),
-            ) {
-                *guard = Some(spool);
-            }
+        let notifier = config.spool_notifier("events");
+        let opened = self.spool.ensure(config.buffer_lock_mode, || {
+            config
+                .open_spool(
+                    spool_dir(config),
+                    config.effective_buffer_retention_ms(),
+                    config.effective_buffer_max_bytes(),
+                )
+                .map(|spool| spool.with_notifier(notifier.clone()))
+        });
+        if let Err(e) = opened {
+            notifier.io_error("open", &e);
         }
     }
 
-    fn append_to_spool(&self, events: &[Event], now: i64) {
+    /// Spool `events`; `false` if they could not be written.
+    fn append_to_spool(&self, events: &[Event], now: i64) -> bool {
         let records: Vec<(i64, String)> = events
             .iter()
             .filter_map(|e| {
@@ -105,16 +147,48 @@ impl EventsService {
                 serde_json::to_string(e).ok().map(|json| (ts, json))
             })
             .collect();
-        if let Some(spool) = self.spool.lock().unwrap().as_mut() {
-            let _ = spool.append(&records, now);
+        let mut guard = self.spool.lock().unwrap();
+        let Some(spool) = guard.as_mut() else {
+            return false;
+        };
+        match spool.append(&records, now) {
+            Ok(()) => true,
+            Err(e) => {
+                spool.report_io_error("append", &e);
+                false
+            }
         }
     }
 
-    /// Drain the spool to the server, oldest segment first. Returns false if the server is still down
-    /// (a transient failure), leaving the rest buffered; terminal failures drop the offending segment.
-    async fn drain_spool(&self, path: &str, now: i64) -> bool {
+    /// Drain the event spool now (a no-op when buffering is off). Fails with the transient error if
+    /// the backend is still unreachable, leaving the rest buffered.
+    pub(crate) async fn flush_spool(&self) -> Result<(), ResponseError> {
+        let svc = self.get_api_service();
+        if !svc.config.buffering_enabled() {
+            return Ok(());
+        }
+        let lock_mode = svc.config.buffer_lock_mode;
+        let dir = spool_dir(&svc.config);
+        drop(svc);
+        self.spool.hand_over(&dir, lock_mode).await;
+        self.ensure_spool(&self.get_api_service().config);
+        let path = format!("{}/create", self.base_url);
+        self.drain_spool(&path, Utc::now().timestamp_millis()).await
+    }
+
+    /// Drain the spool to the server, oldest segment first. Fails if the server is still down (a
+    /// transient failure) or at a segment that can't be read (a
+    /// [backlog-blocked](ResponseError::is_backlog_blocked) error), leaving the rest buffered;
+    /// rejected events are dead-lettered. One drain runs at a time.
+    async fn drain_spool(&self, path: &str, now: i64) -> Result<(), ResponseError> {
+        // Buffered metadata writes go first, so e.g. a series created offline exists by now.
+        let mutations = self.get_api_service().mutations.clone();
+        mutations.drain().await?;
+        let _draining = self.drain_lock.lock().await;
         if let Some(spool) = self.spool.lock().unwrap().as_mut() {
-            let _ = spool.roll(now);
+            if let Err(e) = spool.roll(now) {
+                spool.report_io_error("roll", &e);
+            }
         }
         loop {
             let seq = self
@@ -124,15 +198,24 @@ impl EventsService {
                 .as_ref()
                 .and_then(|s| s.oldest_sealed_seq());
             let Some(seq) = seq else {
-                return true;
+                return Ok(());
+            };
+            let read = self.spool.lock().unwrap().as_ref().map(|s| {
+                s.read_segment(seq, now)
+                    .inspect_err(|e| s.report_io_error("read", e))
+            });
+            let lines = match read {
+                Some(Ok(lines)) => lines,
+                // Keep an unreadable segment (e.g. its encryption key is missing) for a later
+                // drain or an operator's purge; the records behind it wait too, to stay in order.
+                Some(Err(e)) => {
+                    return Err(ResponseError::backlog_blocked(format!(
+                        "event spool segment {} can't be read: {}",
+                        seq, e
+                    )))
+                }
+                None => return Ok(()),
             };
-            let lines = self
-                .spool
-                .lock()
-                .unwrap()
-                .as_ref()
-                .map(|s| s.read_segment(seq, now).unwrap_or_default())
-                .unwrap_or_default();
             if lines.is_empty() {
                 if let Some(s) = self.spool.lock().unwrap().as_mut() {
                     let _ = s.delete_segment(seq);
@@ -143,23 +226,63 @@ impl EventsService {
                 .iter()
                 .filter_map(|l| serde_json::from_str(l).ok())
                 .collect();
-            let mut batch = DataWrapper::new();
-            batch.set_items(events);
-            match self
-                .execute_post_request::<DataWrapper<Event>, _>(path, &batch)
-                .await
-            {
-                Err(e) if e.is_bufferable() => return false, // server down or auth not yet restored: keep the rest
-                _ => {
-                    // success or terminal error: drop the segment (terminal would never succeed)
+            let sent = events.len() as u64;
+            // A rejected segment is bisected so only the offending events are dead-lettered.
+            match bisect(events, |batch| self.post_events(path, batch)).await {
+                Err(e) => return Err(e), // server down or auth not yet restored: keep the rest
+                Ok(rejected) => {
+                    let delivered = sent - rejected.len() as u64;
+                    self.dead_letter
+                        .append(&self.get_api_service().config, rejected);
                     if let Some(s) = self.spool.lock().unwrap().as_mut() {
                         let _ = s.delete_segment(seq);
+                        s.report_drained(seq, delivered);
                     }
                 }
             }
         }
     }
 
+    async fn post_events(&self, path: &str, events: Vec<Event>) -> Result<(), ResponseError> {
+        let mut batch = DataWrapper::new();
+        batch.set_items(events);
+        self.execute_post_request::<DataWrapper<Event>, _>(path, &batch)
+            .await
+            .map(|_| ())
+    }
+
+    /// Spooled events the backend rejected while draining (see [`crate::dead_letter`]).
+    pub fn dead_letter_count(&self) -> u64 {
+        self.dead_letter.count(&self.get_api_service().config)
+    }
+
+    pub fn dead_letters(&self) -> io::Result<Vec<DeadLetterRecord<Event>>> {
+        self.dead_letter.records(&self.get_api_service().config)
+    }
+
+    /// Write the dead letters to `path` as newline-delimited JSON; returns how many.
+    pub fn export_dead_letters(&self, path: &Path) -> io::Result<usize> {
+        self.dead_letter
+            .export(&self.get_api_service().config, path)
+    }
+
+    pub fn clear_dead_letters(&self) -> io::Result<()> {
+        self.dead_letter.clear(&self.get_api_service().config)
+    }
+
+    /// Resend the dead letters, each passed through `fix` first (return `None` to discard one).
+    /// Events rejected again stay dead-lettered with the new error.
+    pub async fn replay_dead_letters<F>(&self, fix: F) -> Result<ReplayReport, ResponseError>
+    where
+        F: FnMut(Event) -> Option<Event>,
+    {
+        let config = self.get_api_service().config.clone();
+        let path = format!("{}/create", self.base_url);
+        self.dead_letter
+            .replay(&config, fix, |batch| self.post_events(&path, batch))
+            .await
+    }
+
     pub async fn delete<I>(&self, json: &I) -> Result<DataWrapper<Event>, ResponseError>
     where
         for<'a> &'a I: Into<DataWrapper<EventIdCollection>>,
@@ -390,6 +513,11 @@ impl DataWrapperDeserialization for EventCount {
     }
 }
 
+/// Directory of the event ingest spool.
+fn spool_dir(config: &DataHubConfig) -> std::path::PathBuf {
+    config.buffer_directory().join("events")
+}
+
 /// A result for a buffered (not-yet-confirmed) ingest: HTTP 202 with no items. Callers can detect
 /// buffering via `get_http_status_code() == Some(202)` and `buffered_count()`.
 fn buffered_wrapper() -> DataWrapper<Event> {
@@ -0,0 +1,183 @@
+//! Background flushing of the durable ingest spools.
+//!
+//! Without it a spool is only drained by the next `insert_datapoints` / `events.create` call, so
+//! a producer that goes quiet after an outage never delivers its backlog. With
+//! `DataHubConfig::set_buffer_flush_interval` a task drains the spools (and the mutation log)
+//! every interval. While the
+//! backend is unreachable it only probes with a small request, doubling the wait up to the
+//! configured maximum, and resumes draining once the probe succeeds.
+
+use crate::http::ResponseError;
+use crate::ApiService;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+/// A running flush task.
+pub(crate) struct Flusher {
+    stop: Arc<Notify>,
+    handle: JoinHandle<()>,
+}
+
+impl Flusher {
+    /// Start flushing `api`'s spools on the current Tokio runtime, if its configuration asks for
+    /// it and there is a runtime.
+    pub(crate) fn start(api: &Arc<ApiService>) -> Option<Flusher> {
+        let interval = api.config.buffer_flush_interval?;
+        let runtime = tokio::runtime::Handle::try_current().ok()?;
+        let max_backoff = api.config.buffer_flush_max_backoff.max(interval);
+        let stop = Arc::new(Notify::new());
+        let handle = runtime.spawn(run(
+            Arc::downgrade(api),
+            interval,
+            max_backoff,
+            stop.clone(),
+        ));
+        Some(Flusher { stop, handle })
+    }
+
+    /// Stop the task and wait for it to finish its current step, aborting it at `deadline`.
+    pub(crate) async fn stop(mut self, deadline: tokio::time::Instant) {
+        self.stop.notify_one();
+        let finished = tokio::time::timeout_at(deadline, &mut self.handle).await;
+        if finished.is_err() {
+            self.handle.abort();
+        }
+    }
+}
+
+async fn run(api: Weak<ApiService>, interval: Duration, max_backoff: Duration, stop: Arc<Notify>) {
+    let mut delay = interval;
+    let mut reachable = true;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stop.notified() => return,
+        }
+        let Some(api) = api.upgrade() else {
+            return;
+        };
+        if api.mutations.pending_count().await == 0
+            && api.time_series.pending_count().await == 0
+            && api.events.pending_count().await == 0
+        {
+            delay = interval;
+            continue;
+        }
+        // After a failure, check the backend answers before sending a whole segment at it.
+        let result = match reachable {
+            true => api.flush_buffers().await,
+            false => match probe(&api).await {
+                Ok(()) => api.flush_buffers().await,
+                Err(e) => Err(e),
+            },
+        };
+        reachable = !matches!(&result, Err(e) if e.is_bufferable());
+        delay = if reachable {
+            interval
+        } else {
+            (delay * 2).min(max_backoff)
+        };
+    }
+}
+
+async fn probe(api: &ApiService) -> Result<(), ResponseError> {
+    api.time_series.list_with_limit(Some(1)).await.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+    use crate::tests::mock_backend::MockBackend;
+    use crate::ApiService;
+    use chrono::Utc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn backlog_is_flushed_without_new_ingest() {
+        let up = Arc::new(AtomicBool::new(false));
+        let backend_up = up.clone();
+        let backend = MockBackend::start(move |req| match backend_up.load(Ordering::SeqCst) {
+            false => (503, String::new()),
+            true if req.path == "/timeseries" => (200, r#"{"items":[]}"#.to_string()),
+            true => (204, String::new()),
+        })
+        .await;
+        let dir = std::env::temp_dir().join(format!("datahub_flush_{}", uuid::Uuid::new_v4()));
+        let mut config = backend.config();
+        config
+            .set_buffer_dir(&dir)
+            .set_buffer_flush_interval(Duration::from_millis(20))
+            .set_buffer_flush_max_backoff(Duration::from_millis(40));
+        let api = ApiService::new(config);
+
+        let mut json = DataWrapper::new();
+        let mut collection = DatapointsCollection::from_external_id("temp");
+        collection.datapoints.push(DatapointString::new(
+            &Utc::now().timestamp_millis().to_string(),
+            "1",
+        ));
+        json.add_item(collection);
+        let response = api.time_series.insert_datapoints(&mut json).await.unwrap();
+        assert_eq!(response.get_http_status_code(), Some(202));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(api.time_series.buffered_count(), 1);
+
+        up.store(true, Ordering::SeqCst);
+        for _ in 0..50 {
+            if api.time_series.buffered_count() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(api.time_series.buffered_count(), 0);
+        // The backend was probed before the backlog was resent.
+        assert!(!backend.requests_to("/timeseries").is_empty());
+
+        api.shutdown(Duration::from_secs(1)).await.unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn shutdown_does_not_wait_past_its_timeout_for_a_stuck_flush() {
+        let slow = Arc::new(AtomicBool::new(false));
+        let backend_slow = slow.clone();
+        let backend = MockBackend::start(move |_| match backend_slow.load(Ordering::SeqCst) {
+            false => (503, String::new()),
+            true => {
+                std::thread::sleep(Duration::from_secs(2));
+                (503, String::new())
+            }
+        })
+        .await;
+        let dir = std::env::temp_dir().join(format!("datahub_flush_{}", uuid::Uuid::new_v4()));
+        let mut config = backend.config();
+        config
+            .set_buffer_dir(&dir)
+            .set_buffer_flush_interval(Duration::from_millis(20))
+            .set_buffer_flush_max_backoff(Duration::from_millis(20));
+        let api = ApiService::new(config);
+
+        let mut json = DataWrapper::new();
+        let mut collection = DatapointsCollection::from_external_id("temp");
+        collection.datapoints.push(DatapointString::new(
+            &Utc::now().timestamp_millis().to_string(),
+            "1",
+        ));
+        json.add_item(collection);
+        api.time_series.insert_datapoints(&mut json).await.unwrap();
+
+        slow.store(true, Ordering::SeqCst);
+        // Let the background flush get stuck on the slow backend.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let started = std::time::Instant::now();
+        assert!(api.shutdown(Duration::from_millis(300)).await.is_err());
+        assert!(started.elapsed() < Duration::from_millis(1500));
+        assert_eq!(api.time_series.buffered_count(), 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
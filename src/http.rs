@@ -28,6 +28,15 @@ impl ResponseError {
         }
     }
 
+    /// A spool whose oldest segment can't be read (e.g. its encryption key is gone): nothing
+    /// behind the segment is sent, and new records are spooled after it. Answered as 423 Locked.
+    pub(crate) fn backlog_blocked(message: String) -> Self {
+        ResponseError {
+            status: StatusCode::LOCKED,
+            message,
+        }
+    }
+
     pub fn from_err(error: Error) -> Self {
         if let Some(status) = error.status() {
             return ResponseError {
@@ -71,6 +80,13 @@ impl ResponseError {
         code == 401 || code == 403
     }
 
+    /// Whether a spooled backlog is stuck behind a segment that can't be read, so new writes were
+    /// spooled behind it instead of sent. It stays stuck until the segment's key is back or the
+    /// segment is purged through `spool()`.
+    pub fn is_backlog_blocked(&self) -> bool {
+        self.status == StatusCode::LOCKED
+    }
+
     /// Whether this error is worth buffering and retrying rather than surfacing as terminal. True for
     /// transient failures ([`is_transient`](Self::is_transient)) and auth failures
     /// ([`is_auth_failure`](Self::is_auth_failure)); a genuine terminal 4xx (e.g. 400 Bad Request) is
@@ -3,6 +3,7 @@ mod test;
 
 use crate::generic::{ApiServiceProvider, DataWrapper, IdAndExtId};
 use crate::http::ResponseError;
+use crate::mutations::{buffered_items, MutationKind};
 use crate::ApiService;
 use serde::{Deserialize, Serialize};
 use std::sync::Weak;
@@ -46,7 +47,16 @@ impl LabelsService {
         for<'a> &'a I: Into<DataWrapper<Label>>,
     {
         let path = &format!("{}/create", self.base_url);
-        self.execute_post_request(path, &data.into()).await
+        let body = data.into();
+        let mutations = self.get_api_service().mutations.clone();
+        mutations
+            .submit(
+                MutationKind::LabelCreate,
+                &body,
+                buffered_items,
+                self.execute_post_request(path, &body),
+            )
+            .await
     }
 
     /// Update existing labels (identified by `id`). PATCH semantics: only the fields you set are
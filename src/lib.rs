@@ -4,6 +4,7 @@ use reqwest::ClientBuilder;
 use std::sync::{Arc, Weak};
 
 use crate::datahub::DataHubConfig;
+use crate::http::ResponseError;
 pub use crate::events::EventsService;
 pub use crate::files::{FileService, FileUpload};
 pub use crate::resources::ResourceService;
@@ -23,17 +24,24 @@ pub mod blocking;
 pub mod buffer;
 #[cfg(test)]
 mod buffer_integration;
+#[cfg(test)]
+mod buffer_recovery;
 pub mod datahub;
+pub mod datapoints;
 pub mod datasets;
+pub mod dead_letter;
+pub mod encryption;
 pub mod errors;
 pub mod events;
 pub mod fields;
 pub mod files;
+mod flush;
 pub mod filters;
 pub mod generic;
 pub mod graph_data_wrapper;
 pub mod http;
 pub mod labels;
+pub mod mutations;
 #[cfg(test)]
 mod multi_tenant_integration;
 pub mod relations;
@@ -57,10 +65,11 @@ use crate::datasets::*;
 
 pub use subscriptions::{
     DataCollectionString, DataSort, DataWrapperMessage, EventAction, EventObject, ListenError,
-    Subscription, SubscriptionFilter, SubscriptionListener, SubscriptionMessage,
-    SubscriptionRetriever, WsDatapoint,
+    ListenerHandle, Subscription, SubscriptionFilter, SubscriptionListener, SubscriptionMessage,
+    SubscriptionReceiver, SubscriptionRetriever, WsDatapoint,
 };
 use crate::functions::FunctionsService;
+use crate::mutations::MutationLog;
 //pub use filters::Filter;
 
 pub struct ApiService {
@@ -75,7 +84,10 @@ pub struct ApiService {
     pub functions: FunctionsService,
     pub labels: LabelsService,
     pub edges: EdgesService,
+    // Shared so ingest can replay it without holding the ApiService across awaits.
+    pub(crate) mutations: Arc<MutationLog>,
     pub(crate) http_client: Client,
+    flusher: std::sync::Mutex<Option<flush::Flusher>>,
 }
 
 /// Drive a future to completion on a self-contained, single-threaded Tokio runtime.
@@ -131,9 +143,12 @@ pub fn create_api_service() -> Arc<ApiService> {
             functions: FunctionsService::new(Weak::clone(weak_self), &base_url_clone),
             labels: LabelsService::new(Weak::clone(weak_self), &base_url_clone),
             edges: EdgesService::new(Weak::clone(weak_self), &base_url_clone),
+            mutations: Arc::new(MutationLog::new(Weak::clone(weak_self), &base_url_clone)),
             http_client,
+            flusher: std::sync::Mutex::new(None),
         }
     });
+    api_service.start_background_flush();
     api_service
 }
 impl ApiService {
@@ -167,12 +182,66 @@ impl ApiService {
                 functions: FunctionsService::new(Weak::clone(weak_self), &base_url_clone),
                 labels: LabelsService::new(Weak::clone(weak_self), &base_url_clone),
                 edges: EdgesService::new(Weak::clone(weak_self), &base_url_clone),
+                mutations: Arc::new(MutationLog::new(Weak::clone(weak_self), &base_url_clone)),
                 http_client,
+                flusher: std::sync::Mutex::new(None),
             }
         });
+        api_service.start_background_flush();
 
         api_service
     }
+    /// Start the background spool flush configured with
+    /// [`DataHubConfig::set_buffer_flush_interval`] on the current Tokio runtime. The
+    /// constructors call this; call it yourself for a service created outside a runtime. Does
+    /// nothing if the task is running, no interval is set, or there is no runtime.
+    pub fn start_background_flush(self: &Arc<Self>) {
+        let mut flusher = self.flusher.lock().unwrap();
+        if flusher.is_none() {
+            *flusher = flush::Flusher::start(self);
+        }
+    }
+
+    /// Drain the mutation log, then the datapoint and event spools, now. Fails with the transient
+    /// error if the backend is still unreachable, or with a
+    /// [backlog-blocked](ResponseError::is_backlog_blocked) error if a spool is stuck behind a
+    /// segment it can't read; whatever was not sent stays buffered.
+    pub async fn flush_buffers(&self) -> Result<(), ResponseError> {
+        self.mutations.drain().await?;
+        self.time_series.flush_spool().await?;
+        self.events.flush_spool().await
+    }
+
+    /// The log of metadata writes buffered while the backend was unreachable (see
+    /// [`mutations`] and [`DataHubConfig::enable_metadata_buffering`]).
+    pub fn mutations(&self) -> &MutationLog {
+        &self.mutations
+    }
+
+    /// Stop the background flush and make a last attempt to flush the spools, giving up after
+    /// `timeout` (which also bounds the wait for a background flush in progress). Records not
+    /// flushed stay on disk for the next start. Call it before the process exits.
+    pub async fn shutdown(&self, timeout: std::time::Duration) -> Result<(), ResponseError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let flusher = self.flusher.lock().unwrap().take();
+        if let Some(flusher) = flusher {
+            flusher.stop(deadline).await;
+        }
+        match tokio::time::timeout_at(deadline, self.flush_buffers()).await {
+            Ok(result) => result,
+            Err(_) => Err(ResponseError {
+                status: oauth2::http::StatusCode::SERVICE_UNAVAILABLE,
+                message: format!(
+                    "timed out flushing buffers; {} metadata writes, {} datapoints and {} events \
+                     remain buffered",
+                    self.mutations.buffered_count(),
+                    self.time_series.buffered_count(),
+                    self.events.buffered_count()
+                ),
+            }),
+        }
+    }
+
     pub fn api_service_from_env() -> Arc<ApiService> {
         let dataplatform_api: DataHubConfig /* Type */ = DataHubConfig::from_env().unwrap();
         let mut headers = HeaderMap::new();
@@ -204,9 +273,12 @@ impl ApiService {
                 functions: FunctionsService::new(Weak::clone(weak_self), &base_url_clone),
                 labels: LabelsService::new(Weak::clone(weak_self), &base_url_clone),
                 edges: EdgesService::new(Weak::clone(weak_self), &base_url_clone),
+                mutations: Arc::new(MutationLog::new(Weak::clone(weak_self), &base_url_clone)),
                 http_client,
+                flusher: std::sync::Mutex::new(None),
             }
         });
+        api_service.start_background_flush();
 
         api_service
     }
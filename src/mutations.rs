@@ -0,0 +1,542 @@
+//! Durable buffering for metadata writes.
+//!
+//! With `DataHubConfig::enable_metadata_buffering`, the metadata calls an edge gateway makes while
+//! registering what it finds — `time_series.create`, `resources.create` / `update`,
+//! `edges.create` and `labels.create` — no longer fail when the backend is unreachable. The
+//! request is appended to an ordered mutation log (a [`DurableSpool`] in `mutations/` under the
+//! buffer directory) and the call answers 202 with no items, as buffered ingest does.
+//!
+//! The log is replayed oldest first, one request at a time, and always before the datapoint and
+//! event spools are drained: a series created offline exists before its buffered datapoints are
+//! sent. While it holds a backlog, new metadata writes queue behind it, and datapoint and event
+//! ingest buffers instead of overtaking it. A replayed single-item create the backend answers with
+//! 409 Conflict was already applied (typically the original request got through before its
+//! response was lost) and counts as delivered. A 409 on an update or a multi-item create may mean
+//! only part of it exists, so it is dead-lettered like any other rejection, as for datapoints.
+//!
+//! Unlike the ingest spools, the log has no time window, only the buffer size cap: a dropped
+//! create would orphan everything that depends on it.
+
+use crate::buffer::{LazySpool, SpoolAdmin};
+use crate::datahub::{DataHubConfig, DEFAULT_BUFFER_MAX_BYTES};
+use crate::dead_letter::{DeadLetterRecord, DeadLetterSpool};
+use crate::generic::{ApiServiceProvider, DataWrapper};
+use crate::graph_data_wrapper::{GraphDataWrapper, GraphNode};
+use crate::http::ResponseError;
+use crate::ApiService;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::sync::Weak;
+
+/// The metadata writes that can be buffered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MutationKind {
+    TimeSeriesCreate,
+    ResourceCreate,
+    ResourceUpdate,
+    EdgeCreate,
+    LabelCreate,
+}
+
+impl MutationKind {
+    /// The endpoint the write is replayed to, relative to the base URL.
+    pub fn path(self) -> &'static str {
+        match self {
+            MutationKind::TimeSeriesCreate => "/timeseries/create",
+            MutationKind::ResourceCreate => "/resources/create",
+            MutationKind::ResourceUpdate => "/resources/update",
+            MutationKind::EdgeCreate => "/edges/create",
+            MutationKind::LabelCreate => "/labels/create",
+        }
+    }
+}
+
+/// A buffered metadata write: the request body, exactly as it would have been sent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mutation {
+    pub kind: MutationKind,
+    pub body: serde_json::Value,
+}
+
+impl Mutation {
+    /// Whether this creates exactly one item, so that a 409 means that item already exists.
+    fn is_single_create(&self) -> bool {
+        if self.kind == MutationKind::ResourceUpdate {
+            return false;
+        }
+        let count = |field: &str| {
+            self.body
+                .get(field)
+                .and_then(|v| v.as_array())
+                .map_or(0, Vec::len)
+        };
+        count("items") + count("nodes") + count("relations") == 1
+    }
+}
+
+/// The answer to a logged write that returns a list: 202, no items.
+pub(crate) fn buffered_items<T>() -> DataWrapper<T> {
+    let mut w = DataWrapper::new();
+    w.set_http_status_code(202);
+    w
+}
+
+/// The answer to a logged write that returns a graph: 202, no nodes.
+pub(crate) fn buffered_graph<T: GraphNode>() -> GraphDataWrapper<T> {
+    let mut w = GraphDataWrapper::new();
+    w.http_status_code = Some(202);
+    w
+}
+
+/// Directory of the mutation log.
+fn spool_dir(config: &DataHubConfig) -> std::path::PathBuf {
+    config.buffer_directory().join("mutations")
+}
+
+/// The mutation log of an [`ApiService`], reached through [`ApiService::mutations`].
+pub struct MutationLog {
+    api_service: Weak<ApiService>,
+    base_url: String,
+    // Lazily opened on first use; None while metadata buffering is off.
+    spool: LazySpool,
+    // Serializes replays (metadata writes, ingest drains, the background flush).
+    drain_lock: tokio::sync::Mutex<()>,
+    dead_letter: DeadLetterSpool<Mutation>,
+}
+
+impl ApiServiceProvider for MutationLog {
+    fn api_service(&self) -> &Weak<ApiService> {
+        &self.api_service
+    }
+}
+
+impl MutationLog {
+    pub(crate) fn new(api_service: Weak<ApiService>, base_url: &str) -> Self {
+        MutationLog {
+            api_service,
+            base_url: base_url.to_string(),
+            spool: LazySpool::default(),
+            drain_lock: tokio::sync::Mutex::new(()),
+            dead_letter: DeadLetterSpool::new("mutations"),
+        }
+    }
+
+    /// Writes waiting in the log (0 when metadata buffering is off).
+    pub fn buffered_count(&self) -> u64 {
+        self.spool.lock().unwrap().as_ref().map_or(0, |s| s.size())
+    }
+
+    /// Inspect and administer the log. Opens it (finding any backlog left on disk) if metadata
+    /// buffering is enabled; when it is off the log reads as empty.
+    pub fn spool(&self) -> SpoolAdmin<'_, Mutation> {
+        let svc = self.get_api_service();
+        if svc.config.metadata_buffering_enabled() {
+            self.ensure_spool(&svc.config);
+        }
+        SpoolAdmin::new(&self.spool)
+    }
+
+    /// Replayed writes the backend rejected (see [`crate::dead_letter`]).
+    pub fn dead_letter_count(&self) -> u64 {
+        self.dead_letter.count(&self.get_api_service().config)
+    }
+
+    pub fn dead_letters(&self) -> io::Result<Vec<DeadLetterRecord<Mutation>>> {
+        self.dead_letter.records(&self.get_api_service().config)
+    }
+
+    /// Write the dead letters to `path` as newline-delimited JSON; returns how many.
+    pub fn export_dead_letters(&self, path: &Path) -> io::Result<usize> {
+        self.dead_letter
+            .export(&self.get_api_service().config, path)
+    }
+
+    pub fn clear_dead_letters(&self) -> io::Result<()> {
+        self.dead_letter.clear(&self.get_api_service().config)
+    }
+
+    /// Writes in the log, opening it (and so finding a backlog left on disk, after a hand-over
+    /// if configured) if needed.
+    pub(crate) async fn pending_count(&self) -> u64 {
+        let svc = self.get_api_service();
+        if svc.config.metadata_buffering_enabled() {
+            let lock_mode = svc.config.buffer_lock_mode;
+            let dir = spool_dir(&svc.config);
+            drop(svc);
+            self.spool.hand_over(&dir, lock_mode).await;
+            self.ensure_spool(&self.get_api_service().config);
+        }
+        self.buffered_count()
+    }
+
+    fn ensure_spool(&self, config: &DataHubConfig) {
+        let notifier = config.spool_notifier("mutations");
+        let max_bytes = config.buffer_max_bytes.unwrap_or(DEFAULT_BUFFER_MAX_BYTES);
+        let opened = self.spool.ensure(config.buffer_lock_mode, || {
+            config
+                .open_spool(spool_dir(config), None, Some(max_bytes))
+                .map(|spool| spool.with_notifier(notifier.clone()))
+        });
+        if let Err(e) = opened {
+            notifier.io_error("open", &e);
+        }
+    }
+
+    /// Send a metadata write with `send`, or log it as `kind` with `body` if metadata buffering is
+    /// on and it can't be sent now: when the log already holds a backlog that can't be replayed,
+    /// or when `send` fails with a bufferable error. A logged write answers `buffered()`.
+    pub(crate) async fn submit<R, B, Fut>(
+        &self,
+        kind: MutationKind,
+        body: &B,
+        buffered: impl FnOnce() -> R,
+        send: Fut,
+    ) -> Result<R, ResponseError>
+    where
+        B: Serialize,
+        Fut: Future<Output = Result<R, ResponseError>>,
+    {
+        let svc = self.get_api_service();
+        if !svc.config.metadata_buffering_enabled() {
+            drop(svc);
+            return send.await;
+        }
+        drop(svc); // don't hold the ApiService Arc across awaits
+
+        // Opens the log too.
+        if let Err(e) = self.drain().await {
+            return match self.append(kind, body) {
+                true => Ok(buffered()),
+                false => Err(e),
+            };
+        }
+        match send.await {
+            Err(e) if e.is_bufferable() => match self.append(kind, body) {
+                true => Ok(buffered()),
+                // Not logged, e.g. another process holds the log directory.
+                false => Err(e),
+            },
+            result => result,
+        }
+    }
+
+    /// Log one write; `false` if it could not be written.
+    fn append<B: Serialize>(&self, kind: MutationKind, body: &B) -> bool {
+        let Ok(body) = serde_json::to_value(body) else {
+            return false;
+        };
+        let Ok(json) = serde_json::to_string(&Mutation { kind, body }) else {
+            return false;
+        };
+        let now = Utc::now().timestamp_millis();
+        let mut guard = self.spool.lock().unwrap();
+        let Some(spool) = guard.as_mut() else {
+            return false;
+        };
+        match spool.append(&[(now, json)], now) {
+            Ok(()) => true,
+            Err(e) => {
+                spool.report_io_error("append", &e);
+                false
+            }
+        }
+    }
+
+    /// Replay the log, oldest write first (a no-op when metadata buffering is off or the log is
+    /// empty). Fails with the transient error if the backend is still unreachable, or with a
+    /// [backlog-blocked](ResponseError::is_backlog_blocked) error at a segment that can't be read,
+    /// leaving that write and everything after it logged; rejected writes are dead-lettered. One
+    /// replay runs at a time.
+    pub(crate) async fn drain(&self) -> Result<(), ResponseError> {
+        let svc = self.get_api_service();
+        if !svc.config.metadata_buffering_enabled() {
+            return Ok(());
+        }
+        let lock_mode = svc.config.buffer_lock_mode;
+        let dir = spool_dir(&svc.config);
+        drop(svc);
+        self.spool.hand_over(&dir, lock_mode).await;
+        self.ensure_spool(&self.get_api_service().config);
+        let _draining = self.drain_lock.lock().await;
+        let now = Utc::now().timestamp_millis();
+        if let Some(spool) = self.spool.lock().unwrap().as_mut() {
+            if spool.size() == 0 {
+                return Ok(());
+            }
+            if let Err(e) = spool.roll(now) {
+                spool.report_io_error("roll", &e);
+            }
+        }
+        loop {
+            let seq = self
+                .spool
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|s| s.oldest_sealed_seq());
+            let Some(seq) = seq else {
+                return Ok(());
+            };
+            let read = self.spool.lock().unwrap().as_ref().map(|s| {
+                s.read_segment(seq, now)
+                    .inspect_err(|e| s.report_io_error("read", e))
+            });
+            let lines = match read {
+                Some(Ok(lines)) => lines,
+                // Keep an unreadable segment; everything logged after it waits, to stay in order.
+                Some(Err(e)) => {
+                    return Err(ResponseError::backlog_blocked(format!(
+                        "mutation log segment {} can't be read: {}",
+                        seq, e
+                    )))
+                }
+                None => return Ok(()),
+            };
+            // Each write with its line in the segment; a line that doesn't parse is skipped.
+            let mutations: Vec<(usize, Mutation)> = lines
+                .iter()
+                .enumerate()
+                .filter_map(|(i, l)| Some((i, serde_json::from_str(l).ok()?)))
+                .collect();
+            let mut delivered = 0u64;
+            let mut rejected = vec![];
+            for (line, mutation) in &mutations {
+                match self.replay(mutation).await {
+                    Ok(()) => delivered += 1,
+                    Err(e) if e.is_bufferable() => {
+                        // Keep what is left of the segment for the next replay, in order.
+                        self.dead_letter
+                            .append(&self.get_api_service().config, rejected);
+                        self.keep_from(seq, *line, now);
+                        return Err(e);
+                    }
+                    Err(e) => rejected.push((mutation.clone(), e)),
+                }
+            }
+            self.dead_letter
+                .append(&self.get_api_service().config, rejected);
+            if let Some(s) = self.spool.lock().unwrap().as_mut() {
+                let _ = s.delete_segment(seq);
+                s.report_drained(seq, delivered);
+            }
+        }
+    }
+
+    /// Send one logged write. A 409 on a single-item create means it was already applied.
+    async fn replay(&self, mutation: &Mutation) -> Result<(), ResponseError> {
+        let path = format!("{}{}", self.base_url, mutation.kind.path());
+        match self
+            .execute_post_request::<String, _>(&path, &mutation.body)
+            .await
+        {
+            Err(e) if e.get_status().as_u16() == 409 && mutation.is_single_create() => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Rewrite segment `seq` to hold only its lines from `line` on, after the writes before them
+    /// were replayed.
+    fn keep_from(&self, seq: u64, line: usize, now: i64) {
+        if line == 0 {
+            return;
+        }
+        let mut guard = self.spool.lock().unwrap();
+        let Some(spool) = guard.as_mut() else {
+            return;
+        };
+        let mut index = 0usize;
+        let result = spool.retain_in(
+            seq,
+            |_, _| {
+                index += 1;
+                index > line
+            },
+            now,
+        );
+        if let Err(e) = result {
+            spool.report_io_error("rewrite", &e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mutation, MutationKind};
+    use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+    use crate::labels::Label;
+    use crate::tests::mock_backend::MockBackend;
+    use crate::timeseries::TimeSeries;
+    use crate::ApiService;
+    use chrono::Utc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    fn temp_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("datahub_mutations_{}", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn metadata_writes_are_logged_offline_and_replayed_before_datapoints() {
+        let up = Arc::new(AtomicBool::new(false));
+        let backend_up = up.clone();
+        let backend = MockBackend::start(move |req| match backend_up.load(Ordering::SeqCst) {
+            false => (503, String::new()),
+            true if req.path == "/labels/create" => (409, "duplicate".to_string()),
+            true => (201, r#"{"items":[]}"#.to_string()),
+        })
+        .await;
+        let dir = temp_dir();
+        let mut config = backend.config();
+        config.set_buffer_dir(&dir).enable_metadata_buffering();
+        let api = ApiService::new(config);
+
+        let series = TimeSeries::new("pump-1.temp", "Pump 1 temperature");
+        let created = api.time_series.create_one(&series).await.unwrap();
+        assert_eq!(created.get_http_status_code(), Some(202));
+        let label = Label::new("PUMP");
+        let created = api.labels.create(&vec![label]).await.unwrap();
+        assert_eq!(created.get_http_status_code(), Some(202));
+        assert_eq!(api.mutations().buffered_count(), 2);
+
+        let mut json = DataWrapper::new();
+        let mut collection = DatapointsCollection::from_external_id("pump-1.temp");
+        let ts = Utc::now().timestamp_millis().to_string();
+        collection.datapoints.push(DatapointString::new(&ts, "1"));
+        json.add_item(collection);
+        api.time_series.insert_datapoints(&mut json).await.unwrap();
+        assert_eq!(api.time_series.buffered_count(), 1);
+
+        up.store(true, Ordering::SeqCst);
+        api.flush_buffers().await.unwrap();
+        assert_eq!(api.mutations().buffered_count(), 0);
+        assert_eq!(api.time_series.buffered_count(), 0);
+        // The 409 on the replayed label means it already exists, not that it was rejected.
+        assert_eq!(api.mutations().dead_letter_count(), 0);
+        let order: Vec<String> = backend.requests().into_iter().map(|r| r.path).collect();
+        let position = |path: &str| order.iter().rposition(|p| p == path).unwrap();
+        assert!(position("/timeseries/create") < position("/labels/create"));
+        assert!(position("/labels/create") < position("/timeseries/data"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn a_partly_replayed_segment_keeps_the_rest_in_order() {
+        let fail_edges = Arc::new(AtomicBool::new(true));
+        let failing = fail_edges.clone();
+        let up = Arc::new(AtomicBool::new(false));
+        let backend_up = up.clone();
+        let backend = MockBackend::start(move |req| {
+            match (backend_up.load(Ordering::SeqCst), req.path.as_str()) {
+                (false, _) => (503, String::new()),
+                (true, "/edges/create") if failing.load(Ordering::SeqCst) => (503, String::new()),
+                (true, "/resources/create") => (400, "bad resource".to_string()),
+                _ => (201, r#"{"items":[]}"#.to_string()),
+            }
+        })
+        .await;
+        let dir = temp_dir();
+        let mut config = backend.config();
+        config.set_buffer_dir(&dir).enable_metadata_buffering();
+        let api = ApiService::new(config);
+
+        api.labels.create(&vec![Label::new("A")]).await.unwrap();
+        api.resources.create(vec![], vec![]).await.unwrap();
+        api.edges.create(&vec![]).await.unwrap();
+        api.labels.create(&vec![Label::new("B")]).await.unwrap();
+        assert_eq!(api.mutations().buffered_count(), 4);
+
+        up.store(true, Ordering::SeqCst);
+        assert!(api.flush_buffers().await.is_err());
+        // The label went through, the resource was dead-lettered, the edge and the last label wait.
+        assert_eq!(api.mutations().buffered_count(), 2);
+        assert_eq!(api.mutations().dead_letter_count(), 1);
+
+        fail_edges.store(false, Ordering::SeqCst);
+        api.flush_buffers().await.unwrap();
+        assert_eq!(api.mutations().buffered_count(), 0);
+        let replayed: Vec<String> = backend.requests().into_iter().map(|r| r.path).collect();
+        let tail = &replayed[replayed.len() - 2..];
+        assert_eq!(tail, ["/edges/create", "/labels/create"]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn a_conflict_on_a_multi_item_create_is_dead_lettered() {
+        let up = Arc::new(AtomicBool::new(false));
+        let backend_up = up.clone();
+        let backend = MockBackend::start(move |_| match backend_up.load(Ordering::SeqCst) {
+            false => (503, String::new()),
+            true => (409, "duplicate".to_string()),
+        })
+        .await;
+        let dir = temp_dir();
+        let mut config = backend.config();
+        config.set_buffer_dir(&dir).enable_metadata_buffering();
+        let api = ApiService::new(config);
+
+        api.labels.create(&vec![Label::new("A")]).await.unwrap();
+        let labels = vec![Label::new("B"), Label::new("C")];
+        api.labels.create(&labels).await.unwrap();
+
+        up.store(true, Ordering::SeqCst);
+        api.flush_buffers().await.unwrap();
+        assert_eq!(api.mutations().buffered_count(), 0);
+        // Only "B" and "C" may be missing; the single "A" already exists.
+        let dead = api.mutations().dead_letters().unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].record.body["items"].as_array().unwrap().len(), 2);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn an_unparseable_line_does_not_shift_what_is_kept() {
+        let up = Arc::new(AtomicBool::new(false));
+        let backend_up = up.clone();
+        let backend = MockBackend::start(move |req| {
+            match (backend_up.load(Ordering::SeqCst), req.path.as_str()) {
+                (true, "/labels/create") => (201, r#"{"items":[]}"#.to_string()),
+                _ => (503, String::new()),
+            }
+        })
+        .await;
+        let dir = temp_dir();
+        let mut config = backend.config();
+        config.set_buffer_dir(&dir).enable_metadata_buffering();
+        let api = ApiService::new(config);
+
+        api.labels.create(&vec![Label::new("A")]).await.unwrap();
+        api.edges.create(&vec![]).await.unwrap();
+        let now = Utc::now().timestamp_millis();
+        let garbage = [(now, "not a mutation".to_string())];
+        if let Some(log) = api.mutations().spool.lock().unwrap().as_mut() {
+            log.append(&garbage, now).unwrap();
+        }
+        api.labels.create(&vec![Label::new("B")]).await.unwrap();
+
+        up.store(true, Ordering::SeqCst);
+        assert!(api.flush_buffers().await.is_err());
+        // Only "A" went through: the edge waits, with everything after it.
+        assert_eq!(api.mutations().buffered_count(), 3);
+        let log = api.mutations().spool();
+        let kept: Vec<Mutation> = log.peek(log.segments()[0].seq, 1).unwrap();
+        assert_eq!(kept[0].kind, MutationKind::EdgeCreate);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn writes_fail_as_before_without_metadata_buffering() {
+        let backend = MockBackend::start(|_| (503, String::new())).await;
+        let dir = temp_dir();
+        let mut config = backend.config();
+        config.set_buffer_dir(&dir).enable_buffering();
+        let api = ApiService::new(config);
+        let err = api.labels.create(&vec![Label::new("A")]).await.unwrap_err();
+        assert_eq!(err.get_status().as_u16(), 503);
+        assert_eq!(api.mutations().buffered_count(), 0);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -8,6 +8,7 @@
 use crate::generic::{ApiServiceProvider, DataWrapper, IdAndExtId};
 use crate::graph_data_wrapper::GraphDataWrapper;
 use crate::http::ResponseError;
+use crate::mutations::{buffered_items, MutationKind};
 use crate::relations::{EdgeProxy, RelForm, RelTypeForm, RelationshipType};
 use crate::resources::Resource;
 use crate::ApiService;
@@ -80,7 +81,16 @@ impl EdgesService {
         for<'a> &'a I: Into<DataWrapper<RelForm>>,
     {
         let path = &format!("{}/create", self.base_url);
-        self.execute_post_request(path, &data.into()).await
+        let body = data.into();
+        let mutations = self.get_api_service().mutations.clone();
+        mutations
+            .submit(
+                MutationKind::EdgeCreate,
+                &body,
+                buffered_items,
+                self.execute_post_request(path, &body),
+            )
+            .await
     }
 
     /// `POST /edges/delete` — delete relationships by id. Answers **204**.
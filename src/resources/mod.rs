@@ -10,6 +10,7 @@ use crate::generic::{
 };
 use crate::graph_data_wrapper::{GraphDataWrapper, GraphNode};
 use crate::http::{process_response, ResponseError};
+use crate::mutations::{buffered_graph, MutationKind};
 use crate::relations::{EdgeProxy, RelForm, RelatedNode};
 use crate::ApiService;
 use chrono::{DateTime, Utc};
@@ -49,7 +50,14 @@ impl ResourceService {
         let payload: GraphDataWrapper<Resource, RelForm> =
             GraphDataWrapper::with_relations(nodes, relations);
         let url = &format!("{}/create", self.base_url);
-        self.execute_post_request::<GraphDataWrapper<Resource>, _>(&url, &payload)
+        let mutations = self.get_api_service().mutations.clone();
+        mutations
+            .submit(
+                MutationKind::ResourceCreate,
+                &payload,
+                buffered_graph,
+                self.execute_post_request::<GraphDataWrapper<Resource>, _>(url, &payload),
+            )
             .await
     }
     pub async fn by_ids<I>(&self, input: &I) -> Result<GraphDataWrapper<Resource>, ResponseError>
@@ -111,7 +119,14 @@ impl ResourceService {
             payload.relations = Some(vec![]);
         }
         let url = &format!("{}/update", self.base_url);
-        self.execute_post_request::<GraphDataWrapper<Resource>, _>(&url, &payload)
+        let mutations = self.get_api_service().mutations.clone();
+        mutations
+            .submit(
+                MutationKind::ResourceUpdate,
+                &payload,
+                buffered_graph,
+                self.execute_post_request::<GraphDataWrapper<Resource>, _>(url, &payload),
+            )
             .await
     }
 
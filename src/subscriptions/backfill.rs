@@ -0,0 +1,294 @@
+//! Historical backfill ahead of live subscription delivery.
+//!
+//! A listener only sees what is written after it connects. [`BackfillListener`] first replays the
+//! subscription's series from a point in time, read with
+//! [`retrieve_datapoints`](crate::TimeSeriesService::retrieve_datapoints), and then hands out the
+//! live messages. The live listener is opened before the history is read, so nothing written in
+//! between is missed. Points written while the history was being read then arrive twice: once in
+//! the history and once live. A live datapoints message whose every point was already replayed is
+//! acked and skipped; a point counts as replayed when both its timestamp and its value match. Only
+//! the last [`OVERLAP_WINDOW`] of each series' history is compared, since that is where the two
+//! meet.
+//!
+//! While the history is read, live messages wait in the live listener's buffer. Once about a
+//! thousand are waiting the listener stops reading the socket, and the backend holds the rest
+//! until the history is done; a long backfill of a busy subscription delays live delivery by that
+//! much.
+
+use super::listen::{ListenError, ListenerHandle, SubscriptionListener, SubscriptionMessage};
+use super::payload::{LiveDatapoint, LiveValue, SeriesDatapoints, SubscriptionPayload};
+use super::EventAction;
+use crate::generic::{DataWrapper, DatapointsCollection, IdAndExtId, RetrieveFilter};
+use crate::http::ResponseError;
+use crate::ApiService;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::Weak;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use thiserror::Error;
+
+/// How far back from the newest replayed point of a series live points are checked against the
+/// history.
+pub const OVERLAP_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// What a [`BackfillListener`] hands out: pages of history first, then live messages.
+#[derive(Debug, Clone)]
+pub enum BackfillItem {
+    /// A page of one series' history, in timestamp order. Series are replayed one after another.
+    History(SeriesDatapoints),
+    /// A live message; ack or nack it as usual.
+    Live(SubscriptionMessage),
+}
+
+#[derive(Debug, Error)]
+pub enum BackfillError {
+    /// Reading a series' history failed; the backfill goes on with the next series.
+    #[error("backfill of {series} failed: {source}")]
+    History {
+        series: String,
+        #[source]
+        source: ResponseError,
+    },
+    #[error(transparent)]
+    Listen(#[from] ListenError),
+}
+
+/// A stream of a subscription's history followed by its live messages. Opened with
+/// [`SubscriptionsService::listen_with_backfill`](super::SubscriptionsService::listen_with_backfill).
+pub struct BackfillListener {
+    handle: ListenerHandle,
+    stream: BoxStream<'static, Result<BackfillItem, BackfillError>>,
+}
+
+impl BackfillListener {
+    pub(crate) fn new(
+        api_service: Weak<ApiService>,
+        series: Vec<IdAndExtId>,
+        since: DateTime<Utc>,
+        listener: SubscriptionListener,
+    ) -> Self {
+        let handle = listener.handle();
+        let backfill = Backfill {
+            api_service,
+            series: series.into(),
+            page: None,
+            since,
+            listener,
+            tails: HashMap::new(),
+        };
+        let stream = futures::stream::unfold(backfill, |mut backfill| async move {
+            let item = backfill.next().await?;
+            Some((item, backfill))
+        })
+        .boxed();
+        BackfillListener { handle, stream }
+    }
+
+    /// The next page of history or live message, or `None` once the listener has stopped.
+    pub async fn next(&mut self) -> Option<Result<BackfillItem, BackfillError>> {
+        self.stream.next().await
+    }
+
+    /// A handle to the live listener, to ack, nack and change the subscription set.
+    pub fn handle(&self) -> ListenerHandle {
+        self.handle.clone()
+    }
+
+    /// Close the live listener.
+    pub async fn close(self) -> Result<(), ListenError> {
+        self.handle.close().await
+    }
+}
+
+impl Stream for BackfillListener {
+    type Item = Result<BackfillItem, BackfillError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+struct Backfill {
+    api_service: Weak<ApiService>,
+    // Series still to replay, and the next page of the one being replayed.
+    series: VecDeque<IdAndExtId>,
+    page: Option<RetrieveFilter>,
+    since: DateTime<Utc>,
+    listener: SubscriptionListener,
+    // Per series: the replayed points (timestamp in ms -> value) within OVERLAP_WINDOW of its
+    // newest one.
+    tails: HashMap<String, BTreeMap<i64, LiveValue>>,
+}
+
+/// A point of history as the backend returns it: numeric series have numbers, string series text.
+#[derive(Deserialize)]
+struct HistoryPoint {
+    timestamp: DateTime<Utc>,
+    #[serde(default)]
+    value: Option<serde_json::Value>,
+}
+
+impl Backfill {
+    async fn next(&mut self) -> Option<Result<BackfillItem, BackfillError>> {
+        if let Some(history) = self.next_history().await {
+            return Some(history);
+        }
+        loop {
+            let message = match self.listener.next().await? {
+                Ok(message) => message,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if self.replayed(&message) {
+                // A failed ack only means another redelivery, which is skipped again.
+                let _ = self.listener.ack(&[&message.message_id]).await;
+                continue;
+            }
+            return Some(Ok(BackfillItem::Live(message)));
+        }
+    }
+
+    /// The next non-empty page of history, following cursors and then the next series.
+    async fn next_history(&mut self) -> Option<Result<BackfillItem, BackfillError>> {
+        loop {
+            let filter = match self.page.take() {
+                Some(filter) => filter,
+                None => {
+                    let series = self.series.pop_front()?;
+                    RetrieveFilter {
+                        start: Some(self.since),
+                        id: series.id,
+                        external_id: series.external_id,
+                        ..Default::default()
+                    }
+                }
+            };
+            let Some(service) = self.api_service.upgrade() else {
+                self.series.clear();
+                return Some(Err(ListenError::Request(
+                    "api service has been dropped".to_string(),
+                )
+                .into()));
+            };
+            let request = DataWrapper::from_vec(vec![filter.clone()]);
+            let page = match service
+                .time_series
+                .retrieve_datapoints_as::<HistoryPoint>(&request)
+                .await
+            {
+                Ok(page) => page,
+                Err(source) => {
+                    return Some(Err(BackfillError::History {
+                        series: filter_name(&filter),
+                        source,
+                    }))
+                }
+            };
+            let Some(collection) = page.get_items().first() else {
+                continue;
+            };
+            if let Some(cursor) = &collection.next_cursor {
+                if !collection.datapoints.is_empty() {
+                    self.page = Some(RetrieveFilter {
+                        cursor: Some(cursor.clone()),
+                        ..filter
+                    });
+                }
+            }
+            if collection.datapoints.is_empty() {
+                continue;
+            }
+            return Some(Ok(BackfillItem::History(self.replay(collection))));
+        }
+    }
+
+    /// Convert a page for the caller, remembering the tail of its series.
+    fn replay(&mut self, collection: &DatapointsCollection<HistoryPoint>) -> SeriesDatapoints {
+        let datapoints: Vec<LiveDatapoint> = collection
+            .datapoints
+            .iter()
+            .filter_map(|p| {
+                let value = match p.value.as_ref()? {
+                    serde_json::Value::Number(n) => LiveValue::Number(n.as_f64()?),
+                    serde_json::Value::String(s) => LiveValue::Text(s.clone()),
+                    _ => return None,
+                };
+                Some(LiveDatapoint {
+                    timestamp: p.timestamp,
+                    value,
+                })
+            })
+            .collect();
+        if let Some(name) = series_name(collection.id, collection.external_id.as_deref()) {
+            let tail = self.tails.entry(name).or_default();
+            tail.extend(
+                datapoints
+                    .iter()
+                    .map(|p| (p.timestamp.timestamp_millis(), p.value.clone())),
+            );
+            if let Some((&newest, _)) = tail.last_key_value() {
+                let from = newest - OVERLAP_WINDOW.as_millis() as i64;
+                *tail = tail.split_off(&from);
+            }
+        }
+        SeriesDatapoints {
+            id: collection.id,
+            external_id: collection.external_id.clone(),
+            value_type: None,
+            datapoints,
+            inclusive_begin: None,
+            exclusive_end: None,
+        }
+    }
+
+    /// Whether `message` creates only datapoints the history already replayed.
+    fn replayed(&self, message: &SubscriptionMessage) -> bool {
+        let Ok(SubscriptionPayload::Datapoints(change)) = message.decode() else {
+            return false;
+        };
+        if change.action != EventAction::Create {
+            return false;
+        }
+        let mut points = change.series.iter().flat_map(|series| {
+            let tail = series_name(series.id, series.external_id.as_deref())
+                .and_then(|name| self.tails.get(&name));
+            series.datapoints.iter().map(move |p| (tail, p))
+        });
+        let mut any = false;
+        let all = points.all(|(tail, p)| {
+            any = true;
+            tail.and_then(|t| t.get(&p.timestamp.timestamp_millis()))
+                .is_some_and(|replayed| same_value(replayed, &p.value))
+        });
+        any && all
+    }
+}
+
+/// Whether a replayed value and a live one are the same; a live message may carry a string
+/// series' numeric-looking value as a number.
+fn same_value(replayed: &LiveValue, live: &LiveValue) -> bool {
+    match (replayed, live) {
+        (LiveValue::Number(a), LiveValue::Number(b)) => a == b,
+        (LiveValue::Text(a), LiveValue::Text(b)) => a == b,
+        (LiveValue::Text(t), LiveValue::Number(n)) | (LiveValue::Number(n), LiveValue::Text(t)) => {
+            t.trim().parse::<f64>() == Ok(*n)
+        }
+    }
+}
+
+/// The key a series' tail is kept under: its external id, or else `#id`.
+fn series_name(id: Option<u64>, external_id: Option<&str>) -> Option<String> {
+    match (external_id, id) {
+        (Some(external_id), _) => Some(external_id.to_string()),
+        (None, Some(id)) => Some(format!("#{}", id)),
+        (None, None) => None,
+    }
+}
+
+fn filter_name(filter: &RetrieveFilter) -> String {
+    series_name(filter.id, filter.external_id.as_deref()).unwrap_or_else(|| "<unnamed>".into())
+}
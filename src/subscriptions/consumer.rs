@@ -0,0 +1,416 @@
+//! A handler-driven consumer on top of [`SubscriptionListener`].
+//!
+//! [`SubscriptionConsumer::run`] calls an async handler for every message, a bounded number at a
+//! time, while the listener keeps the socket polled. A message whose handler succeeds is acked;
+//! acks are batched and sent on a timer. A failed handler nacks its message so Pulsar redelivers
+//! it, and once a message has failed `max_attempts` times it is handed to the
+//! [`DeadLetterSink`], if one is set, and acked. Without a sink it keeps being redelivered.
+//!
+//! With [`DeliveryOrder::PerSeries`], messages for the same series are handled one at a time in
+//! the order they arrived; messages for different series still run concurrently. A message is
+//! keyed by its first item's external id (or id). A nack sends a message to the back of Pulsar's
+//! queue, so a failure can still reorder a series.
+
+use super::listen::{ListenError, ListenerHandle, SubscriptionListener, SubscriptionMessage};
+use super::sink::SubscriptionSink;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::{Id, JoinError, JoinSet};
+
+const DEFAULT_CONCURRENCY: usize = 16;
+const DEFAULT_ACK_INTERVAL: Duration = Duration::from_secs(1);
+// Acks are also sent as soon as this many are waiting.
+const MAX_ACK_BATCH: usize = 500;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Whether messages may be handled out of the order they arrived in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeliveryOrder {
+    #[default]
+    Unordered,
+    /// One message at a time per series, in arrival order.
+    PerSeries,
+}
+
+/// A message whose handler failed `attempts` times in a row, the last time with `error`.
+#[derive(Debug, Clone)]
+pub struct FailedMessage {
+    pub message: SubscriptionMessage,
+    pub attempts: u32,
+    pub error: String,
+}
+
+/// Where a [`SubscriptionConsumer`] puts messages that keep failing. If `dead_letter` fails, the
+/// message is nacked instead and tried again.
+pub trait DeadLetterSink: Send + Sync {
+    fn dead_letter(&self, failed: FailedMessage) -> io::Result<()>;
+}
+
+impl<F> DeadLetterSink for F
+where
+    F: Fn(FailedMessage) -> io::Result<()> + Send + Sync,
+{
+    fn dead_letter(&self, failed: FailedMessage) -> io::Result<()> {
+        self(failed)
+    }
+}
+
+/// What a [`SubscriptionConsumer`] did before it stopped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConsumerStats {
+    /// Messages whose handler succeeded.
+    pub handled: u64,
+    /// Handler failures, counting every attempt.
+    pub failed: u64,
+    /// Messages handed to the dead-letter sink.
+    pub dead_lettered: u64,
+}
+
+type ErrorHandler = dyn Fn(&ListenError) + Send + Sync;
+
+/// Runs an async handler over a listener's messages; see the [module docs](self).
+pub struct SubscriptionConsumer {
+    listener: SubscriptionListener,
+    concurrency: usize,
+    order: DeliveryOrder,
+    ack_interval: Duration,
+    max_attempts: u32,
+    dead_letter: Option<Arc<dyn DeadLetterSink>>,
+    on_error: Arc<ErrorHandler>,
+}
+
+impl SubscriptionConsumer {
+    pub fn new(listener: SubscriptionListener) -> Self {
+        SubscriptionConsumer {
+            listener,
+            concurrency: DEFAULT_CONCURRENCY,
+            order: DeliveryOrder::Unordered,
+            ack_interval: DEFAULT_ACK_INTERVAL,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            dead_letter: None,
+            on_error: Arc::new(|e: &ListenError| eprintln!("subscription consumer: {}", e)),
+        }
+    }
+
+    /// How many handlers may run at once (default 16). Messages waiting for their series under
+    /// [`DeliveryOrder::PerSeries`] count towards it.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn with_order(mut self, order: DeliveryOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// How often collected acks are sent (default 1 s).
+    pub fn with_ack_interval(mut self, interval: Duration) -> Self {
+        self.ack_interval = interval;
+        self
+    }
+
+    /// How many times a message may fail before it is dead-lettered (default 5).
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    pub fn with_dead_letter(mut self, sink: impl DeadLetterSink + 'static) -> Self {
+        self.dead_letter = Some(Arc::new(sink));
+        self
+    }
+
+    /// Called with errors the listener yields (a refused subscription, a failed reconnect), acks
+    /// or nacks that could not be sent, and dead letters the sink refused. By default they are
+    /// printed to stderr.
+    pub fn with_error_handler(mut self, f: impl Fn(&ListenError) + Send + Sync + 'static) -> Self {
+        self.on_error = Arc::new(f);
+        self
+    }
+
+    /// A handle to the listener, e.g. to change subscriptions while the consumer runs. Closing it
+    /// stops the consumer too, but acks not sent yet are lost and those messages are redelivered;
+    /// [`run_until`](Self::run_until) stops cleanly.
+    pub fn handle(&self) -> ListenerHandle {
+        self.listener.handle()
+    }
+
+    /// Handle messages until the listener closes. Returns once the running handlers have finished
+    /// and their acks are sent.
+    pub async fn run<F, Fut, E>(self, handler: F) -> ConsumerStats
+    where
+        F: Fn(SubscriptionMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        self.run_until(handler, std::future::pending()).await
+    }
+
+    /// Forward every message to `sink` until the listener closes. A message is acked once the sink
+    /// has stored it; see the [`sink`](super::sink) module.
+    pub async fn run_sink(self, sink: impl SubscriptionSink) -> ConsumerStats {
+        self.run_sink_until(sink, std::future::pending()).await
+    }
+
+    /// As [`run_sink`](Self::run_sink), stopping as [`run_until`](Self::run_until) does.
+    pub async fn run_sink_until(
+        self,
+        sink: impl SubscriptionSink,
+        shutdown: impl Future<Output = ()>,
+    ) -> ConsumerStats {
+        let sink = Arc::new(sink);
+        let handler = move |message: SubscriptionMessage| {
+            let sink = sink.clone();
+            async move { sink.send(&message).await }
+        };
+        self.run_until(handler, shutdown).await
+    }
+
+    /// As [`run`](Self::run), but stop taking messages when `shutdown` completes: messages waiting
+    /// for their series are nacked, running handlers finish and are acked, and the listener is
+    /// closed.
+    pub async fn run_until<F, Fut, E>(
+        self,
+        handler: F,
+        shutdown: impl Future<Output = ()>,
+    ) -> ConsumerStats
+    where
+        F: Fn(SubscriptionMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        let (mut receiver, handle) = self.listener.split();
+        let handler = Arc::new(handler);
+        let mut run = Run {
+            order: self.order,
+            max_attempts: self.max_attempts,
+            dead_letter: self.dead_letter,
+            on_error: self.on_error,
+            handle,
+            running: JoinSet::new(),
+            in_flight: HashMap::new(),
+            busy: HashSet::new(),
+            waiting: HashMap::new(),
+            queued: 0,
+            attempts: HashMap::new(),
+            acks: Vec::new(),
+            stats: ConsumerStats::default(),
+        };
+        let mut ticker = tokio::time::interval(self.ack_interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        tokio::pin!(shutdown);
+        let mut open = true;
+        while open || run.outstanding() > 0 {
+            let step = tokio::select! {
+                _ = &mut shutdown, if open => Step::Shutdown,
+                message = receiver.next(), if open && run.outstanding() < self.concurrency => {
+                    Step::Message(message)
+                }
+                Some(done) = run.running.join_next_with_id() => Step::Done(done),
+                _ = ticker.tick() => Step::Tick,
+            };
+            match step {
+                Step::Shutdown => {
+                    open = false;
+                    run.release_waiting().await;
+                }
+                Step::Message(Some(Ok(message))) => run.dispatch(message, &handler),
+                Step::Message(Some(Err(e))) => (run.on_error)(&e),
+                // The listener was closed.
+                Step::Message(None) => open = false,
+                Step::Done(done) => run.complete(done, &handler).await,
+                Step::Tick => run.flush_acks().await,
+            }
+            if run.acks.len() >= MAX_ACK_BATCH {
+                run.flush_acks().await;
+            }
+        }
+        run.flush_acks().await;
+        drop(receiver);
+        let _ = run.handle.close().await;
+        run.stats
+    }
+}
+
+enum Step {
+    Shutdown,
+    Message(Option<Result<SubscriptionMessage, ListenError>>),
+    Done(Result<(Id, Result<(), String>), JoinError>),
+    Tick,
+}
+
+/// The state of one [`SubscriptionConsumer::run_until`].
+struct Run {
+    order: DeliveryOrder,
+    max_attempts: u32,
+    dead_letter: Option<Arc<dyn DeadLetterSink>>,
+    on_error: Arc<ErrorHandler>,
+    handle: ListenerHandle,
+    running: JoinSet<Result<(), String>>,
+    // The message each running handler was given, so a panicked one can still be nacked.
+    in_flight: HashMap<Id, SubscriptionMessage>,
+    // Series with a running handler, and the messages queued behind it (PerSeries only).
+    busy: HashSet<String>,
+    waiting: HashMap<String, VecDeque<SubscriptionMessage>>,
+    queued: usize,
+    // Failures so far per message id, forgotten once the message is acked.
+    attempts: HashMap<String, u32>,
+    acks: Vec<String>,
+    stats: ConsumerStats,
+}
+
+impl Run {
+    fn outstanding(&self) -> usize {
+        self.in_flight.len() + self.queued
+    }
+
+    fn dispatch<F, Fut, E>(&mut self, message: SubscriptionMessage, handler: &Arc<F>)
+    where
+        F: Fn(SubscriptionMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        if let Some(key) = self.series_key(&message) {
+            if !self.busy.insert(key.clone()) {
+                self.waiting.entry(key).or_default().push_back(message);
+                self.queued += 1;
+                return;
+            }
+        }
+        self.spawn(message, handler);
+    }
+
+    fn spawn<F, Fut, E>(&mut self, message: SubscriptionMessage, handler: &Arc<F>)
+    where
+        F: Fn(SubscriptionMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        let handler = handler.clone();
+        let given = message.clone();
+        let task = self
+            .running
+            .spawn(async move { handler(given).await.map_err(|e| e.to_string()) });
+        self.in_flight.insert(task.id(), message);
+    }
+
+    async fn complete<F, Fut, E>(
+        &mut self,
+        done: Result<(Id, Result<(), String>), JoinError>,
+        handler: &Arc<F>,
+    ) where
+        F: Fn(SubscriptionMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        let (id, outcome) = match done {
+            Ok((id, outcome)) => (id, outcome),
+            Err(e) if e.is_panic() => (e.id(), Err("handler panicked".to_string())),
+            Err(e) => (e.id(), Err("handler was cancelled".to_string())),
+        };
+        let Some(message) = self.in_flight.remove(&id) else {
+            return;
+        };
+        // Start the next message of this series, if one is waiting.
+        if let Some(key) = self.series_key(&message) {
+            match self.waiting.get_mut(&key).and_then(VecDeque::pop_front) {
+                Some(next) => {
+                    self.queued -= 1;
+                    self.spawn(next, handler);
+                }
+                None => {
+                    self.waiting.remove(&key);
+                    self.busy.remove(&key);
+                }
+            }
+        }
+        let message_id = message.message_id.clone();
+        let error = match outcome {
+            Ok(()) => {
+                self.stats.handled += 1;
+                self.attempts.remove(&message_id);
+                self.acks.push(message_id);
+                return;
+            }
+            Err(error) => error,
+        };
+        self.stats.failed += 1;
+        let attempts = {
+            let attempts = self.attempts.entry(message_id.clone()).or_insert(0);
+            *attempts += 1;
+            *attempts
+        };
+        if let Some(sink) = self
+            .dead_letter
+            .as_ref()
+            .filter(|_| attempts >= self.max_attempts)
+        {
+            let failed = FailedMessage {
+                message,
+                attempts,
+                error,
+            };
+            match sink.dead_letter(failed) {
+                Ok(()) => {
+                    self.stats.dead_lettered += 1;
+                    self.attempts.remove(&message_id);
+                    self.acks.push(message_id);
+                    return;
+                }
+                Err(e) => (self.on_error)(&ListenError::DeadLetter {
+                    message_id: message_id.clone(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        if let Err(e) = self.handle.nack(&[message_id]).await {
+            (self.on_error)(&e);
+        }
+    }
+
+    /// Nack the messages that were waiting for their series; they are redelivered later.
+    async fn release_waiting(&mut self) {
+        let ids: Vec<String> = self
+            .waiting
+            .drain()
+            .flat_map(|(_, queue)| queue)
+            .map(|m| m.message_id)
+            .collect();
+        self.queued = 0;
+        if !ids.is_empty() {
+            if let Err(e) = self.handle.nack(&ids).await {
+                (self.on_error)(&e);
+            }
+        }
+    }
+
+    async fn flush_acks(&mut self) {
+        if self.acks.is_empty() {
+            return;
+        }
+        let acks = std::mem::take(&mut self.acks);
+        if let Err(e) = self.handle.ack(&acks).await {
+            // Unacked messages are redelivered; nothing is lost.
+            (self.on_error)(&e);
+        }
+    }
+
+    fn series_key(&self, message: &SubscriptionMessage) -> Option<String> {
+        if self.order != DeliveryOrder::PerSeries {
+            return None;
+        }
+        let item = message.payload.raw_items().first()?;
+        match (item.get("externalId"), item.get("id")) {
+            (Some(serde_json::Value::String(ext)), _) => Some(ext.clone()),
+            // Ids arrive as numbers or numeric strings; either names the same series.
+            (_, Some(serde_json::Value::String(id))) => Some(format!("#{}", id)),
+            (_, Some(serde_json::Value::Number(id))) => Some(format!("#{}", id)),
+            _ => None,
+        }
+    }
+}
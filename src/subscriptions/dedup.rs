@@ -0,0 +1,278 @@
+//! Turning at-least-once delivery into effectively-once processing.
+//!
+//! The backend redelivers every message that wasn't acked when a connection dropped, so a handler
+//! can see a message it already processed. A listener opened with
+//! [`ListenOptions::with_dedup`](super::ListenOptions::with_dedup) records what was processed in a
+//! [`DedupStore`] when it is acked, and acks a redelivery of it without handing it out again.
+//!
+//! A message counts as processed by its `message_id`. A datapoints message also counts as
+//! processed when every one of its `(series, timestamp)` points was, which catches the same points
+//! arriving under a new message id. A message only some of whose points were processed is
+//! delivered whole.
+//!
+//! [`MemoryDedupStore`] remembers the most recently used keys, up to a capacity.
+//! [`FileDedupStore`] does the same and also appends every key to a file, so it survives restarts.
+//! Keys name series and message ids, so the file can be encrypted like the ingest spools (see
+//! [`crate::encryption`]); [`SubscriptionsService::dedup_store`](super::SubscriptionsService::dedup_store)
+//! opens one with the client's buffer encryption.
+
+use super::listen::SubscriptionMessage;
+use super::payload::SubscriptionPayload;
+use crate::encryption::{self, KeyProvider, SpoolCipher};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Encrypted keys are sealed as spool records stamped with this time.
+const SEALED_KEY_TIMESTAMP: i64 = 0;
+
+/// Where a listener records processed messages. Keys are opaque strings.
+pub trait DedupStore: Send {
+    /// Whether `key` has been recorded.
+    fn contains(&mut self, key: &str) -> io::Result<bool>;
+    /// Record `key` as processed.
+    fn insert(&mut self, key: &str) -> io::Result<()>;
+}
+
+/// An in-memory store of the `capacity` most recently used keys.
+#[derive(Debug)]
+pub struct MemoryDedupStore {
+    capacity: usize,
+    // Key -> last use; last use -> key, oldest first.
+    uses: HashMap<String, u64>,
+    order: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl MemoryDedupStore {
+    pub fn new(capacity: usize) -> Self {
+        MemoryDedupStore {
+            capacity: capacity.max(1),
+            uses: HashMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.uses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.uses.is_empty()
+    }
+
+    fn touch(&mut self, key: &str) -> bool {
+        self.clock += 1;
+        match self.uses.get_mut(key) {
+            Some(used) => {
+                let key = self.order.remove(used).expect("order tracks every key");
+                *used = self.clock;
+                self.order.insert(self.clock, key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn record(&mut self, key: &str) {
+        if self.touch(key) {
+            return;
+        }
+        if self.uses.len() == self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.uses.remove(&oldest);
+            }
+        }
+        self.uses.insert(key.to_string(), self.clock);
+        self.order.insert(self.clock, key.to_string());
+    }
+
+    /// The keys, least recently used first.
+    fn keys(&self) -> impl Iterator<Item = &str> {
+        self.order.values().map(String::as_str)
+    }
+}
+
+impl DedupStore for MemoryDedupStore {
+    fn contains(&mut self, key: &str) -> io::Result<bool> {
+        Ok(self.touch(key))
+    }
+
+    fn insert(&mut self, key: &str) -> io::Result<()> {
+        self.record(key);
+        Ok(())
+    }
+}
+
+/// A [`MemoryDedupStore`] backed by a file, one key per line. Opening it reloads the keys, so
+/// duplicates are caught across restarts. Each key is written through to the OS as it is recorded
+/// (not fsynced). The file is rewritten with only the remembered keys once it holds twice the
+/// capacity.
+pub struct FileDedupStore {
+    path: PathBuf,
+    memory: MemoryDedupStore,
+    file: BufWriter<File>,
+    lines: usize,
+    cipher: Option<SpoolCipher>,
+}
+
+impl fmt::Debug for FileDedupStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileDedupStore")
+            .field("path", &self.path)
+            .field("memory", &self.memory)
+            .field("lines", &self.lines)
+            .field("encrypted", &self.cipher.is_some())
+            .finish()
+    }
+}
+
+impl FileDedupStore {
+    pub fn open(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        Self::open_with(path.as_ref(), capacity, None)
+    }
+
+    /// [`open`](Self::open), writing each key sealed with `keys`' current key, as the spools do
+    /// with `DataHubConfig::set_buffer_encryption`. Keys written in plain text before are still
+    /// read; one sealed with a key `keys` no longer has fails the open.
+    pub fn open_encrypted(
+        path: impl AsRef<Path>,
+        capacity: usize,
+        keys: Arc<dyn KeyProvider>,
+    ) -> io::Result<Self> {
+        Self::open_with(path.as_ref(), capacity, Some(SpoolCipher::new(keys)))
+    }
+
+    fn open_with(path: &Path, capacity: usize, cipher: Option<SpoolCipher>) -> io::Result<Self> {
+        let path = path.to_path_buf();
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut memory = MemoryDedupStore::new(capacity);
+        let mut lines = 0;
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if !line.is_empty() {
+                        let key = encryption::open(cipher.as_ref(), SEALED_KEY_TIMESTAMP, &line)?;
+                        memory.record(&key);
+                        lines += 1;
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut store = FileDedupStore {
+            path,
+            memory,
+            file: BufWriter::new(file),
+            lines,
+            cipher,
+        };
+        if store.lines > store.memory.capacity * 2 {
+            store.compact()?;
+        }
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `key` as it is stored: sealed if the store is encrypted.
+    fn stored(&self, key: &str) -> io::Result<String> {
+        match &self.cipher {
+            Some(cipher) => cipher.seal(SEALED_KEY_TIMESTAMP, key),
+            None => Ok(key.to_string()),
+        }
+    }
+
+    /// Rewrite the file with the remembered keys (temp file + rename).
+    fn compact(&mut self) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            for key in self.memory.keys() {
+                writeln!(out, "{}", self.stored(key)?)?;
+            }
+            out.flush()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.lines = self.memory.len();
+        Ok(())
+    }
+}
+
+impl DedupStore for FileDedupStore {
+    fn contains(&mut self, key: &str) -> io::Result<bool> {
+        self.memory.contains(key)
+    }
+
+    fn insert(&mut self, key: &str) -> io::Result<()> {
+        // Keys are written one per line, so a line break would split one into two.
+        let key = key.replace(['\n', '\r'], " ");
+        let line = self.stored(&key)?;
+        self.memory.record(&key);
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        self.lines += 1;
+        if self.lines > self.memory.capacity * 2 {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+/// The keys that mark `message` as processed: its message id, then one per datapoint of a
+/// datapoints message.
+pub(crate) fn dedup_keys(message: &SubscriptionMessage) -> (String, Vec<String>) {
+    let id_key = format!("m:{}", message.message_id);
+    let mut point_keys = Vec::new();
+    if let Ok(SubscriptionPayload::Datapoints(change)) = message.decode() {
+        for series in &change.series {
+            let name = match (&series.external_id, series.id) {
+                (Some(external_id), _) => external_id.clone(),
+                (None, Some(id)) => format!("#{}", id),
+                (None, None) => continue,
+            };
+            for point in &series.datapoints {
+                point_keys.push(format!(
+                    "d:{:?}:{}@{}",
+                    change.action,
+                    name,
+                    point.timestamp.timestamp_millis()
+                ));
+            }
+        }
+    }
+    (id_key, point_keys)
+}
+
+/// Whether `message` (keyed by [`dedup_keys`]) was processed before.
+pub(crate) fn is_processed(
+    store: &mut dyn DedupStore,
+    id_key: &str,
+    point_keys: &[String],
+) -> io::Result<bool> {
+    if store.contains(id_key)? {
+        return Ok(true);
+    }
+    if point_keys.is_empty() {
+        return Ok(false);
+    }
+    for key in point_keys {
+        if !store.contains(key)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -1,18 +1,27 @@
-use futures::{SinkExt, StreamExt};
+use futures::{SinkExt, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 use thiserror::Error;
 use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use tokio_tungstenite::tungstenite::http;
-use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::{Error as TungsteniteError, Message};
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 
+use super::dedup::{dedup_keys, is_processed, DedupStore};
 use crate::ApiService;
 use std::sync::Weak;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum ListenError {
     #[error("failed to build request: {0}")]
     Request(String),
@@ -26,13 +35,31 @@ pub enum ListenError {
     Serialize(#[from] serde_json::Error),
     #[error("subscription '{external_id}' error: {reason}")]
     Subscription { external_id: String, reason: String },
+    #[error("listener is closed")]
+    Closed,
+    /// The connection is down and being re-established; nothing was sent. An ack is recorded by
+    /// the dedup store all the same, and an interest change is replayed on reconnect.
+    #[error("listener is disconnected")]
+    Disconnected,
+    /// The dedup store failed (see [`ListenOptions::with_dedup`]). The message was delivered
+    /// unscreened, or its ack was not recorded; the listener goes on.
+    #[error("dedup store error: {0}")]
+    Dedup(String),
+    /// A consumer's [`DeadLetterSink`](super::DeadLetterSink) failed to take a message; it was
+    /// nacked instead and will be tried again.
+    #[error("dead-lettering message {message_id} failed: {reason}")]
+    DeadLetter { message_id: String, reason: String },
+    /// No message arrived in time (blocking listener only); the listener is still open.
+    #[error("no message within {0:?}")]
+    Timeout(Duration),
 }
 
 /// One message delivered by the backend. Carries the opaque `message_id` the client must
-/// echo back via [`SubscriptionListener::ack`] / [`SubscriptionListener::nack`], and the
+/// echo back via [`SubscriptionListener::ack`] / [`SubscriptionListener::nack`] (or a
+/// [`ListenerHandle`]), and the
 /// `subscription_external_id` it was delivered for (set from the frame — useful when one
 /// listener multiplexes several subscriptions).
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SubscriptionMessage {
     #[serde(rename = "subscriptionExternalId", default)]
     pub subscription_external_id: String,
@@ -43,14 +70,89 @@ pub struct SubscriptionMessage {
 
 /// Envelope the backend's Pulsar consumer wraps around every fan-out event. Mirrors the
 /// `DataWrapperMessage` Avro schema on the backend.
+///
+/// `items` reads each item as a datapoint collection, which only fits `DATAPOINTS` payloads; items
+/// that don't fit are left out. [`decode`](Self::decode) reads `raw_items` by `event_object`
+/// instead. `raw_items` is what gets serialized; `items` only when `raw_items` is empty. Build one
+/// with [`new`](Self::new).
 #[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[serde(from = "WireDataWrapperMessage", into = "WireDataWrapperMessage")]
+#[non_exhaustive]
 pub struct DataWrapperMessage {
     pub event_action: EventAction,
     pub event_object: EventObject,
-    #[serde(default)]
     pub items: Vec<DataCollectionString>,
     pub tenant_id: Option<String>,
+    /// The items exactly as the backend sent them.
+    pub raw_items: Vec<serde_json::Value>,
+}
+
+impl DataWrapperMessage {
+    /// A message carrying `raw_items`; `items` holds those that read as datapoint collections.
+    pub fn new(
+        event_action: EventAction,
+        event_object: EventObject,
+        raw_items: Vec<serde_json::Value>,
+        tenant_id: Option<String>,
+    ) -> Self {
+        WireDataWrapperMessage {
+            event_action,
+            event_object,
+            items: raw_items,
+            tenant_id,
+        }
+        .into()
+    }
+
+    /// The items exactly as the backend sent them.
+    pub fn raw_items(&self) -> &[serde_json::Value] {
+        &self.raw_items
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WireDataWrapperMessage {
+    event_action: EventAction,
+    event_object: EventObject,
+    #[serde(default)]
+    items: Vec<serde_json::Value>,
+    tenant_id: Option<String>,
+}
+
+impl From<WireDataWrapperMessage> for DataWrapperMessage {
+    fn from(wire: WireDataWrapperMessage) -> Self {
+        DataWrapperMessage {
+            event_action: wire.event_action,
+            event_object: wire.event_object,
+            items: wire
+                .items
+                .iter()
+                .filter_map(|item| DataCollectionString::deserialize(item).ok())
+                .collect(),
+            tenant_id: wire.tenant_id,
+            raw_items: wire.items,
+        }
+    }
+}
+
+impl From<DataWrapperMessage> for WireDataWrapperMessage {
+    fn from(message: DataWrapperMessage) -> Self {
+        let items = match message.raw_items.is_empty() {
+            true => message
+                .items
+                .iter()
+                .filter_map(|item| serde_json::to_value(item).ok())
+                .collect(),
+            false => message.raw_items,
+        };
+        WireDataWrapperMessage {
+            event_action: message.event_action,
+            event_object: message.event_object,
+            items,
+            tenant_id: message.tenant_id,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -173,237 +275,921 @@ pub(crate) fn decode_text_frame(text: &str) -> Result<DecodedFrame, ListenError>
     })
 }
 
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// What a listener yields: a message, or an error the caller should see (a subscription the server
+/// refused, an undecodable frame, a reconnect that ultimately failed).
+type ListenItem = Result<SubscriptionMessage, ListenError>;
+
 /// Live WebSocket listener multiplexing one or more subscriptions' fan-out topics.
 ///
-/// Drive it by calling [`SubscriptionListener::next`] in a loop; ack processed messages with
-/// [`SubscriptionListener::ack`]; anything left unacked at close is redelivered by Pulsar on
-/// the next listener to connect with the same subscription external id. Add or drop subscriptions
-/// at runtime with [`SubscriptionListener::subscribe`] / [`SubscriptionListener::unsubscribe`] /
-/// [`SubscriptionListener::set_subscriptions`]. Each [`SubscriptionMessage`] carries the
-/// `subscription_external_id` it belongs to.
+/// Consume it as a [`Stream`] of messages (or with the inherent [`SubscriptionListener::next`]);
+/// ack processed messages with [`SubscriptionListener::ack`]; anything left unacked at close is
+/// redelivered by Pulsar on the next listener to connect with the same subscription external id.
+/// Add or drop subscriptions at runtime with [`SubscriptionListener::subscribe`] /
+/// [`SubscriptionListener::unsubscribe`] / [`SubscriptionListener::set_subscriptions`]. Each
+/// [`SubscriptionMessage`] carries the `subscription_external_id` it belongs to.
 ///
-/// The server pings every 15s and closes idle sessions after ~45s. `next` transparently
-/// handles incoming pings, but only while it is being polled — call it often enough that a
-/// pong can be flushed back. Heavy per-message work should run on another task with messages
-/// fanned out through a channel.
+/// The socket is owned by a background task, so it keeps answering the server's pings (every 15s;
+/// idle sessions are closed after ~45s) while the caller is busy. [`split`](Self::split) separates
+/// the stream of messages from a cloneable [`ListenerHandle`], so processing tasks can ack, nack and
+/// change subscriptions without owning the listener. Decoded messages wait in a bounded queue; when
+/// the consumer falls that far behind, the task stops reading and the server is pushed back on.
+/// [`SubscriptionConsumer`](super::SubscriptionConsumer) runs a handler per message on top of this,
+/// with bounded concurrency, batched acks and retries.
 ///
-/// If the connection drops (network blip, server restart, idle-close), `next` re-establishes it —
+/// If the connection drops (network blip, server restart, idle-close), the task re-establishes it —
 /// fetching a fresh token and replaying the current interest set, with exponential backoff — so a
-/// transient outage is invisible to the caller. Pulsar redelivers anything left unacked.
+/// transient outage is invisible to the caller. Pulsar redelivers anything left unacked. If the
+/// reconnect ultimately fails, the error is yielded and the attempts resume once it has been taken.
+/// The stream ends after [`close`](Self::close), or once every handle and the stream are dropped.
 pub struct SubscriptionListener {
-    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
-    buffered: VecDeque<SubscriptionMessage>,
-    // Context for transparent reconnects: a fresh token is fetched and the URL is rebuilt from the
-    // current interest set on each attempt, so a dropped connection (network blip, server restart)
-    // is re-established and the same subscriptions resume — without the caller noticing.
-    api_service: Weak<ApiService>,
-    host_base_url: String,
-    interest: Vec<String>,
+    receiver: SubscriptionReceiver,
+    handle: ListenerHandle,
+}
+
+/// The receive half of a [`SubscriptionListener`]: a [`Stream`] of its messages. Dropping it stops
+/// the listener.
+pub struct SubscriptionReceiver {
+    messages: mpsc::Receiver<ListenItem>,
+}
+
+/// The control half of a [`SubscriptionListener`]: ack, nack and change subscriptions from any task.
+/// Cheap to clone; calls fail with [`ListenError::Closed`] once the listener has stopped.
+#[derive(Clone, Debug)]
+pub struct ListenerHandle {
+    commands: mpsc::UnboundedSender<Command>,
+    state: watch::Receiver<ConnectionState>,
+    counters: Arc<Counters>,
 }
 
-// Reconnect backoff: a brief blip recovers in well under a second; a longer outage backs off to 30s
-// and gives up after RECONNECT_MAX_RETRIES attempts, surfacing the error so the caller regains
-// control (it may call `next` again to keep trying, or `close`).
-const RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
-const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
-const RECONNECT_MAX_RETRIES: u32 = 8;
+/// How a listener retries a dropped connection: exponential backoff from `initial_backoff` up to
+/// `max_backoff`, for at most `max_retries` attempts (`None`: until it reconnects). When the
+/// attempts run out the listener yields the last error, reports [`ConnectionState::Failed`], and
+/// starts another round `max_backoff` later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub max_retries: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    // A brief blip recovers in well under a second; a longer outage backs off to 30s and gives up
+    // after 8 attempts, surfacing the error so the caller regains control.
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_retries: Some(8),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// The default backoff, retried until the connection is back.
+    pub fn unlimited() -> Self {
+        ReconnectPolicy {
+            max_retries: None,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    pub fn with_max_retries(mut self, retries: Option<u32>) -> Self {
+        self.max_retries = retries;
+        self
+    }
+}
+
+/// The state of a listener's connection, as reported by
+/// [`ListenerHandle::connection_state`] and [`ListenOptions::on_state_change`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The connection dropped; this is the `attempt`-th try (from 1) to re-establish it.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// A round of reconnect attempts ran out; see [`ReconnectPolicy`].
+    Failed,
+    /// The listener has stopped.
+    Closed,
+}
+
+/// Counters of a listener since it connected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListenerStats {
+    /// Connections re-established after a drop.
+    pub reconnects: u64,
+    /// Messages delivered again after this listener had already received them (nacked, or unacked
+    /// when the connection dropped).
+    pub redelivered: u64,
+    /// Redeliveries of processed messages, acked without being handed out (see
+    /// [`ListenOptions::with_dedup`]).
+    pub duplicates: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    reconnects: AtomicU64,
+    redelivered: AtomicU64,
+    duplicates: AtomicU64,
+}
+
+type StateCallback = dyn Fn(&ConnectionState) + Send + Sync;
+type SharedDedupStore = Arc<Mutex<dyn DedupStore>>;
+
+/// Options for [`SubscriptionsService::listen_with`](super::SubscriptionsService::listen_with).
+#[derive(Clone, Default)]
+pub struct ListenOptions {
+    reconnect: ReconnectPolicy,
+    on_state_change: Option<Arc<StateCallback>>,
+    dedup: Option<SharedDedupStore>,
+}
+
+impl ListenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    /// Called from the listener's task on every connection state change.
+    pub fn on_state_change(mut self, f: impl Fn(&ConnectionState) + Send + Sync + 'static) -> Self {
+        self.on_state_change = Some(Arc::new(f));
+        self
+    }
+
+    /// Skip messages that were already processed: acked messages are recorded in `store`, and a
+    /// redelivery of one is acked by the listener instead of being handed out. See
+    /// [`dedup`](super::dedup).
+    pub fn with_dedup(mut self, store: impl DedupStore + 'static) -> Self {
+        self.dedup = Some(Arc::new(Mutex::new(store)));
+        self
+    }
+}
+
+impl fmt::Debug for ListenOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ListenOptions")
+            .field("reconnect", &self.reconnect)
+            .field("on_state_change", &self.on_state_change.is_some())
+            .field("dedup", &self.dedup.is_some())
+            .finish()
+    }
+}
+
+/// A request from a [`ListenerHandle`] to the task that owns the socket.
+enum Command {
+    Send {
+        frame: String,
+        // Applied before the frame is sent, so a reconnect replays an interest change, and an ack
+        // is recorded as processed, even if the send itself fails.
+        effect: Option<Effect>,
+        reply: oneshot::Sender<Result<(), ListenError>>,
+    },
+    Close {
+        reply: oneshot::Sender<()>,
+    },
+}
+
+enum Effect {
+    Interest(Interest),
+    Acked(Vec<String>),
+    Nacked(Vec<String>),
+}
+
+enum Interest {
+    Add(Vec<String>),
+    Remove(Vec<String>),
+    Set(Vec<String>),
+}
+
+// Messages handed to the receiver but not yet taken, and decoded messages waiting for room there.
+// The task only reads the next frame while fewer than MAX_PENDING are waiting.
+const CHANNEL_CAPACITY: usize = 64;
+const MAX_PENDING: usize = 1024;
+
+// How many recently delivered message ids a listener remembers to count redeliveries.
+const RECENT_IDS: usize = 10_000;
+
+// Delivered messages whose dedup keys wait for an ack are forgotten after UNSETTLED_TTL (by then
+// the backend has redelivered them), and the oldest once MAX_UNSETTLED are waiting.
+const UNSETTLED_TTL: Duration = Duration::from_secs(30 * 60);
+const MAX_UNSETTLED: usize = 10_000;
 
 impl SubscriptionListener {
     pub(crate) async fn connect(
         api_service: Weak<ApiService>,
         host_base_url: String,
         interest: Vec<String>,
+        options: ListenOptions,
     ) -> Result<Self, ListenError> {
-        let ws = Self::open(&api_service, &host_base_url, &interest).await?;
-        Ok(SubscriptionListener {
+        let ws = open(&api_service, &host_base_url, &interest).await?;
+        let (messages_tx, messages) = mpsc::channel(CHANNEL_CAPACITY);
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let (state_tx, state) = watch::channel(ConnectionState::Connected);
+        let counters = Arc::new(Counters::default());
+        let driver = Driver {
             ws,
-            buffered: VecDeque::new(),
             api_service,
             host_base_url,
             interest,
+            messages: messages_tx,
+            commands: commands_rx,
+            handles_open: true,
+            pending: VecDeque::new(),
+            broken: false,
+            policy: options.reconnect,
+            state: state_tx,
+            on_state_change: options.on_state_change,
+            counters: counters.clone(),
+            recent: VecDeque::new(),
+            recent_ids: HashSet::new(),
+            dedup: options.dedup,
+            unsettled: HashMap::new(),
+        };
+        tokio::spawn(driver.run());
+        Ok(SubscriptionListener {
+            receiver: SubscriptionReceiver { messages },
+            handle: ListenerHandle {
+                commands,
+                state,
+                counters,
+            },
         })
     }
 
-    /// Fetch a fresh token, build the listen URL from the current interest set, and open the socket.
-    async fn open(
-        api_service: &Weak<ApiService>,
-        host_base_url: &str,
-        interest: &[String],
-    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, ListenError> {
-        let token = Self::fetch_token(api_service).await?;
-        let ws_url = build_ws_url(host_base_url, interest)?;
-        let mut request = ws_url
-            .into_client_request()
-            .map_err(|e| ListenError::Request(e.to_string()))?;
-        let header_value: http::HeaderValue = format!("Bearer {}", token)
-            .parse()
-            .map_err(|e: http::header::InvalidHeaderValue| ListenError::Request(e.to_string()))?;
-        request.headers_mut().insert("Authorization", header_value);
-
-        let (ws, _response) = connect_async(request)
-            .await
-            .map_err(|e| ListenError::Handshake(e.to_string()))?;
-        Ok(ws)
+    /// See [`ListenerHandle::connection_state`].
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.handle.connection_state()
     }
 
-    async fn fetch_token(api_service: &Weak<ApiService>) -> Result<String, ListenError> {
-        let service = api_service
-            .upgrade()
-            .ok_or_else(|| ListenError::Request("api service has been dropped".to_string()))?;
-        service
-            .config
-            .get_api_token()
-            .await
-            .map_err(|e| ListenError::Request(format!("failed to get api token: {}", e)))
+    /// See [`ListenerHandle::stats`].
+    pub fn stats(&self) -> ListenerStats {
+        self.handle.stats()
     }
 
-    /// Re-establish a dropped connection, replaying the current interest set. Retries with
-    /// exponential backoff up to RECONNECT_MAX_RETRIES, then returns the last error.
-    async fn reconnect(&mut self) -> Result<(), ListenError> {
-        let mut delay = RECONNECT_INITIAL_BACKOFF;
-        let mut last_err = ListenError::WebSocket("connection lost".to_string());
-        for _ in 0..RECONNECT_MAX_RETRIES {
-            tokio::time::sleep(delay).await;
-            match Self::open(&self.api_service, &self.host_base_url, &self.interest).await {
-                Ok(ws) => {
-                    self.ws = ws;
-                    return Ok(());
-                }
-                Err(e) => {
-                    last_err = e;
-                    delay = (delay * 2).min(RECONNECT_MAX_BACKOFF);
-                }
-            }
-        }
-        Err(last_err)
+    /// Wait for the next message. Connection drops are handled in the background; returns
+    /// `Some(Err(_))` when a subscription is refused, a frame can't be decoded or a reconnect
+    /// ultimately fails (keep calling `next` to keep trying), and `None` once the listener is closed.
+    pub async fn next(&mut self) -> Option<ListenItem> {
+        self.receiver.next().await
     }
 
-    /// Wait for the next message. Transparently reconnects (fresh token, current interest set, with
-    /// backoff) when the connection drops, so brief outages and server restarts are invisible to the
-    /// caller. Returns `Some(Err(_))` only when a reconnect ultimately fails or a frame can't be
-    /// decoded — calling `next` again after that resumes the reconnect attempts.
-    pub async fn next(&mut self) -> Option<Result<SubscriptionMessage, ListenError>> {
-        loop {
-            if let Some(msg) = self.buffered.pop_front() {
-                return Some(Ok(msg));
-            }
-            let frame = match self.ws.next().await {
-                Some(Ok(f)) => f,
-                // Stream ended or a transport error — the connection is gone; re-establish it.
-                None | Some(Err(_)) => match self.reconnect().await {
-                    Ok(()) => continue,
-                    Err(e) => return Some(Err(e)),
-                },
-            };
-            match frame {
-                Message::Text(text) => match decode_text_frame(&text) {
-                    Ok(DecodedFrame::Messages(messages)) => {
-                        for m in messages {
-                            self.buffered.push_back(m);
-                        }
-                    }
-                    // A subscription-level error (e.g. unknown id) is surfaced to the caller but
-                    // does NOT close the socket — keep calling `next` to receive from the other
-                    // subscriptions on this connection.
-                    Ok(DecodedFrame::SubscriptionError { external_id, reason }) => {
-                        return Some(Err(ListenError::Subscription { external_id, reason }));
-                    }
-                    Err(e) => return Some(Err(e)),
-                },
-                // Peer initiated close (e.g. idle timeout) — reconnect rather than ending the stream.
-                Message::Close(_) => match self.reconnect().await {
-                    Ok(()) => continue,
-                    Err(e) => return Some(Err(e)),
-                },
-                // Ping / Pong / Binary / raw Frame — tungstenite queues a pong for pings and
-                // flushes it on the next write. Loop and keep reading.
-                _ => continue,
-            }
-        }
+    /// A handle for acking and changing subscriptions from other tasks.
+    pub fn handle(&self) -> ListenerHandle {
+        self.handle.clone()
+    }
+
+    /// Split into the stream of messages and a cloneable control handle.
+    pub fn split(self) -> (SubscriptionReceiver, ListenerHandle) {
+        (self.receiver, self.handle)
     }
 
     /// Ack the given ids so Pulsar considers them delivered. Unknown ids are ignored server-side.
-    pub async fn ack<S: AsRef<str>>(&mut self, message_ids: &[S]) -> Result<(), ListenError> {
-        self.send_action("ack", message_ids).await
+    pub async fn ack<S: AsRef<str>>(&self, message_ids: &[S]) -> Result<(), ListenError> {
+        self.handle.ack(message_ids).await
     }
 
     /// Nack so Pulsar redelivers on the next receive cycle.
-    pub async fn nack<S: AsRef<str>>(&mut self, message_ids: &[S]) -> Result<(), ListenError> {
-        self.send_action("nack", message_ids).await
+    pub async fn nack<S: AsRef<str>>(&self, message_ids: &[S]) -> Result<(), ListenError> {
+        self.handle.nack(message_ids).await
     }
 
     /// Add subscriptions to the live set without reconnecting. The interest set is also updated so a
     /// later reconnect replays it.
-    pub async fn subscribe<S: AsRef<str>>(&mut self, external_ids: &[S]) -> Result<(), ListenError> {
-        for id in external_ids {
-            let id = id.as_ref().to_string();
-            if !self.interest.contains(&id) {
-                self.interest.push(id);
-            }
-        }
-        self.send_interest("subscribe", external_ids).await
+    pub async fn subscribe<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        self.handle.subscribe(external_ids).await
     }
 
     /// Remove subscriptions from the live set.
-    pub async fn unsubscribe<S: AsRef<str>>(
-        &mut self,
+    pub async fn unsubscribe<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        self.handle.unsubscribe(external_ids).await
+    }
+
+    /// Replace the whole live set of subscriptions.
+    pub async fn set_subscriptions<S: AsRef<str>>(
+        &self,
         external_ids: &[S],
     ) -> Result<(), ListenError> {
-        let removing: Vec<String> = external_ids.iter().map(|s| s.as_ref().to_string()).collect();
-        self.interest.retain(|id| !removing.contains(id));
-        self.send_interest("unsubscribe", external_ids).await
+        self.handle.set_subscriptions(external_ids).await
     }
 
-    /// Replace the whole live set of subscriptions.
+    /// Send a Close frame and drain remaining frames until the peer closes its side. A reconnect in
+    /// progress is abandoned.
+    pub async fn close(self) -> Result<(), ListenError> {
+        let SubscriptionListener { receiver, handle } = self;
+        let closed = handle.request_close();
+        // Nobody reads the messages any more; this also cuts short a reconnect backoff.
+        drop(receiver);
+        if let Some(closed) = closed {
+            let _ = closed.await;
+        }
+        Ok(())
+    }
+}
+
+impl Stream for SubscriptionListener {
+    type Item = ListenItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+impl SubscriptionReceiver {
+    /// Wait for the next message; see [`SubscriptionListener::next`].
+    pub async fn next(&mut self) -> Option<ListenItem> {
+        self.messages.recv().await
+    }
+}
+
+impl Stream for SubscriptionReceiver {
+    type Item = ListenItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.messages.poll_recv(cx)
+    }
+}
+
+impl ListenerHandle {
+    /// See [`SubscriptionListener::ack`].
+    pub async fn ack<S: AsRef<str>>(&self, message_ids: &[S]) -> Result<(), ListenError> {
+        let ids = to_strings(message_ids);
+        self.send_action("ack", Effect::Acked(ids.clone()), ids)
+            .await
+    }
+
+    /// See [`SubscriptionListener::nack`].
+    pub async fn nack<S: AsRef<str>>(&self, message_ids: &[S]) -> Result<(), ListenError> {
+        let ids = to_strings(message_ids);
+        self.send_action("nack", Effect::Nacked(ids.clone()), ids)
+            .await
+    }
+
+    /// See [`SubscriptionListener::subscribe`].
+    pub async fn subscribe<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        let ids = to_strings(external_ids);
+        self.send_interest("subscribe", Interest::Add(ids.clone()), ids)
+            .await
+    }
+
+    /// See [`SubscriptionListener::unsubscribe`].
+    pub async fn unsubscribe<S: AsRef<str>>(&self, external_ids: &[S]) -> Result<(), ListenError> {
+        let ids = to_strings(external_ids);
+        self.send_interest("unsubscribe", Interest::Remove(ids.clone()), ids)
+            .await
+    }
+
+    /// See [`SubscriptionListener::set_subscriptions`].
     pub async fn set_subscriptions<S: AsRef<str>>(
-        &mut self,
+        &self,
         external_ids: &[S],
     ) -> Result<(), ListenError> {
-        self.interest = external_ids.iter().map(|s| s.as_ref().to_string()).collect();
-        self.send_interest("set", external_ids).await
+        let ids = to_strings(external_ids);
+        self.send_interest("set", Interest::Set(ids.clone()), ids)
+            .await
+    }
+
+    /// Close the connection, as [`SubscriptionListener::close`], from a handle. A reconnect in
+    /// progress is abandoned. The receive half then ends.
+    pub async fn close(&self) -> Result<(), ListenError> {
+        if let Some(closed) = self.request_close() {
+            let _ = closed.await;
+        }
+        Ok(())
+    }
+
+    /// Whether the listener has stopped (closed, or its receive half was dropped).
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
     }
 
-    async fn send_action<S: AsRef<str>>(
-        &mut self,
+    /// Watch the connection: `borrow()` reads the current state, `changed().await` waits for the
+    /// next one.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    pub fn stats(&self) -> ListenerStats {
+        ListenerStats {
+            reconnects: self.counters.reconnects.load(Ordering::Relaxed),
+            redelivered: self.counters.redelivered.load(Ordering::Relaxed),
+            duplicates: self.counters.duplicates.load(Ordering::Relaxed),
+        }
+    }
+
+    fn request_close(&self) -> Option<oneshot::Receiver<()>> {
+        let (reply, closed) = oneshot::channel();
+        self.commands.send(Command::Close { reply }).ok()?;
+        Some(closed)
+    }
+
+    async fn send_action(
+        &self,
         action: &str,
-        ids: &[S],
+        effect: Effect,
+        ids: Vec<String>,
     ) -> Result<(), ListenError> {
-        let ids: Vec<&str> = ids.iter().map(|s| s.as_ref()).collect();
-        let frame = serde_json::to_string(&serde_json::json!({
+        let frame = serde_json::json!({
             "action": action,
             "messageIds": ids,
-        }))?;
-        self.ws
-            .send(Message::Text(frame.into()))
-            .await
-            .map_err(|e| ListenError::WebSocket(e.to_string()))
+        });
+        self.send(frame, Some(effect)).await
     }
 
-    async fn send_interest<S: AsRef<str>>(
-        &mut self,
+    async fn send_interest(
+        &self,
         action: &str,
-        external_ids: &[S],
+        interest: Interest,
+        external_ids: Vec<String>,
     ) -> Result<(), ListenError> {
-        let ids: Vec<&str> = external_ids.iter().map(|s| s.as_ref()).collect();
-        let frame = serde_json::to_string(&serde_json::json!({
+        let frame = serde_json::json!({
             "action": action,
-            "externalIds": ids,
-        }))?;
+            "externalIds": external_ids,
+        });
+        self.send(frame, Some(Effect::Interest(interest))).await
+    }
+
+    async fn send(
+        &self,
+        frame: serde_json::Value,
+        effect: Option<Effect>,
+    ) -> Result<(), ListenError> {
+        let frame = serde_json::to_string(&frame)?;
+        let (reply, sent) = oneshot::channel();
+        self.commands
+            .send(Command::Send {
+                frame,
+                effect,
+                reply,
+            })
+            .map_err(|_| ListenError::Closed)?;
+        sent.await.map_err(|_| ListenError::Closed)?
+    }
+}
+
+fn to_strings<S: AsRef<str>>(ids: &[S]) -> Vec<String> {
+    ids.iter().map(|s| s.as_ref().to_string()).collect()
+}
+
+/// Fetch a fresh token, build the listen URL from the current interest set, and open the socket.
+async fn open(
+    api_service: &Weak<ApiService>,
+    host_base_url: &str,
+    interest: &[String],
+) -> Result<WsStream, ListenError> {
+    let token = fetch_token(api_service).await?;
+    let ws_url = build_ws_url(host_base_url, interest)?;
+    let mut request = ws_url
+        .into_client_request()
+        .map_err(|e| ListenError::Request(e.to_string()))?;
+    let header_value: http::HeaderValue = format!("Bearer {}", token)
+        .parse()
+        .map_err(|e: http::header::InvalidHeaderValue| ListenError::Request(e.to_string()))?;
+    request.headers_mut().insert("Authorization", header_value);
+
+    let (ws, _response) = connect_async(request)
+        .await
+        .map_err(|e| ListenError::Handshake(e.to_string()))?;
+    Ok(ws)
+}
+
+async fn fetch_token(api_service: &Weak<ApiService>) -> Result<String, ListenError> {
+    let service = api_service
+        .upgrade()
+        .ok_or_else(|| ListenError::Request("api service has been dropped".to_string()))?;
+    service
+        .config
+        .get_api_token()
+        .await
+        .map_err(|e| ListenError::Request(format!("failed to get api token: {}", e)))
+}
+
+/// The task that owns the socket: reads and decodes frames into `pending`, hands them to the
+/// receiver as it makes room, carries out handle commands, and reconnects when the connection drops.
+struct Driver {
+    ws: WsStream,
+    // Context for transparent reconnects: a fresh token is fetched and the URL is rebuilt from the
+    // current interest set on each attempt.
+    api_service: Weak<ApiService>,
+    host_base_url: String,
+    interest: Vec<String>,
+    messages: mpsc::Sender<ListenItem>,
+    commands: mpsc::UnboundedReceiver<Command>,
+    // False once every handle is gone.
+    handles_open: bool,
+    pending: VecDeque<ListenItem>,
+    // The connection is gone and the last round of reconnect attempts failed. The next round starts,
+    // at the longest backoff, once that failure has been handed to the receiver.
+    broken: bool,
+    policy: ReconnectPolicy,
+    state: watch::Sender<ConnectionState>,
+    on_state_change: Option<Arc<StateCallback>>,
+    counters: Arc<Counters>,
+    // The last RECENT_IDS message ids delivered, oldest first, to count redeliveries.
+    recent: VecDeque<String>,
+    recent_ids: HashSet<String>,
+    dedup: Option<SharedDedupStore>,
+    // Message id -> when it was delivered and its dedup keys, for messages not yet acked or
+    // nacked.
+    unsettled: HashMap<String, (Instant, Vec<String>)>,
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        self.set_state(ConnectionState::Closed);
+    }
+}
+
+/// What woke the [`Driver`] up.
+enum Wake {
+    Command(Option<Command>),
+    Delivered,
+    Frame(Option<Result<Message, TungsteniteError>>),
+    Retry,
+}
+
+impl Driver {
+    async fn run(mut self) {
+        loop {
+            let wake = tokio::select! {
+                biased;
+                command = self.commands.recv(), if self.handles_open => Wake::Command(command),
+                permit = self.messages.reserve(), if !self.pending.is_empty() => match permit {
+                    Ok(permit) => {
+                        permit.send(self.pending.pop_front().expect("pending is not empty"));
+                        Wake::Delivered
+                    }
+                    Err(_) => return,
+                },
+                _ = self.messages.closed(), if self.pending.is_empty() => return,
+                frame = self.ws.next(), if !self.broken && self.pending.len() < MAX_PENDING => {
+                    Wake::Frame(frame)
+                }
+                _ = std::future::ready(()), if self.broken && self.pending.is_empty() => Wake::Retry,
+            };
+            match wake {
+                Wake::Command(Some(Command::Send {
+                    frame,
+                    effect,
+                    reply,
+                })) => {
+                    self.apply_effect(effect).await;
+                    let sent = match self.broken {
+                        true => Err(ListenError::Disconnected),
+                        false => self.send(frame).await,
+                    };
+                    let _ = reply.send(sent);
+                }
+                Wake::Command(Some(Command::Close { reply })) => {
+                    if !self.broken {
+                        self.close().await;
+                    }
+                    let _ = reply.send(());
+                    return;
+                }
+                // Every handle is gone; keep delivering while the receive half is read.
+                Wake::Command(None) => self.handles_open = false,
+                Wake::Delivered => {}
+                Wake::Frame(frame) => {
+                    if !self.on_frame(frame).await {
+                        return;
+                    }
+                }
+                Wake::Retry => {
+                    if !self.reconnect().await {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn apply_effect(&mut self, effect: Option<Effect>) {
+        match effect {
+            Some(Effect::Interest(interest)) => self.apply(interest),
+            Some(Effect::Acked(ids)) => self.settle(&ids, true).await,
+            Some(Effect::Nacked(ids)) => self.settle(&ids, false).await,
+            None => {}
+        }
+    }
+
+    /// Carry out a handle command while there is no connection: its effect is applied, a send
+    /// fails with [`ListenError::Disconnected`], and a close is answered at once. Returns false
+    /// once the listener is closed.
+    async fn command_while_disconnected(&mut self, command: Option<Command>) -> bool {
+        match command {
+            Some(Command::Send { effect, reply, .. }) => {
+                self.apply_effect(effect).await;
+                let _ = reply.send(Err(ListenError::Disconnected));
+                true
+            }
+            Some(Command::Close { reply }) => {
+                let _ = reply.send(());
+                false
+            }
+            None => {
+                self.handles_open = false;
+                true
+            }
+        }
+    }
+
+    /// Handle one frame. Returns false once the listener is closed.
+    async fn on_frame(&mut self, frame: Option<Result<Message, TungsteniteError>>) -> bool {
+        match frame {
+            Some(Ok(Message::Text(text))) => match decode_text_frame(&text) {
+                Ok(DecodedFrame::Messages(messages)) => {
+                    let mut duplicates = Vec::new();
+                    for message in &messages {
+                        self.note_delivery(&message.message_id);
+                    }
+                    let deliver = self.screen(&messages).await;
+                    for (message, deliver) in messages.into_iter().zip(deliver) {
+                        match deliver {
+                            true => self.pending.push_back(Ok(message)),
+                            false => duplicates.push(message.message_id),
+                        }
+                    }
+                    if !duplicates.is_empty() {
+                        self.ack_duplicates(duplicates).await;
+                    }
+                }
+                // A subscription-level error (e.g. unknown id) is surfaced to the caller but does
+                // NOT close the socket — the other subscriptions on this connection keep delivering.
+                Ok(DecodedFrame::SubscriptionError {
+                    external_id,
+                    reason,
+                }) => self.pending.push_back(Err(ListenError::Subscription {
+                    external_id,
+                    reason,
+                })),
+                Err(e) => self.pending.push_back(Err(e)),
+            },
+            // Stream ended, a transport error, or a peer-initiated close (e.g. idle timeout) — the
+            // connection is gone; re-establish it rather than ending the stream.
+            None | Some(Err(_)) | Some(Ok(Message::Close(_))) => return self.reconnect().await,
+            // Ping / Pong / Binary / raw Frame — tungstenite queues a pong for pings and flushes it
+            // on the next read or write.
+            Some(Ok(_)) => {}
+        }
+        true
+    }
+
+    /// Re-establish a dropped connection, replaying the current interest set. Retries with
+    /// exponential backoff as the policy allows, then queues the last error for the receiver. A
+    /// round following a failed one starts at the longest backoff. Handle commands are answered
+    /// throughout (see [`command_while_disconnected`](Self::command_while_disconnected)); returns
+    /// false if one closed the listener.
+    async fn reconnect(&mut self) -> bool {
+        let mut delay = if self.broken {
+            self.policy.max_backoff
+        } else {
+            self.policy.initial_backoff
+        };
+        let mut last_err = ListenError::WebSocket("connection lost".to_string());
+        let mut attempt = 0;
+        while self.policy.max_retries.is_none_or(|max| attempt < max) {
+            attempt += 1;
+            // After a failed round, stay Failed until an attempt is actually made.
+            if !self.broken {
+                self.set_state(ConnectionState::Reconnecting { attempt });
+            }
+            let backoff = tokio::time::sleep(delay);
+            tokio::pin!(backoff);
+            loop {
+                tokio::select! {
+                    _ = &mut backoff => break,
+                    // Nobody is listening any more.
+                    _ = self.messages.closed() => return true,
+                    command = self.commands.recv(), if self.handles_open => {
+                        if !self.command_while_disconnected(command).await {
+                            return false;
+                        }
+                    }
+                }
+            }
+            self.set_state(ConnectionState::Reconnecting { attempt });
+            let (api_service, host_base_url) =
+                (self.api_service.clone(), self.host_base_url.clone());
+            let interest = self.interest.clone();
+            let connect = open(&api_service, &host_base_url, &interest);
+            tokio::pin!(connect);
+            let opened = loop {
+                tokio::select! {
+                    opened = &mut connect => break opened,
+                    _ = self.messages.closed() => return true,
+                    command = self.commands.recv(), if self.handles_open => {
+                        if !self.command_while_disconnected(command).await {
+                            return false;
+                        }
+                    }
+                }
+            };
+            match opened {
+                Ok(ws) => {
+                    self.ws = ws;
+                    self.broken = false;
+                    self.counters.reconnects.fetch_add(1, Ordering::Relaxed);
+                    self.set_state(ConnectionState::Connected);
+                    if self.interest != interest {
+                        // Changed while connecting; the URL carried the old set.
+                        let frame = serde_json::json!({
+                            "action": "set",
+                            "externalIds": self.interest,
+                        });
+                        let _ = self.send(frame.to_string()).await;
+                    }
+                    return true;
+                }
+                Err(e) => {
+                    last_err = e;
+                    delay = (delay * 2).min(self.policy.max_backoff);
+                }
+            }
+        }
+        self.broken = true;
+        self.set_state(ConnectionState::Failed);
+        self.pending.push_back(Err(last_err));
+        true
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        if *self.state.borrow() == state {
+            return;
+        }
+        if let Some(callback) = &self.on_state_change {
+            callback(&state);
+        }
+        self.state.send_replace(state);
+    }
+
+    /// Count `message_id` as redelivered if it was delivered before.
+    fn note_delivery(&mut self, message_id: &str) {
+        if self.recent_ids.contains(message_id) {
+            self.counters.redelivered.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if self.recent.len() == RECENT_IDS {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.recent_ids.remove(&oldest);
+            }
+        }
+        self.recent.push_back(message_id.to_string());
+        self.recent_ids.insert(message_id.to_string());
+    }
+
+    /// With a dedup store: whether each of `messages` should be delivered. A new message is
+    /// remembered until it is settled; a processed one is counted as a duplicate. The store is
+    /// read on a blocking thread. A failing store falls back to delivering, and the failure is
+    /// queued for the receiver as [`ListenError::Dedup`].
+    async fn screen(&mut self, messages: &[SubscriptionMessage]) -> Vec<bool> {
+        let Some(store) = self.dedup.clone() else {
+            return vec![true; messages.len()];
+        };
+        let keys: Vec<(String, Vec<String>)> = messages.iter().map(dedup_keys).collect();
+        let lookup = keys.clone();
+        let processed = tokio::task::spawn_blocking(move || {
+            let mut store = store.lock().unwrap_or_else(|e| e.into_inner());
+            lookup
+                .iter()
+                .map(|(id_key, point_keys)| is_processed(&mut *store, id_key, point_keys))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_else(|e| {
+            let failed = || Err(io::Error::other(e.to_string()));
+            messages.iter().map(|_| failed()).collect()
+        });
+        let mut deliver = Vec::with_capacity(messages.len());
+        let screened = messages.iter().zip(keys).zip(processed);
+        for ((message, (id_key, point_keys)), processed) in screened {
+            match processed {
+                Ok(true) => {
+                    self.counters.duplicates.fetch_add(1, Ordering::Relaxed);
+                    deliver.push(false);
+                    continue;
+                }
+                Ok(false) => {}
+                Err(e) => self.pending.push_back(Err(ListenError::Dedup(format!(
+                    "lookup of message {} failed: {}",
+                    message.message_id, e
+                )))),
+            }
+            let mut keys = point_keys;
+            keys.push(id_key);
+            self.remember_unsettled(message.message_id.clone(), keys);
+            deliver.push(true);
+        }
+        deliver
+    }
+
+    /// Keep the dedup keys of a delivered message until it is settled, dropping expired entries
+    /// (and the oldest, if need be) to stay under MAX_UNSETTLED.
+    fn remember_unsettled(&mut self, message_id: String, keys: Vec<String>) {
+        if self.unsettled.len() >= MAX_UNSETTLED {
+            self.unsettled
+                .retain(|_, (delivered, _)| delivered.elapsed() < UNSETTLED_TTL);
+        }
+        if self.unsettled.len() >= MAX_UNSETTLED {
+            let oldest = self
+                .unsettled
+                .iter()
+                .min_by_key(|(_, (delivered, _))| *delivered)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                self.unsettled.remove(&oldest);
+            }
+        }
+        self.unsettled.insert(message_id, (Instant::now(), keys));
+    }
+
+    /// Acked messages are recorded as processed, on a blocking thread; nacked ones are forgotten
+    /// until redelivered. A failed write is queued for the receiver as [`ListenError::Dedup`].
+    async fn settle(&mut self, message_ids: &[String], acked: bool) {
+        let Some(store) = self.dedup.clone() else {
+            return;
+        };
+        let mut keys: Vec<String> = Vec::new();
+        for message_id in message_ids {
+            let Some((delivered, message_keys)) = self.unsettled.remove(message_id) else {
+                continue;
+            };
+            if acked && delivered.elapsed() < UNSETTLED_TTL {
+                keys.extend(message_keys);
+            }
+        }
+        if keys.is_empty() {
+            return;
+        }
+        let written = tokio::task::spawn_blocking(move || {
+            let mut store = store.lock().unwrap_or_else(|e| e.into_inner());
+            keys.iter().try_for_each(|key| store.insert(key))
+        })
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e.to_string())));
+        if let Err(e) = written {
+            self.pending.push_back(Err(ListenError::Dedup(format!(
+                "recording acked messages failed: {}",
+                e
+            ))));
+        }
+    }
+
+    /// Ack redeliveries of processed messages so the backend stops sending them.
+    async fn ack_duplicates(&mut self, message_ids: Vec<String>) {
+        let frame = serde_json::json!({
+            "action": "ack",
+            "messageIds": message_ids,
+        });
+        // A failed ack only means another redelivery, which is screened again.
+        let _ = self.send(frame.to_string()).await;
+    }
+
+    fn apply(&mut self, interest: Interest) {
+        match interest {
+            Interest::Add(ids) => {
+                for id in ids {
+                    if !self.interest.contains(&id) {
+                        self.interest.push(id);
+                    }
+                }
+            }
+            Interest::Remove(ids) => self.interest.retain(|id| !ids.contains(id)),
+            Interest::Set(ids) => self.interest = ids,
+        }
+    }
+
+    async fn send(&mut self, frame: String) -> Result<(), ListenError> {
         self.ws
-            .send(Message::Text(frame.into()))
+            .send(Message::Text(frame))
             .await
             .map_err(|e| ListenError::WebSocket(e.to_string()))
     }
 
     /// Send a Close frame and drain remaining frames until the peer closes its side.
-    pub async fn close(mut self) -> Result<(), ListenError> {
+    async fn close(&mut self) {
         let _ = self.ws.close(None).await;
         while let Some(frame) = self.ws.next().await {
             if frame.is_err() {
                 break;
             }
         }
-        Ok(())
     }
 }
 
@@ -0,0 +1,505 @@
+//! An in-memory copy of the resource graph, kept current from a subscription.
+//!
+//! [`GraphMirror::bootstrap`] loads one or more connected components with
+//! [`fetch_related`](crate::resources::ResourceService::fetch_related). The mirror then applies the
+//! `RESOURCE`, `RELATION` and `RESOURCE_AND_RELATION` messages of a subscription to stay in step
+//! with the backend. Feed them with [`follow`](GraphMirror::follow), or run the mirror as a
+//! [`SubscriptionSink`] on a consumer of your own.
+//!
+//! A listener only sees changes made after it connects, so open it before bootstrapping: changes
+//! made while the snapshot is read then wait in the listener and are applied after it.
+//! [`GraphMirror::bootstrap_following`] does both in that order.
+//!
+//! Resources and relations that arrive complete are applied as they are. A relation without its
+//! endpoints is read back with [`EdgesService::by_ids`](crate::EdgesService::by_ids). A
+//! `RESOURCE_AND_RELATION` payload has no fixed shape, so the resources it names are read back
+//! with [`ResourceService::by_ids`](crate::resources::ResourceService::by_ids), along with their
+//! relations.
+//!
+//! Every change to the mirror is announced as a [`GraphChange`] on
+//! [`changes`](GraphMirror::changes). A message that keeps failing to apply is dead-lettered and
+//! announced as [`GraphChange::Resync`], as the mirror may have missed a change.
+
+use super::consumer::{ConsumerStats, DeadLetterSink, FailedMessage, SubscriptionConsumer};
+use super::listen::{SubscriptionListener, SubscriptionMessage};
+use super::payload::{Change, EntityRef, SubscriptionPayload};
+use super::sink::{SinkError, SubscriptionSink};
+use crate::generic::IdAndExtId;
+use crate::graph_data_wrapper::GraphDataWrapper;
+use crate::http::ResponseError;
+use crate::relations::{EdgeProxy, RelationDirection};
+use crate::resources::{RelatedResourcesForm, Resource, ResourceNetwork};
+use crate::ApiService;
+use futures::future::BoxFuture;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::io;
+use std::sync::{Arc, RwLock, Weak};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+// Change notifications a slow receiver may fall behind by before it sees `Lagged`.
+const CHANGE_CAPACITY: usize = 1024;
+
+/// One change to a [`GraphMirror`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphChange {
+    NodeAdded(Resource),
+    NodeUpdated(Resource),
+    /// The node as it was. Its relations are removed first, each with an `EdgeRemoved`.
+    NodeRemoved(Resource),
+    EdgeAdded(EdgeProxy),
+    EdgeUpdated(EdgeProxy),
+    EdgeRemoved(EdgeProxy),
+    /// Message `message_id` could not be applied and was given up on, so the mirror may be out of
+    /// step with the backend. [`load`](GraphMirror::load) the affected components again.
+    Resync {
+        message_id: String,
+        error: String,
+    },
+}
+
+/// The resource graph in memory; see the [module docs](self). Clones share the same graph.
+#[derive(Clone)]
+pub struct GraphMirror {
+    api_service: Weak<ApiService>,
+    graph: Arc<RwLock<Graph>>,
+    changes: broadcast::Sender<GraphChange>,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: HashMap<u64, Resource>,
+    by_external_id: HashMap<String, u64>,
+    edges: HashMap<u64, EdgeProxy>,
+    // Per node, the ids of the edges starting and ending at it.
+    outgoing: HashMap<u64, BTreeSet<u64>>,
+    incoming: HashMap<u64, BTreeSet<u64>>,
+}
+
+impl GraphMirror {
+    /// An empty mirror; fill it with [`load`](Self::load) or by applying messages.
+    pub fn new(api_service: &Arc<ApiService>) -> Self {
+        let (changes, _) = broadcast::channel(CHANGE_CAPACITY);
+        GraphMirror {
+            api_service: Arc::downgrade(api_service),
+            graph: Arc::new(RwLock::new(Graph::default())),
+            changes,
+        }
+    }
+
+    /// A mirror holding the components reached from `roots`. Open the listener it will
+    /// [`follow`](Self::follow) first, or changes made in between are lost; see
+    /// [`bootstrap_following`](Self::bootstrap_following).
+    pub async fn bootstrap(
+        api_service: &Arc<ApiService>,
+        roots: &[RelatedResourcesForm],
+    ) -> Result<Self, ResponseError> {
+        let mirror = Self::new(api_service);
+        for form in roots {
+            mirror.load(form).await?;
+        }
+        Ok(mirror)
+    }
+
+    /// [`bootstrap`](Self::bootstrap) after `listener` was opened, then follow it on a spawned task.
+    /// Changes made while the components were read are applied once they are loaded.
+    pub async fn bootstrap_following(
+        api_service: &Arc<ApiService>,
+        roots: &[RelatedResourcesForm],
+        listener: SubscriptionListener,
+    ) -> Result<(Self, JoinHandle<ConsumerStats>), ResponseError> {
+        let mirror = Self::bootstrap(api_service, roots).await?;
+        let follower = tokio::spawn({
+            let mirror = mirror.clone();
+            async move { mirror.follow(listener).await }
+        });
+        Ok((mirror, follower))
+    }
+
+    /// Add the component reached from `form`'s start node. Nodes and edges the mirror already
+    /// holds are updated; ones the backend no longer has are kept.
+    pub async fn load(&self, form: &RelatedResourcesForm) -> Result<(), ResponseError> {
+        let network = self.service()?.resources.fetch_related(form).await?;
+        self.merge(network.nodes, network.edges);
+        Ok(())
+    }
+
+    /// Notifications of every change from now on.
+    pub fn changes(&self) -> broadcast::Receiver<GraphChange> {
+        self.changes.subscribe()
+    }
+
+    /// Apply `listener`'s messages until it closes, one at a time and in order. Each message is
+    /// acked once applied; one that can't be applied is retried, as by a
+    /// [`SubscriptionConsumer`], and then dead-lettered with a [`GraphChange::Resync`]. On a
+    /// consumer of your own, pass the mirror to
+    /// [`with_dead_letter`](SubscriptionConsumer::with_dead_letter) for the same.
+    pub async fn follow(&self, listener: SubscriptionListener) -> ConsumerStats {
+        SubscriptionConsumer::new(listener)
+            .with_concurrency(1)
+            .with_dead_letter(self.clone())
+            .run_sink(self.clone())
+            .await
+    }
+
+    pub fn node(&self, id: u64) -> Option<Resource> {
+        self.read().nodes.get(&id).cloned()
+    }
+
+    pub fn node_by_external_id(&self, external_id: &str) -> Option<Resource> {
+        let graph = self.read();
+        let id = graph.by_external_id.get(external_id)?;
+        graph.nodes.get(id).cloned()
+    }
+
+    pub fn edge(&self, id: u64) -> Option<EdgeProxy> {
+        self.read().edges.get(&id).cloned()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.read().nodes.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.read().edges.len()
+    }
+
+    /// The resources with a relation ending at `id`. Hierarchies point from child to parent
+    /// (`PART_OF`, `BELONGS_TO`), so these are its children.
+    pub fn children(&self, id: u64) -> Vec<Resource> {
+        let graph = self.read();
+        let starts = graph
+            .edge_ids(&graph.incoming, id)
+            .filter_map(|e| graph.edges[&e].start);
+        graph.resources(starts)
+    }
+
+    /// The resources related to `id` either way.
+    pub fn neighbours(&self, id: u64) -> Vec<Resource> {
+        let graph = self.read();
+        graph.resources(graph.neighbour_ids(id))
+    }
+
+    /// The shortest chain of resources from `from` to `to`, following relations either way.
+    /// `None` if they aren't connected or either isn't in the mirror.
+    pub fn path(&self, from: u64, to: u64) -> Option<Vec<Resource>> {
+        let graph = self.read();
+        if !graph.nodes.contains_key(&from) || !graph.nodes.contains_key(&to) {
+            return None;
+        }
+        let mut previous: HashMap<u64, u64> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        let mut seen = HashSet::from([from]);
+        while let Some(node) = queue.pop_front() {
+            if node == to {
+                let mut path = vec![to];
+                while let Some(&before) = previous.get(path.last().unwrap()) {
+                    path.push(before);
+                }
+                path.reverse();
+                return Some(path.iter().map(|id| graph.nodes[id].clone()).collect());
+            }
+            for next in graph.neighbour_ids(node) {
+                if graph.nodes.contains_key(&next) && seen.insert(next) {
+                    previous.insert(next, node);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Everything the mirror holds, in the shape `fetch_related` returns.
+    pub fn network(&self) -> ResourceNetwork {
+        let graph = self.read();
+        let mut nodes: Vec<Resource> = graph.nodes.values().cloned().collect();
+        nodes.sort_by_key(|n| n.id);
+        let mut edges: Vec<EdgeProxy> = graph.edges.values().cloned().collect();
+        edges.sort_by_key(|e| e.id);
+        ResourceNetwork {
+            nodes,
+            edges,
+            labels: vec![],
+        }
+    }
+
+    /// Apply one subscription message. Payloads other than resources and relations are ignored.
+    pub async fn apply(&self, message: &SubscriptionMessage) -> Result<(), SinkError> {
+        match message.decode()? {
+            SubscriptionPayload::Resource(change) => match change {
+                Change::Created(nodes) | Change::Updated(nodes) | Change::Renamed(nodes) => {
+                    self.merge(nodes, vec![])
+                }
+                Change::Deleted(refs) => self.remove_nodes(&refs),
+            },
+            SubscriptionPayload::Relation(change) => match change {
+                Change::Created(edges) | Change::Updated(edges) | Change::Renamed(edges) => {
+                    let (complete, partial): (Vec<_>, Vec<_>) = edges
+                        .into_iter()
+                        .partition(|e| e.start.is_some() && e.end.is_some());
+                    self.merge(vec![], complete);
+                    let ids: Vec<IdAndExtId> = partial
+                        .iter()
+                        .filter_map(|e| e.id)
+                        .map(IdAndExtId::from_id)
+                        .collect();
+                    if !ids.is_empty() {
+                        let graph = self.service()?.edges.by_ids(&ids).await?;
+                        self.merge_wrapper(graph);
+                    }
+                }
+                Change::Deleted(refs) => {
+                    let ids: Vec<u64> = refs.iter().filter_map(numeric_id).collect();
+                    self.write(|graph, out| {
+                        for id in ids {
+                            graph.remove_edge(id, out);
+                        }
+                    });
+                }
+            },
+            SubscriptionPayload::ResourceAndRelation(change) => match change {
+                Change::Created(items) | Change::Updated(items) | Change::Renamed(items) => {
+                    let ids = named_resources(&items);
+                    if !ids.is_empty() {
+                        let graph = self.service()?.resources.by_ids(&ids).await?;
+                        self.merge_wrapper(graph);
+                    }
+                }
+                Change::Deleted(refs) => self.remove_nodes(&refs),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn merge_wrapper(&self, graph: GraphDataWrapper<Resource>) {
+        let edges = graph.relations().cloned().unwrap_or_default();
+        self.merge(graph.nodes.unwrap_or_default(), edges);
+    }
+
+    fn merge(&self, nodes: Vec<Resource>, edges: Vec<EdgeProxy>) {
+        self.write(|graph, out| {
+            for node in nodes {
+                for edge in implied_edges(&node) {
+                    // A relation carried by a node lacks the edge's detail; keep the one we have.
+                    if edge.id.is_some_and(|id| !graph.edges.contains_key(&id)) {
+                        graph.upsert_edge(edge, out);
+                    }
+                }
+                graph.upsert_node(node, out);
+            }
+            for edge in edges {
+                graph.upsert_edge(edge, out);
+            }
+        });
+    }
+
+    fn remove_nodes(&self, refs: &[EntityRef]) {
+        self.write(|graph, out| {
+            for entity in refs {
+                let id = numeric_id(entity).or_else(|| {
+                    let external_id = entity.external_id.as_ref()?;
+                    graph.by_external_id.get(external_id).copied()
+                });
+                if let Some(id) = id {
+                    graph.remove_node(id, out);
+                }
+            }
+        });
+    }
+
+    /// Change the graph, then announce what changed.
+    fn write(&self, f: impl FnOnce(&mut Graph, &mut Vec<GraphChange>)) {
+        let mut out = Vec::new();
+        f(&mut self.graph.write().unwrap(), &mut out);
+        for change in out {
+            // No receivers is fine.
+            let _ = self.changes.send(change);
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Graph> {
+        self.graph.read().unwrap()
+    }
+
+    fn service(&self) -> Result<Arc<ApiService>, ResponseError> {
+        self.api_service
+            .upgrade()
+            .ok_or_else(|| ResponseError::from("api service has been dropped".to_string()))
+    }
+}
+
+impl SubscriptionSink for GraphMirror {
+    fn send<'a>(
+        &'a self,
+        message: &'a SubscriptionMessage,
+    ) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(self.apply(message))
+    }
+}
+
+/// Announces a message given up on as a [`GraphChange::Resync`].
+impl DeadLetterSink for GraphMirror {
+    fn dead_letter(&self, failed: FailedMessage) -> io::Result<()> {
+        // No receivers is fine.
+        let _ = self.changes.send(GraphChange::Resync {
+            message_id: failed.message.message_id,
+            error: failed.error,
+        });
+        Ok(())
+    }
+}
+
+impl Graph {
+    fn upsert_node(&mut self, node: Resource, out: &mut Vec<GraphChange>) {
+        let Some(id) = node.id else {
+            return;
+        };
+        match self.nodes.insert(id, node.clone()) {
+            Some(old) if old == node => return,
+            Some(old) => {
+                if old.external_id != node.external_id {
+                    self.by_external_id.remove(&old.external_id);
+                }
+                out.push(GraphChange::NodeUpdated(node.clone()));
+            }
+            None => out.push(GraphChange::NodeAdded(node.clone())),
+        }
+        self.by_external_id.insert(node.external_id, id);
+    }
+
+    fn upsert_edge(&mut self, edge: EdgeProxy, out: &mut Vec<GraphChange>) {
+        let (Some(id), Some(start), Some(end)) = (edge.id, edge.start, edge.end) else {
+            return;
+        };
+        match self.edges.get(&id) {
+            Some(old) if *old == edge => return,
+            Some(_) => {
+                // An edge moved to other endpoints is unlinked from the old ones.
+                self.unlink(id);
+                out.push(GraphChange::EdgeUpdated(edge.clone()));
+            }
+            None => out.push(GraphChange::EdgeAdded(edge.clone())),
+        }
+        self.outgoing.entry(start).or_default().insert(id);
+        self.incoming.entry(end).or_default().insert(id);
+        self.edges.insert(id, edge);
+    }
+
+    fn remove_edge(&mut self, id: u64, out: &mut Vec<GraphChange>) {
+        self.unlink(id);
+        if let Some(edge) = self.edges.remove(&id) {
+            out.push(GraphChange::EdgeRemoved(edge));
+        }
+    }
+
+    fn unlink(&mut self, id: u64) {
+        let Some(edge) = self.edges.get(&id) else {
+            return;
+        };
+        let (start, end) = (edge.start, edge.end);
+        for (index, node) in [(&mut self.outgoing, start), (&mut self.incoming, end)] {
+            if let Some(ids) = node.and_then(|n| index.get_mut(&n)) {
+                ids.remove(&id);
+            }
+        }
+    }
+
+    fn remove_node(&mut self, id: u64, out: &mut Vec<GraphChange>) {
+        let edges: Vec<u64> = self
+            .edge_ids(&self.outgoing, id)
+            .chain(self.edge_ids(&self.incoming, id))
+            .collect();
+        for edge in edges {
+            self.remove_edge(edge, out);
+        }
+        self.outgoing.remove(&id);
+        self.incoming.remove(&id);
+        if let Some(node) = self.nodes.remove(&id) {
+            self.by_external_id.remove(&node.external_id);
+            out.push(GraphChange::NodeRemoved(node));
+        }
+    }
+
+    fn edge_ids<'a>(
+        &'a self,
+        index: &'a HashMap<u64, BTreeSet<u64>>,
+        node: u64,
+    ) -> impl Iterator<Item = u64> + 'a {
+        index.get(&node).into_iter().flatten().copied()
+    }
+
+    fn neighbour_ids(&self, id: u64) -> BTreeSet<u64> {
+        let ends = self
+            .edge_ids(&self.outgoing, id)
+            .filter_map(|e| self.edges[&e].end);
+        let starts = self
+            .edge_ids(&self.incoming, id)
+            .filter_map(|e| self.edges[&e].start);
+        ends.chain(starts).filter(|&n| n != id).collect()
+    }
+
+    /// The nodes among `ids` that the mirror holds, by id.
+    fn resources(&self, ids: impl IntoIterator<Item = u64>) -> Vec<Resource> {
+        let ids: BTreeSet<u64> = ids.into_iter().collect();
+        ids.iter()
+            .filter_map(|id| self.nodes.get(id).cloned())
+            .collect()
+    }
+}
+
+/// The edges a resource's `related_resources` describe.
+fn implied_edges(node: &Resource) -> Vec<EdgeProxy> {
+    let Some(id) = node.id else {
+        return vec![];
+    };
+    node.related_resources
+        .iter()
+        .filter_map(|related| {
+            let other = related.id?;
+            let (start, end) = match related.direction? {
+                RelationDirection::Outbound => (id, other),
+                RelationDirection::Inbound => (other, id),
+            };
+            Some(EdgeProxy {
+                id: Some(related.edge_id?),
+                start: Some(start),
+                end: Some(end),
+                relationship_type: related.relationship_type.clone(),
+                description: None,
+                relationship_type_id: None,
+                metadata: HashMap::new(),
+            })
+        })
+        .collect()
+}
+
+/// The resources a `RESOURCE_AND_RELATION` item names: its own id or external id, and for a
+/// relation, the ids at both ends.
+fn named_resources(items: &[serde_json::Value]) -> Vec<IdAndExtId> {
+    let id = |value: &serde_json::Value| match value {
+        serde_json::Value::String(s) => s.parse().ok(),
+        value => value.as_u64(),
+    };
+    let mut ids = Vec::new();
+    for item in items {
+        match (item.get("start").and_then(id), item.get("end").and_then(id)) {
+            (Some(start), Some(end)) => {
+                ids.push(IdAndExtId::from_id(start));
+                ids.push(IdAndExtId::from_id(end));
+            }
+            _ => match (item.get("id").and_then(id), item.get("externalId")) {
+                (Some(n), _) => ids.push(IdAndExtId::from_id(n)),
+                (None, Some(serde_json::Value::String(e))) => {
+                    ids.push(IdAndExtId::from_external_id(e))
+                }
+                _ => {}
+            },
+        }
+    }
+    ids
+}
+
+fn numeric_id(entity: &EntityRef) -> Option<u64> {
+    entity.id.as_deref()?.parse().ok()
+}
@@ -1,17 +1,39 @@
+pub mod backfill;
+pub mod consumer;
+pub mod dedup;
 pub mod listen;
+pub mod mirror;
+pub mod payload;
+pub mod sink;
 mod test;
 
 use std::sync::Weak;
+pub use backfill::{BackfillError, BackfillItem, BackfillListener};
+pub use consumer::{
+    ConsumerStats, DeadLetterSink, DeliveryOrder, FailedMessage, SubscriptionConsumer,
+};
+pub use dedup::{DedupStore, FileDedupStore, MemoryDedupStore};
 pub use listen::{
-    DataCollectionString, DataWrapperMessage, EventAction, EventObject, ListenError,
-    SubscriptionListener, SubscriptionMessage, WsDatapoint,
+    ConnectionState, DataCollectionString, DataWrapperMessage, EventAction, EventObject,
+    ListenError, ListenOptions, ListenerHandle, ListenerStats, ReconnectPolicy,
+    SubscriptionListener, SubscriptionMessage, SubscriptionReceiver, WsDatapoint,
+};
+pub use mirror::{GraphChange, GraphMirror};
+pub use payload::{
+    Change, DatapointsChange, EntityRef, LiveDatapoint, LiveValue, PayloadError, SeriesDatapoints,
+    SubscriptionPayload,
 };
+pub use sink::{DataHubSink, NdjsonFileSink, SinkError, SubscriptionSink, WebhookSink};
 
-use crate::generic::{ApiServiceProvider, DataHubEntity, DataWrapper, IdAndExtId};
+use crate::fields::{Field, ListField};
+use crate::generic::{
+    ApiServiceProvider, DataHubEntity, DataWrapper, DataWrapperDeserialization, IdAndExtId,
+};
 use crate::http::ResponseError;
 use crate::ApiService;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 pub struct SubscriptionsService {
     pub(crate) api_service: Weak<ApiService>,
@@ -56,14 +78,136 @@ impl SubscriptionsService {
         self.execute_post_request(path, &json.into()).await
     }
 
+    /// Rename subscriptions and add or remove their series (`POST /subscriptions/update`). Build
+    /// each change with [`SubscriptionUpdate`]. Unstable: the backend doesn't serve this route on
+    /// its main branch yet, so it needs the `unstable-subscription-update` feature.
+    #[cfg(feature = "unstable-subscription-update")]
+    pub async fn update<I>(&self, input: &I) -> Result<DataWrapper<Subscription>, ResponseError>
+    where
+        for<'a> &'a I: Into<DataWrapper<SubscriptionUpdate>>,
+    {
+        let path = &format!("{}/update", self.base_url);
+        self.execute_post_request::<DataWrapper<Subscription>, _>(path, &input.into())
+            .await
+    }
+
+    /// One page of [`list`](Self::list), with the cursor to the next. Pass it back in
+    /// `retriever.cursor` to continue.
+    pub async fn list_page(
+        &self,
+        retriever: &SubscriptionRetriever,
+    ) -> Result<SubscriptionPage, ResponseError> {
+        let path = &format!("{}/list", self.base_url);
+        self.execute_post_request::<SubscriptionPage, _>(path, retriever)
+            .await
+    }
+
+    /// Every subscription matching `retriever`, following `nextCursor` page by page.
+    pub async fn list_all(
+        &self,
+        retriever: &SubscriptionRetriever,
+    ) -> Result<DataWrapper<Subscription>, ResponseError> {
+        let mut retriever = retriever.clone();
+        let mut items: Vec<Subscription> = vec![];
+        let mut seen: HashSet<String> = HashSet::new();
+        loop {
+            let page = self.list_page(&retriever).await?;
+            let empty = page.items.is_empty();
+            items.extend(page.items);
+            // Stop on an empty page or a cursor already followed, so a backend that hands the
+            // same cursor back can't keep this looping.
+            match page.next_cursor {
+                Some(cursor) if !empty && !cursor.is_empty() && seen.insert(cursor.clone()) => {
+                    retriever.cursor = Some(cursor);
+                }
+                _ => break,
+            }
+        }
+        Ok(DataWrapper::from_vec(items))
+    }
+
+    /// Look up subscriptions by id or externalId, system-managed ones included. The backend has
+    /// no `/byids` endpoint for subscriptions yet, so this pages through the tenant's
+    /// subscriptions until every id is found: one `list` request per 100 subscriptions, all of
+    /// them when an id doesn't exist. Keep the result rather than calling it in a loop.
+    pub async fn by_ids(
+        &self,
+        ids: &[IdAndExtId],
+    ) -> Result<DataWrapper<Subscription>, ResponseError> {
+        let mut retriever = SubscriptionRetriever {
+            include_system_managed: true,
+            ..Default::default()
+        };
+        let mut matched: Vec<Subscription> = vec![];
+        let mut found = vec![false; ids.len()];
+        let mut seen: HashSet<String> = HashSet::new();
+        loop {
+            let page = self.list_page(&retriever).await?;
+            let empty = page.items.is_empty();
+            for s in page.items {
+                let mut wanted = false;
+                for (id, found) in ids.iter().zip(found.iter_mut()) {
+                    if id.id.is_some_and(|i| s.id == Some(i))
+                        || id.external_id.as_ref() == Some(&s.external_id)
+                    {
+                        *found = true;
+                        wanted = true;
+                    }
+                }
+                if wanted {
+                    matched.push(s);
+                }
+            }
+            if found.iter().all(|f| *f) {
+                break;
+            }
+            // As in `list_all`: stop on an empty page or a cursor already followed.
+            match page.next_cursor {
+                Some(cursor) if !empty && !cursor.is_empty() && seen.insert(cursor.clone()) => {
+                    retriever.cursor = Some(cursor);
+                }
+                _ => break,
+            }
+        }
+        Ok(DataWrapper::from_vec(matched))
+    }
+
+    /// The subscription with `external_id`, or an error 404 if none exists. See
+    /// [`by_ids`](Self::by_ids) for what the lookup costs.
+    pub async fn get(&self, external_id: &str) -> Result<Subscription, ResponseError> {
+        let dw = self
+            .by_ids(&[IdAndExtId {
+                id: None,
+                external_id: Some(external_id.to_string()),
+            }])
+            .await?;
+        dw.get_items()
+            .first()
+            .cloned()
+            .ok_or_else(|| ResponseError {
+                status: oauth2::http::StatusCode::NOT_FOUND,
+                message: format!("Subscription with externalId={} not found", external_id),
+            })
+    }
+
     /// Open a WebSocket listener that multiplexes the named subscriptions' fan-out topics. The
     /// `subscription_external_ids` seed the initial set (may be empty — add them later with
-    /// [`SubscriptionListener::subscribe`]). Returns a [`SubscriptionListener`] the caller drives
-    /// with `next` / `ack` / `nack` / `close`. The handshake uses the bearer token currently
+    /// [`SubscriptionListener::subscribe`]). Returns a [`SubscriptionListener`] the caller consumes
+    /// as a stream (or with `next`) and drives with `ack` / `nack` / `close`. The handshake uses the bearer token currently
     /// cached in the API service.
     pub async fn listen<S: AsRef<str>>(
         &self,
         subscription_external_ids: &[S],
+    ) -> Result<SubscriptionListener, ListenError> {
+        self.listen_with(subscription_external_ids, ListenOptions::default())
+            .await
+    }
+
+    /// As [`listen`](Self::listen), with a reconnect policy and connection state callback.
+    pub async fn listen_with<S: AsRef<str>>(
+        &self,
+        subscription_external_ids: &[S],
+        options: ListenOptions,
     ) -> Result<SubscriptionListener, ListenError> {
         // The listener fetches its own token and builds the URL (and re-does both on reconnect), so
         // it just needs a handle to the api service, the host base, and the initial interest set.
@@ -71,9 +215,58 @@ impl SubscriptionsService {
             .iter()
             .map(|s| s.as_ref().to_string())
             .collect();
-        SubscriptionListener::connect(self.api_service.clone(), self.host_base_url.clone(), interest)
+        SubscriptionListener::connect(
+            self.api_service.clone(),
+            self.host_base_url.clone(),
+            interest,
+            options,
+        )
+        .await
+    }
+
+    /// A [`FileDedupStore`] at `<buffer dir>/dedup/<name>.keys`, encrypted with the client's
+    /// buffer encryption if one is set (see [`crate::encryption`]).
+    pub fn dedup_store(&self, name: &str, capacity: usize) -> std::io::Result<FileDedupStore> {
+        let config = &self.get_api_service().config;
+        let path = config
+            .buffer_directory()
+            .join("dedup")
+            .join(format!("{}.keys", name));
+        match &config.buffer_encryption {
+            Some(keys) => FileDedupStore::open_encrypted(path, capacity, keys.clone()),
+            None => FileDedupStore::open(path, capacity),
+        }
+    }
+
+    /// Replay `subscription`'s series from `since`, then deliver its live messages. See
+    /// [`backfill`].
+    pub async fn listen_with_backfill(
+        &self,
+        subscription: &Subscription,
+        since: DateTime<Utc>,
+    ) -> Result<BackfillListener, ListenError> {
+        self.listen_with_backfill_options(subscription, since, ListenOptions::default())
             .await
     }
+
+    /// As [`listen_with_backfill`](Self::listen_with_backfill), with the live listener opened as
+    /// by [`listen_with`](Self::listen_with).
+    pub async fn listen_with_backfill_options(
+        &self,
+        subscription: &Subscription,
+        since: DateTime<Utc>,
+        options: ListenOptions,
+    ) -> Result<BackfillListener, ListenError> {
+        let listener = self
+            .listen_with(&[subscription.external_id.as_str()], options)
+            .await?;
+        Ok(BackfillListener::new(
+            self.api_service.clone(),
+            subscription.timeseries.clone(),
+            since,
+            listener,
+        ))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -134,6 +327,12 @@ pub struct SubscriptionRetriever {
     pub filter: SubscriptionFilter,
     pub limit: u32,
     pub sort: DataSort,
+    /// Also list the subscriptions the platform manages itself (e.g. for functions).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_system_managed: bool,
+    /// Where to continue from: the `next_cursor` of a previous [`SubscriptionPage`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
 }
 
 impl Default for SubscriptionRetriever {
@@ -142,6 +341,118 @@ impl Default for SubscriptionRetriever {
             filter: SubscriptionFilter::default(),
             limit: 100,
             sort: DataSort::default(),
+            include_system_managed: false,
+            cursor: None,
         }
     }
 }
+
+/// One page of subscriptions from [`SubscriptionsService::list_page`].
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionPage {
+    #[serde(default)]
+    pub items: Vec<Subscription>,
+    /// Absent on the last page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl DataWrapperDeserialization for SubscriptionPage {
+    fn deserialize_and_set_status(
+        body: &str,
+        _status_code: u16,
+    ) -> Result<Self, serde_json::Error> {
+        if body.is_empty() {
+            return Ok(SubscriptionPage::default());
+        }
+        serde_json::from_str(body)
+    }
+}
+
+/// A change to one subscription, sent with `SubscriptionsService::update` (behind the
+/// `unstable-subscription-update` feature). Target it by id or external id, then chain the
+/// changes:
+///
+/// ```
+/// use dataplatform_rust_sdk::fields::{Field, ListField};
+/// use dataplatform_rust_sdk::generic::IdAndExtId;
+/// use dataplatform_rust_sdk::subscriptions::SubscriptionUpdate;
+///
+/// SubscriptionUpdate::by_external_id("pumps")
+///     .name(Field::value("All pumps"))
+///     .timeseries(ListField::add(vec![IdAndExtId::from_external_id("pump-2.temp")]));
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, with = "crate::serde_helper::opt_string_id")]
+    pub id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub update: SubscriptionUpdateFields,
+}
+
+impl SubscriptionUpdate {
+    /// Target the subscription by its numeric `id`.
+    pub fn by_id(id: u64) -> Self {
+        Self {
+            id: Some(id),
+            external_id: None,
+            update: SubscriptionUpdateFields::default(),
+        }
+    }
+
+    /// Target the subscription by its `external_id`.
+    pub fn by_external_id(external_id: &str) -> Self {
+        Self {
+            id: None,
+            external_id: Some(external_id.to_string()),
+            update: SubscriptionUpdateFields::default(),
+        }
+    }
+
+    /// Rename the subscription.
+    pub fn name(mut self, field: Field<String>) -> Self {
+        self.update.name = Some(field);
+        self
+    }
+
+    /// Replace the subscribed series, or add and remove some.
+    pub fn timeseries(mut self, field: ListField<IdAndExtId>) -> Self {
+        self.update.timeseries = Some(field);
+        self
+    }
+}
+
+/// The fields a [`SubscriptionUpdate`] changes; `None` leaves a field as it is.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionUpdateFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<Field<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeseries: Option<ListField<IdAndExtId>>,
+}
+
+impl From<SubscriptionUpdate> for DataWrapper<SubscriptionUpdate> {
+    fn from(value: SubscriptionUpdate) -> Self {
+        DataWrapper::from_vec(vec![value])
+    }
+}
+impl From<&SubscriptionUpdate> for DataWrapper<SubscriptionUpdate> {
+    fn from(value: &SubscriptionUpdate) -> Self {
+        DataWrapper::from_vec(vec![value.clone()])
+    }
+}
+impl From<Vec<SubscriptionUpdate>> for DataWrapper<SubscriptionUpdate> {
+    fn from(value: Vec<SubscriptionUpdate>) -> Self {
+        DataWrapper::from_vec(value)
+    }
+}
+impl From<&Vec<SubscriptionUpdate>> for DataWrapper<SubscriptionUpdate> {
+    fn from(value: &Vec<SubscriptionUpdate>) -> Self {
+        DataWrapper::from_vec(value.clone())
+    }
+}
@@ -0,0 +1,244 @@
+//! Typed decoding of subscription payloads.
+//!
+//! The backend sends every change as a [`DataWrapperMessage`] whose `items` depend on its
+//! `event_object`: datapoint collections for `DATAPOINTS`, events for `EVENT`, resources for
+//! `RESOURCE`, and so on. [`DataWrapperMessage::decode`] turns them into a [`SubscriptionPayload`]
+//! a handler can `match` on. Created, updated and renamed entities decode into the SDK's own types;
+//! a delete carries only what identifies the deleted entities ([`EntityRef`]).
+
+use super::listen::{DataWrapperMessage, EventAction, EventObject, SubscriptionMessage};
+use crate::datapoints::parse_timestamp;
+use crate::events::Event;
+use crate::functions::Function;
+use crate::labels::Label;
+use crate::relations::EdgeProxy;
+use crate::resources::Resource;
+use crate::timeseries::TimeSeries;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+/// A decoded subscription payload, by the kind of object that changed.
+#[derive(Debug, Clone)]
+pub enum SubscriptionPayload {
+    Datapoints(DatapointsChange),
+    Timeseries(Change<TimeSeries>),
+    Event(Change<Event>),
+    Resource(Change<Resource>),
+    Relation(Change<EdgeProxy>),
+    Label(Change<Label>),
+    Function(Change<Function>),
+    /// A combined resource-and-relation change, left as JSON.
+    ResourceAndRelation(Change<serde_json::Value>),
+}
+
+/// What happened to the entities of one payload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<T> {
+    Created(Vec<T>),
+    Updated(Vec<T>),
+    Renamed(Vec<T>),
+    Deleted(Vec<EntityRef>),
+}
+
+impl<T> Change<T> {
+    pub fn action(&self) -> EventAction {
+        match self {
+            Change::Created(_) => EventAction::Create,
+            Change::Updated(_) => EventAction::Update,
+            Change::Renamed(_) => EventAction::Rename,
+            Change::Deleted(_) => EventAction::Delete,
+        }
+    }
+}
+
+/// A deleted entity, named by whatever the backend sent: its id (numeric, or a UUID for events),
+/// its external id, or both.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityRef {
+    #[serde(default, deserialize_with = "id_as_string")]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub external_id: Option<String>,
+}
+
+/// Datapoints written to (or deleted from) the subscribed series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatapointsChange {
+    pub action: EventAction,
+    pub series: Vec<SeriesDatapoints>,
+}
+
+/// The datapoints of one series in a [`DatapointsChange`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesDatapoints {
+    pub id: Option<u64>,
+    pub external_id: Option<String>,
+    pub value_type: Option<String>,
+    pub datapoints: Vec<LiveDatapoint>,
+    /// The range a delete covers, when it was given as one.
+    pub inclusive_begin: Option<DateTime<Utc>>,
+    pub exclusive_end: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveDatapoint {
+    pub timestamp: DateTime<Utc>,
+    pub value: LiveValue,
+}
+
+/// A datapoint value: a number, unless the series is string-typed or the value isn't numeric.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiveValue {
+    Number(f64),
+    Text(String),
+}
+
+impl LiveValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            LiveValue::Number(v) => Some(*v),
+            LiveValue::Text(_) => None,
+        }
+    }
+}
+
+/// An item of a payload that doesn't match the shape its `event_object` and `event_action` call for.
+#[derive(Debug, Error)]
+#[error("{object:?} {action:?} item {index} does not decode: {reason}")]
+pub struct PayloadError {
+    pub object: EventObject,
+    pub action: EventAction,
+    pub index: usize,
+    pub reason: String,
+}
+
+impl DataWrapperMessage {
+    /// Decode the items according to `event_object` and `event_action`.
+    pub fn decode(&self) -> Result<SubscriptionPayload, PayloadError> {
+        Ok(match self.event_object {
+            EventObject::Datapoints => SubscriptionPayload::Datapoints(DatapointsChange {
+                action: self.event_action.clone(),
+                series: self.decode_items(decode_series)?,
+            }),
+            EventObject::Timeseries => SubscriptionPayload::Timeseries(self.change()?),
+            EventObject::Event => SubscriptionPayload::Event(self.change()?),
+            EventObject::Resource => SubscriptionPayload::Resource(self.change()?),
+            EventObject::Relation => SubscriptionPayload::Relation(self.change()?),
+            EventObject::Label => SubscriptionPayload::Label(self.change()?),
+            EventObject::Function => SubscriptionPayload::Function(self.change()?),
+            EventObject::ResourceAndRelation => {
+                SubscriptionPayload::ResourceAndRelation(self.change()?)
+            }
+        })
+    }
+
+    fn change<T: DeserializeOwned>(&self) -> Result<Change<T>, PayloadError> {
+        let decode = |item: &serde_json::Value| T::deserialize(item).map_err(|e| e.to_string());
+        Ok(match self.event_action {
+            EventAction::Create => Change::Created(self.decode_items(decode)?),
+            EventAction::Update => Change::Updated(self.decode_items(decode)?),
+            EventAction::Rename => Change::Renamed(self.decode_items(decode)?),
+            EventAction::Delete => Change::Deleted(
+                self.decode_items(|item| EntityRef::deserialize(item).map_err(|e| e.to_string()))?,
+            ),
+        })
+    }
+
+    fn decode_items<T>(
+        &self,
+        decode: impl Fn(&serde_json::Value) -> Result<T, String>,
+    ) -> Result<Vec<T>, PayloadError> {
+        self.raw_items()
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                decode(item).map_err(|reason| PayloadError {
+                    object: self.event_object.clone(),
+                    action: self.event_action.clone(),
+                    index,
+                    reason,
+                })
+            })
+            .collect()
+    }
+}
+
+impl SubscriptionMessage {
+    /// Decode the payload; see [`DataWrapperMessage::decode`].
+    pub fn decode(&self) -> Result<SubscriptionPayload, PayloadError> {
+        self.payload.decode()
+    }
+}
+
+/// The wire shape of a datapoint collection; timestamps and values are parsed afterwards.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WireSeries {
+    #[serde(default, with = "crate::serde_helper::opt_string_id")]
+    id: Option<u64>,
+    #[serde(default)]
+    external_id: Option<String>,
+    #[serde(default)]
+    value_type: Option<String>,
+    #[serde(default)]
+    datapoints: Vec<WirePoint>,
+    #[serde(default)]
+    inclusive_begin: Option<String>,
+    #[serde(default)]
+    exclusive_end: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WirePoint {
+    timestamp: String,
+    value: String,
+}
+
+fn decode_series(item: &serde_json::Value) -> Result<SeriesDatapoints, String> {
+    let wire = WireSeries::deserialize(item).map_err(|e| e.to_string())?;
+    let text = wire
+        .value_type
+        .as_deref()
+        .is_some_and(|t| t.eq_ignore_ascii_case("string"));
+    let datapoints = wire
+        .datapoints
+        .into_iter()
+        .map(|p| {
+            let value = match p.value.trim().parse::<f64>() {
+                Ok(v) if !text => LiveValue::Number(v),
+                _ => LiveValue::Text(p.value),
+            };
+            Ok(LiveDatapoint {
+                timestamp: timestamp(&p.timestamp)?,
+                value,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    let bound = |t: Option<String>| t.as_deref().map(timestamp).transpose();
+    Ok(SeriesDatapoints {
+        id: wire.id,
+        external_id: wire.external_id,
+        value_type: wire.value_type,
+        datapoints,
+        inclusive_begin: bound(wire.inclusive_begin)?,
+        exclusive_end: bound(wire.exclusive_end)?,
+    })
+}
+
+fn timestamp(raw: &str) -> Result<DateTime<Utc>, String> {
+    parse_timestamp(raw).ok_or_else(|| format!("unreadable timestamp {:?}", raw))
+}
+
+/// Ids arrive as numbers, numeric strings or UUIDs; keep them as text.
+fn id_as_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(
+        match Option::<serde_json::Value>::deserialize(deserializer)? {
+            Some(serde_json::Value::String(s)) => Some(s),
+            Some(serde_json::Value::Number(n)) => Some(n.to_string()),
+            _ => None,
+        },
+    )
+}
@@ -0,0 +1,476 @@
+//! Forwarding subscription messages to somewhere else.
+//!
+//! A [`SubscriptionSink`] takes one message at a time and returns once the message is safely
+//! stored. [`SubscriptionConsumer::run_sink`](super::SubscriptionConsumer::run_sink) feeds a sink
+//! from a listener and acks a message only after the sink confirmed it. A message the sink fails on
+//! is nacked and redelivered, and once it has failed too often it goes to the consumer's
+//! dead-letter sink. Nothing is acked that was not forwarded.
+//!
+//! Three sinks come with the SDK:
+//!
+//! - [`NdjsonFileSink`] appends each message as a JSON line to rolling files.
+//! - [`WebhookSink`] posts each message to an HTTP endpoint, retrying and optionally signing it.
+//! - [`DataHubSink`] writes datapoints and events into another [`ApiService`], e.g. a second
+//!   tenant.
+//!
+//! A message can reach a sink more than once: after a reconnect, or when a sink failed after it
+//! had partly stored it. A file may then hold a line twice, and a webhook gets the same
+//! [`MESSAGE_ID_HEADER`] again. A [`DataHubSink`] writes the same datapoints and event ids again,
+//! which the backend collapses.
+
+use super::listen::{EventAction, SubscriptionMessage};
+use super::payload::{
+    Change, DatapointsChange, EntityRef, LiveValue, PayloadError, SubscriptionPayload,
+};
+use crate::events::{Event, EventIdCollection};
+use crate::generic::{DataWrapper, DatapointString, DatapointsCollection, DeleteFilter};
+use crate::http::ResponseError;
+use crate::ApiService;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use uuid::Uuid;
+
+const DEFAULT_MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_WEBHOOK_RETRIES: u32 = 3;
+const DEFAULT_WEBHOOK_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Header carrying the message id, so a receiver can drop redeliveries.
+pub const MESSAGE_ID_HEADER: &str = "X-DataHub-Message-Id";
+/// Header carrying the Unix time (seconds) a signed delivery was made.
+pub const TIMESTAMP_HEADER: &str = "X-DataHub-Timestamp";
+/// Header carrying `sha256=<hex>`, the HMAC-SHA256 of `"{timestamp}.{body}"`.
+pub const SIGNATURE_HEADER: &str = "X-DataHub-Signature";
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error("sink i/o failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("webhook request failed: {0}")]
+    Request(String),
+    #[error("webhook answered {status}: {body}")]
+    Rejected { status: u16, body: String },
+    #[error(transparent)]
+    Payload(#[from] PayloadError),
+    /// The message can't be forwarded as it is, e.g. a series has no external id to write to.
+    #[error("cannot forward message: {0}")]
+    Unsupported(String),
+    #[error(transparent)]
+    DataHub(#[from] ResponseError),
+}
+
+/// Somewhere a [`SubscriptionConsumer`](super::SubscriptionConsumer) forwards messages to.
+pub trait SubscriptionSink: Send + Sync + 'static {
+    /// Store `message`, returning once it is safe to ack. An error has it redelivered.
+    fn send<'a>(&'a self, message: &'a SubscriptionMessage)
+        -> BoxFuture<'a, Result<(), SinkError>>;
+}
+
+/// Appends each message as one JSON line to `{prefix}-{time}.ndjson` files in a directory. A new
+/// file is started once the current one reaches [`with_max_bytes`](Self::with_max_bytes) or is
+/// older than [`with_max_age`](Self::with_max_age). Writes run on tokio's blocking thread pool.
+pub struct NdjsonFileSink {
+    rolling: Rolling,
+    // Shared with the blocking task that writes each line.
+    current: Arc<Mutex<Option<OpenFile>>>,
+}
+
+/// Where an [`NdjsonFileSink`] writes and when it starts a new file.
+#[derive(Clone)]
+struct Rolling {
+    dir: PathBuf,
+    prefix: String,
+    max_bytes: u64,
+    max_age: Option<Duration>,
+    sync: bool,
+}
+
+struct OpenFile {
+    file: File,
+    bytes: u64,
+    opened: Instant,
+}
+
+impl NdjsonFileSink {
+    /// Write into `dir`, creating it if needed.
+    pub fn open(dir: impl AsRef<Path>, prefix: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(NdjsonFileSink {
+            rolling: Rolling {
+                dir: dir.as_ref().to_path_buf(),
+                prefix: prefix.to_string(),
+                max_bytes: DEFAULT_MAX_FILE_BYTES,
+                max_age: None,
+                sync: false,
+            },
+            current: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Roll over to a new file once this many bytes are written (default 64 MiB).
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.rolling.max_bytes = max_bytes.max(1);
+        self
+    }
+
+    /// Roll over to a new file once the current one is this old.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.rolling.max_age = Some(max_age);
+        self
+    }
+
+    /// Sync every line to disk before it is acked. Without it a line survives the process
+    /// crashing, but not the machine.
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.rolling.sync = sync;
+        self
+    }
+}
+
+impl Rolling {
+    fn append(&self, current: &Mutex<Option<OpenFile>>, line: &[u8]) -> io::Result<()> {
+        let mut current = current.lock().unwrap();
+        let expired = current.as_ref().is_some_and(|open| {
+            open.bytes >= self.max_bytes
+                || self.max_age.is_some_and(|age| open.opened.elapsed() >= age)
+        });
+        if expired {
+            *current = None;
+        }
+        let open = match current.as_mut() {
+            Some(open) => open,
+            None => current.insert(self.create()?),
+        };
+        let written = open.file.write_all(line).and_then(|()| match self.sync {
+            true => open.file.sync_data(),
+            false => Ok(()),
+        });
+        match written {
+            Ok(()) => {
+                open.bytes += line.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                // The file may end in part of a line; the retry goes to a new one.
+                *current = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn create(&self) -> io::Result<OpenFile> {
+        let stamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+        // Files rolled within the same millisecond get a counter.
+        let mut n = 0;
+        loop {
+            let name = match n {
+                0 => format!("{}-{}.ndjson", self.prefix, stamp),
+                n => format!("{}-{}-{}.ndjson", self.prefix, stamp, n),
+            };
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.dir.join(name))
+            {
+                Ok(file) => {
+                    return Ok(OpenFile {
+                        file,
+                        bytes: 0,
+                        opened: Instant::now(),
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl SubscriptionSink for NdjsonFileSink {
+    fn send<'a>(
+        &'a self,
+        message: &'a SubscriptionMessage,
+    ) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(async move {
+            let mut line = serde_json::to_vec(message).map_err(io::Error::from)?;
+            line.push(b'\n');
+            // File writes (and syncs) block; keep them off the runtime's worker threads.
+            let (rolling, current) = (self.rolling.clone(), self.current.clone());
+            tokio::task::spawn_blocking(move || rolling.append(&current, &line))
+                .await
+                .map_err(|e| io::Error::other(e.to_string()))??;
+            Ok(())
+        })
+    }
+}
+
+/// Posts each message as JSON to a URL. Connection failures, timeouts, 408, 429 and 5xx answers
+/// are retried with a doubling backoff; any other non-2xx answer fails the message at once.
+///
+/// With [`with_secret`](Self::with_secret) every request carries [`TIMESTAMP_HEADER`] and
+/// [`SIGNATURE_HEADER`]: `sha256=` and the hex HMAC-SHA256 of the timestamp, a `.`, and the body.
+/// A receiver recomputes it with the shared secret and rejects stale timestamps.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<Vec<u8>>,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+    max_retries: u32,
+    initial_backoff: Duration,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> Self {
+        WebhookSink {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+            secret: None,
+            headers: Vec::new(),
+            timeout: DEFAULT_WEBHOOK_TIMEOUT,
+            max_retries: DEFAULT_WEBHOOK_RETRIES,
+            initial_backoff: DEFAULT_WEBHOOK_BACKOFF,
+        }
+    }
+
+    /// Sign every request with this shared secret.
+    pub fn with_secret(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.secret = Some(secret.as_ref().to_vec());
+        self
+    }
+
+    /// Send an extra header with every request, e.g. an API key.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// How long one request may take (default 10 s).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times a failed request is retried (default 3).
+    pub fn with_max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// The wait before the first retry, doubled for each one after (default 500 ms).
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    async fn post(&self, message_id: &str, body: &[u8]) -> Result<(), (bool, SinkError)> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .timeout(self.timeout)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(MESSAGE_ID_HEADER, message_id);
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(secret) = &self.secret {
+            let timestamp = Utc::now().timestamp().to_string();
+            request = request
+                .header(SIGNATURE_HEADER, sign(secret, &timestamp, body))
+                .header(TIMESTAMP_HEADER, timestamp);
+        }
+        let response = request
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| (true, SinkError::Request(e.to_string())))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let retry = status.is_server_error()
+            || status == reqwest::StatusCode::REQUEST_TIMEOUT
+            || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+        let body = response.text().await.unwrap_or_default();
+        Err((
+            retry,
+            SinkError::Rejected {
+                status: status.as_u16(),
+                body,
+            },
+        ))
+    }
+}
+
+impl SubscriptionSink for WebhookSink {
+    fn send<'a>(
+        &'a self,
+        message: &'a SubscriptionMessage,
+    ) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(async move {
+            let body = serde_json::to_vec(message).map_err(io::Error::from)?;
+            let mut backoff = self.initial_backoff;
+            let mut retries = 0;
+            loop {
+                match self.post(&message.message_id, &body).await {
+                    Ok(()) => return Ok(()),
+                    Err((true, _)) if retries < self.max_retries => {
+                        retries += 1;
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                    Err((_, e)) => return Err(e),
+                }
+            }
+        })
+    }
+}
+
+/// The [`SIGNATURE_HEADER`] value for `body` sent at `timestamp`.
+pub fn sign(secret: &[u8], timestamp: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+/// Writes the datapoints and events a subscription delivers into another [`ApiService`], such as
+/// a client for a different tenant.
+///
+/// - Created and updated datapoints are inserted, deleted ones deleted, into the series with the
+///   same external id. Series are matched by external id only, since numeric ids differ between
+///   tenants; a series without one fails the message.
+/// - Created and updated events are created under their original id, which replaces an earlier
+///   copy. Deleted events are deleted by id or external id.
+///
+/// Other payloads (time series, resources, relations and so on) are acked without being written.
+pub struct DataHubSink {
+    target: Arc<ApiService>,
+}
+
+impl DataHubSink {
+    pub fn new(target: Arc<ApiService>) -> Self {
+        DataHubSink { target }
+    }
+
+    async fn datapoints(&self, change: DatapointsChange) -> Result<(), SinkError> {
+        match change.action {
+            EventAction::Create | EventAction::Update => {
+                let mut insert = DataWrapper::new();
+                for series in change.series {
+                    let mut collection = DatapointsCollection::from_external_id(&target_series(
+                        &series.external_id,
+                    )?);
+                    collection.datapoints = series
+                        .datapoints
+                        .iter()
+                        .map(|p| {
+                            let value = match &p.value {
+                                LiveValue::Number(v) => v.to_string(),
+                                LiveValue::Text(t) => t.clone(),
+                            };
+                            DatapointString::from_datetime(p.timestamp, &value)
+                        })
+                        .collect::<Vec<DatapointString>>();
+                    insert.add_item(collection);
+                }
+                if !insert.get_items().is_empty() {
+                    self.target
+                        .time_series
+                        .insert_datapoints(&mut insert)
+                        .await?;
+                }
+            }
+            EventAction::Delete => {
+                let mut filters = Vec::new();
+                for series in change.series {
+                    let external_id = target_series(&series.external_id)?;
+                    if let (Some(begin), Some(end)) = (series.inclusive_begin, series.exclusive_end)
+                    {
+                        filters.push(delete_filter(&external_id, begin, end));
+                    }
+                    for p in &series.datapoints {
+                        let end = p.timestamp + chrono::Duration::milliseconds(1);
+                        filters.push(delete_filter(&external_id, p.timestamp, end));
+                    }
+                }
+                if !filters.is_empty() {
+                    self.target
+                        .time_series
+                        .delete_datapoints(&DataWrapper::from_vec(filters))
+                        .await?;
+                }
+            }
+            EventAction::Rename => {}
+        }
+        Ok(())
+    }
+
+    async fn events(&self, change: Change<Event>) -> Result<(), SinkError> {
+        match change {
+            Change::Created(events) | Change::Updated(events) | Change::Renamed(events) => {
+                if !events.is_empty() {
+                    self.target.events.create(&events).await?;
+                }
+            }
+            Change::Deleted(refs) => {
+                let ids: Vec<EventIdCollection> = refs.iter().filter_map(event_id).collect();
+                if !ids.is_empty() {
+                    self.target.events.delete(&ids).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SubscriptionSink for DataHubSink {
+    fn send<'a>(
+        &'a self,
+        message: &'a SubscriptionMessage,
+    ) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(async move {
+            match message.decode()? {
+                SubscriptionPayload::Datapoints(change) => self.datapoints(change).await,
+                SubscriptionPayload::Event(change) => self.events(change).await,
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+fn target_series(external_id: &Option<String>) -> Result<String, SinkError> {
+    external_id
+        .clone()
+        .ok_or_else(|| SinkError::Unsupported("a series has no external id".to_string()))
+}
+
+fn delete_filter(external_id: &str, begin: DateTime<Utc>, end: DateTime<Utc>) -> DeleteFilter {
+    DeleteFilter {
+        id: None,
+        external_id: Some(external_id.to_string()),
+        inclusive_begin: Some(begin),
+        exclusive_end: Some(end),
+    }
+}
+
+fn event_id(entity: &EntityRef) -> Option<EventIdCollection> {
+    match entity.id.as_deref().and_then(|id| Uuid::parse_str(id).ok()) {
+        Some(id) => Some(EventIdCollection::from_uuid(id)),
+        None => Some(EventIdCollection {
+            id: None,
+            external_id: Some(entity.external_id.clone()?),
+        }),
+    }
+}
@@ -4,8 +4,8 @@ mod tests {
     use crate::subscriptions::listen::build_ws_url;
     use crate::tests::cleanup::{cleanup_subscriptions, cleanup_timeseries};
     use crate::subscriptions::{
-        DataSort, EventAction, EventObject, Subscription, SubscriptionFilter, SubscriptionMessage,
-        SubscriptionRetriever,
+        DataSort, DataWrapperMessage, EventAction, EventObject, Subscription, SubscriptionFilter,
+        SubscriptionMessage, SubscriptionRetriever,
     };
     use crate::timeseries::TimeSeries;
     use crate::{create_api_service, ApiService};
@@ -158,6 +158,7 @@ mod tests {
                     },
                     limit: 100,
                     sort: DataSort::default(),
+                    ..Default::default()
                 })
                 .await?;
             assert!(
@@ -179,6 +180,7 @@ mod tests {
                     },
                     limit: 100,
                     sort: DataSort::default(),
+                    ..Default::default()
                 })
                 .await?;
             assert!(
@@ -412,6 +414,127 @@ mod tests {
         assert_eq!(back, serde_json::json!("RESOURCE_AND_RELATION"));
     }
 
+    fn message(action: &str, object: &str, items: serde_json::Value) -> DataWrapperMessage {
+        serde_json::from_value(serde_json::json!({
+            "eventAction": action,
+            "eventObject": object,
+            "items": items,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_datapoint_payload_decodes_timestamps_and_values() {
+        use crate::subscriptions::{LiveValue, SubscriptionPayload};
+        let msg = message(
+            "CREATE",
+            "DATAPOINTS",
+            serde_json::json!([
+                {"id": "30", "externalId": "pump-1.temp", "valueType": "float", "datapoints": [
+                    {"timestamp": "1723759200000", "value": "21.5"},
+                    {"timestamp": "2026-04-18T12:00:00Z", "value": "22"}
+                ]},
+                {"externalId": "pump-1.state", "valueType": "string", "datapoints": [
+                    {"timestamp": "1723759200000", "value": "42"}
+                ]}
+            ]),
+        );
+        let SubscriptionPayload::Datapoints(change) = msg.decode().unwrap() else {
+            panic!("expected datapoints");
+        };
+        assert_eq!(change.action, EventAction::Create);
+        let temp = &change.series[0];
+        assert_eq!(temp.id, Some(30));
+        assert_eq!(temp.datapoints[0].timestamp.timestamp_millis(), 1723759200000);
+        assert_eq!(temp.datapoints[0].value, LiveValue::Number(21.5));
+        assert_eq!(temp.datapoints[1].value.as_f64(), Some(22.0));
+        // A string-typed series keeps numeric-looking values as text.
+        assert_eq!(
+            change.series[1].datapoints[0].value,
+            LiveValue::Text("42".to_string())
+        );
+    }
+
+    #[test]
+    fn test_entity_payloads_decode_by_event_object_and_action() {
+        use crate::subscriptions::{Change, EntityRef, SubscriptionPayload};
+        let created = message(
+            "CREATE",
+            "EVENT",
+            serde_json::json!([{
+                "id": "0190c0de-0000-7000-8000-000000000001",
+                "externalId": "alarm-1",
+                "eventTime": "2026-04-18T12:00:00Z",
+                "relatedResources": [{"externalId": "pump-1"}]
+            }]),
+        );
+        // The datapoint view can't read an event; it leaves the item out rather than failing.
+        assert!(created.items.is_empty());
+        let Ok(SubscriptionPayload::Event(Change::Created(events))) = created.decode() else {
+            panic!("expected created events");
+        };
+        assert_eq!(events[0].external_id, "alarm-1");
+        assert_eq!(events[0].related_resources.len(), 1);
+
+        let renamed = message("RENAME", "LABEL", serde_json::json!([{"id": "7", "name": "PUMP"}]));
+        let Ok(SubscriptionPayload::Label(change)) = renamed.decode() else {
+            panic!("expected a label change");
+        };
+        assert_eq!(change.action(), EventAction::Rename);
+
+        let deleted = message(
+            "DELETE",
+            "RESOURCE",
+            serde_json::json!([{"id": 12}, {"externalId": "pump-2"}]),
+        );
+        let Ok(SubscriptionPayload::Resource(Change::Deleted(refs))) = deleted.decode() else {
+            panic!("expected deleted resources");
+        };
+        assert_eq!(
+            refs,
+            [
+                EntityRef { id: Some("12".to_string()), external_id: None },
+                EntityRef { id: None, external_id: Some("pump-2".to_string()) },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_payload_decode_errors_name_the_item_and_raw_items_round_trip() {
+        let msg = message(
+            "CREATE",
+            "DATAPOINTS",
+            serde_json::json!([
+                {"externalId": "a", "datapoints": [{"timestamp": "1", "value": "1"}]},
+                {"externalId": "b", "datapoints": [{"timestamp": "yesterday", "value": "1"}]}
+            ]),
+        );
+        let err = msg.decode().unwrap_err();
+        assert_eq!(err.index, 1);
+        assert!(err.reason.contains("yesterday"), "{}", err);
+
+        let wire = serde_json::to_value(&msg).unwrap();
+        assert_eq!(wire["items"], serde_json::json!(msg.raw_items()));
+        assert_eq!(wire["eventObject"], "DATAPOINTS");
+    }
+
+    #[test]
+    fn test_data_wrapper_message_new_and_serialize_items_without_raw_items() {
+        let item = serde_json::json!({"externalId": "a", "datapoints": [{"timestamp": "1", "value": "1"}]});
+        let mut msg = DataWrapperMessage::new(
+            EventAction::Create,
+            EventObject::Datapoints,
+            vec![item, serde_json::json!("not datapoints")],
+            None,
+        );
+        assert_eq!(msg.items.len(), 1);
+        assert_eq!(msg.raw_items.len(), 2);
+
+        msg.raw_items.clear();
+        let wire = serde_json::to_value(&msg).unwrap();
+        assert_eq!(wire["items"][0]["externalId"], "a");
+    }
+
     // End-to-end: requires the backend consumer running so datapoints written via the REST API are
     // fanned out over Pulsar to the subscription topic. See it live with:
     //   cargo test subscriptions::test::tests::test_subscription_listen_end_to_end -- --nocapture
@@ -496,6 +619,1183 @@ mod tests {
         result
     }
 
+    #[tokio::test]
+    async fn test_listener_streams_and_acks_through_a_split_handle() {
+        use crate::subscriptions::ListenError;
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use futures::StreamExt;
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let listener = api_service.subscriptions.listen(&["pumps"]).await.unwrap();
+        let (mut receiver, handle) = listener.split();
+        server.send(batch("pumps", &["m1", "m2", "m3"])).await;
+
+        // The receive half is a plain Stream; acking happens on another task through the handle.
+        let acker = handle.clone();
+        let ids: Vec<String> = receiver
+            .by_ref()
+            .take(3)
+            .map(|m| m.unwrap().message_id)
+            .collect()
+            .await;
+        assert_eq!(ids, ["m1", "m2", "m3"]);
+        tokio::spawn(async move { acker.ack(&ids).await })
+            .await
+            .unwrap()
+            .unwrap();
+        let received = server.wait_for_received(1).await;
+        assert_eq!(received[0]["action"], "ack");
+        assert_eq!(received[0]["messageIds"], serde_json::json!(["m1", "m2", "m3"]));
+
+        // Dropping the receive half stops the listener.
+        drop(receiver);
+        for _ in 0..100 {
+            if handle.is_closed() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(matches!(handle.nack(&["m1"]).await, Err(ListenError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn test_listener_reconnects_with_the_current_interest_set() {
+        use crate::tests::mock_listen::{batch, MockListenServer};
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let mut listener = api_service.subscriptions.listen(&["a"]).await.unwrap();
+        listener.handle().subscribe(&["b"]).await.unwrap();
+        assert_eq!(server.wait_for_received(1).await[0]["action"], "subscribe");
+
+        server.drop_connection();
+        server.send_on(2, batch("b", &["m1"])).await;
+        let msg = listener.next().await.unwrap().unwrap();
+        assert_eq!(msg.subscription_external_id, "b");
+        assert_eq!(
+            server.paths()[1],
+            "/timeseries/datapoints/subscription/listen/a/b"
+        );
+
+        listener.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listener_reports_reconnects_and_redeliveries() {
+        use crate::subscriptions::{ConnectionState, ListenOptions, ReconnectPolicy};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_in = seen.clone();
+        let options = ListenOptions::new()
+            .with_reconnect_policy(
+                ReconnectPolicy::unlimited().with_initial_backoff(Duration::from_millis(10)),
+            )
+            .on_state_change(move |state| seen_in.lock().unwrap().push(state.clone()));
+        let mut listener = api_service
+            .subscriptions
+            .listen_with(&["pumps"], options)
+            .await
+            .unwrap();
+        let mut state = listener.connection_state();
+        assert_eq!(*state.borrow(), ConnectionState::Connected);
+
+        server.send(batch("pumps", &["m1"])).await;
+        listener.next().await.unwrap().unwrap();
+        server.drop_connection();
+        // m1 was never acked, so the new connection delivers it again.
+        server.send_on(2, batch("pumps", &["m1", "m2"])).await;
+        listener.next().await.unwrap().unwrap();
+        listener.next().await.unwrap().unwrap();
+
+        state
+            .wait_for(|s| *s == ConnectionState::Connected)
+            .await
+            .unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                ConnectionState::Reconnecting { attempt: 1 },
+                ConnectionState::Connected
+            ]
+        );
+        let stats = listener.stats();
+        assert_eq!((stats.reconnects, stats.redelivered), (1, 1));
+
+        listener.close().await.unwrap();
+        assert_eq!(*state.borrow(), ConnectionState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_listener_fails_when_reconnect_attempts_run_out() {
+        use crate::subscriptions::{ConnectionState, ListenError, ListenOptions, ReconnectPolicy};
+        use crate::tests::mock_listen::MockListenServer;
+        use std::time::Duration;
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let policy = ReconnectPolicy::default()
+            .with_initial_backoff(Duration::from_millis(10))
+            .with_max_retries(Some(2));
+        let mut listener = api_service
+            .subscriptions
+            .listen_with(
+                &["pumps"],
+                ListenOptions::new().with_reconnect_policy(policy),
+            )
+            .await
+            .unwrap();
+        let mut state = listener.connection_state();
+
+        server.shut_down();
+        state
+            .wait_for(|s| *s == ConnectionState::Failed)
+            .await
+            .unwrap();
+        assert!(matches!(
+            listener.next().await,
+            Some(Err(ListenError::Handshake(_)))
+        ));
+        // The next round waits for the longest backoff.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*state.borrow(), ConnectionState::Failed);
+        assert_eq!(listener.stats().reconnects, 0);
+    }
+
+    #[tokio::test]
+    async fn test_listener_answers_handles_while_reconnecting() {
+        use crate::subscriptions::{ConnectionState, ListenError, ListenOptions, ReconnectPolicy};
+        use crate::tests::mock_listen::MockListenServer;
+        use std::time::Duration;
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let policy = ReconnectPolicy::unlimited()
+            .with_initial_backoff(Duration::from_secs(60))
+            .with_max_backoff(Duration::from_secs(60));
+        let listener = api_service
+            .subscriptions
+            .listen_with(
+                &["pumps"],
+                ListenOptions::new().with_reconnect_policy(policy),
+            )
+            .await
+            .unwrap();
+        let mut state = listener.connection_state();
+
+        server.shut_down();
+        state
+            .wait_for(|s| matches!(s, ConnectionState::Reconnecting { .. }))
+            .await
+            .unwrap();
+        let handle = listener.handle();
+        let acked = tokio::time::timeout(Duration::from_secs(1), handle.ack(&["m1"])).await;
+        assert!(matches!(acked, Ok(Err(ListenError::Disconnected))));
+        tokio::time::timeout(Duration::from_secs(1), handle.close())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(handle.is_closed());
+    }
+
+    #[test]
+    fn test_dedup_stores_evict_least_recently_used_and_reload_from_disk() {
+        use crate::subscriptions::{DedupStore, FileDedupStore, MemoryDedupStore};
+
+        let mut memory = MemoryDedupStore::new(2);
+        memory.insert("a").unwrap();
+        memory.insert("b").unwrap();
+        assert!(memory.contains("a").unwrap());
+        memory.insert("c").unwrap();
+        assert!(!memory.contains("b").unwrap());
+        assert!(memory.contains("a").unwrap() && memory.contains("c").unwrap());
+
+        let dir = std::env::temp_dir().join(format!("datahub_dedup_{}", uuid::Uuid::new_v4()));
+        let path = dir.join("processed");
+        {
+            let mut store = FileDedupStore::open(&path, 2).unwrap();
+            for key in ["a", "b", "c", "d", "e"] {
+                store.insert(key).unwrap();
+            }
+        }
+        // The file was compacted to the remembered keys.
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "d\ne\n");
+        let mut store = FileDedupStore::open(&path, 2).unwrap();
+        assert!(store.contains("e").unwrap());
+        assert!(!store.contains("a").unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_dedup_store_encrypts_its_keys() {
+        use crate::encryption::StaticKeys;
+        use crate::subscriptions::{DedupStore, FileDedupStore};
+        use std::sync::Arc;
+
+        let dir = std::env::temp_dir().join(format!("datahub_dedup_{}", uuid::Uuid::new_v4()));
+        let path = dir.join("processed");
+        let keys = Arc::new(StaticKeys::new("k1", [3; 32]));
+        {
+            let mut store = FileDedupStore::open_encrypted(&path, 10, keys.clone()).unwrap();
+            store.insert("m:secret-series").unwrap();
+        }
+        let stored = std::fs::read_to_string(&path).unwrap();
+        assert!(!stored.contains("secret-series"));
+        let mut store = FileDedupStore::open_encrypted(&path, 10, keys).unwrap();
+        assert!(store.contains("m:secret-series").unwrap());
+        // Without the key the store can't be read.
+        assert!(FileDedupStore::open(&path, 10).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listener_dedup_skips_processed_messages_and_datapoints() {
+        use crate::subscriptions::{ListenOptions, MemoryDedupStore, ReconnectPolicy};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use std::time::Duration;
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let options = ListenOptions::new()
+            .with_reconnect_policy(
+                ReconnectPolicy::default().with_initial_backoff(Duration::from_millis(10)),
+            )
+            .with_dedup(MemoryDedupStore::new(100));
+        let mut listener = api_service
+            .subscriptions
+            .listen_with(&["pumps"], options)
+            .await
+            .unwrap();
+        server.send(batch("pumps", &["m1"])).await;
+        let m1 = listener.next().await.unwrap().unwrap();
+        listener.ack(&[&m1.message_id]).await.unwrap();
+        server.wait_for_received(1).await;
+
+        server.drop_connection();
+        // m1 again, m2 with the same datapoint under a new id, and m3 with a new datapoint.
+        let mut frame = batch("pumps", &["m1", "m2", "m3"]);
+        frame["messages"][2]["payload"]["items"][0]["datapoints"][0]["timestamp"] =
+            "1723759260000".into();
+        server.send_on(2, frame).await;
+        let next = listener.next().await.unwrap().unwrap();
+        assert_eq!(next.message_id, "m3");
+
+        let received = server.wait_for_received(2).await;
+        assert_eq!(received[1]["action"], "ack");
+        assert_eq!(received[1]["messageIds"], serde_json::json!(["m1", "m2"]));
+        assert_eq!(listener.stats().duplicates, 2);
+
+        listener.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listener_reports_a_failing_dedup_store_and_delivers_anyway() {
+        use crate::subscriptions::{DedupStore, ListenError, ListenOptions};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+
+        struct Broken;
+        impl DedupStore for Broken {
+            fn contains(&mut self, _: &str) -> std::io::Result<bool> {
+                Err(std::io::Error::other("disk gone"))
+            }
+            fn insert(&mut self, _: &str) -> std::io::Result<()> {
+                Err(std::io::Error::other("disk gone"))
+            }
+        }
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let options = ListenOptions::new().with_dedup(Broken);
+        let mut listener = api_service
+            .subscriptions
+            .listen_with(&["pumps"], options)
+            .await
+            .unwrap();
+        server.send(batch("pumps", &["m1"])).await;
+        let err = listener.next().await.unwrap().unwrap_err();
+        assert!(matches!(err, ListenError::Dedup(ref e) if e.contains("disk gone")));
+        let m1 = listener.next().await.unwrap().unwrap();
+        assert_eq!(m1.message_id, "m1");
+
+        listener.ack(&["m1"]).await.unwrap();
+        let err = listener.next().await.unwrap().unwrap_err();
+        assert!(matches!(err, ListenError::Dedup(_)));
+        listener.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backfill_replays_history_then_skips_the_live_overlap() {
+        use crate::generic::IdAndExtId;
+        use crate::subscriptions::{BackfillItem, Subscription};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+
+        let server = MockListenServer::start_with(|request| {
+            let page = match request.json()["items"][0]["cursor"].as_str() {
+                None => serde_json::json!({
+                    "externalId": "pump-1.temp",
+                    "datapoints": [
+                        {"timestamp": "2024-08-15T21:58:00Z", "value": 21.1},
+                        {"timestamp": "2024-08-15T21:59:00Z", "value": 21.3}
+                    ],
+                    "nextCursor": "c1"
+                }),
+                Some(_) => serde_json::json!({
+                    "externalId": "pump-1.temp",
+                    "datapoints": [{"timestamp": "2024-08-15T22:00:00Z", "value": 21.5}]
+                }),
+            };
+            (200, serde_json::json!({ "items": [page] }).to_string())
+        })
+        .await;
+        let api_service = server.service();
+        let subscription = Subscription::new(
+            "pumps".to_string(),
+            "Pumps".to_string(),
+            vec![IdAndExtId::from_external_id("pump-1.temp")],
+        );
+        let since = "2024-08-15T21:00:00Z".parse().unwrap();
+        let mut listener = api_service
+            .subscriptions
+            .listen_with_backfill(&subscription, since)
+            .await
+            .unwrap();
+
+        let mut history = Vec::new();
+        for _ in 0..2 {
+            match listener.next().await.unwrap().unwrap() {
+                BackfillItem::History(series) => history.extend(series.datapoints),
+                other => panic!("expected history, got {:?}", other),
+            }
+        }
+        let values: Vec<f64> = history.iter().filter_map(|p| p.value.as_f64()).collect();
+        assert_eq!(values, [21.1, 21.3, 21.5]);
+        let requests = server.requests();
+        assert_eq!(requests[0].json()["items"][0]["start"], "2024-08-15T21:00:00Z");
+        assert_eq!(requests[1].json()["items"][0]["cursor"], "c1");
+
+        // m1 carries the 22:00 point the history ended with; m2 is new.
+        let mut frame = batch("pumps", &["m1", "m2"]);
+        frame["messages"][1]["payload"]["items"][0]["datapoints"][0]["timestamp"] =
+            "1723759260000".into();
+        server.send(frame).await;
+        match listener.next().await.unwrap().unwrap() {
+            BackfillItem::Live(message) => assert_eq!(message.message_id, "m2"),
+            other => panic!("expected a live message, got {:?}", other),
+        }
+        let received = server.wait_for_received(1).await;
+        assert_eq!(received[0]["action"], "ack");
+        assert_eq!(received[0]["messageIds"], serde_json::json!(["m1"]));
+
+        listener.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backfill_keeps_string_points_and_matches_the_overlap_by_value() {
+        use crate::generic::IdAndExtId;
+        use crate::subscriptions::{BackfillItem, LiveValue, Subscription};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+
+        let server = MockListenServer::start_with(|_| {
+            let page = serde_json::json!({
+                "externalId": "pump-1.temp",
+                "datapoints": [{"timestamp": "2024-08-15T22:00:00Z", "value": "open"}]
+            });
+            (200, serde_json::json!({ "items": [page] }).to_string())
+        })
+        .await;
+        let api_service = server.service();
+        let subscription = Subscription::new(
+            "pumps".to_string(),
+            "Pumps".to_string(),
+            vec![IdAndExtId::from_external_id("pump-1.temp")],
+        );
+        let since = "2024-08-15T21:00:00Z".parse().unwrap();
+        let mut listener = api_service
+            .subscriptions
+            .listen_with_backfill(&subscription, since)
+            .await
+            .unwrap();
+        match listener.next().await.unwrap().unwrap() {
+            BackfillItem::History(series) => {
+                let open = LiveValue::Text("open".to_string());
+                assert_eq!(series.datapoints[0].value, open)
+            }
+            other => panic!("expected history, got {:?}", other),
+        }
+
+        // Both carry the 22:00 point; only m1 has the value that was replayed.
+        let mut frame = batch("pumps", &["m1", "m2"]);
+        frame["messages"][0]["payload"]["items"][0]["datapoints"][0]["value"] = "open".into();
+        server.send(frame).await;
+        match listener.next().await.unwrap().unwrap() {
+            BackfillItem::Live(message) => assert_eq!(message.message_id, "m2"),
+            other => panic!("expected a live message, got {:?}", other),
+        }
+        let received = server.wait_for_received(1).await;
+        assert_eq!(received[0]["messageIds"], serde_json::json!(["m1"]));
+
+        listener.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_all_follows_cursors_and_lookups_include_system_managed() {
+        use crate::tests::mock_backend::MockBackend;
+
+        let backend = MockBackend::start(|request| {
+            let sub = |n: u64| {
+                serde_json::json!({
+                    "id": n.to_string(),
+                    "externalId": format!("sub-{}", n),
+                    "name": format!("Sub {}", n),
+                    "timeseries": []
+                })
+            };
+            let page = match request.json()["cursor"].as_str() {
+                None => serde_json::json!({ "items": [sub(1), sub(2)], "nextCursor": "c1" }),
+                Some("c1") => serde_json::json!({ "items": [sub(3)], "nextCursor": "c2" }),
+                // A backend handing back a cursor already followed must not loop.
+                Some(_) => serde_json::json!({ "items": [sub(4)], "nextCursor": "c2" }),
+            };
+            (200, page.to_string())
+        })
+        .await;
+        let api_service = backend.service();
+
+        let all = api_service
+            .subscriptions
+            .list_all(&SubscriptionRetriever::default())
+            .await
+            .unwrap();
+        let ids: Vec<&str> = all
+            .get_items()
+            .iter()
+            .map(|s| s.external_id.as_str())
+            .collect();
+        assert_eq!(ids, ["sub-1", "sub-2", "sub-3", "sub-4"]);
+        let requests = backend.requests_to("/subscriptions/list");
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].json().get("cursor").is_none());
+        assert!(requests[0].json().get("includeSystemManaged").is_none());
+        assert_eq!(requests[2].json()["cursor"], "c2");
+
+        let found = api_service
+            .subscriptions
+            .by_ids(&[
+                IdAndExtId::from_id(2),
+                IdAndExtId::from_external_id("sub-3"),
+            ])
+            .await
+            .unwrap();
+        let ids: Vec<Option<u64>> = found.get_items().iter().map(|s| s.id).collect();
+        assert_eq!(ids, [Some(2), Some(3)]);
+        let last = backend.requests().last().unwrap().json();
+        assert_eq!(last["includeSystemManaged"], true);
+        // Paging stops at the page that holds the last id asked for.
+        assert_eq!(backend.requests_to("/subscriptions/list").len(), 5);
+
+        assert_eq!(
+            api_service.subscriptions.get("sub-4").await.unwrap().name,
+            "Sub 4"
+        );
+        let missing = api_service.subscriptions.get("nope").await.unwrap_err();
+        assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(feature = "unstable-subscription-update")]
+    #[tokio::test]
+    async fn test_update_sends_rename_and_timeseries_delta() {
+        use crate::fields::{Field, ListField};
+        use crate::subscriptions::SubscriptionUpdate;
+        use crate::tests::mock_backend::MockBackend;
+
+        let backend = MockBackend::start(|_| {
+            let body = serde_json::json!({ "items": [{
+                "id": "7", "externalId": "pumps", "name": "All pumps",
+                "timeseries": [{"externalId": "pump-2.temp"}]
+            }]});
+            (200, body.to_string())
+        })
+        .await;
+        let update = SubscriptionUpdate::by_external_id("pumps")
+            .name(Field::value("All pumps"))
+            .timeseries(ListField::delta(
+                Some(vec![IdAndExtId::from_external_id("pump-2.temp")]),
+                Some(vec![IdAndExtId::from_external_id("pump-1.temp")]),
+            ));
+        let updated = backend
+            .service()
+            .subscriptions
+            .update(&update)
+            .await
+            .unwrap();
+        assert_eq!(updated.get_items()[0].name, "All pumps");
+
+        let requests = backend.requests_to("/subscriptions/update");
+        assert_eq!(
+            requests[0].json(),
+            serde_json::json!({ "items": [{
+                "externalId": "pumps",
+                "update": {
+                    "name": { "set": "All pumps", "setNull": false },
+                    "timeseries": {
+                        "add": [{ "externalId": "pump-2.temp" }],
+                        "remove": [{ "externalId": "pump-1.temp" }]
+                    }
+                }
+            }]})
+        );
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn test_blocking_listener_iterates_times_out_and_acks() {
+        use crate::blocking;
+        use crate::subscriptions::{ConnectionState, ListenError};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use std::time::Duration;
+
+        // The mock runs on its own runtime; the blocking client brings another.
+        let mock_rt = tokio::runtime::Runtime::new().unwrap();
+        let server = mock_rt.block_on(MockListenServer::start());
+        let api = blocking::ApiService::new(server.config());
+        let mut listener = api.subscriptions.listen(&["pumps"]).unwrap();
+        assert_eq!(listener.connection_state(), ConnectionState::Connected);
+
+        assert!(matches!(
+            listener.next_timeout(Duration::from_millis(50)),
+            Some(Err(ListenError::Timeout(_)))
+        ));
+        mock_rt.block_on(server.send(batch("pumps", &["m1", "m2"])));
+        let ids: Vec<String> = listener
+            .by_ref()
+            .take(2)
+            .map(|m| m.unwrap().message_id)
+            .collect();
+        assert_eq!(ids, ["m1", "m2"]);
+
+        // Acks from another thread go through a handle.
+        let handle = listener.handle();
+        std::thread::spawn(move || handle.ack(&["m1", "m2"]).unwrap())
+            .join()
+            .unwrap();
+        let received = mock_rt.block_on(server.wait_for_received(1));
+        assert_eq!(received[0]["messageIds"], serde_json::json!(["m1", "m2"]));
+
+        listener.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_consumer_runs_handlers_concurrently_and_batches_acks() {
+        use crate::subscriptions::SubscriptionConsumer;
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let listener = api_service.subscriptions.listen(&["pumps"]).await.unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (running_in, peak_in) = (running.clone(), peak.clone());
+        let consumer = SubscriptionConsumer::new(listener)
+            .with_concurrency(2)
+            .with_ack_interval(Duration::from_millis(50));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let consumer = tokio::spawn(consumer.run_until(
+            move |_message| {
+                let (running, peak) = (running_in.clone(), peak_in.clone());
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok::<(), String>(())
+                }
+            },
+            async {
+                let _ = stopped.await;
+            },
+        ));
+        server.send(batch("pumps", &["m1", "m2", "m3", "m4", "m5"])).await;
+
+        // Every message is acked, in fewer frames than messages.
+        let acked = || -> Vec<String> {
+            server
+                .received()
+                .iter()
+                .filter(|f| f["action"] == "ack")
+                .flat_map(|f| f["messageIds"].as_array().unwrap().clone())
+                .map(|id| id.as_str().unwrap().to_string())
+                .collect()
+        };
+        for _ in 0..200 {
+            if acked().len() == 5 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut ids = acked();
+        ids.sort();
+        assert_eq!(ids, ["m1", "m2", "m3", "m4", "m5"]);
+        assert!(server.received().len() < 5);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+
+        stop.send(()).unwrap();
+        let stats = consumer.await.unwrap();
+        assert_eq!(stats.handled, 5);
+        assert_eq!(stats.failed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_consumer_nacks_failures_then_dead_letters() {
+        use crate::subscriptions::{FailedMessage, SubscriptionConsumer};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let listener = api_service.subscriptions.listen(&["pumps"]).await.unwrap();
+        let dead = Arc::new(Mutex::new(Vec::<FailedMessage>::new()));
+        let sink = dead.clone();
+        let consumer = SubscriptionConsumer::new(listener)
+            .with_max_attempts(2)
+            .with_ack_interval(Duration::from_millis(10))
+            .with_dead_letter(move |failed: FailedMessage| {
+                sink.lock().unwrap().push(failed);
+                Ok(())
+            });
+        let handle = consumer.handle();
+        let consumer = tokio::spawn(consumer.run(|message: SubscriptionMessage| async move {
+            match message.message_id.as_str() {
+                "bad" => Err("cannot parse reading"),
+                _ => Ok(()),
+            }
+        }));
+
+        server.send(batch("pumps", &["bad", "good"])).await;
+        let frames = server.wait_for_received(2).await;
+        assert!(frames.iter().any(|f| f["action"] == "nack" && f["messageIds"][0] == "bad"));
+        // Pulsar redelivers the nacked message; the second failure dead-letters it.
+        server.send(batch("pumps", &["bad"])).await;
+        for _ in 0..200 {
+            if !dead.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        handle.close().await.unwrap();
+        let stats = consumer.await.unwrap();
+
+        let dead = dead.lock().unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].message.message_id, "bad");
+        assert_eq!(dead[0].attempts, 2);
+        assert_eq!(dead[0].error, "cannot parse reading");
+        assert_eq!(stats.handled, 1);
+        assert_eq!(stats.failed, 2);
+        assert_eq!(stats.dead_lettered, 1);
+    }
+
+    #[tokio::test]
+    async fn test_consumer_reports_a_failing_dead_letter_sink() {
+        use crate::subscriptions::{FailedMessage, ListenError, SubscriptionConsumer};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use std::sync::{Arc, Mutex};
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let listener = api_service.subscriptions.listen(&["pumps"]).await.unwrap();
+        let errors = Arc::new(Mutex::new(Vec::<String>::new()));
+        let seen = errors.clone();
+        let consumer = SubscriptionConsumer::new(listener)
+            .with_max_attempts(1)
+            .with_dead_letter(|_: FailedMessage| Err(std::io::Error::other("disk full")))
+            .with_error_handler(move |e: &ListenError| match e {
+                ListenError::DeadLetter { message_id, reason } => {
+                    seen.lock().unwrap().push(format!("{message_id}: {reason}"))
+                }
+                other => panic!("unexpected error: {other}"),
+            });
+        let handle = consumer.handle();
+        let consumer = tokio::spawn(
+            consumer.run(|_: SubscriptionMessage| async move { Err("cannot parse reading") }),
+        );
+
+        server.send(batch("pumps", &["bad"])).await;
+        // Not dead-lettered, so nacked for redelivery.
+        let frames = server.wait_for_received(1).await;
+        assert!(frames
+            .iter()
+            .any(|f| f["action"] == "nack" && f["messageIds"][0] == "bad"));
+        handle.close().await.unwrap();
+        let stats = consumer.await.unwrap();
+
+        assert_eq!(*errors.lock().unwrap(), vec!["bad: disk full".to_string()]);
+        assert_eq!(stats.dead_lettered, 0);
+    }
+
+    #[tokio::test]
+    async fn test_consumer_keeps_each_series_in_order() {
+        use crate::subscriptions::{DeliveryOrder, SubscriptionConsumer};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let listener = api_service.subscriptions.listen(&["pumps"]).await.unwrap();
+        let finished = Arc::new(Mutex::new(Vec::<String>::new()));
+        let log = finished.clone();
+        let consumer = SubscriptionConsumer::new(listener)
+            .with_concurrency(4)
+            .with_order(DeliveryOrder::PerSeries);
+        let handle = consumer.handle();
+        let consumer = tokio::spawn(consumer.run(move |message: SubscriptionMessage| {
+            let log = log.clone();
+            async move {
+                // The first message is the slowest; unordered, it would finish last.
+                let delay = if message.message_id == "m1" { 50 } else { 5 };
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                log.lock().unwrap().push(message.message_id);
+                Ok::<(), String>(())
+            }
+        }));
+
+        // All three carry the same series (see `batch`).
+        server.send(batch("pumps", &["m1", "m2", "m3"])).await;
+        for _ in 0..200 {
+            if finished.lock().unwrap().len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        handle.close().await.unwrap();
+        consumer.await.unwrap();
+        assert_eq!(*finished.lock().unwrap(), ["m1", "m2", "m3"]);
+    }
+
+    fn sink_message(
+        id: &str,
+        action: &str,
+        object: &str,
+        items: serde_json::Value,
+    ) -> SubscriptionMessage {
+        SubscriptionMessage {
+            subscription_external_id: "pumps".to_string(),
+            message_id: id.to_string(),
+            payload: message(action, object, items),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_sink_rolls_files_and_acks_after_writing() {
+        use crate::subscriptions::{NdjsonFileSink, SubscriptionConsumer};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use std::time::Duration;
+
+        let dir = std::env::temp_dir().join(format!("datahub_sink_{}", uuid::Uuid::new_v4()));
+        // Every line fills a file, so each message starts a new one.
+        let sink = NdjsonFileSink::open(&dir, "pumps")
+            .unwrap()
+            .with_max_bytes(1);
+        let server = MockListenServer::start().await;
+        let api_service = server.service();
+        let listener = api_service.subscriptions.listen(&["pumps"]).await.unwrap();
+        let consumer = SubscriptionConsumer::new(listener)
+            .with_concurrency(1)
+            .with_ack_interval(Duration::from_millis(10));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let consumer = tokio::spawn(consumer.run_sink_until(sink, async {
+            let _ = stopped.await;
+        }));
+        server.send(batch("pumps", &["m1", "m2", "m3"])).await;
+        let acked = || {
+            server
+                .received()
+                .iter()
+                .filter(|f| f["action"] == "ack")
+                .map(|f| f["messageIds"].as_array().unwrap().len())
+                .sum::<usize>()
+        };
+        for _ in 0..200 {
+            if acked() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(acked(), 3);
+        stop.send(()).unwrap();
+        assert_eq!(consumer.await.unwrap().handled, 3);
+
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 3);
+        let mut ids = Vec::new();
+        for file in &files {
+            let text = std::fs::read_to_string(file).unwrap();
+            assert_eq!(text.lines().count(), 1);
+            let line: serde_json::Value = serde_json::from_str(text.trim_end()).unwrap();
+            assert_eq!(line["payload"]["items"][0]["externalId"], "pump-1.temp");
+            ids.push(line["messageId"].as_str().unwrap().to_string());
+        }
+        ids.sort();
+        assert_eq!(ids, ["m1", "m2", "m3"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_sink_retries_server_errors_and_signs_requests() {
+        use crate::subscriptions::sink::{sign, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+        use crate::subscriptions::{SinkError, SubscriptionSink, WebhookSink};
+        use crate::tests::mock_backend::MockBackend;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let calls = AtomicUsize::new(0);
+        let backend = MockBackend::start(move |request| match request.path.as_str() {
+            "/hook" if calls.fetch_add(1, Ordering::SeqCst) == 0 => (503, String::new()),
+            "/hook" => (200, String::new()),
+            _ => (400, "bad payload".to_string()),
+        })
+        .await;
+        let message = sink_message(
+            "m1",
+            "CREATE",
+            "DATAPOINTS",
+            serde_json::json!([{"externalId": "pump-1.temp", "datapoints": []}]),
+        );
+
+        let sink = WebhookSink::new(&format!("{}/hook", backend.base_url))
+            .with_secret("s3cret")
+            .with_initial_backoff(Duration::from_millis(10));
+        sink.send(&message).await.unwrap();
+        let requests = backend.requests_to("/hook");
+        assert_eq!(requests.len(), 2);
+        let last = &requests[1];
+        assert_eq!(last.header("X-DataHub-Message-Id"), Some("m1"));
+        assert_eq!(last.json()["messageId"], "m1");
+        let timestamp = last.header(TIMESTAMP_HEADER).unwrap();
+        assert_eq!(
+            last.header(SIGNATURE_HEADER).unwrap(),
+            sign(b"s3cret", timestamp, last.body.as_bytes())
+        );
+
+        // A client error is not retried.
+        let sink = WebhookSink::new(&format!("{}/other", backend.base_url));
+        let err = sink.send(&message).await.unwrap_err();
+        assert!(matches!(err, SinkError::Rejected { status: 400, .. }));
+        assert_eq!(backend.requests_to("/other").len(), 1);
+    }
+
+    #[test]
+    fn test_webhook_signature_is_hmac_sha256_of_timestamp_and_body() {
+        use crate::subscriptions::sink::sign;
+        // hmac.new(b"key", b"1700000000.{}", hashlib.sha256).hexdigest() in Python.
+        assert_eq!(
+            sign(b"key", "1700000000", b"{}"),
+            "sha256=9d713ed406bb7076d4123f0dc2c39d2df5c654ed4b0cd56b52c8b4c940bd63ae"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_datahub_sink_mirrors_datapoints_and_events() {
+        use crate::subscriptions::{DataHubSink, SinkError, SubscriptionSink};
+        use crate::tests::mock_backend::MockBackend;
+
+        let target = MockBackend::start(|_| (200, r#"{"items":[]}"#.to_string())).await;
+        let sink = DataHubSink::new(target.service());
+
+        let points = sink_message(
+            "m1",
+            "CREATE",
+            "DATAPOINTS",
+            serde_json::json!([{"id": "30", "externalId": "pump-1.temp", "datapoints": [
+                {"timestamp": "1723759200000", "value": "21.5"}
+            ]}]),
+        );
+        sink.send(&points).await.unwrap();
+        // Written by external id only; the source tenant's numeric id means nothing to the target.
+        let inserted = &target.requests_to("/timeseries/data")[0].json()["items"][0];
+        assert_eq!(inserted["externalId"], "pump-1.temp");
+        assert!(inserted["id"].is_null());
+        assert_eq!(
+            inserted["datapoints"],
+            serde_json::json!([{"timestamp": "1723759200000", "value": "21.5"}])
+        );
+
+        let deleted = sink_message(
+            "m2",
+            "DELETE",
+            "DATAPOINTS",
+            serde_json::json!([{
+                "externalId": "pump-1.temp",
+                "inclusiveBegin": "2024-08-15T00:00:00Z",
+                "exclusiveEnd": "2024-08-16T00:00:00Z"
+            }]),
+        );
+        sink.send(&deleted).await.unwrap();
+        let filter = target.requests_to("/timeseries/data/delete")[0].json();
+        assert_eq!(filter["items"][0]["externalId"], "pump-1.temp");
+        assert_eq!(filter["items"][0]["inclusiveBegin"], "2024-08-15T00:00:00Z");
+
+        let event_id = "0190c0de-0000-7000-8000-000000000001";
+        let created = sink_message(
+            "m3",
+            "CREATE",
+            "EVENT",
+            serde_json::json!([{
+                "id": event_id,
+                "externalId": "alarm-1",
+                "eventTime": "2026-04-18T12:00:00Z"
+            }]),
+        );
+        sink.send(&created).await.unwrap();
+        let events = target.requests_to("/events/create")[0].json();
+        assert_eq!(events["items"][0]["id"], event_id);
+        assert_eq!(events["items"][0]["externalId"], "alarm-1");
+        let removed = sink_message(
+            "m4",
+            "DELETE",
+            "EVENT",
+            serde_json::json!([{"id": event_id}]),
+        );
+        sink.send(&removed).await.unwrap();
+        let ids = target.requests_to("/events/delete")[0].json();
+        assert_eq!(ids["items"], serde_json::json!([{"id": event_id}]));
+
+        // Anything else is acked unwritten; a series without an external id can't be mirrored.
+        let label = sink_message("m5", "CREATE", "LABEL", serde_json::json!([]));
+        sink.send(&label).await.unwrap();
+        let unnamed = sink_message(
+            "m6",
+            "CREATE",
+            "DATAPOINTS",
+            serde_json::json!([{"id": "30", "datapoints": []}]),
+        );
+        let err = sink.send(&unnamed).await.unwrap_err();
+        assert!(matches!(err, SinkError::Unsupported(_)));
+        assert_eq!(target.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_graph_mirror_bootstraps_and_answers_queries() {
+        use crate::resources::{RelatedResourcesForm, Resource};
+        use crate::subscriptions::GraphMirror;
+        use crate::tests::mock_backend::MockBackend;
+
+        let backend = MockBackend::start(|_| {
+            let body = r#"{
+                "nodes": [
+                    {"id":"1","externalId":"cooling_system","name":"Cooling system","isRoot":true},
+                    {"id":"2","externalId":"sensor_a","name":"Sensor A","isRoot":false},
+                    {"id":"3","externalId":"sensor_b","name":"Sensor B","isRoot":false},
+                    {"id":"4","externalId":"pump","name":"Pump","isRoot":false}
+                ],
+                "edges": [
+                    {"id":"10","start":2,"end":1,"type":"PART_OF"},
+                    {"id":"11","start":3,"end":1,"type":"PART_OF"},
+                    {"id":"12","start":4,"end":2,"type":"PART_OF"}
+                ]
+            }"#;
+            (200, body.to_string())
+        })
+        .await;
+        let api_service = backend.service();
+        let roots = [RelatedResourcesForm::from_external_id("cooling_system")];
+        let mirror = GraphMirror::bootstrap(&api_service, &roots).await.unwrap();
+        let requests = backend.requests_to("/resources/fetch-related");
+        assert_eq!(requests[0].json()["externalId"], "cooling_system");
+
+        let ids = |nodes: Vec<Resource>| nodes.iter().filter_map(|n| n.id).collect::<Vec<_>>();
+        assert_eq!((mirror.node_count(), mirror.edge_count()), (4, 3));
+        assert_eq!(ids(mirror.children(1)), [2, 3]);
+        assert_eq!(ids(mirror.children(2)), [4]);
+        assert_eq!(ids(mirror.neighbours(2)), [1, 4]);
+        assert_eq!(ids(mirror.path(4, 3).unwrap()), [4, 2, 1, 3]);
+        assert!(mirror.path(4, 99).is_none());
+        assert_eq!(mirror.node_by_external_id("sensor_b").unwrap().id, Some(3));
+        assert_eq!(mirror.network().edges.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_graph_mirror_applies_changes_made_while_bootstrapping_and_announces_resyncs() {
+        use crate::resources::RelatedResourcesForm;
+        use crate::subscriptions::{DeadLetterSink, FailedMessage, GraphChange, GraphMirror};
+        use crate::tests::mock_listen::{batch, MockListenServer};
+        use std::time::Duration;
+
+        let server = MockListenServer::start_with(|_| {
+            let body = r#"{
+                "nodes": [{"id":"1","externalId":"cooling_system","name":"Cooling system","isRoot":true}],
+                "edges": []
+            }"#;
+            (200, body.to_string())
+        })
+        .await;
+        let api_service = server.service();
+        let listener = api_service.subscriptions.listen(&["graph"]).await.unwrap();
+        let handle = listener.handle();
+        // Created after the listener connected, before the snapshot was read.
+        let frame = serde_json::json!({
+            "subscriptionExternalId": "graph",
+            "messages": [{
+                "messageId": "m1",
+                "payload": {"eventAction": "CREATE", "eventObject": "RESOURCE", "items": [
+                    {"id": "2", "externalId": "pump", "name": "Pump", "isRoot": false}
+                ]}
+            }]
+        });
+        server.send(frame).await;
+        let roots = [RelatedResourcesForm::from_external_id("cooling_system")];
+        let (mirror, follower) = GraphMirror::bootstrap_following(&api_service, &roots, listener)
+            .await
+            .unwrap();
+        for _ in 0..200 {
+            if mirror.node_count() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(mirror.node(1).is_some() && mirror.node(2).is_some());
+        handle.close().await.unwrap();
+        assert_eq!(follower.await.unwrap().handled, 1);
+
+        let mut changes = mirror.changes();
+        let message: crate::subscriptions::SubscriptionMessage =
+            serde_json::from_value(batch("graph", &["m2"])["messages"][0].clone()).unwrap();
+        let failed = FailedMessage {
+            message,
+            attempts: 5,
+            error: "edges/byids: 503".to_string(),
+        };
+        mirror.dead_letter(failed).unwrap();
+        let resync = GraphChange::Resync {
+            message_id: "m2".to_string(),
+            error: "edges/byids: 503".to_string(),
+        };
+        assert_eq!(changes.try_recv().unwrap(), resync);
+    }
+
+    #[tokio::test]
+    async fn test_graph_mirror_follows_resource_and_relation_messages() {
+        use crate::subscriptions::{GraphChange, GraphMirror};
+        use crate::tests::mock_listen::MockListenServer;
+        use std::time::Duration;
+
+        let server = MockListenServer::start_with(|request| {
+            let body = match request.path.as_str() {
+                "/resources/byids" => serde_json::json!({
+                    "nodes": [{"id":"3","externalId":"sensor_b","name":"Sensor B (moved)","isRoot":false}],
+                    "relations": [{"id":"13","start":3,"end":5,"type":"PART_OF"}]
+                }),
+                "/edges/byids" => serde_json::json!({
+                    "nodes": [],
+                    "relations": [{"id":"14","start":5,"end":1,"type":"FEEDS"}]
+                }),
+                _ => return (404, String::new()),
+            };
+            (200, body.to_string())
+        })
+        .await;
+        let api_service = server.service();
+        let mirror = GraphMirror::new(&api_service);
+        let mut changes = mirror.changes();
+        let listener = api_service.subscriptions.listen(&["graph"]).await.unwrap();
+        let handle = listener.handle();
+        let follower = tokio::spawn({
+            let mirror = mirror.clone();
+            async move { mirror.follow(listener).await }
+        });
+
+        let node = |id: u64, name: &str| {
+            let id = id.to_string();
+            serde_json::json!({"id": id, "externalId": name, "name": name, "isRoot": false})
+        };
+        let message = |id: &str, action: &str, object: &str, items: serde_json::Value| {
+            serde_json::json!({
+                "messageId": id,
+                "payload": {"eventAction": action, "eventObject": object, "items": items}
+            })
+        };
+        let frame = serde_json::json!({
+            "subscriptionExternalId": "graph",
+            "messages": [
+                message("m1", "CREATE", "RESOURCE", serde_json::json!([
+                    node(1, "cooling_system"), node(3, "sensor_b"), node(5, "chiller")
+                ])),
+                message("m2", "CREATE", "RELATION", serde_json::json!([
+                    {"id": "11", "start": 3, "end": 1, "type": "PART_OF"}
+                ])),
+                // No endpoints: read back from /edges/byids.
+                message("m3", "CREATE", "RELATION", serde_json::json!([{"id": "14"}])),
+                // Untyped: the named resource is read back from /resources/byids.
+                message("m4", "UPDATE", "RESOURCE_AND_RELATION", serde_json::json!([{"id": "3"}])),
+                message("m5", "DELETE", "RESOURCE", serde_json::json!([{"id": "1"}])),
+            ]
+        });
+        server.send(frame).await;
+        let acked = || {
+            server
+                .received()
+                .iter()
+                .filter(|f| f["action"] == "ack")
+                .map(|f| f["messageIds"].as_array().unwrap().len())
+                .sum::<usize>()
+        };
+        for _ in 0..200 {
+            if acked() == 5 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(acked(), 5);
+
+        let mut seen = Vec::new();
+        while let Ok(change) = changes.try_recv() {
+            seen.push(match change {
+                GraphChange::NodeAdded(n) => format!("+node {}", n.id.unwrap()),
+                GraphChange::NodeUpdated(n) => format!("~node {}", n.id.unwrap()),
+                GraphChange::NodeRemoved(n) => format!("-node {}", n.id.unwrap()),
+                GraphChange::EdgeAdded(e) => format!("+edge {}", e.id.unwrap()),
+                GraphChange::EdgeUpdated(e) => format!("~edge {}", e.id.unwrap()),
+                GraphChange::EdgeRemoved(e) => format!("-edge {}", e.id.unwrap()),
+                GraphChange::Resync { message_id, .. } => format!("resync {}", message_id),
+            });
+        }
+        assert_eq!(
+            seen,
+            [
+                "+node 1", "+node 3", "+node 5", "+edge 11", "+edge 14", "~node 3", "+edge 13",
+                "-edge 11", "-edge 14", "-node 1",
+            ]
+        );
+        let requests = server.requests();
+        let edges = requests.iter().find(|r| r.path == "/edges/byids").unwrap();
+        assert_eq!(edges.json()["items"], serde_json::json!([{"id": "14"}]));
+        let resources = requests
+            .iter()
+            .find(|r| r.path == "/resources/byids")
+            .unwrap();
+        assert_eq!(resources.json()["items"], serde_json::json!([{"id": "3"}]));
+
+        assert_eq!((mirror.node_count(), mirror.edge_count()), (2, 1));
+        assert_eq!(mirror.node(3).unwrap().name, "Sensor B (moved)");
+        assert_eq!(mirror.path(3, 5).unwrap().len(), 2);
+
+        handle.close().await.unwrap();
+        assert_eq!(follower.await.unwrap().handled, 5);
+    }
+
     #[allow(dead_code)]
     fn _require_send<T: Send>(_: &T) {}
 
@@ -403,6 +403,403 @@ pub mod cleanup {
         }
     }
 }
+
+pub mod mock_backend {
+    //! An in-process HTTP backend for offline tests.
+    //!
+    //! Accepts any number of (keep-alive) connections, records every request, and answers each
+    //! with whatever the test's `respond` closure returns for it. Good enough for reqwest; not a
+    //! general HTTP server — no chunked bodies, no pipelining.
+
+    use crate::datahub::DataHubConfig;
+    use crate::ApiService;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[derive(Debug, Clone)]
+    pub struct RecordedRequest {
+        pub method: String,
+        pub path: String,
+        pub headers: Vec<(String, String)>,
+        pub body: String,
+    }
+
+    impl RecordedRequest {
+        pub fn json(&self) -> serde_json::Value {
+            serde_json::from_str(&self.body).unwrap_or(serde_json::Value::Null)
+        }
+
+        /// The value of header `name` (case-insensitive), if sent.
+        pub fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    pub(super) type Responder = dyn Fn(&RecordedRequest) -> (u16, String) + Send + Sync;
+
+    pub struct MockBackend {
+        pub base_url: String,
+        requests: Arc<Mutex<Vec<RecordedRequest>>>,
+        handle: tokio::task::JoinHandle<()>,
+    }
+
+    impl MockBackend {
+        /// Start a backend answering every request with `respond(request)` → `(status, body)`.
+        pub async fn start<F>(respond: F) -> Self
+        where
+            F: Fn(&RecordedRequest) -> (u16, String) + Send + Sync + 'static,
+        {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base_url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let respond: Arc<Responder> = Arc::new(respond);
+            let recorded = requests.clone();
+            let handle = tokio::spawn(async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    tokio::spawn(serve(socket, recorded.clone(), respond.clone()));
+                }
+            });
+            MockBackend {
+                base_url,
+                requests,
+                handle,
+            }
+        }
+
+        /// Every request received so far, in arrival order.
+        pub fn requests(&self) -> Vec<RecordedRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        /// Requests received for `path` (exact match on the path, query string excluded).
+        pub fn requests_to(&self, path: &str) -> Vec<RecordedRequest> {
+            self.requests()
+                .into_iter()
+                .filter(|r| r.path.split('?').next() == Some(path))
+                .collect()
+        }
+
+        /// The config of a client pointed at this backend with a static token.
+        pub fn config(&self) -> DataHubConfig {
+            DataHubConfig::from_vars(
+                self.base_url.clone(),
+                Some("mock-token".to_string()),
+                None,
+                None,
+                None,
+                None,
+            )
+        }
+
+        /// A client pointed at this backend with a static token.
+        pub fn service(&self) -> Arc<ApiService> {
+            ApiService::new(self.config())
+        }
+    }
+
+    impl Drop for MockBackend {
+        fn drop(&mut self) {
+            self.handle.abort();
+        }
+    }
+
+    pub(super) async fn serve(
+        mut socket: TcpStream,
+        requests: Arc<Mutex<Vec<RecordedRequest>>>,
+        respond: Arc<Responder>,
+    ) {
+        let mut data: Vec<u8> = Vec::new();
+        loop {
+            // Read until a full request (headers + Content-Length body) is buffered.
+            let (head_len, body_len) = loop {
+                if let Some(idx) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&data[..idx]).to_string();
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse::<usize>().ok())?
+                        })
+                        .unwrap_or(0);
+                    if data.len() >= idx + 4 + length {
+                        break (idx, length);
+                    }
+                }
+                let mut chunk = [0u8; 8192];
+                match socket.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => data.extend_from_slice(&chunk[..n]),
+                }
+            };
+            let head = String::from_utf8_lossy(&data[..head_len]).to_string();
+            let body =
+                String::from_utf8_lossy(&data[head_len + 4..head_len + 4 + body_len]).to_string();
+            data.drain(..head_len + 4 + body_len);
+
+            let mut lines = head.lines();
+            let mut request_line = lines.next().unwrap_or_default().split(' ');
+            let headers = lines
+                .filter_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    Some((name.trim().to_string(), value.trim().to_string()))
+                })
+                .collect();
+            let request = RecordedRequest {
+                method: request_line.next().unwrap_or_default().to_string(),
+                path: request_line.next().unwrap_or_default().to_string(),
+                headers,
+                body,
+            };
+            requests.lock().unwrap().push(request.clone());
+            let (status, body) = respond(&request);
+            let response = format!(
+                "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            if socket.write_all(response.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+pub mod mock_listen {
+    //! An in-process subscription listen endpoint for offline tests.
+    //!
+    //! Accepts WebSocket connections on any path, records each handshake path and every text frame
+    //! the client sends, and pushes the frames a test hands it to the most recent connection.
+    //! `drop_connection` cuts that connection without a close handshake, as a network failure would.
+    //! Plain HTTP requests are answered as by a [`MockBackend`](super::mock_backend::MockBackend).
+
+    use super::mock_backend::{serve, RecordedRequest, Responder};
+    use crate::datahub::DataHubConfig;
+    use crate::ApiService;
+    use futures::{SinkExt, StreamExt};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[derive(Default)]
+    struct State {
+        paths: Vec<String>,
+        received: Vec<serde_json::Value>,
+        current: Option<mpsc::UnboundedSender<Message>>,
+    }
+
+    pub struct MockListenServer {
+        pub base_url: String,
+        state: Arc<Mutex<State>>,
+        requests: Arc<Mutex<Vec<RecordedRequest>>>,
+        handle: tokio::task::JoinHandle<()>,
+    }
+
+    impl MockListenServer {
+        pub async fn start() -> Self {
+            Self::start_with(|_| (404, String::new())).await
+        }
+
+        /// As `start`, answering plain HTTP requests with `respond(request)` → `(status, body)`.
+        pub async fn start_with<F>(respond: F) -> Self
+        where
+            F: Fn(&RecordedRequest) -> (u16, String) + Send + Sync + 'static,
+        {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base_url = format!("http://{}", listener.local_addr().unwrap());
+            let state = Arc::new(Mutex::new(State::default()));
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let respond: Arc<Responder> = Arc::new(respond);
+            let shared = state.clone();
+            let recorded = requests.clone();
+            let handle = tokio::spawn(async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    if !is_upgrade(&socket).await {
+                        tokio::spawn(serve(socket, recorded.clone(), respond.clone()));
+                        continue;
+                    }
+                    let state = shared.clone();
+                    let mut path = String::new();
+                    #[allow(clippy::result_large_err)] // the signature tungstenite expects
+                    let record_path = |req: &Request, res: Response| {
+                        path = req.uri().path().to_string();
+                        Ok(res)
+                    };
+                    let Ok(ws) = tokio_tungstenite::accept_hdr_async(socket, record_path).await
+                    else {
+                        continue;
+                    };
+                    let (tx, mut outgoing) = mpsc::unbounded_channel();
+                    {
+                        let mut state = state.lock().unwrap();
+                        state.paths.push(path);
+                        state.current = Some(tx);
+                    }
+                    tokio::spawn(async move {
+                        let (mut sink, mut stream) = ws.split();
+                        loop {
+                            tokio::select! {
+                                frame = outgoing.recv() => match frame {
+                                    Some(frame) => {
+                                        if sink.send(frame).await.is_err() {
+                                            return;
+                                        }
+                                    }
+                                    // Dropped by `drop_connection`: vanish without a close frame.
+                                    None => return,
+                                },
+                                incoming = stream.next() => match incoming {
+                                    Some(Ok(Message::Text(text))) => {
+                                        let value = serde_json::from_str(&text)
+                                            .unwrap_or(serde_json::Value::Null);
+                                        state.lock().unwrap().received.push(value);
+                                    }
+                                    Some(Ok(_)) => {}
+                                    None | Some(Err(_)) => return,
+                                },
+                            }
+                        }
+                    });
+                }
+            });
+            MockListenServer {
+                base_url,
+                state,
+                requests,
+                handle,
+            }
+        }
+
+        /// The config of a client pointed at this endpoint with a static token.
+        pub fn config(&self) -> DataHubConfig {
+            DataHubConfig::from_vars(
+                self.base_url.clone(),
+                Some("mock-token".to_string()),
+                None,
+                None,
+                None,
+                None,
+            )
+        }
+
+        /// A client pointed at this endpoint with a static token.
+        pub fn service(&self) -> Arc<ApiService> {
+            ApiService::new(self.config())
+        }
+
+        /// The handshake path of every connection so far, in order.
+        pub fn paths(&self) -> Vec<String> {
+            self.state.lock().unwrap().paths.clone()
+        }
+
+        /// Every plain HTTP request received so far, in arrival order.
+        pub fn requests(&self) -> Vec<RecordedRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        /// Every text frame the client has sent, parsed as JSON.
+        pub fn received(&self) -> Vec<serde_json::Value> {
+            self.state.lock().unwrap().received.clone()
+        }
+
+        /// Send `frame` to the latest connection, waiting for the `connections`-th to be accepted.
+        pub async fn send_on(&self, connections: usize, frame: serde_json::Value) {
+            self.wait_until(|s| s.paths.len() >= connections && s.current.is_some())
+                .await;
+            let state = self.state.lock().unwrap();
+            let _ = state
+                .current
+                .as_ref()
+                .unwrap()
+                .send(Message::Text(frame.to_string()));
+        }
+
+        /// Send `frame` to the first connection.
+        pub async fn send(&self, frame: serde_json::Value) {
+            self.send_on(1, frame).await
+        }
+
+        /// Cut the latest connection without a close handshake.
+        pub fn drop_connection(&self) {
+            self.state.lock().unwrap().current = None;
+        }
+
+        /// Stop accepting connections and cut the latest one, as an outage would.
+        pub fn shut_down(&self) {
+            self.handle.abort();
+            self.drop_connection();
+        }
+
+        /// Wait (up to 5s) until the client has sent at least `n` frames.
+        pub async fn wait_for_received(&self, n: usize) -> Vec<serde_json::Value> {
+            self.wait_until(|s| s.received.len() >= n).await;
+            self.received()
+        }
+
+        async fn wait_until(&self, done: impl Fn(&State) -> bool) {
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+            while !done(&self.state.lock().unwrap()) {
+                assert!(
+                    tokio::time::Instant::now() < deadline,
+                    "mock listen server: timed out waiting"
+                );
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    impl Drop for MockListenServer {
+        fn drop(&mut self) {
+            self.handle.abort();
+        }
+    }
+
+    /// Whether the request waiting on `socket` asks for a WebSocket upgrade (without consuming it).
+    async fn is_upgrade(socket: &tokio::net::TcpStream) -> bool {
+        let mut head = [0u8; 4096];
+        loop {
+            let Ok(n) = socket.peek(&mut head).await else {
+                return false;
+            };
+            let text = String::from_utf8_lossy(&head[..n]).to_ascii_lowercase();
+            if text.contains("\r\n\r\n") || n == head.len() {
+                return text.contains("upgrade: websocket");
+            }
+            if n == 0 {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    /// A batch frame delivering one datapoint per message id for `subscription`.
+    pub fn batch(subscription: &str, message_ids: &[&str]) -> serde_json::Value {
+        let messages: Vec<serde_json::Value> = message_ids
+            .iter()
+            .map(|id| {
+                serde_json::json!({
+                    "messageId": id,
+                    "payload": {
+                        "eventAction": "CREATE",
+                        "eventObject": "DATAPOINTS",
+                        "items": [{
+                            "externalId": "pump-1.temp",
+                            "datapoints": [{"timestamp": "1723759200000", "value": "21.5"}]
+                        }]
+                    }
+                })
+            })
+            .collect();
+        serde_json::json!({ "subscriptionExternalId": subscription, "messages": messages })
+    }
+}
 #[test]
 fn test_to_snake_lower_cased_allow_start_with_digits() {
     // tests validation function for externalId
@@ -452,3 +849,21 @@ async fn test_create_api_with_token() {
     let api = DataHubConfig::from_map(map).unwrap();
     assert_eq!(api.get_api_token().await.unwrap(), "testtoken".to_string());
 }
+#[test]
+fn test_buffer_durability_from_env() {
+    let map = |level: &str| {
+        hashmap! {
+            "BASE_URL".to_string() => "http://localhost:8081".to_string(),
+            "BUFFER_DURABILITY".to_string() => level.to_string(),
+            "BUFFER_GROUP_COMMIT_MS".to_string() => "20".to_string()
+        }
+    };
+    let config = DataHubConfig::from_map(map("per_append")).unwrap();
+    assert_eq!(config.buffer_durability, crate::buffer::Durability::PerAppend);
+    let config = DataHubConfig::from_map(map("Group_Commit")).unwrap();
+    assert_eq!(
+        config.buffer_durability,
+        crate::buffer::Durability::GroupCommit(std::time::Duration::from_millis(20))
+    );
+    assert!(DataHubConfig::from_map(map("always")).is_err());
+}
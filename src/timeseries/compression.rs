@@ -0,0 +1,516 @@
+//! Client-side lossy compression of numeric datapoints, the way process historians do it before
+//! storage.
+//!
+//! Three methods are available, each optionally combined with a `max_interval` heartbeat that
+//! stores a point at least that often however flat the signal is:
+//!
+//! - **Absolute deadband**: store a point only when it differs from the last stored value by more
+//!   than a fixed amount.
+//! - **Percent deadband**: the same, with the threshold a percentage of the last stored value.
+//! - **Swinging door**: store a point only when a straight line from the last stored point can no
+//!   longer pass within `tolerance` of every point since. Straight ramps collapse to their ends.
+//!
+//! Like exception reporting in a historian, the deadbands also store the last suppressed point
+//! before an exception. A step then keeps its corner instead of turning into a slow ramp.
+//!
+//! A [`Compressor`] is streaming and keeps per-series state; swinging door holds back the latest
+//! point until the next one shows whether it is needed, and [`Compressor::finish`] releases it.
+//! Non-numeric values and out-of-order timestamps pass through unchanged and restart compression.
+//!
+//! Settings come from [`CompressionSettings`] constructors or from a time series' metadata (see
+//! [`CompressionSettings::from_metadata`]). Use them with a
+//! [`DatapointWriter`](super::DatapointWriter) through
+//! [`DatapointWriterOptions::with_compression`](super::DatapointWriterOptions::with_compression),
+//! or compress a request up front with [`compress_collection`].
+
+use crate::datapoints::parse_timestamp;
+use crate::generic::{DatapointString, DatapointsCollection};
+use crate::timeseries::TimeSeries;
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Metadata key selecting the method: `deadband`, `deadband_percent`, `swinging_door` or `none`.
+pub const METADATA_METHOD: &str = "compression.method";
+/// Metadata key for the deadband width, the percentage, or the swinging-door tolerance.
+pub const METADATA_TOLERANCE: &str = "compression.tolerance";
+/// Metadata key for the heartbeat interval, in milliseconds.
+pub const METADATA_MAX_INTERVAL_MS: &str = "compression.max_interval_ms";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CompressionError {
+    #[error("unknown compression method '{0}'")]
+    UnknownMethod(String),
+    #[error("invalid value '{value}' for '{key}'")]
+    InvalidSetting { key: String, value: String },
+    #[error("'{0}' is required for this compression method")]
+    MissingSetting(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompressionMethod {
+    /// Store a point when it differs from the last stored value by more than this amount.
+    AbsoluteDeadband(f64),
+    /// Store a point when it differs from the last stored value by more than this percentage of
+    /// that value.
+    PercentDeadband(f64),
+    /// Swinging-door trending with this tolerance, in the series' own units.
+    SwingingDoor(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionSettings {
+    pub method: CompressionMethod,
+    /// Store a point at least this often, even when the method would drop it.
+    pub max_interval: Option<Duration>,
+}
+
+impl CompressionSettings {
+    pub fn absolute_deadband(width: f64) -> Self {
+        CompressionSettings {
+            method: CompressionMethod::AbsoluteDeadband(width.abs()),
+            max_interval: None,
+        }
+    }
+
+    pub fn percent_deadband(percent: f64) -> Self {
+        CompressionSettings {
+            method: CompressionMethod::PercentDeadband(percent.abs()),
+            max_interval: None,
+        }
+    }
+
+    pub fn swinging_door(tolerance: f64) -> Self {
+        CompressionSettings {
+            method: CompressionMethod::SwingingDoor(tolerance.abs()),
+            max_interval: None,
+        }
+    }
+
+    pub fn with_max_interval(mut self, max_interval: Duration) -> Self {
+        self.max_interval = Some(max_interval);
+        self
+    }
+
+    /// Read settings from time-series metadata. `Ok(None)` when the series doesn't ask for
+    /// compression (no [`METADATA_METHOD`] key, or `none`).
+    ///
+    /// ```text
+    /// compression.method          = swinging_door
+    /// compression.tolerance       = 0.5
+    /// compression.max_interval_ms = 600000
+    /// ```
+    pub fn from_metadata(
+        metadata: &HashMap<String, String>,
+    ) -> Result<Option<Self>, CompressionError> {
+        let Some(method) = metadata.get(METADATA_METHOD) else {
+            return Ok(None);
+        };
+        let method = method.trim().to_ascii_lowercase();
+        if method.is_empty() || method == "none" {
+            return Ok(None);
+        }
+        let tolerance = || -> Result<f64, CompressionError> {
+            let raw = metadata
+                .get(METADATA_TOLERANCE)
+                .ok_or_else(|| CompressionError::MissingSetting(METADATA_TOLERANCE.to_string()))?;
+            raw.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|t| t.is_finite() && *t >= 0.0)
+                .ok_or_else(|| CompressionError::InvalidSetting {
+                    key: METADATA_TOLERANCE.to_string(),
+                    value: raw.clone(),
+                })
+        };
+        let mut settings = match method.as_str() {
+            "deadband" | "absolute_deadband" => Self::absolute_deadband(tolerance()?),
+            "deadband_percent" | "percent_deadband" => Self::percent_deadband(tolerance()?),
+            "swinging_door" | "swingingdoor" | "sdt" => Self::swinging_door(tolerance()?),
+            _ => return Err(CompressionError::UnknownMethod(method)),
+        };
+        if let Some(raw) = metadata.get(METADATA_MAX_INTERVAL_MS) {
+            let ms = raw
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| CompressionError::InvalidSetting {
+                    key: METADATA_MAX_INTERVAL_MS.to_string(),
+                    value: raw.clone(),
+                })?;
+            settings.max_interval = Some(Duration::from_millis(ms));
+        }
+        Ok(Some(settings))
+    }
+}
+
+impl TimeSeries {
+    /// This series' compression settings from its metadata; see
+    /// [`CompressionSettings::from_metadata`].
+    pub fn compression_settings(&self) -> Result<Option<CompressionSettings>, CompressionError> {
+        match &self.metadata {
+            Some(metadata) => CompressionSettings::from_metadata(metadata),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Points in and out of a compressor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    pub input_points: u64,
+    pub output_points: u64,
+}
+
+impl CompressionStats {
+    /// Input points per output point; `10.0` means ten times fewer points were sent. `1.0` before
+    /// anything has been stored.
+    pub fn ratio(&self) -> f64 {
+        if self.output_points == 0 {
+            1.0
+        } else {
+            self.input_points as f64 / self.output_points as f64
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Point {
+    ts: i64,
+    value: f64,
+    raw: DatapointString,
+}
+
+impl Point {
+    fn parse(dp: &DatapointString) -> Option<Point> {
+        let value = dp
+            .value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())?;
+        let ts = parse_timestamp(&dp.timestamp)?.timestamp_millis();
+        Some(Point {
+            ts,
+            value,
+            raw: dp.clone(),
+        })
+    }
+}
+
+/// Streaming compressor for one series.
+#[derive(Debug, Clone)]
+pub struct Compressor {
+    settings: CompressionSettings,
+    /// The last point stored.
+    archived: Option<Point>,
+    /// The latest point received and not stored yet.
+    held: Option<Point>,
+    /// Swinging door: the tightest upper and lower slopes (per ms) from `archived`.
+    upper: f64,
+    lower: f64,
+    stats: CompressionStats,
+}
+
+impl Compressor {
+    pub fn new(settings: CompressionSettings) -> Self {
+        Compressor {
+            settings,
+            archived: None,
+            held: None,
+            upper: f64::INFINITY,
+            lower: f64::NEG_INFINITY,
+            stats: CompressionStats::default(),
+        }
+    }
+
+    pub fn settings(&self) -> &CompressionSettings {
+        &self.settings
+    }
+
+    pub fn stats(&self) -> CompressionStats {
+        self.stats
+    }
+
+    /// Feed one point; any points to store are appended to `out`.
+    pub fn push(&mut self, dp: DatapointString, out: &mut Vec<DatapointString>) {
+        self.stats.input_points += 1;
+        let Some(point) = Point::parse(&dp) else {
+            self.finish(out);
+            self.archived = None;
+            self.store_raw(dp, out);
+            return;
+        };
+        let Some(archived) = self.archived.clone() else {
+            self.store(point, out);
+            return;
+        };
+        let last_ts = self.held.as_ref().map_or(archived.ts, |h| h.ts);
+        if point.ts <= last_ts {
+            self.finish(out);
+            self.store(point, out);
+            return;
+        }
+        match self.settings.method {
+            CompressionMethod::AbsoluteDeadband(width) => {
+                self.deadband(&archived, point, width, out)
+            }
+            CompressionMethod::PercentDeadband(percent) => {
+                let width = archived.value.abs() * percent / 100.0;
+                self.deadband(&archived, point, width, out)
+            }
+            CompressionMethod::SwingingDoor(tolerance) => {
+                self.swinging_door(&archived, point, tolerance, out)
+            }
+        }
+        // Heartbeat: if the point just received is still held back and the last stored point is
+        // too old, store it now.
+        if let (Some(max), Some(held), Some(archived)) =
+            (self.settings.max_interval, &self.held, &self.archived)
+        {
+            if held.ts - archived.ts >= max.as_millis() as i64 {
+                self.finish(out);
+            }
+        }
+    }
+
+    /// Store the held-back point, if any. Call at the end of a stream or before a flush;
+    /// compression continues from that point afterwards.
+    pub fn finish(&mut self, out: &mut Vec<DatapointString>) {
+        if let Some(held) = self.held.take() {
+            self.store(held, out);
+        }
+    }
+
+    fn deadband(
+        &mut self,
+        archived: &Point,
+        point: Point,
+        width: f64,
+        out: &mut Vec<DatapointString>,
+    ) {
+        if (point.value - archived.value).abs() > width {
+            self.finish(out);
+            self.store(point, out);
+        } else {
+            self.held = Some(point);
+        }
+    }
+
+    fn swinging_door(
+        &mut self,
+        archived: &Point,
+        point: Point,
+        tolerance: f64,
+        out: &mut Vec<DatapointString>,
+    ) {
+        let (upper, lower) = door(archived, &point, tolerance);
+        let (upper, lower) = (self.upper.min(upper), self.lower.max(lower));
+        if lower > upper {
+            // The doors have opened past parallel: no line from `archived` covers every point
+            // up to this one, so the previous point is the last that can be represented.
+            let held = self
+                .held
+                .take()
+                .expect("a later point always has a held predecessor");
+            let (upper, lower) = door(&held, &point, tolerance);
+            self.store(held, out);
+            self.upper = upper;
+            self.lower = lower;
+        } else {
+            self.upper = upper;
+            self.lower = lower;
+        }
+        self.held = Some(point);
+    }
+
+    fn store(&mut self, point: Point, out: &mut Vec<DatapointString>) {
+        out.push(point.raw.clone());
+        self.stats.output_points += 1;
+        self.archived = Some(point);
+        self.held = None;
+        self.upper = f64::INFINITY;
+        self.lower = f64::NEG_INFINITY;
+    }
+
+    fn store_raw(&mut self, dp: DatapointString, out: &mut Vec<DatapointString>) {
+        out.push(dp);
+        self.stats.output_points += 1;
+    }
+}
+
+/// Upper and lower slopes from `from` through `to` ± `tolerance`.
+fn door(from: &Point, to: &Point, tolerance: f64) -> (f64, f64) {
+    let dt = (to.ts - from.ts) as f64;
+    (
+        (to.value + tolerance - from.value) / dt,
+        (to.value - tolerance - from.value) / dt,
+    )
+}
+
+/// Compress a whole list of points in one go, including the final held-back point.
+pub fn compress_datapoints(
+    points: Vec<DatapointString>,
+    settings: &CompressionSettings,
+) -> (Vec<DatapointString>, CompressionStats) {
+    let mut compressor = Compressor::new(settings.clone());
+    let mut out = Vec::new();
+    for dp in points {
+        compressor.push(dp, &mut out);
+    }
+    compressor.finish(&mut out);
+    (out, compressor.stats())
+}
+
+/// Compress a collection's points in place before handing it to
+/// [`insert_datapoints`](super::TimeSeriesService::insert_datapoints).
+pub fn compress_collection(
+    collection: &mut DatapointsCollection<DatapointString>,
+    settings: &CompressionSettings,
+) -> CompressionStats {
+    let (points, stats) = compress_datapoints(std::mem::take(&mut collection.datapoints), settings);
+    collection.datapoints = points;
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(values: &[(i64, &str)]) -> Vec<DatapointString> {
+        values
+            .iter()
+            .map(|(ts, v)| DatapointString::new(&ts.to_string(), v))
+            .collect()
+    }
+
+    fn timestamps(points: &[DatapointString]) -> Vec<i64> {
+        points
+            .iter()
+            .map(|p| p.timestamp.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn absolute_deadband_keeps_the_corner_of_a_step() {
+        let input = series(&[(0, "10"), (1, "10.2"), (2, "9.9"), (3, "15"), (4, "15.1")]);
+        let (out, stats) = compress_datapoints(input, &CompressionSettings::absolute_deadband(1.0));
+        // 0 stored first; 3 is an exception, preceded by the suppressed 2; 4 is held, then
+        // released at the end of the stream.
+        assert_eq!(timestamps(&out), vec![0, 2, 3, 4]);
+        assert_eq!(stats.input_points, 5);
+        assert_eq!(stats.output_points, 4);
+    }
+
+    #[test]
+    fn percent_deadband_scales_with_the_value() {
+        let settings = CompressionSettings::percent_deadband(10.0);
+        let mut c = Compressor::new(settings);
+        let mut out = vec![];
+        for (ts, v) in [(0, "100"), (1, "109"), (2, "91"), (3, "120")] {
+            c.push(DatapointString::new(&ts.to_string(), v), &mut out);
+        }
+        assert_eq!(timestamps(&out), vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn swinging_door_collapses_a_ramp() {
+        let input: Vec<DatapointString> = (0..100)
+            .map(|i| DatapointString::new(&i.to_string(), &(i as f64 * 0.5).to_string()))
+            .collect();
+        let (out, stats) = compress_datapoints(input, &CompressionSettings::swinging_door(0.1));
+        assert_eq!(timestamps(&out), vec![0, 99]);
+        assert_eq!(stats.ratio(), 50.0);
+    }
+
+    #[test]
+    fn swinging_door_keeps_the_turning_point() {
+        let mut points = vec![];
+        for i in 0..=10 {
+            points.push((i, i as f64));
+        }
+        for i in 11..=20 {
+            points.push((i, (20 - i) as f64));
+        }
+        let input = points
+            .iter()
+            .map(|(t, v)| DatapointString::new(&t.to_string(), &v.to_string()))
+            .collect();
+        let (out, _) = compress_datapoints(input, &CompressionSettings::swinging_door(0.25));
+        assert_eq!(timestamps(&out), vec![0, 10, 20]);
+    }
+
+    #[test]
+    fn heartbeat_stores_a_flat_signal_periodically() {
+        let input: Vec<DatapointString> = (0..10)
+            .map(|i| DatapointString::new(&(i * 1000).to_string(), "1"))
+            .collect();
+        let settings =
+            CompressionSettings::absolute_deadband(1.0).with_max_interval(Duration::from_secs(3));
+        let mut c = Compressor::new(settings);
+        let mut out = vec![];
+        for dp in input {
+            c.push(dp, &mut out);
+        }
+        assert_eq!(timestamps(&out), vec![0, 3000, 6000, 9000]);
+    }
+
+    #[test]
+    fn non_numeric_and_out_of_order_points_pass_through() {
+        let input = series(&[(0, "1"), (1, "1"), (2, "on"), (3, "1"), (1, "1")]);
+        let (out, _) = compress_datapoints(input, &CompressionSettings::absolute_deadband(5.0));
+        assert_eq!(timestamps(&out), vec![0, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn rfc3339_timestamps_are_understood() {
+        let input = vec![
+            DatapointString::new("2024-01-01T00:00:00Z", "1"),
+            DatapointString::new("2024-01-01T00:00:01Z", "1"),
+            DatapointString::new("2024-01-01T00:00:02Z", "1"),
+        ];
+        let (out, _) = compress_datapoints(input, &CompressionSettings::swinging_door(0.1));
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[1].timestamp, "2024-01-01T00:00:02Z");
+    }
+
+    #[test]
+    fn settings_from_metadata() {
+        let mut md = HashMap::new();
+        assert_eq!(CompressionSettings::from_metadata(&md), Ok(None));
+
+        md.insert(METADATA_METHOD.to_string(), "swinging_door".to_string());
+        assert_eq!(
+            CompressionSettings::from_metadata(&md),
+            Err(CompressionError::MissingSetting(
+                METADATA_TOLERANCE.to_string()
+            ))
+        );
+
+        md.insert(METADATA_TOLERANCE.to_string(), "0.5".to_string());
+        md.insert(METADATA_MAX_INTERVAL_MS.to_string(), "60000".to_string());
+        assert_eq!(
+            CompressionSettings::from_metadata(&md),
+            Ok(Some(
+                CompressionSettings::swinging_door(0.5).with_max_interval(Duration::from_secs(60))
+            ))
+        );
+
+        md.insert(METADATA_METHOD.to_string(), "gzip".to_string());
+        assert!(matches!(
+            CompressionSettings::from_metadata(&md),
+            Err(CompressionError::UnknownMethod(_))
+        ));
+
+        let mut ts = TimeSeries::new("a", "a");
+        assert_eq!(ts.compression_settings(), Ok(None));
+        md.insert(METADATA_METHOD.to_string(), "deadband".to_string());
+        ts.set_metadata(md);
+        assert_eq!(
+            ts.compression_settings(),
+            Ok(Some(
+                CompressionSettings::absolute_deadband(0.5)
+                    .with_max_interval(Duration::from_secs(60))
+            ))
+        );
+    }
+}
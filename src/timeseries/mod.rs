@@ -1,49 +1,105 @@
+pub mod compression;
 mod test;
+pub mod validation;
+pub mod writer;
 
-use crate::buffer::DurableSpool;
-use crate::datahub::DataHubConfig;
+use crate::buffer::{LazySpool, SpoolAdmin};
+use crate::datahub::{DataHubConfig, DEFAULT_BUFFER_MAX_BYTES, DEFAULT_BUFFER_RETENTION_MS};
+use crate::dead_letter::{bisect, DeadLetterRecord, DeadLetterSpool, ReplayReport};
+use crate::datapoints::expression::{Alignment, CalculatedSeries, Expression, ExpressionError};
 use crate::fields::{Field, ListField, MapField};
 use crate::generic::{
     ApiServiceProvider, DataWrapper, Datapoint, DatapointString, DatapointsCollection,
     DeleteFilter, IdAndExtId, RetrieveFilter, SearchAndFilterForm,
     SearchForm,
 };
+use crate::mutations::{buffered_items, MutationKind};
 use crate::relations::RelatedNode;
 use crate::http::{process_response, ResponseError};
 use crate::serde_helper::is_zero;
+use crate::unit::{UnitConverter, UnitError};
+use crate::timeseries::writer::SeriesKey;
 use crate::ApiService;
 use chrono::{DateTime, Utc};
 use futures::{future::join_all, FutureExt};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::clone::Clone;
 use std::collections::HashMap;
+use std::io;
+use std::path::Path;
 use std::sync::{Mutex, Weak};
+use std::time::{Duration, Instant};
 
-/// A single spooled datapoint (flattened from a `DatapointsCollection`), used by durable buffering.
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct SpoolDatapoint {
+pub use compression::{
+    compress_collection, compress_datapoints, CompressionError, CompressionMethod,
+    CompressionSettings, CompressionStats, Compressor,
+};
+pub use validation::{
+    DuplicateAction, InvalidAction, PointIssue, PointReport, ValidationError, ValidationOptions,
+    ValidationReport,
+};
+pub use writer::{
+    DatapointWriter, DatapointWriterOptions, OverflowPolicy, WriterError, WriterStats,
+};
+
+/// A single spooled datapoint (flattened from a `DatapointsCollection`), used by durable buffering
+/// and in dead letters.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SpoolDatapoint {
     #[serde(default, with = "crate::serde_helper::opt_string_id", skip_serializing_if = "Option::is_none")]
-    id: Option<u64>,
+    pub id: Option<u64>,
     #[serde(rename = "externalId", skip_serializing_if = "Option::is_none")]
-    external_id: Option<String>,
-    timestamp: String,
-    value: String,
+    pub external_id: Option<String>,
+    pub timestamp: String,
+    pub value: String,
+}
+
+impl SpoolAdmin<'_, SpoolDatapoint> {
+    /// Remove every spooled datapoint of one series, matched by id or external id. Returns the
+    /// number removed.
+    pub fn purge_series(&self, series: &IdAndExtId) -> io::Result<u64> {
+        self.purge_where(|dp| {
+            (series.id.is_some() && dp.id == series.id)
+                || (series.external_id.is_some() && dp.external_id == series.external_id)
+        })
+    }
 }
 
 pub struct TimeSeriesService {
     pub(crate) api_service: Weak<ApiService>,
     base_url: String,
     // Durable spool for datapoint ingestion (lazily opened on first buffered send; None if off).
-    spool: Mutex<Option<DurableSpool>>,
+    spool: LazySpool,
+    // Spooled datapoints the backend rejected on drain.
+    dead_letter: DeadLetterSpool<SpoolDatapoint>,
+    // Serializes drains (ingest calls, the background flush, `flush_buffers`).
+    drain_lock: tokio::sync::Mutex<()>,
+    // Points removed by `InvalidAction::Quarantine` (lazily opened on first use).
+    quarantine: LazySpool,
+    // Value types looked up for validation.
+    value_types: Mutex<HashMap<SeriesKey, String>>,
+    // Series whose value type could not be looked up, and when; not asked for again until
+    // VALUE_TYPE_RETRY has passed.
+    value_type_misses: Mutex<HashMap<SeriesKey, Instant>>,
 }
 
+/// How long a series whose value type could not be looked up is checked as float before the
+/// lookup is tried again.
+const VALUE_TYPE_RETRY: Duration = Duration::from_secs(60);
+
 impl TimeSeriesService {
     pub fn new(api_service: Weak<ApiService>, base_url: &String) -> Self {
         let base_url = format!("{}/timeseries", base_url);
         TimeSeriesService {
             api_service,
             base_url,
-            spool: Mutex::new(None),
+            spool: LazySpool::default(),
+            dead_letter: DeadLetterSpool::new("datapoints"),
+            drain_lock: tokio::sync::Mutex::new(()),
+            quarantine: LazySpool::default(),
+            value_types: Mutex::new(HashMap::new()),
+            value_type_misses: Mutex::new(HashMap::new()),
         }
     }
 
@@ -65,7 +121,14 @@ impl TimeSeriesService {
         json: &DataWrapper<TimeSeries>,
     ) -> Result<DataWrapper<TimeSeries>, ResponseError> {
         let path = &format!("{}/create", self.base_url);
-        self.execute_post_request::<DataWrapper<TimeSeries>, _>(path, json)
+        let mutations = self.get_api_service().mutations.clone();
+        mutations
+            .submit(
+                MutationKind::TimeSeriesCreate,
+                json,
+                buffered_items,
+                self.execute_post_request::<DataWrapper<TimeSeries>, _>(path, json),
+            )
             .await
     }
 
@@ -196,29 +259,202 @@ impl TimeSeriesService {
         self.insert_datapoints(&mut data_request).await
     }
 
+    /// Start a [`DatapointWriter`] that batches datapoints in the background and sends them
+    /// through [`insert_datapoints`](Self::insert_datapoints). Must be called inside a Tokio
+    /// runtime.
+    pub fn writer(&self, options: DatapointWriterOptions) -> DatapointWriter {
+        DatapointWriter::spawn(self.api_service.clone(), options)
+    }
+
     /// Insert datapoints. With durable buffering enabled on the client this flushes any on-disk
     /// backlog first, sends in <=100k-datapoint chunks, and spools to disk on a transient failure
     /// (e.g. the server is unreachable); otherwise it behaves exactly as before. Retries are safe:
     /// datapoints dedup on `(series, timestamp)` in the backend's ReplacingMergeTree.
+    ///
+    /// With `DataHubConfig::set_datapoint_validation` the request is validated first, as by
+    /// [`insert_datapoints_validated`](Self::insert_datapoints_validated); a rejected request
+    /// fails with a 400 before anything is sent.
     pub async fn insert_datapoints(
         &self,
         json: &mut DataWrapper<DatapointsCollection<DatapointString>>,
+    ) -> Result<DataWrapper<String>, ResponseError> {
+        let validation = self.get_api_service().config.datapoint_validation.clone();
+        match validation {
+            Some(options) => Ok(self.insert_datapoints_validated(json, &options).await?.0),
+            None => self.send_datapoints(json).await,
+        }
+    }
+
+    /// Validate and sanitise `json` in place (see [`validation`]) without sending it. Value
+    /// types of series not named in `options` are looked up once and cached; a series that
+    /// can't be looked up is checked as `float`, and not looked up again for a minute. No lookup
+    /// is made while the datapoint spool holds a backlog, as the points are buffered behind it.
+    /// Under [`InvalidAction::Quarantine`] the invalid points are written to the quarantine
+    /// spool; if that fails, [`ValidationReport::quarantine_error`] says why.
+    pub async fn validate_datapoints(
+        &self,
+        json: &mut DataWrapper<DatapointsCollection<DatapointString>>,
+        options: &ValidationOptions,
+    ) -> ValidationReport {
+        let value_types = if options.lookup_value_types {
+            self.lookup_value_types(json, options).await
+        } else {
+            HashMap::new()
+        };
+        let mut report = validation::sanitize(json, options, &value_types, Utc::now());
+        if options.action == InvalidAction::Quarantine && !report.invalid.is_empty() {
+            if let Err(e) = self.quarantine_points(&report.invalid) {
+                report.quarantine_error = Some(e.to_string());
+            }
+        }
+        report
+    }
+
+    /// [`validate_datapoints`](Self::validate_datapoints), then send what is left. Under
+    /// [`InvalidAction::Reject`] nothing is sent if any point is invalid.
+    pub async fn insert_datapoints_validated(
+        &self,
+        json: &mut DataWrapper<DatapointsCollection<DatapointString>>,
+        options: &ValidationOptions,
+    ) -> Result<(DataWrapper<String>, ValidationReport), ValidationError> {
+        let report = self.validate_datapoints(json, options).await;
+        if options.action == InvalidAction::Reject && !report.is_clean() {
+            return Err(ValidationError::Rejected(report));
+        }
+        if report.accepted == 0 {
+            let mut w = DataWrapper::new();
+            w.set_http_status_code(204);
+            return Ok((w, report));
+        }
+        Ok((self.send_datapoints(json).await?, report))
+    }
+
+    /// Invalid points held in the quarantine spool.
+    pub fn quarantined_count(&self) -> u64 {
+        self.quarantine.lock().unwrap().as_ref().map_or(0, |s| s.size())
+    }
+
+    async fn lookup_value_types(
+        &self,
+        json: &DataWrapper<DatapointsCollection<DatapointString>>,
+        options: &ValidationOptions,
+    ) -> HashMap<SeriesKey, String> {
+        let keys: Vec<SeriesKey> = json
+            .get_items()
+            .iter()
+            .filter_map(|c| SeriesKey::of(c.id, c.external_id.as_ref()))
+            .collect();
+        // Only the cached types of the series in `json`.
+        let cached = || {
+            let cache = self.value_types.lock().unwrap();
+            keys.iter()
+                .filter_map(|key| cache.get(key).map(|t| (key.clone(), t.clone())))
+                .collect()
+        };
+        // The backend was unreachable at the last send; these points will be spooled behind it.
+        if self.get_api_service().config.buffering_enabled() && self.buffered_count() > 0 {
+            return cached();
+        }
+        let mut missing: Vec<SeriesKey> = vec![];
+        {
+            let cache = self.value_types.lock().unwrap();
+            let mut misses = self.value_type_misses.lock().unwrap();
+            misses.retain(|_, at| at.elapsed() < VALUE_TYPE_RETRY);
+            for key in &keys {
+                if options.value_type(key).is_none()
+                    && !cache.contains_key(key)
+                    && !misses.contains_key(key)
+                    && !missing.contains(key)
+                {
+                    missing.push(key.clone());
+                }
+            }
+        }
+        if !missing.is_empty() {
+            let ids = missing
+                .iter()
+                .map(|key| match key {
+                    SeriesKey::Id(id) => IdAndExtId::from_id(*id),
+                    SeriesKey::ExternalId(ext) => IdAndExtId::from_external_id(ext),
+                })
+                .collect();
+            // Unknown series fail the lookup; they are then checked as float.
+            if let Ok(found) = self.by_ids(&DataWrapper::from_vec(ids)).await {
+                let mut cache = self.value_types.lock().unwrap();
+                for ts in found.get_items() {
+                    for key in validation::series_keys(ts) {
+                        cache.insert(key, ts.value_type.clone());
+                    }
+                }
+            }
+            let cache = self.value_types.lock().unwrap();
+            let mut misses = self.value_type_misses.lock().unwrap();
+            let now = Instant::now();
+            for key in missing.into_iter().filter(|key| !cache.contains_key(key)) {
+                misses.insert(key, now);
+            }
+        }
+        cached()
+    }
+
+    /// Write `points` to the quarantine spool. Failures are reported to the buffer observer
+    /// and returned.
+    fn quarantine_points(&self, points: &[PointReport]) -> io::Result<()> {
+        let config = &self.get_api_service().config;
+        let notifier = config.spool_notifier("quarantine");
+        let retention_ms = config
+            .buffer_retention_ms
+            .unwrap_or(DEFAULT_BUFFER_RETENTION_MS);
+        let max_bytes = config.buffer_max_bytes.unwrap_or(DEFAULT_BUFFER_MAX_BYTES);
+        let opened = self.quarantine.ensure(config.buffer_lock_mode, || {
+            let dir = config.buffer_directory().join("quarantine");
+            config
+                .open_spool(dir, Some(retention_ms), Some(max_bytes))
+                .map(|spool| spool.with_notifier(notifier.clone()))
+        });
+        if let Err(e) = opened {
+            notifier.io_error("open", &e);
+            return Err(e);
+        }
+        let mut guard = self.quarantine.lock().unwrap();
+        // A recent open failed and isn't retried yet.
+        let Some(spool) = guard.as_mut() else {
+            return Err(io::Error::other("the quarantine spool could not be opened"));
+        };
+        let now = Utc::now().timestamp_millis();
+        let records: Vec<(i64, String)> = points
+            .iter()
+            .filter_map(|p| serde_json::to_string(p).ok().map(|json| (now, json)))
+            .collect();
+        spool
+            .append(&records, now)
+            .inspect_err(|e| spool.report_io_error("append", e))
+    }
+
+    async fn send_datapoints(
+        &self,
+        json: &mut DataWrapper<DatapointsCollection<DatapointString>>,
     ) -> Result<DataWrapper<String>, ResponseError> {
         let svc = self.get_api_service();
         if !svc.config.buffering_enabled() {
             drop(svc);
             return self.insert_datapoints_unbuffered(json).await;
         }
-        self.ensure_spool(&svc.config);
+        let lock_mode = svc.config.buffer_lock_mode;
+        let dir = spool_dir(&svc.config);
         drop(svc); // don't hold the ApiService Arc across awaits
+        self.spool.hand_over(&dir, lock_mode).await;
+        self.ensure_spool(&self.get_api_service().config);
 
         let path = format!("{}/data", self.base_url);
         let now = Utc::now().timestamp_millis();
         let new_dps = flatten_collections(json.get_items());
 
-        if !self.drain_spool(&path, now).await {
-            self.append_to_spool(&new_dps, now);
-            return Ok(buffered_string_wrapper());
+        if let Err(e) = self.drain_spool(&path, now).await {
+            return match self.append_to_spool(&new_dps, now) {
+                true => Ok(buffered_string_wrapper()),
+                false => Err(e),
+            };
         }
         match self.post_datapoint_chunks(&path, &new_dps).await {
             Ok(()) => {
@@ -226,10 +462,11 @@ impl TimeSeriesService {
                 w.set_http_status_code(204);
                 Ok(w)
             }
-            Err(e) if e.is_bufferable() => {
-                self.append_to_spool(&new_dps, now);
-                Ok(buffered_string_wrapper())
-            }
+            Err(e) if e.is_bufferable() => match self.append_to_spool(&new_dps, now) {
+                true => Ok(buffered_string_wrapper()),
+                // Not spooled, e.g. another process holds the spool directory.
+                false => Err(e),
+            },
             Err(e) => Err(e),
         }
     }
@@ -239,21 +476,48 @@ impl TimeSeriesService {
         self.spool.lock().unwrap().as_ref().map_or(0, |s| s.size())
     }
 
+    /// Inspect and administer the durable datapoint spool. Opens the spool (finding any backlog left
+    /// on disk) if buffering is enabled; when it is off the spool reads as empty.
+    pub fn spool(&self) -> SpoolAdmin<'_, SpoolDatapoint> {
+        let svc = self.get_api_service();
+        if svc.config.buffering_enabled() {
+            self.ensure_spool(&svc.config);
+        }
+        SpoolAdmin::new(&self.spool)
+    }
+
+    /// Records in the spool, opening it (and so finding a backlog left on disk, after a
+    /// hand-over if configured) if needed.
+    pub(crate) async fn pending_count(&self) -> u64 {
+        let svc = self.get_api_service();
+        if svc.config.buffering_enabled() {
+            let lock_mode = svc.config.buffer_lock_mode;
+            let dir = spool_dir(&svc.config);
+            drop(svc);
+            self.spool.hand_over(&dir, lock_mode).await;
+            self.ensure_spool(&self.get_api_service().config);
+        }
+        self.buffered_count()
+    }
+
     fn ensure_spool(&self, config: &DataHubConfig) {
-        let mut guard = self.spool.lock().unwrap();
-        if guard.is_none() {
-            let dir = config.buffer_directory().join("datapoints");
-            if let Ok(spool) = DurableSpool::open(
-                dir,
-                config.effective_buffer_retention_ms(),
-                config.effective_buffer_max_bytes(),
-            ) {
-                *guard = Some(spool);
-            }
+        let notifier = config.spool_notifier("datapoints");
+        let opened = self.spool.ensure(config.buffer_lock_mode, || {
+            config
+                .open_spool(
+                    spool_dir(config),
+                    config.effective_buffer_retention_ms(),
+                    config.effective_buffer_max_bytes(),
+                )
+                .map(|spool| spool.with_notifier(notifier.clone()))
+        });
+        if let Err(e) = opened {
+            notifier.io_error("open", &e);
         }
     }
 
-    fn append_to_spool(&self, dps: &[SpoolDatapoint], now: i64) {
+    /// Spool `dps`; `false` if they could not be written.
+    fn append_to_spool(&self, dps: &[SpoolDatapoint], now: i64) -> bool {
         let records: Vec<(i64, String)> = dps
             .iter()
             .filter_map(|dp| {
@@ -261,8 +525,16 @@ impl TimeSeriesService {
                 serde_json::to_string(dp).ok().map(|json| (ts, json))
             })
             .collect();
-        if let Some(spool) = self.spool.lock().unwrap().as_mut() {
-            let _ = spool.append(&records, now);
+        let mut guard = self.spool.lock().unwrap();
+        let Some(spool) = guard.as_mut() else {
+            return false;
+        };
+        match spool.append(&records, now) {
+            Ok(()) => true,
+            Err(e) => {
+                spool.report_io_error("append", &e);
+                false
+            }
         }
     }
 
@@ -279,11 +551,35 @@ impl TimeSeriesService {
         Ok(())
     }
 
-    /// Drain the datapoint spool, oldest segment first. Returns false on a transient failure (server
-    /// still down), leaving the rest buffered; terminal failures drop the offending segment.
-    async fn drain_spool(&self, path: &str, now: i64) -> bool {
+    /// Drain the datapoint spool now (a no-op when buffering is off). Fails with the transient
+    /// error if the backend is still unreachable, leaving the rest buffered.
+    pub(crate) async fn flush_spool(&self) -> Result<(), ResponseError> {
+        let svc = self.get_api_service();
+        if !svc.config.buffering_enabled() {
+            return Ok(());
+        }
+        let lock_mode = svc.config.buffer_lock_mode;
+        let dir = spool_dir(&svc.config);
+        drop(svc);
+        self.spool.hand_over(&dir, lock_mode).await;
+        self.ensure_spool(&self.get_api_service().config);
+        let path = format!("{}/data", self.base_url);
+        self.drain_spool(&path, Utc::now().timestamp_millis()).await
+    }
+
+    /// Drain the datapoint spool, oldest segment first. Fails on a transient failure (server still
+    /// down), or with a [backlog-blocked](ResponseError::is_backlog_blocked) error at a segment
+    /// that can't be read, leaving the rest buffered; rejected records are dead-lettered. One
+    /// drain runs at a time.
+    async fn drain_spool(&self, path: &str, now: i64) -> Result<(), ResponseError> {
+        // Buffered metadata writes go first, so e.g. a series created offline exists by now.
+        let mutations = self.get_api_service().mutations.clone();
+        mutations.drain().await?;
+        let _draining = self.drain_lock.lock().await;
         if let Some(spool) = self.spool.lock().unwrap().as_mut() {
-            let _ = spool.roll(now);
+            if let Err(e) = spool.roll(now) {
+                spool.report_io_error("roll", &e);
+            }
         }
         loop {
             let seq = self
@@ -293,15 +589,24 @@ impl TimeSeriesService {
                 .as_ref()
                 .and_then(|s| s.oldest_sealed_seq());
             let Some(seq) = seq else {
-                return true;
+                return Ok(());
+            };
+            let read = self.spool.lock().unwrap().as_ref().map(|s| {
+                s.read_segment(seq, now)
+                    .inspect_err(|e| s.report_io_error("read", e))
+            });
+            let lines = match read {
+                Some(Ok(lines)) => lines,
+                // Keep an unreadable segment (e.g. its encryption key is missing) for a later
+                // drain or an operator's purge; the records behind it wait too, to stay in order.
+                Some(Err(e)) => {
+                    return Err(ResponseError::backlog_blocked(format!(
+                        "datapoint spool segment {} can't be read: {}",
+                        seq, e
+                    )))
+                }
+                None => return Ok(()),
             };
-            let lines = self
-                .spool
-                .lock()
-                .unwrap()
-                .as_ref()
-                .map(|s| s.read_segment(seq, now).unwrap_or_default())
-                .unwrap_or_default();
             if lines.is_empty() {
                 if let Some(s) = self.spool.lock().unwrap().as_mut() {
                     let _ = s.delete_segment(seq);
@@ -312,17 +617,60 @@ impl TimeSeriesService {
                 .iter()
                 .filter_map(|l| serde_json::from_str(l).ok())
                 .collect();
-            match self.post_datapoint_chunks(path, &dps).await {
-                Err(e) if e.is_bufferable() => return false,
-                _ => {
+            let sent = dps.len() as u64;
+            // A rejected segment is bisected so only the offending points are dead-lettered.
+            let send = |batch: Vec<SpoolDatapoint>| async move {
+                self.post_datapoint_chunks(path, &batch).await
+            };
+            match bisect(dps, send).await {
+                Err(e) => return Err(e),
+                Ok(rejected) => {
+                    let delivered = sent - rejected.len() as u64;
+                    self.dead_letter
+                        .append(&self.get_api_service().config, rejected);
                     if let Some(s) = self.spool.lock().unwrap().as_mut() {
                         let _ = s.delete_segment(seq);
+                        s.report_drained(seq, delivered);
                     }
                 }
             }
         }
     }
 
+    /// Spooled datapoints the backend rejected while draining (see [`crate::dead_letter`]).
+    pub fn dead_letter_count(&self) -> u64 {
+        self.dead_letter.count(&self.get_api_service().config)
+    }
+
+    pub fn dead_letters(&self) -> io::Result<Vec<DeadLetterRecord<SpoolDatapoint>>> {
+        self.dead_letter.records(&self.get_api_service().config)
+    }
+
+    /// Write the dead letters to `path` as newline-delimited JSON; returns how many.
+    pub fn export_dead_letters(&self, path: &Path) -> io::Result<usize> {
+        self.dead_letter
+            .export(&self.get_api_service().config, path)
+    }
+
+    pub fn clear_dead_letters(&self) -> io::Result<()> {
+        self.dead_letter.clear(&self.get_api_service().config)
+    }
+
+    /// Resend the dead letters, each passed through `fix` first (return `None` to discard one).
+    /// Points rejected again stay dead-lettered with the new error.
+    pub async fn replay_dead_letters<F>(&self, fix: F) -> Result<ReplayReport, ResponseError>
+    where
+        F: FnMut(SpoolDatapoint) -> Option<SpoolDatapoint>,
+    {
+        let config = self.get_api_service().config.clone();
+        let path = format!("{}/data", self.base_url);
+        let path = path.as_str();
+        let send = |batch: Vec<SpoolDatapoint>| async move {
+            self.post_datapoint_chunks(path, &batch).await
+        };
+        self.dead_letter.replay(&config, fix, send).await
+    }
+
     async fn insert_datapoints_unbuffered(
         &self,
         json: &mut DataWrapper<DatapointsCollection<DatapointString>>,
@@ -427,8 +775,17 @@ impl TimeSeriesService {
         &self,
         json: &DataWrapper<RetrieveFilter>,
     ) -> Result<DataWrapper<DatapointsCollection<Datapoint>>, ResponseError> {
+        self.retrieve_datapoints_as(json).await
+    }
+
+    /// [`retrieve_datapoints`](Self::retrieve_datapoints) with the points read as `T`, e.g. to
+    /// keep the string values a [`Datapoint`] can't hold.
+    pub(crate) async fn retrieve_datapoints_as<T: DeserializeOwned>(
+        &self,
+        json: &DataWrapper<RetrieveFilter>,
+    ) -> Result<DataWrapper<DatapointsCollection<T>>, ResponseError> {
         let path = &format!("{}/data/list", self.base_url);
-        self.execute_post_request::<DataWrapper<DatapointsCollection<Datapoint>>, _>(path, json)
+        self.execute_post_request::<DataWrapper<DatapointsCollection<T>>, _>(path, json)
             .await
     }
 
@@ -449,6 +806,163 @@ impl TimeSeriesService {
         self.execute_post_request::<DataWrapper<DatapointsCollection<Datapoint>>, _>(path, json)
             .await
     }
+
+    /// [`retrieve_datapoints`](Self::retrieve_datapoints), with values and aggregates converted
+    /// to `target_unit` (an external id, symbol or alias from the unit catalog). Each series is
+    /// converted from the unit the response names, or else from the series' own unit. Fails
+    /// without converting anything if any series' unit is unknown or measures another quantity.
+    pub async fn retrieve_datapoints_in_unit(
+        &self,
+        json: &DataWrapper<RetrieveFilter>,
+        target_unit: &str,
+    ) -> Result<DataWrapper<DatapointsCollection<Datapoint>>, UnitError> {
+        let catalog = self.get_api_service().units.catalog().await?;
+        let target = catalog.require(target_unit)?.clone();
+        let mut result = self.retrieve_datapoints(json).await?;
+
+        // Series whose unit the response doesn't carry are looked up once, together.
+        let missing: Vec<IdAndExtId> = result
+            .get_items()
+            .iter()
+            .filter(|c| !c.datapoints.is_empty() && collection_unit(c).is_none())
+            .map(|c| match c.id {
+                Some(id) => IdAndExtId::from_id(id),
+                None => IdAndExtId::from_external_id(c.external_id.as_deref().unwrap_or_default()),
+            })
+            .collect();
+        let mut series_units: HashMap<String, String> = HashMap::new();
+        if !missing.is_empty() {
+            for ts in self.by_ids(&DataWrapper::from_vec(missing)).await?.get_items() {
+                if let Some(unit) = ts.unit_external_id.clone().or_else(|| ts.unit.clone()) {
+                    if let Some(id) = ts.id {
+                        series_units.insert(id.to_string(), unit.clone());
+                    }
+                    series_units.insert(ts.external_id.clone(), unit);
+                }
+            }
+        }
+
+        let mut converters = Vec::new();
+        for collection in result.get_items() {
+            if collection.datapoints.is_empty() {
+                converters.push(None);
+                continue;
+            }
+            let source = collection_unit(collection)
+                .or_else(|| collection.id.and_then(|id| series_units.get(&id.to_string())))
+                .or_else(|| {
+                    collection
+                        .external_id
+                        .as_ref()
+                        .and_then(|ext| series_units.get(ext))
+                })
+                .ok_or_else(|| UnitError::UnknownSeriesUnit(series_name(collection)))?;
+            converters.push(Some(UnitConverter::new(catalog.require(source)?, &target)?));
+        }
+        for (collection, converter) in result.get_items_mut().iter_mut().zip(converters) {
+            if let Some(converter) = converter {
+                collection.datapoints.iter_mut().for_each(|dp| converter.convert_datapoint(dp));
+                collection.unit_external_id = Some(target.external_id.clone());
+                collection.unit = Some(target.symbol.clone());
+            }
+        }
+        Ok(result)
+    }
+
+    /// [`insert_datapoints`](Self::insert_datapoints), converting each collection's values from
+    /// the unit it names (`unit_external_id`, else `unit`) to `target_unit` first. Collections
+    /// that name no unit are taken to be in `target_unit` already. Nothing is sent if any
+    /// collection can't be converted.
+    pub async fn insert_datapoints_in_unit(
+        &self,
+        json: &mut DataWrapper<DatapointsCollection<DatapointString>>,
+        target_unit: &str,
+    ) -> Result<DataWrapper<String>, UnitError> {
+        let catalog = self.get_api_service().units.catalog().await?;
+        let target = catalog.require(target_unit)?.clone();
+        let mut converted = json.get_items().clone();
+        for collection in converted.iter_mut() {
+            if let Some(source) = collection_unit(collection) {
+                let converter = UnitConverter::new(catalog.require(source)?, &target)?;
+                if !converter.is_identity() {
+                    for dp in collection.datapoints.iter_mut() {
+                        converter.convert_datapoint_string(dp)?;
+                    }
+                }
+            }
+            collection.unit_external_id = Some(target.external_id.clone());
+            collection.unit = None;
+        }
+        json.set_items(converted);
+        Ok(self.insert_datapoints(json).await?)
+    }
+
+    /// Evaluate `expression` over `[start, end)`, retrieving every series it references (from
+    /// `start - expression.lookback()`, following cursors). See [`crate::datapoints::expression`].
+    pub async fn calculate(
+        &self,
+        expression: &Expression,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        alignment: &Alignment,
+    ) -> Result<Vec<Datapoint>, ExpressionError> {
+        let from = start - expression.lookback();
+        let mut inputs: HashMap<String, Vec<Datapoint>> = HashMap::new();
+        for external_id in expression.series() {
+            let mut filter = RetrieveFilter {
+                start: Some(from),
+                end: Some(end),
+                external_id: Some(external_id.clone()),
+                ..Default::default()
+            };
+            let points = inputs.entry(external_id).or_default();
+            loop {
+                let page = self
+                    .retrieve_datapoints(&DataWrapper::from_vec(vec![filter.clone()]))
+                    .await?;
+                let Some(collection) = page.get_items().first() else {
+                    break;
+                };
+                points.extend(collection.datapoints.iter().cloned());
+                match &collection.next_cursor {
+                    Some(cursor) if !collection.datapoints.is_empty() => {
+                        filter.cursor = Some(cursor.clone())
+                    }
+                    _ => break,
+                }
+            }
+        }
+        Ok(expression
+            .evaluate(&inputs, alignment)?
+            .into_iter()
+            .filter(|p| p.timestamp >= start && p.timestamp < end)
+            .collect())
+    }
+
+    /// A [`CalculatedSeries`] writing the result of `expression` into `target_external_id`.
+    pub fn calculated_series(
+        &self,
+        expression: Expression,
+        target_external_id: &str,
+    ) -> CalculatedSeries {
+        CalculatedSeries::new(self.api_service.clone(), expression, target_external_id)
+    }
+}
+
+/// The unit a datapoints collection names, if any.
+fn collection_unit<T>(collection: &DatapointsCollection<T>) -> Option<&String> {
+    collection
+        .unit_external_id
+        .as_ref()
+        .or(collection.unit.as_ref())
+}
+
+fn series_name<T>(collection: &DatapointsCollection<T>) -> String {
+    match (&collection.external_id, collection.id) {
+        (Some(ext), _) => ext.clone(),
+        (None, Some(id)) => id.to_string(),
+        (None, None) => "<unnamed>".to_string(),
+    }
 }
 
 /// Flatten datapoint collections into individual spool records (one timestamp each, for retention).
@@ -498,6 +1012,11 @@ fn regroup_datapoints(
     dw
 }
 
+/// Directory of the datapoint ingest spool.
+fn spool_dir(config: &DataHubConfig) -> std::path::PathBuf {
+    config.buffer_directory().join("datapoints")
+}
+
 /// A result for a buffered (not-yet-confirmed) datapoint insert: HTTP 202 with no items.
 fn buffered_string_wrapper() -> DataWrapper<String> {
     let mut w = DataWrapper::new();
@@ -0,0 +1,678 @@
+//! Ingest-time validation and sanitisation of datapoints.
+//!
+//! The backend rejects a whole request for a single bad point: a value that isn't a number of
+//! the series' `value_type` (`NaN`, infinities, `"12,5"`, `"3.5"` for a `bigint` series), a
+//! timestamp before 1971 or far in the future, or two points with the same timestamp in one
+//! series. With durable buffering a rejected spool segment is dropped whole, so one bad point
+//! can lose many good ones.
+//!
+//! [`ValidationOptions`] checks every point before it is sent, sorts each series by time and
+//! removes duplicate timestamps, and handles invalid points by [`InvalidAction`]: reject the
+//! request, drop the points, or drop them and keep a copy in a quarantine spool. Every invalid
+//! point is listed in the [`ValidationReport`].
+//!
+//! Validate explicitly with
+//! [`TimeSeriesService::validate_datapoints`](super::TimeSeriesService::validate_datapoints) or
+//! [`insert_datapoints_validated`](super::TimeSeriesService::insert_datapoints_validated), or
+//! for every `insert_datapoints` (and so every [`DatapointWriter`](super::DatapointWriter)
+//! flush) with `DataHubConfig::set_datapoint_validation`.
+
+use crate::datapoints::parse_timestamp;
+use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+use crate::http::ResponseError;
+use crate::timeseries::writer::SeriesKey;
+use crate::timeseries::TimeSeries;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+
+/// What happens to points that fail validation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum InvalidAction {
+    /// Send nothing and fail with [`ValidationError::Rejected`]; the request is left as it was.
+    Reject,
+    /// Remove the invalid points and send the rest.
+    Drop,
+    /// As `Drop`, keeping the invalid points and their issue in the quarantine spool.
+    Quarantine,
+}
+
+/// What happens when a series has several points with one timestamp in one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateAction {
+    /// Keep the point that came last in the request, as the backend would on a later insert.
+    KeepLast,
+    KeepFirst,
+    /// Keep the first point and treat the others as invalid.
+    Invalid,
+}
+
+/// Why a point failed validation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum PointIssue {
+    /// The collection names neither an id nor an external id.
+    MissingSeries,
+    InvalidTimestamp,
+    TooEarly {
+        earliest: DateTime<Utc>,
+    },
+    TooFarInFuture {
+        latest: DateTime<Utc>,
+    },
+    NotANumber,
+    Infinite,
+    Unparsable,
+    NotAnInteger,
+    Duplicate,
+}
+
+impl fmt::Display for PointIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PointIssue::MissingSeries => write!(f, "no id or external id"),
+            PointIssue::InvalidTimestamp => write!(f, "invalid timestamp"),
+            PointIssue::TooEarly { earliest } => write!(f, "timestamp before {earliest}"),
+            PointIssue::TooFarInFuture { latest } => write!(f, "timestamp after {latest}"),
+            PointIssue::NotANumber => write!(f, "value is NaN"),
+            PointIssue::Infinite => write!(f, "value is infinite"),
+            PointIssue::Unparsable => write!(f, "value is not a number"),
+            PointIssue::NotAnInteger => write!(f, "value is not an integer for a bigint series"),
+            PointIssue::Duplicate => write!(f, "duplicate timestamp"),
+        }
+    }
+}
+
+/// One invalid point.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PointReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub timestamp: String,
+    pub value: String,
+    pub issue: PointIssue,
+}
+
+impl fmt::Display for PointReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let series = match (&self.external_id, self.id) {
+            (Some(ext), _) => ext.clone(),
+            (None, Some(id)) => id.to_string(),
+            (None, None) => "<unnamed>".to_string(),
+        };
+        write!(
+            f,
+            "{series} @ {} = {:?}: {}",
+            self.timestamp, self.value, self.issue
+        )
+    }
+}
+
+/// The outcome of validating one request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub action: InvalidAction,
+    /// Points looked at.
+    pub checked: usize,
+    /// Points left in the request to send.
+    pub accepted: usize,
+    /// Duplicate timestamps removed under [`DuplicateAction::KeepFirst`] or `KeepLast`.
+    pub duplicates_removed: usize,
+    /// Series whose points were not in time order.
+    pub reordered_series: usize,
+    pub invalid: Vec<PointReport>,
+    /// Why the invalid points could not be written to the quarantine spool, under
+    /// [`InvalidAction::Quarantine`]. They have been removed from the request all the same.
+    pub quarantine_error: Option<String>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.invalid.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} datapoints invalid",
+            self.invalid.len(),
+            self.checked
+        )?;
+        if let Some(first) = self.invalid.first() {
+            write!(f, " (first: {first})")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("datapoints rejected: {0}")]
+    Rejected(ValidationReport),
+    #[error(transparent)]
+    Request(#[from] ResponseError),
+}
+
+impl From<ValidationError> for ResponseError {
+    fn from(e: ValidationError) -> Self {
+        match e {
+            ValidationError::Rejected(report) => {
+                ResponseError::bad_request(format!("datapoints rejected: {report}"))
+            }
+            ValidationError::Request(e) => e,
+        }
+    }
+}
+
+/// Settings for datapoint validation. Series value types given here are used as is; others are
+/// looked up (and cached) unless [`without_value_type_lookup`](Self::without_value_type_lookup)
+/// is set. A series whose type is unknown is checked as `float`; a failed lookup is retried after
+/// a minute.
+#[derive(Debug, Clone)]
+pub struct ValidationOptions {
+    pub action: InvalidAction,
+    pub duplicates: DuplicateAction,
+    /// Earliest timestamp accepted (default 1971-01-01).
+    pub earliest: DateTime<Utc>,
+    /// How far past now a timestamp may be (default 365 days).
+    pub max_future: Duration,
+    pub lookup_value_types: bool,
+    value_types: HashMap<SeriesKey, String>,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        ValidationOptions::new(InvalidAction::Reject)
+    }
+}
+
+impl ValidationOptions {
+    pub fn new(action: InvalidAction) -> Self {
+        ValidationOptions {
+            action,
+            duplicates: DuplicateAction::KeepLast,
+            earliest: Utc.with_ymd_and_hms(1971, 1, 1, 0, 0, 0).unwrap(),
+            max_future: Duration::days(365),
+            lookup_value_types: true,
+            value_types: HashMap::new(),
+        }
+    }
+
+    pub fn with_duplicates(mut self, duplicates: DuplicateAction) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    pub fn with_earliest(mut self, earliest: DateTime<Utc>) -> Self {
+        self.earliest = earliest;
+        self
+    }
+
+    pub fn with_max_future(mut self, max_future: Duration) -> Self {
+        self.max_future = max_future;
+        self
+    }
+
+    /// Check values of the series written by this external id as `value_type` (`"float"` or
+    /// `"bigint"`).
+    pub fn with_value_type(mut self, external_id: &str, value_type: &str) -> Self {
+        self.value_types.insert(
+            SeriesKey::ExternalId(external_id.to_string()),
+            value_type.to_string(),
+        );
+        self
+    }
+
+    /// Check values of `time_series`, whether written by id or external id, as its value type.
+    pub fn with_time_series(mut self, time_series: &TimeSeries) -> Self {
+        for key in series_keys(time_series) {
+            self.value_types.insert(key, time_series.value_type.clone());
+        }
+        self
+    }
+
+    /// Don't fetch value types of series not given with `with_value_type`/`with_time_series`.
+    pub fn without_value_type_lookup(mut self) -> Self {
+        self.lookup_value_types = false;
+        self
+    }
+
+    pub(crate) fn value_type(&self, key: &SeriesKey) -> Option<&String> {
+        self.value_types.get(key)
+    }
+}
+
+/// The keys `time_series` can be written by.
+pub(crate) fn series_keys(time_series: &TimeSeries) -> Vec<SeriesKey> {
+    let mut keys = vec![SeriesKey::ExternalId(time_series.external_id.clone())];
+    if let Some(id) = time_series.id {
+        keys.push(SeriesKey::Id(id));
+    }
+    keys
+}
+
+fn check_value(value: &str, value_type: Option<&str>) -> Option<PointIssue> {
+    let value = value.trim();
+    if value_type == Some("bigint") && value.parse::<i64>().is_ok() {
+        return None;
+    }
+    let issue = match value.parse::<f64>() {
+        Err(_) => PointIssue::Unparsable,
+        Ok(v) if v.is_nan() => PointIssue::NotANumber,
+        Ok(v) if v.is_infinite() => PointIssue::Infinite,
+        Ok(_) if value_type == Some("bigint") => PointIssue::NotAnInteger,
+        Ok(_) => return None,
+    };
+    Some(issue)
+}
+
+/// Validate `json` in place: merge collections of the same series, sort each by time, resolve
+/// duplicate timestamps and, unless the action is `Reject` and something is invalid, remove the
+/// invalid points. `value_types` are the looked-up types; those in `options` take precedence.
+pub(crate) fn sanitize(
+    json: &mut DataWrapper<DatapointsCollection<DatapointString>>,
+    options: &ValidationOptions,
+    value_types: &HashMap<SeriesKey, String>,
+    now: DateTime<Utc>,
+) -> ValidationReport {
+    let latest = now + options.max_future;
+    let mut report = ValidationReport {
+        action: options.action,
+        checked: 0,
+        accepted: 0,
+        duplicates_removed: 0,
+        reordered_series: 0,
+        invalid: vec![],
+        quarantine_error: None,
+    };
+    let invalid =
+        |c: &DatapointsCollection<DatapointString>, dp: &DatapointString, issue| PointReport {
+            id: c.id,
+            external_id: c.external_id.clone(),
+            timestamp: dp.timestamp.clone(),
+            value: dp.value.clone(),
+            issue,
+        };
+
+    let mut merged: Vec<(SeriesKey, DatapointsCollection<DatapointString>)> = vec![];
+    for collection in json.get_items() {
+        report.checked += collection.datapoints.len();
+        let Some(key) = SeriesKey::of(collection.id, collection.external_id.as_ref()) else {
+            for dp in &collection.datapoints {
+                report
+                    .invalid
+                    .push(invalid(collection, dp, PointIssue::MissingSeries));
+            }
+            continue;
+        };
+        match merged.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => existing
+                .datapoints
+                .extend(collection.datapoints.iter().cloned()),
+            None => merged.push((key, collection.clone())),
+        }
+    }
+
+    for (key, collection) in merged.iter_mut() {
+        let value_type = options
+            .value_type(key)
+            .or_else(|| value_types.get(key))
+            .map(String::as_str);
+        let mut valid: Vec<(i64, DatapointString)> = vec![];
+        for dp in &collection.datapoints {
+            let issue = match parse_timestamp(&dp.timestamp) {
+                None => Some(PointIssue::InvalidTimestamp),
+                Some(t) if t < options.earliest => Some(PointIssue::TooEarly {
+                    earliest: options.earliest,
+                }),
+                Some(t) if t > latest => Some(PointIssue::TooFarInFuture { latest }),
+                Some(t) => {
+                    let issue = check_value(&dp.value, value_type);
+                    if issue.is_none() {
+                        valid.push((t.timestamp_millis(), dp.clone()));
+                    }
+                    issue
+                }
+            };
+            if let Some(issue) = issue {
+                report.invalid.push(invalid(collection, dp, issue));
+            }
+        }
+
+        if valid.windows(2).any(|w| w[0].0 > w[1].0) {
+            report.reordered_series += 1;
+            valid.sort_by_key(|(t, _)| *t);
+        }
+        let mut kept: Vec<(i64, DatapointString)> = Vec::with_capacity(valid.len());
+        for (t, dp) in valid {
+            match kept.last_mut() {
+                Some((last, previous)) if *last == t => match options.duplicates {
+                    DuplicateAction::KeepLast => {
+                        *previous = dp;
+                        report.duplicates_removed += 1;
+                    }
+                    DuplicateAction::KeepFirst => report.duplicates_removed += 1,
+                    DuplicateAction::Invalid => {
+                        report
+                            .invalid
+                            .push(invalid(collection, &dp, PointIssue::Duplicate));
+                    }
+                },
+                _ => kept.push((t, dp)),
+            }
+        }
+        collection.datapoints = kept.into_iter().map(|(_, dp)| dp).collect();
+    }
+
+    if options.action == InvalidAction::Reject && !report.is_clean() {
+        return report;
+    }
+    let collections: Vec<_> = merged
+        .into_iter()
+        .map(|(_, c)| c)
+        .filter(|c| !c.datapoints.is_empty())
+        .collect();
+    report.accepted = collections.iter().map(|c| c.datapoints.len()).sum();
+    json.set_items(collections);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> String {
+        (secs * 1000).to_string()
+    }
+
+    fn request(
+        collections: Vec<(&str, Vec<(String, &str)>)>,
+    ) -> DataWrapper<DatapointsCollection<DatapointString>> {
+        let mut json = DataWrapper::new();
+        for (ext, points) in collections {
+            let mut c = DatapointsCollection::from_external_id(ext);
+            c.datapoints = points
+                .iter()
+                .map(|(t, v)| DatapointString::new(t, v))
+                .collect();
+            json.add_item(c);
+        }
+        json
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    fn points(json: &DataWrapper<DatapointsCollection<DatapointString>>) -> Vec<(String, String)> {
+        json.get_items()
+            .iter()
+            .flat_map(|c| {
+                c.datapoints
+                    .iter()
+                    .map(|dp| (dp.timestamp.clone(), dp.value.clone()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flags_values_and_timestamps() {
+        let t = 1_600_000_000;
+        let mut json = request(vec![
+            (
+                "temp",
+                vec![
+                    (at(t), "1.5"),
+                    (at(t + 1), "NaN"),
+                    (at(t + 2), "inf"),
+                    (at(t + 3), "12,5"),
+                    ("yesterday".to_string(), "1"),
+                    (at(0), "1"),
+                    (at(4_000_000_000), "1"),
+                    ("2020-09-13T12:26:44Z".to_string(), "2"),
+                ],
+            ),
+            ("count", vec![(at(t), "3"), (at(t + 1), "3.5")]),
+        ]);
+        let options =
+            ValidationOptions::new(InvalidAction::Drop).with_value_type("count", "bigint");
+        let report = sanitize(&mut json, &options, &HashMap::new(), now());
+        let issues: Vec<&PointIssue> = report.invalid.iter().map(|r| &r.issue).collect();
+        assert_eq!(report.checked, 10);
+        assert_eq!(issues.len(), 7);
+        assert_eq!(issues[0], &PointIssue::NotANumber);
+        assert_eq!(issues[1], &PointIssue::Infinite);
+        assert_eq!(issues[2], &PointIssue::Unparsable);
+        assert_eq!(issues[3], &PointIssue::InvalidTimestamp);
+        assert!(matches!(issues[4], PointIssue::TooEarly { .. }));
+        assert!(matches!(issues[5], PointIssue::TooFarInFuture { .. }));
+        assert_eq!(issues[6], &PointIssue::NotAnInteger);
+        assert_eq!(report.invalid[6].external_id.as_deref(), Some("count"));
+        assert_eq!(report.accepted, 3);
+        assert_eq!(
+            points(&json),
+            vec![
+                (at(t), "1.5".to_string()),
+                ("2020-09-13T12:26:44Z".to_string(), "2".to_string()),
+                (at(t), "3".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn sorts_merges_and_deduplicates() {
+        let t = 1_600_000_000;
+        let mut json = request(vec![
+            ("temp", vec![(at(t + 2), "3"), (at(t), "1")]),
+            ("other", vec![(at(t), "9")]),
+            ("temp", vec![(at(t + 1), "2"), (at(t), "1b")]),
+        ]);
+        let report = sanitize(
+            &mut json,
+            &ValidationOptions::new(InvalidAction::Drop),
+            &HashMap::new(),
+            now(),
+        );
+        assert_eq!(report.reordered_series, 1);
+        assert_eq!(report.duplicates_removed, 0);
+        // "1b" is unparsable, so (t, "1") stays.
+        assert_eq!(json.get_items().len(), 2);
+        assert_eq!(
+            points(&json)[..3],
+            [
+                (at(t), "1".to_string()),
+                (at(t + 1), "2".to_string()),
+                (at(t + 2), "3".to_string())
+            ]
+        );
+
+        let dupes = || {
+            request(vec![(
+                "temp",
+                vec![(at(t), "1"), (at(t), "2"), (at(t), "3")],
+            )])
+        };
+        let mut json = dupes();
+        let report = sanitize(
+            &mut json,
+            &ValidationOptions::new(InvalidAction::Drop),
+            &HashMap::new(),
+            now(),
+        );
+        assert_eq!(report.duplicates_removed, 2);
+        assert_eq!(points(&json), vec![(at(t), "3".to_string())]);
+
+        let mut json = dupes();
+        let options =
+            ValidationOptions::new(InvalidAction::Drop).with_duplicates(DuplicateAction::KeepFirst);
+        sanitize(&mut json, &options, &HashMap::new(), now());
+        assert_eq!(points(&json), vec![(at(t), "1".to_string())]);
+
+        let mut json = dupes();
+        let options =
+            ValidationOptions::new(InvalidAction::Reject).with_duplicates(DuplicateAction::Invalid);
+        let report = sanitize(&mut json, &options, &HashMap::new(), now());
+        assert_eq!(report.invalid.len(), 2);
+        assert_eq!(report.invalid[0].issue, PointIssue::Duplicate);
+        // Rejected requests are left as they were.
+        assert_eq!(points(&json).len(), 3);
+    }
+
+    #[test]
+    fn looked_up_types_apply_unless_overridden() {
+        let mut json = request(vec![("count", vec![(at(1_600_000_000), "2.5")])]);
+        let looked_up = HashMap::from([(
+            SeriesKey::ExternalId("count".to_string()),
+            "bigint".to_string(),
+        )]);
+        let report = sanitize(
+            &mut json.clone(),
+            &ValidationOptions::default(),
+            &looked_up,
+            now(),
+        );
+        assert_eq!(report.invalid[0].issue, PointIssue::NotAnInteger);
+        let options = ValidationOptions::default().with_value_type("count", "float");
+        assert!(sanitize(&mut json, &options, &looked_up, now()).is_clean());
+    }
+
+    #[tokio::test]
+    async fn insert_looks_up_types_and_quarantines() {
+        use crate::tests::mock_backend::MockBackend;
+        use crate::ApiService;
+
+        let backend = MockBackend::start(|req| match req.path.as_str() {
+            "/timeseries/byids" => (
+                200,
+                serde_json::json!({ "items": [{
+                    "id": "7", "externalId": "count", "name": "count", "valueType": "bigint"
+                }]})
+                .to_string(),
+            ),
+            _ => (204, String::new()),
+        })
+        .await;
+        let dir = std::env::temp_dir().join(format!("datahub_validation_{}", uuid::Uuid::new_v4()));
+        let mut config = backend.config();
+        config
+            .set_buffer_dir(&dir)
+            .set_datapoint_validation(ValidationOptions::new(InvalidAction::Quarantine));
+        let api = ApiService::new(config);
+
+        let t = 1_600_000_000;
+        let mut json = request(vec![("count", vec![(at(t), "3"), (at(t + 1), "3.5")])]);
+        api.time_series.insert_datapoints(&mut json).await.unwrap();
+        let sent = backend.requests_to("/timeseries/data");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].json()["items"][0]["datapoints"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(api.time_series.quarantined_count(), 1);
+
+        // The type is cached, and a rejected request sends nothing.
+        let mut json = request(vec![("count", vec![(at(t), "x")])]);
+        let err = api
+            .time_series
+            .insert_datapoints_validated(&mut json, &ValidationOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ValidationError::Rejected(r) if r.invalid.len() == 1));
+        assert_eq!(backend.requests_to("/timeseries/byids").len(), 1);
+        assert_eq!(backend.requests_to("/timeseries/data").len(), 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn a_quarantine_that_cannot_be_written_is_reported() {
+        use crate::buffer::BufferEvent;
+        use crate::tests::mock_backend::MockBackend;
+        use crate::ApiService;
+        use std::sync::{Arc, Mutex};
+
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        // A file where the buffer directory should be: the quarantine spool can't be opened.
+        let file =
+            std::env::temp_dir().join(format!("datahub_validation_{}", uuid::Uuid::new_v4()));
+        std::fs::write(&file, "").unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let mut config = backend.config();
+        config
+            .set_buffer_dir(&file)
+            .set_buffer_observer(move |e: &BufferEvent| seen.lock().unwrap().push(e.clone()));
+        let api = ApiService::new(config);
+
+        let mut json = request(vec![("temp", vec![(at(1_600_000_000), "x")])]);
+        let options = ValidationOptions::new(InvalidAction::Quarantine);
+        let report = api
+            .time_series
+            .validate_datapoints(&mut json, &options)
+            .await;
+        assert_eq!(report.invalid.len(), 1);
+        assert!(report.quarantine_error.is_some());
+        assert!(events.lock().unwrap().iter().any(|e| matches!(
+            e,
+            BufferEvent::IoError {
+                spool: "quarantine",
+                operation: "open",
+                ..
+            }
+        )));
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[tokio::test]
+    async fn failed_lookups_are_cached_and_skipped_while_buffering() {
+        use crate::tests::mock_backend::MockBackend;
+        use crate::ApiService;
+
+        let backend = MockBackend::start(|req| match req.path.as_str() {
+            "/timeseries/byids" => (400, "unknown series".to_string()),
+            "/timeseries/data" => (503, String::new()),
+            _ => (204, String::new()),
+        })
+        .await;
+        let dir = std::env::temp_dir().join(format!("datahub_validation_{}", uuid::Uuid::new_v4()));
+        let mut config = backend.config();
+        config
+            .set_buffer_dir(&dir)
+            .enable_buffering()
+            .set_datapoint_validation(ValidationOptions::new(InvalidAction::Drop));
+        let api = ApiService::new(config);
+
+        // Recent enough to be spooled.
+        let t = Utc::now().timestamp();
+        let options = ValidationOptions::default();
+        for _ in 0..2 {
+            let mut json = request(vec![("count", vec![(at(t), "3")])]);
+            let report = api
+                .time_series
+                .validate_datapoints(&mut json, &options)
+                .await;
+            assert_eq!(report.accepted, 1);
+        }
+        // Not found, and not asked for again for a while.
+        assert_eq!(backend.requests_to("/timeseries/byids").len(), 1);
+
+        let mut json = request(vec![("count", vec![(at(t), "3")])]);
+        api.time_series.insert_datapoints(&mut json).await.unwrap();
+        assert_eq!(api.time_series.buffered_count(), 1);
+        // Another series is not looked up: its points go behind the backlog.
+        let mut json = request(vec![("other", vec![(at(t), "3")])]);
+        api.time_series.insert_datapoints(&mut json).await.unwrap();
+        assert_eq!(backend.requests_to("/timeseries/byids").len(), 1);
+        assert_eq!(api.time_series.buffered_count(), 2);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,909 @@
+//! Background batching for datapoint ingestion.
+//!
+//! Calling [`TimeSeriesService::insert_datapoints`](super::TimeSeriesService::insert_datapoints)
+//! once per sample produces one tiny request per sample. A [`DatapointWriter`] accepts points
+//! individually or in collections, coalesces them per series on a background task, and sends them
+//! as one request when a batch reaches `max_batch_points` or its oldest point is `max_batch_age`
+//! old — whichever comes first.
+//!
+//! Every flush goes through `insert_datapoints`, so a client with durable buffering enabled spools
+//! a batch the backend can't take right now exactly as a direct call would. Without buffering, a
+//! failed flush loses that batch; the error is reported by the next [`DatapointWriter::flush`].
+//!
+//! Memory is bounded by `max_buffered_points`: points accepted but not yet sent. Past it,
+//! [`OverflowPolicy::Block`] makes `write` wait until a flush frees room (backpressure), while
+//! [`OverflowPolicy::DropNewest`] discards the incoming points and counts them in
+//! [`WriterStats::dropped_points`].
+//!
+//! Points can also be compressed per series before they are batched (deadband or swinging door,
+//! see [`compression`](super::compression)), set for every series with
+//! [`DatapointWriterOptions::with_compression`] or per series, e.g. from its metadata with
+//! [`DatapointWriterOptions::with_time_series_compression`].
+
+use crate::generic::{DataWrapper, DatapointString, DatapointsCollection};
+use crate::http::ResponseError;
+use crate::timeseries::compression::{
+    CompressionError, CompressionSettings, CompressionStats, Compressor,
+};
+use crate::timeseries::TimeSeries;
+use crate::ApiService;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// The backend's per-request datapoint limit; a batch never grows past it.
+const MAX_POINTS_PER_REQUEST: usize = 100_000;
+
+#[derive(Debug, Error)]
+pub enum WriterError {
+    #[error("the datapoint writer has been shut down")]
+    Closed,
+    #[error("datapoints collection names no series: set an id or an external id")]
+    MissingSeries,
+    #[error("flush failed: {0}")]
+    Ingest(#[from] ResponseError),
+}
+
+/// What [`DatapointWriter::write`] does when `max_buffered_points` are already waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Wait until a flush frees room. Slows producers to the rate the backend accepts.
+    #[default]
+    Block,
+    /// Discard the incoming points and count them in [`WriterStats::dropped_points`].
+    DropNewest,
+}
+
+/// Thresholds for a [`DatapointWriter`]. Build with `DatapointWriterOptions::default()` and the
+/// `with_*` setters.
+#[derive(Debug, Clone)]
+pub struct DatapointWriterOptions {
+    /// Flush once this many points are waiting across all series (capped at 100 000, the
+    /// backend's per-request limit). No request carries more. Default 10 000.
+    pub max_batch_points: usize,
+    /// Flush once the oldest waiting point has been held this long. Default 1 s.
+    pub max_batch_age: Duration,
+    /// Upper bound on accepted-but-unsent points, including a batch in flight. Never less than
+    /// `max_batch_points`. Default 1 000 000.
+    pub max_buffered_points: usize,
+    /// What to do when `max_buffered_points` is reached.
+    pub overflow: OverflowPolicy,
+    /// Compression for series without their own settings. Default none.
+    pub compression: Option<CompressionSettings>,
+    series_compression: HashMap<SeriesKey, CompressionSettings>,
+}
+
+impl Default for DatapointWriterOptions {
+    fn default() -> Self {
+        DatapointWriterOptions {
+            max_batch_points: 10_000,
+            max_batch_age: Duration::from_secs(1),
+            max_buffered_points: 1_000_000,
+            overflow: OverflowPolicy::Block,
+            compression: None,
+            series_compression: HashMap::new(),
+        }
+    }
+}
+
+impl DatapointWriterOptions {
+    pub fn with_max_batch_points(mut self, points: usize) -> Self {
+        self.max_batch_points = points;
+        self
+    }
+
+    pub fn with_max_batch_age(mut self, age: Duration) -> Self {
+        self.max_batch_age = age;
+        self
+    }
+
+    pub fn with_max_buffered_points(mut self, points: usize) -> Self {
+        self.max_buffered_points = points;
+        self
+    }
+
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Compress every series without its own settings.
+    pub fn with_compression(mut self, settings: CompressionSettings) -> Self {
+        self.compression = Some(settings);
+        self
+    }
+
+    /// Compress the series written by this external id.
+    pub fn with_series_compression(
+        mut self,
+        external_id: &str,
+        settings: CompressionSettings,
+    ) -> Self {
+        self.series_compression
+            .insert(SeriesKey::ExternalId(external_id.to_string()), settings);
+        self
+    }
+
+    /// Compress the series written by this id.
+    pub fn with_series_compression_by_id(mut self, id: u64, settings: CompressionSettings) -> Self {
+        self.series_compression.insert(SeriesKey::Id(id), settings);
+        self
+    }
+
+    /// Compress `time_series` as its metadata asks (see
+    /// [`CompressionSettings::from_metadata`]), whether written by id or by external id. A series
+    /// whose metadata names no method is left alone.
+    pub fn with_time_series_compression(
+        mut self,
+        time_series: &TimeSeries,
+    ) -> Result<Self, CompressionError> {
+        if let Some(settings) = time_series.compression_settings()? {
+            if let Some(id) = time_series.id {
+                self.series_compression
+                    .insert(SeriesKey::Id(id), settings.clone());
+            }
+            self.series_compression.insert(
+                SeriesKey::ExternalId(time_series.external_id.clone()),
+                settings,
+            );
+        }
+        Ok(self)
+    }
+
+    fn compression_for(&self, series: &SeriesKey) -> Option<&CompressionSettings> {
+        self.series_compression
+            .get(series)
+            .or(self.compression.as_ref())
+    }
+
+    /// Clamp the thresholds into a consistent range.
+    fn normalized(mut self) -> Self {
+        self.max_batch_points = self.max_batch_points.clamp(1, MAX_POINTS_PER_REQUEST);
+        self.max_buffered_points = self.max_buffered_points.max(self.max_batch_points);
+        self
+    }
+}
+
+/// Counters for a [`DatapointWriter`], shared by all of its clones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriterStats {
+    /// Points accepted by `write` (excludes dropped ones).
+    pub accepted_points: u64,
+    /// Points in batches `insert_datapoints` accepted (sent, or spooled by durable buffering).
+    pub flushed_points: u64,
+    /// Points in batches that failed to flush.
+    pub failed_points: u64,
+    /// Points discarded by [`OverflowPolicy::DropNewest`].
+    pub dropped_points: u64,
+    /// Points accepted and not yet flushed.
+    pub pending_points: u64,
+    /// Requests sent.
+    pub flushes: u64,
+    /// Points into and out of compression, for compressed series only.
+    pub compression: CompressionStats,
+}
+
+#[derive(Default)]
+struct Counters {
+    accepted_points: AtomicU64,
+    flushed_points: AtomicU64,
+    failed_points: AtomicU64,
+    dropped_points: AtomicU64,
+    flushes: AtomicU64,
+    compression_input: AtomicU64,
+    compression_output: AtomicU64,
+}
+
+/// Series identity used to coalesce points. An id wins over an external id, as in the request
+/// body.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum SeriesKey {
+    Id(u64),
+    ExternalId(String),
+}
+
+impl SeriesKey {
+    pub(crate) fn of(id: Option<u64>, external_id: Option<&String>) -> Option<Self> {
+        match (id, external_id) {
+            (Some(id), _) => Some(SeriesKey::Id(id)),
+            (None, Some(ext)) => Some(SeriesKey::ExternalId(ext.clone())),
+            (None, None) => None,
+        }
+    }
+
+    fn collection(&self) -> DatapointsCollection<DatapointString> {
+        match self {
+            SeriesKey::Id(id) => DatapointsCollection::from_id(*id),
+            SeriesKey::ExternalId(ext) => DatapointsCollection::from_external_id(ext),
+        }
+    }
+}
+
+enum Command {
+    Points {
+        series: SeriesKey,
+        points: Vec<DatapointString>,
+        permit: OwnedSemaphorePermit,
+    },
+    Flush(oneshot::Sender<Result<(), ResponseError>>),
+    Shutdown(oneshot::Sender<Result<(), ResponseError>>),
+}
+
+/// Cloneable, `Send` handle to a background datapoint batcher. Create one with
+/// [`TimeSeriesService::writer`](super::TimeSeriesService::writer).
+///
+/// The background task runs until [`shutdown`](Self::shutdown) is called or the last clone is
+/// dropped; either way it flushes what it holds before exiting. Dropping the handles without
+/// awaiting `shutdown` gives no chance to observe that final flush's outcome.
+#[derive(Clone)]
+pub struct DatapointWriter {
+    tx: mpsc::UnboundedSender<Command>,
+    capacity: Arc<Semaphore>,
+    counters: Arc<Counters>,
+    options: Arc<DatapointWriterOptions>,
+}
+
+impl DatapointWriter {
+    /// Spawn the background task on the current Tokio runtime. Panics outside one.
+    pub(crate) fn spawn(api_service: Weak<ApiService>, options: DatapointWriterOptions) -> Self {
+        let options = Arc::new(options.normalized());
+        let (tx, rx) = mpsc::unbounded_channel();
+        let counters = Arc::new(Counters::default());
+        tokio::spawn(run(api_service, options.clone(), counters.clone(), rx));
+        DatapointWriter {
+            tx,
+            capacity: Arc::new(Semaphore::new(options.max_buffered_points)),
+            counters,
+            options,
+        }
+    }
+
+    /// Queue one datapoint for the series named by `id` or, failing that, `external_id`.
+    pub async fn write_datapoint(
+        &self,
+        id: Option<u64>,
+        external_id: Option<String>,
+        timestamp: DateTime<Utc>,
+        value: String,
+    ) -> Result<(), WriterError> {
+        let series = SeriesKey::of(id, external_id.as_ref()).ok_or(WriterError::MissingSeries)?;
+        self.enqueue(
+            series,
+            vec![DatapointString::from_datetime(timestamp, &value)],
+        )
+        .await
+    }
+
+    /// Queue every point in `collections`. Collections for the same series are merged.
+    pub async fn write(
+        &self,
+        collections: Vec<DatapointsCollection<DatapointString>>,
+    ) -> Result<(), WriterError> {
+        for collection in collections {
+            let series = SeriesKey::of(collection.id, collection.external_id.as_ref())
+                .ok_or(WriterError::MissingSeries)?;
+            let mut points = collection.datapoints;
+            // Hand large collections over in batch-sized pieces so a single call can't ask for
+            // more capacity than exists.
+            while !points.is_empty() {
+                let rest = points.split_off(points.len().min(self.options.max_batch_points));
+                self.enqueue(series.clone(), points).await?;
+                points = rest;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send everything accepted so far and wait for it. Returns the first flush error since the
+    /// previous `flush`, including errors from background (size/age-triggered) flushes.
+    pub async fn flush(&self) -> Result<(), WriterError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(Command::Flush(tx))
+            .map_err(|_| WriterError::Closed)?;
+        rx.await
+            .map_err(|_| WriterError::Closed)?
+            .map_err(WriterError::from)
+    }
+
+    /// Flush and stop the background task. Other clones see [`WriterError::Closed`] afterwards.
+    pub async fn shutdown(self) -> Result<(), WriterError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(Command::Shutdown(tx))
+            .map_err(|_| WriterError::Closed)?;
+        rx.await
+            .map_err(|_| WriterError::Closed)?
+            .map_err(WriterError::from)
+    }
+
+    /// A snapshot of the writer's counters.
+    pub fn stats(&self) -> WriterStats {
+        let c = &self.counters;
+        WriterStats {
+            accepted_points: c.accepted_points.load(Ordering::Relaxed),
+            flushed_points: c.flushed_points.load(Ordering::Relaxed),
+            failed_points: c.failed_points.load(Ordering::Relaxed),
+            dropped_points: c.dropped_points.load(Ordering::Relaxed),
+            pending_points: (self.options.max_buffered_points - self.capacity.available_permits())
+                as u64,
+            flushes: c.flushes.load(Ordering::Relaxed),
+            compression: CompressionStats {
+                input_points: c.compression_input.load(Ordering::Relaxed),
+                output_points: c.compression_output.load(Ordering::Relaxed),
+            },
+        }
+    }
+
+    async fn enqueue(
+        &self,
+        series: SeriesKey,
+        points: Vec<DatapointString>,
+    ) -> Result<(), WriterError> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        if self.tx.is_closed() {
+            return Err(WriterError::Closed);
+        }
+        let n = points.len() as u32;
+        let permit = match self.options.overflow {
+            OverflowPolicy::Block => self
+                .capacity
+                .clone()
+                .acquire_many_owned(n)
+                .await
+                .map_err(|_| WriterError::Closed)?,
+            OverflowPolicy::DropNewest => match self.capacity.clone().try_acquire_many_owned(n) {
+                Ok(permit) => permit,
+                Err(_) => {
+                    self.counters
+                        .dropped_points
+                        .fetch_add(n as u64, Ordering::Relaxed);
+                    return Ok(());
+                }
+            },
+        };
+        self.tx
+            .send(Command::Points {
+                series,
+                points,
+                permit,
+            })
+            .map_err(|_| WriterError::Closed)?;
+        self.counters
+            .accepted_points
+            .fetch_add(n as u64, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+type Request = DataWrapper<DatapointsCollection<DatapointString>>;
+
+/// Points waiting to be sent, grouped by series in first-seen order.
+#[derive(Default)]
+struct Batch {
+    series: Vec<SeriesKey>,
+    points: HashMap<SeriesKey, Vec<DatapointString>>,
+    permits: Vec<OwnedSemaphorePermit>,
+    count: usize,
+    opened: Option<Instant>,
+}
+
+impl Batch {
+    fn push(
+        &mut self,
+        series: SeriesKey,
+        points: Vec<DatapointString>,
+        permit: Option<OwnedSemaphorePermit>,
+    ) {
+        self.count += points.len();
+        self.opened.get_or_insert_with(Instant::now);
+        self.permits.extend(permit);
+        match self.points.get_mut(&series) {
+            Some(existing) => existing.extend(points),
+            None => {
+                self.series.push(series.clone());
+                self.points.insert(series, points);
+            }
+        }
+    }
+
+    /// Split into requests of at most `max_points` points each, paired with their point count.
+    /// Held-back points released by a flush can push a batch past the cap.
+    fn into_requests(
+        mut self,
+        max_points: usize,
+    ) -> (Vec<(Request, usize)>, Vec<OwnedSemaphorePermit>) {
+        let mut requests = Vec::new();
+        let mut dw = DataWrapper::new();
+        let mut count = 0;
+        for series in &self.series {
+            let mut points = self.points.remove(series).unwrap_or_default();
+            while !points.is_empty() {
+                if count == max_points {
+                    requests.push((std::mem::replace(&mut dw, DataWrapper::new()), count));
+                    count = 0;
+                }
+                let rest = points.split_off(points.len().min(max_points - count));
+                count += points.len();
+                let mut collection = series.collection();
+                collection.datapoints = points;
+                dw.add_item(collection);
+                points = rest;
+            }
+        }
+        if count > 0 {
+            requests.push((dw, count));
+        }
+        (requests, self.permits)
+    }
+}
+
+/// Per-series compressors, created on a series' first points.
+struct Compression {
+    options: Arc<DatapointWriterOptions>,
+    counters: Arc<Counters>,
+    compressors: HashMap<SeriesKey, Compressor>,
+}
+
+impl Compression {
+    /// Add points to the batch, through the series' compressor if it has one.
+    fn admit(
+        &mut self,
+        batch: &mut Batch,
+        series: SeriesKey,
+        points: Vec<DatapointString>,
+        permit: OwnedSemaphorePermit,
+    ) {
+        let points = match self.options.compression_for(&series) {
+            Some(settings) => {
+                let compressor = self
+                    .compressors
+                    .entry(series.clone())
+                    .or_insert_with(|| Compressor::new(settings.clone()));
+                let input = points.len() as u64;
+                let mut out = Vec::new();
+                for dp in points {
+                    compressor.push(dp, &mut out);
+                }
+                self.counters
+                    .compression_input
+                    .fetch_add(input, Ordering::Relaxed);
+                self.counters
+                    .compression_output
+                    .fetch_add(out.len() as u64, Ordering::Relaxed);
+                out
+            }
+            None => points,
+        };
+        // Fully compressed away: nothing to hold capacity for.
+        if !points.is_empty() {
+            batch.push(series, points, Some(permit));
+        }
+    }
+
+    /// Move every held-back point into the batch, so an explicit flush sends all accepted data.
+    fn release(&mut self, batch: &mut Batch) {
+        for (series, compressor) in &mut self.compressors {
+            let mut out = Vec::new();
+            compressor.finish(&mut out);
+            if !out.is_empty() {
+                self.counters
+                    .compression_output
+                    .fetch_add(out.len() as u64, Ordering::Relaxed);
+                batch.push(series.clone(), out, None);
+            }
+        }
+    }
+}
+
+async fn run(
+    api_service: Weak<ApiService>,
+    options: Arc<DatapointWriterOptions>,
+    counters: Arc<Counters>,
+    mut rx: mpsc::UnboundedReceiver<Command>,
+) {
+    let mut batch = Batch::default();
+    let mut compression = Compression {
+        options: options.clone(),
+        counters: counters.clone(),
+        compressors: HashMap::new(),
+    };
+    // The first failure since the last explicit flush, reported to whoever flushes next.
+    let mut pending_error: Option<ResponseError> = None;
+    loop {
+        let deadline = batch.opened.map(|opened| opened + options.max_batch_age);
+        let command = match deadline {
+            Some(deadline) => tokio::select! {
+                command = rx.recv() => command,
+                _ = tokio::time::sleep_until(deadline) => {
+                    send(&api_service, std::mem::take(&mut batch), &options, &counters, &mut pending_error).await;
+                    continue;
+                }
+            },
+            None => rx.recv().await,
+        };
+        match command {
+            Some(Command::Points {
+                series,
+                points,
+                permit,
+            }) => {
+                compression.admit(&mut batch, series, points, permit);
+                if batch.count >= options.max_batch_points {
+                    send(
+                        &api_service,
+                        std::mem::take(&mut batch),
+                        &options,
+                        &counters,
+                        &mut pending_error,
+                    )
+                    .await;
+                }
+            }
+            Some(Command::Flush(reply)) => {
+                compression.release(&mut batch);
+                send(
+                    &api_service,
+                    std::mem::take(&mut batch),
+                    &options,
+                    &counters,
+                    &mut pending_error,
+                )
+                .await;
+                let _ = reply.send(pending_error.take().map_or(Ok(()), Err));
+            }
+            Some(Command::Shutdown(reply)) => {
+                rx.close();
+                // Points already queued behind the shutdown request still belong to this flush.
+                while let Ok(Command::Points {
+                    series,
+                    points,
+                    permit,
+                }) = rx.try_recv()
+                {
+                    compression.admit(&mut batch, series, points, permit);
+                }
+                compression.release(&mut batch);
+                send(
+                    &api_service,
+                    std::mem::take(&mut batch),
+                    &options,
+                    &counters,
+                    &mut pending_error,
+                )
+                .await;
+                let _ = reply.send(pending_error.take().map_or(Ok(()), Err));
+                return;
+            }
+            // Every handle is gone: flush what's left and stop.
+            None => {
+                compression.release(&mut batch);
+                send(
+                    &api_service,
+                    std::mem::take(&mut batch),
+                    &options,
+                    &counters,
+                    &mut pending_error,
+                )
+                .await;
+                return;
+            }
+        }
+    }
+}
+
+/// Send one batch through `insert_datapoints`, in requests of at most `max_batch_points`, then
+/// release its capacity.
+async fn send(
+    api_service: &Weak<ApiService>,
+    batch: Batch,
+    options: &DatapointWriterOptions,
+    counters: &Counters,
+    pending_error: &mut Option<ResponseError>,
+) {
+    let (requests, _permits) = batch.into_requests(options.max_batch_points);
+    for (mut request, count) in requests {
+        send_request(
+            api_service,
+            &mut request,
+            count as u64,
+            counters,
+            pending_error,
+        )
+        .await;
+    }
+}
+
+async fn send_request(
+    api_service: &Weak<ApiService>,
+    request: &mut Request,
+    count: u64,
+    counters: &Counters,
+    pending_error: &mut Option<ResponseError>,
+) {
+    let result = match api_service.upgrade() {
+        Some(api) => api.time_series.insert_datapoints(request).await.map(|_| ()),
+        None => Err(ResponseError::from(
+            "api service has been dropped".to_string(),
+        )),
+    };
+    counters.flushes.fetch_add(1, Ordering::Relaxed);
+    match result {
+        Ok(()) => {
+            counters.flushed_points.fetch_add(count, Ordering::Relaxed);
+        }
+        Err(e) => {
+            counters.failed_points.fetch_add(count, Ordering::Relaxed);
+            pending_error.get_or_insert(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::mock_backend::MockBackend;
+
+    fn points_in(body: &serde_json::Value) -> usize {
+        body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["datapoints"].as_array().unwrap().len())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn coalesces_individual_points_per_series() {
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let api = backend.service();
+        let writer = api
+            .time_series
+            .writer(DatapointWriterOptions::default().with_max_batch_age(Duration::from_secs(60)));
+
+        for i in 0..10 {
+            let series = if i % 2 == 0 { "a" } else { "b" };
+            writer
+                .write_datapoint(None, Some(series.to_string()), Utc::now(), i.to_string())
+                .await
+                .unwrap();
+        }
+        writer.flush().await.unwrap();
+
+        let requests = backend.requests_to("/timeseries/data");
+        assert_eq!(requests.len(), 1, "ten points should travel in one request");
+        let body = requests[0].json();
+        assert_eq!(
+            body["items"].as_array().unwrap().len(),
+            2,
+            "one collection per series"
+        );
+        assert_eq!(points_in(&body), 10);
+        let stats = writer.stats();
+        assert_eq!(stats.flushed_points, 10);
+        assert_eq!(stats.pending_points, 0);
+    }
+
+    #[tokio::test]
+    async fn flushes_when_the_batch_is_full() {
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let api = backend.service();
+        let writer = api.time_series.writer(
+            DatapointWriterOptions::default()
+                .with_max_batch_points(5)
+                .with_max_batch_age(Duration::from_secs(60)),
+        );
+        let mut collection = DatapointsCollection::from_external_id("a");
+        for i in 0..12 {
+            collection
+                .datapoints
+                .push(DatapointString::new(&i.to_string(), "1"));
+        }
+        writer.write(vec![collection]).await.unwrap();
+        writer.shutdown().await.unwrap();
+
+        let sizes: Vec<usize> = backend
+            .requests_to("/timeseries/data")
+            .iter()
+            .map(|r| points_in(&r.json()))
+            .collect();
+        assert_eq!(sizes, vec![5, 5, 2]);
+    }
+
+    #[tokio::test]
+    async fn a_batch_past_the_cap_is_split_into_capped_requests() {
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let api = backend.service();
+        let writer = api.time_series.writer(
+            DatapointWriterOptions::default()
+                .with_max_batch_points(3)
+                .with_max_batch_age(Duration::from_secs(60)),
+        );
+        // 2 + 2 points arrive in pieces below the cap, so the batch reaches 4 before it is sent.
+        for external_id in ["a", "b"] {
+            let mut collection = DatapointsCollection::from_external_id(external_id);
+            collection.datapoints = vec![
+                DatapointString::new("1", "1"),
+                DatapointString::new("2", "2"),
+            ];
+            writer.write(vec![collection]).await.unwrap();
+        }
+        writer.shutdown().await.unwrap();
+
+        let sizes: Vec<usize> = backend
+            .requests_to("/timeseries/data")
+            .iter()
+            .map(|r| points_in(&r.json()))
+            .collect();
+        assert_eq!(sizes, vec![3, 1]);
+    }
+
+    #[tokio::test]
+    async fn flushes_when_the_batch_is_old() {
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let api = backend.service();
+        let writer = api.time_series.writer(
+            DatapointWriterOptions::default().with_max_batch_age(Duration::from_millis(50)),
+        );
+        writer
+            .write_datapoint(Some(7), None, Utc::now(), "1".to_string())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(backend.requests_to("/timeseries/data").len(), 1);
+        assert_eq!(writer.stats().flushed_points, 1);
+    }
+
+    #[tokio::test]
+    async fn drop_policy_discards_points_past_the_bound() {
+        // Nothing ever flushes (huge batch, long age), so capacity only shrinks.
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let api = backend.service();
+        let writer = api.time_series.writer(
+            DatapointWriterOptions::default()
+                .with_max_batch_points(3)
+                .with_max_buffered_points(3)
+                .with_max_batch_age(Duration::from_secs(60))
+                .with_overflow(OverflowPolicy::DropNewest),
+        );
+        // Hold the batch open by never reaching max_batch_points in one go: 2 + 2 > 3.
+        let mut two = DatapointsCollection::from_external_id("a");
+        two.datapoints = vec![
+            DatapointString::new("1", "1"),
+            DatapointString::new("2", "2"),
+        ];
+        writer.write(vec![two.clone()]).await.unwrap();
+        writer.write(vec![two]).await.unwrap();
+        let stats = writer.stats();
+        assert_eq!(stats.accepted_points, 2);
+        assert_eq!(stats.dropped_points, 2);
+    }
+
+    #[tokio::test]
+    async fn block_policy_waits_for_capacity() {
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let api = backend.service();
+        let writer = api.time_series.writer(
+            DatapointWriterOptions::default()
+                .with_max_batch_points(2)
+                .with_max_buffered_points(2)
+                .with_max_batch_age(Duration::from_millis(20)),
+        );
+        // Far more than fit at once; every write must eventually be admitted, none dropped.
+        for i in 0..20 {
+            writer
+                .write_datapoint(None, Some("a".to_string()), Utc::now(), i.to_string())
+                .await
+                .unwrap();
+        }
+        writer.shutdown().await.unwrap();
+        assert_eq!(
+            backend
+                .requests_to("/timeseries/data")
+                .iter()
+                .map(|r| points_in(&r.json()))
+                .sum::<usize>(),
+            20
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_flush_is_reported_once() {
+        let backend = MockBackend::start(|_| (400, "bad datapoint".to_string())).await;
+        let api = backend.service();
+        let writer = api.time_series.writer(DatapointWriterOptions::default());
+        writer
+            .write_datapoint(None, Some("a".to_string()), Utc::now(), "x".to_string())
+            .await
+            .unwrap();
+        let err = writer.flush().await.unwrap_err();
+        assert!(
+            matches!(err, WriterError::Ingest(ref e) if e.get_status() == 400),
+            "{err}"
+        );
+        assert_eq!(writer.stats().failed_points, 1);
+        // The error was delivered; the next flush starts clean.
+        writer.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_closes_every_clone() {
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let api = backend.service();
+        let writer = api.time_series.writer(DatapointWriterOptions::default());
+        let clone = writer.clone();
+        writer.shutdown().await.unwrap();
+        let err = clone
+            .write_datapoint(None, Some("a".to_string()), Utc::now(), "1".to_string())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, WriterError::Closed));
+    }
+
+    #[tokio::test]
+    async fn compresses_series_configured_from_metadata() {
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let api = backend.service();
+        let mut ts = TimeSeries::new("ramp", "ramp");
+        ts.set_metadata(HashMap::from([
+            (
+                "compression.method".to_string(),
+                "swinging_door".to_string(),
+            ),
+            ("compression.tolerance".to_string(), "0.1".to_string()),
+        ]));
+        let writer = api.time_series.writer(
+            DatapointWriterOptions::default()
+                .with_max_batch_points(7)
+                .with_time_series_compression(&ts)
+                .unwrap(),
+        );
+
+        let mut ramp = DatapointsCollection::from_external_id("ramp");
+        let mut raw = DatapointsCollection::from_external_id("raw");
+        for i in 0..50 {
+            ramp.datapoints
+                .push(DatapointString::new(&i.to_string(), &i.to_string()));
+            raw.datapoints
+                .push(DatapointString::new(&i.to_string(), &i.to_string()));
+        }
+        writer.write(vec![ramp, raw]).await.unwrap();
+        writer.flush().await.unwrap();
+
+        let mut sent: HashMap<String, usize> = HashMap::new();
+        for request in backend.requests_to("/timeseries/data") {
+            for c in request.json()["items"].as_array().unwrap() {
+                *sent
+                    .entry(c["externalId"].as_str().unwrap().to_string())
+                    .or_default() += c["datapoints"].as_array().unwrap().len();
+            }
+        }
+        // The straight ramp keeps only its ends; the uncompressed series arrives whole.
+        assert_eq!(sent["ramp"], 2);
+        assert_eq!(sent["raw"], 50);
+        let stats = writer.stats();
+        assert_eq!(stats.compression.input_points, 50);
+        assert_eq!(stats.compression.output_points, 2);
+        assert_eq!(stats.compression.ratio(), 25.0);
+        assert_eq!(stats.pending_points, 0);
+    }
+
+    #[tokio::test]
+    async fn a_collection_without_series_is_rejected() {
+        let backend = MockBackend::start(|_| (204, String::new())).await;
+        let api = backend.service();
+        let writer = api.time_series.writer(DatapointWriterOptions::default());
+        let err = writer
+            .write(vec![DatapointsCollection::default()])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, WriterError::MissingSeries));
+    }
+}
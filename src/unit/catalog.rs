@@ -0,0 +1,426 @@
+//! A local, indexed copy of the unit catalog, and conversion between its units.
+//!
+//! Every [`Unit`] carries a `quantity` (e.g. "Temperature") and a `conversion` map with a
+//! `multiplier` and an `offset` to the quantity's base unit:
+//!
+//! ```text
+//! base = value * multiplier + offset
+//! ```
+//!
+//! Two units convert into each other when their quantities match. [`UnitsService::catalog`]
+//! fetches the catalog once and caches it; [`UnitsService::refresh_catalog`] reloads it.
+//!
+//! [`UnitsService::catalog`]: super::UnitsService::catalog
+//! [`UnitsService::refresh_catalog`]: super::UnitsService::refresh_catalog
+
+use crate::generic::{Datapoint, DatapointString};
+use crate::http::ResponseError;
+use crate::unit::Unit;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum UnitError {
+    #[error("unknown unit '{0}'")]
+    UnknownUnit(String),
+    #[error(
+        "cannot convert {from} ({from_quantity}) to {to} ({to_quantity}): different quantities"
+    )]
+    IncompatibleQuantities {
+        from: String,
+        from_quantity: String,
+        to: String,
+        to_quantity: String,
+    },
+    #[error("unit '{0}' has no conversion multiplier")]
+    MissingConversion(String),
+    #[error("no unit is known for series {0}")]
+    UnknownSeriesUnit(String),
+    #[error("value '{0}' is not numeric and can't be converted")]
+    NonNumericValue(String),
+    #[error(transparent)]
+    Request(#[from] ResponseError),
+}
+
+/// The unit catalog, indexed by external id, symbol and alias.
+#[derive(Debug, Clone, Default)]
+pub struct UnitCatalog {
+    units: Vec<Unit>,
+    exact: HashMap<String, usize>,
+    folded: HashMap<String, usize>,
+}
+
+impl UnitCatalog {
+    pub fn new(units: Vec<Unit>) -> Self {
+        let mut exact = HashMap::new();
+        let mut folded = HashMap::new();
+        let mut index = |key: &str, i: usize| {
+            exact.entry(key.to_string()).or_insert(i);
+            folded.entry(key.to_lowercase()).or_insert(i);
+        };
+        // External ids first, so they win over a symbol or alias spelled the same way; within
+        // each kind, the first unit in catalog order wins.
+        for (i, unit) in units.iter().enumerate() {
+            index(&unit.external_id, i);
+        }
+        for (i, unit) in units.iter().enumerate() {
+            index(&unit.symbol, i);
+        }
+        for (i, unit) in units.iter().enumerate() {
+            for alias in &unit.alias_names {
+                index(alias, i);
+            }
+        }
+        UnitCatalog {
+            units,
+            exact,
+            folded,
+        }
+    }
+
+    pub fn units(&self) -> &[Unit] {
+        &self.units
+    }
+
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+
+    /// Find a unit by external id, symbol or alias — exact match first, then ignoring case.
+    pub fn get(&self, key: &str) -> Option<&Unit> {
+        self.exact
+            .get(key)
+            .or_else(|| self.folded.get(&key.to_lowercase()))
+            .map(|i| &self.units[*i])
+    }
+
+    pub fn by_external_id(&self, external_id: &str) -> Option<&Unit> {
+        self.units.iter().find(|u| u.external_id == external_id)
+    }
+
+    /// All units measuring `quantity` (ignoring case).
+    pub fn by_quantity(&self, quantity: &str) -> Vec<&Unit> {
+        self.units
+            .iter()
+            .filter(|u| u.quantity.eq_ignore_ascii_case(quantity))
+            .collect()
+    }
+
+    /// Like [`get`](Self::get), failing with [`UnitError::UnknownUnit`].
+    pub fn require(&self, key: &str) -> Result<&Unit, UnitError> {
+        self.get(key)
+            .ok_or_else(|| UnitError::UnknownUnit(key.to_string()))
+    }
+
+    /// Whether `a` and `b` measure the same quantity. False if either is unknown.
+    pub fn compatible(&self, a: &str, b: &str) -> bool {
+        match (self.get(a), self.get(b)) {
+            (Some(a), Some(b)) => a.is_compatible_with(b),
+            _ => false,
+        }
+    }
+
+    pub fn converter(&self, from: &str, to: &str) -> Result<UnitConverter, UnitError> {
+        UnitConverter::new(self.require(from)?, self.require(to)?)
+    }
+
+    pub fn convert(&self, value: f64, from: &str, to: &str) -> Result<f64, UnitError> {
+        Ok(self.converter(from, to)?.convert(value))
+    }
+}
+
+impl Unit {
+    pub fn is_compatible_with(&self, other: &Unit) -> bool {
+        self.quantity.eq_ignore_ascii_case(&other.quantity)
+    }
+
+    /// `(multiplier, offset)` to the quantity's base unit.
+    fn to_base(&self) -> Result<(f64, f64), UnitError> {
+        let multiplier = self
+            .conversion
+            .get("multiplier")
+            .copied()
+            .filter(|m| m.is_finite() && *m != 0.0)
+            .ok_or_else(|| UnitError::MissingConversion(self.external_id.clone()))?;
+        let offset = self.conversion.get("offset").copied().unwrap_or(0.0);
+        Ok((multiplier, offset))
+    }
+}
+
+/// A linear conversion between two compatible units: `to = from * scale + shift`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitConverter {
+    scale: f64,
+    shift: f64,
+}
+
+impl UnitConverter {
+    pub fn new(from: &Unit, to: &Unit) -> Result<Self, UnitError> {
+        if !from.is_compatible_with(to) {
+            return Err(UnitError::IncompatibleQuantities {
+                from: from.external_id.clone(),
+                from_quantity: from.quantity.clone(),
+                to: to.external_id.clone(),
+                to_quantity: to.quantity.clone(),
+            });
+        }
+        if from.external_id == to.external_id {
+            return Ok(UnitConverter::identity());
+        }
+        let (m_from, o_from) = from.to_base()?;
+        let (m_to, o_to) = to.to_base()?;
+        Ok(UnitConverter {
+            scale: m_from / m_to,
+            shift: (o_from - o_to) / m_to,
+        })
+    }
+
+    pub fn identity() -> Self {
+        UnitConverter {
+            scale: 1.0,
+            shift: 0.0,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.scale == 1.0 && self.shift == 0.0
+    }
+
+    pub fn convert(&self, value: f64) -> f64 {
+        value * self.scale + self.shift
+    }
+
+    /// Convert a retrieved datapoint's value and aggregates. A sum can't be converted through an
+    /// offset (e.g. °C to °F) without the count behind it, so it is cleared in that case.
+    pub fn convert_datapoint(&self, dp: &mut Datapoint) {
+        for v in [&mut dp.value, &mut dp.min, &mut dp.max, &mut dp.average]
+            .into_iter()
+            .flatten()
+        {
+            *v = self.convert(*v);
+        }
+        dp.sum = if self.shift == 0.0 {
+            dp.sum.map(|s| s * self.scale)
+        } else {
+            None
+        };
+    }
+
+    /// Convert a datapoint on its way in. Fails on a non-numeric value.
+    pub fn convert_datapoint_string(&self, dp: &mut DatapointString) -> Result<(), UnitError> {
+        let value = dp
+            .value
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| UnitError::NonNumericValue(dp.value.clone()))?;
+        dp.value = self.convert(value).to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::{DataWrapper, DatapointsCollection, RetrieveFilter};
+    use crate::tests::mock_backend::MockBackend;
+
+    fn unit(
+        external_id: &str,
+        symbol: &str,
+        aliases: &[&str],
+        quantity: &str,
+        multiplier: f64,
+        offset: f64,
+    ) -> Unit {
+        Unit {
+            id: 0,
+            external_id: external_id.to_string(),
+            name: external_id.to_string(),
+            long_name: external_id.to_string(),
+            symbol: symbol.to_string(),
+            description: String::new(),
+            alias_names: aliases.iter().map(|a| a.to_string()).collect(),
+            quantity: quantity.to_string(),
+            conversion: HashMap::from([
+                ("multiplier".to_string(), multiplier),
+                ("offset".to_string(), offset),
+            ]),
+            source: "test".to_string(),
+            source_reference: String::new(),
+        }
+    }
+
+    fn catalog() -> UnitCatalog {
+        UnitCatalog::new(vec![
+            unit(
+                "temperature:deg_c",
+                "°C",
+                &["degC", "Celsius"],
+                "Temperature",
+                1.0,
+                273.15,
+            ),
+            unit(
+                "temperature:deg_f",
+                "°F",
+                &["degF", "Fahrenheit"],
+                "Temperature",
+                5.0 / 9.0,
+                255.372_222_222_222_2,
+            ),
+            unit("temperature:k", "K", &["kelvin"], "Temperature", 1.0, 0.0),
+            unit("pressure:bar", "bar", &[], "Pressure", 100_000.0, 0.0),
+            unit("pressure:kilopa", "kPa", &[], "Pressure", 1_000.0, 0.0),
+        ])
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn looks_up_by_external_id_symbol_and_alias() {
+        let c = catalog();
+        assert_eq!(c.get("temperature:deg_c").unwrap().symbol, "°C");
+        assert_eq!(c.get("°F").unwrap().external_id, "temperature:deg_f");
+        assert_eq!(c.get("degF").unwrap().external_id, "temperature:deg_f");
+        assert_eq!(c.get("CELSIUS").unwrap().external_id, "temperature:deg_c");
+        assert!(c.get("furlong").is_none());
+        assert_eq!(c.by_quantity("pressure").len(), 2);
+    }
+
+    #[test]
+    fn converts_affine_and_linear_units() {
+        let c = catalog();
+        assert!(close(c.convert(100.0, "degC", "degF").unwrap(), 212.0));
+        assert!(close(c.convert(32.0, "degF", "degC").unwrap(), 0.0));
+        assert!(close(c.convert(0.0, "degC", "K").unwrap(), 273.15));
+        assert!(close(c.convert(1.0, "bar", "kPa").unwrap(), 100.0));
+        assert!(c.converter("degC", "degC").unwrap().is_identity());
+    }
+
+    #[test]
+    fn incompatible_quantities_are_rejected() {
+        let c = catalog();
+        assert!(!c.compatible("degC", "bar"));
+        assert!(matches!(
+            c.converter("degC", "bar"),
+            Err(UnitError::IncompatibleQuantities { .. })
+        ));
+        assert!(matches!(
+            c.converter("degC", "furlong"),
+            Err(UnitError::UnknownUnit(u)) if u == "furlong"
+        ));
+    }
+
+    #[test]
+    fn sums_are_only_converted_without_an_offset() {
+        let c = catalog();
+        let mut dp = Datapoint {
+            timestamp: chrono::Utc::now(),
+            value: None,
+            min: Some(0.0),
+            max: Some(100.0),
+            average: Some(50.0),
+            sum: Some(200.0),
+        };
+        let mut pressure = dp.clone();
+        c.converter("degC", "degF")
+            .unwrap()
+            .convert_datapoint(&mut dp);
+        assert!(close(dp.min.unwrap(), 32.0));
+        assert!(close(dp.max.unwrap(), 212.0));
+        assert!(close(dp.average.unwrap(), 122.0));
+        assert_eq!(dp.sum, None);
+
+        c.converter("bar", "kPa")
+            .unwrap()
+            .convert_datapoint(&mut pressure);
+        assert!(close(pressure.sum.unwrap(), 20_000.0));
+    }
+
+    fn unit_json(u: &Unit) -> serde_json::Value {
+        serde_json::to_value(u).unwrap()
+    }
+
+    #[tokio::test]
+    async fn retrieve_and_insert_convert_through_the_cached_catalog() {
+        let units: Vec<serde_json::Value> = catalog().units().iter().map(unit_json).collect();
+        let backend = MockBackend::start(move |req| match req.path.as_str() {
+            "/units" => (200, serde_json::json!({ "items": units }).to_string()),
+            "/timeseries/byids" => (
+                200,
+                serde_json::json!({ "items": [{
+                    "id": "7", "externalId": "oven", "name": "oven", "metadata": null,
+                    "unit": "°C", "description": null, "unitExternalId": "temperature:deg_c",
+                    "securityCategories": null, "dataSetId": null, "valueType": "float",
+                    "createdTime": null, "lastUpdatedTime": null
+                }]})
+                .to_string(),
+            ),
+            "/timeseries/data/list" => (
+                200,
+                serde_json::json!({ "items": [{
+                    "id": "7", "externalId": "oven", "nextCursor": null,
+                    "unit": null, "unitExternalId": null,
+                    "datapoints": [{ "timestamp": "2024-01-01T00:00:00Z", "value": 100.0 }]
+                }]})
+                .to_string(),
+            ),
+            _ => (204, String::new()),
+        })
+        .await;
+        let api = backend.service();
+
+        let filter = RetrieveFilter {
+            external_id: Some("oven".to_string()),
+            ..Default::default()
+        };
+        let result = api
+            .time_series
+            .retrieve_datapoints_in_unit(&DataWrapper::from_vec(vec![filter.clone()]), "degF")
+            .await
+            .unwrap();
+        let collection = &result.get_items()[0];
+        assert!(close(collection.datapoints[0].value.unwrap(), 212.0));
+        assert_eq!(
+            collection.unit_external_id.as_deref(),
+            Some("temperature:deg_f")
+        );
+
+        let mut insert = DataWrapper::new();
+        let mut points = DatapointsCollection::from_external_id("oven");
+        points.unit_external_id = Some("temperature:deg_f".to_string());
+        points.datapoints.push(DatapointString::new("0", "212"));
+        insert.add_item(points);
+        api.time_series
+            .insert_datapoints_in_unit(&mut insert, "degC")
+            .await
+            .unwrap();
+        let sent = backend.requests_to("/timeseries/data");
+        assert_eq!(sent.len(), 1);
+        let value: f64 = sent[0].json()["items"][0]["datapoints"][0]["value"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(close(value, 100.0));
+
+        let err = api
+            .time_series
+            .retrieve_datapoints_in_unit(&DataWrapper::from_vec(vec![filter]), "bar")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, UnitError::IncompatibleQuantities { .. }),
+            "{err}"
+        );
+
+        // The catalog was fetched once and served from the cache afterwards.
+        assert_eq!(backend.requests_to("/units").len(), 1);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod catalog;
 mod test;
 
 use crate::generic::{ApiServiceProvider, DataWrapper, IdAndExtId};
@@ -6,11 +7,15 @@ use crate::ApiService;
 use serde::{Deserialize, Serialize};
 use std::clone::Clone;
 use std::collections::HashMap;
-use std::sync::Weak;
+use std::sync::{Arc, Mutex, Weak};
+
+pub use catalog::{UnitCatalog, UnitConverter, UnitError};
 
 pub struct UnitsService {
     pub(crate) api_service: Weak<ApiService>,
     base_url: String,
+    // The unit catalog, fetched on first use by `catalog()`.
+    catalog: Mutex<Option<Arc<UnitCatalog>>>,
 }
 
 impl UnitsService {
@@ -19,7 +24,25 @@ impl UnitsService {
         UnitsService {
             api_service,
             base_url: unit_base_url,
+            catalog: Mutex::new(None),
+        }
+    }
+
+    /// The unit catalog, fetched with [`list`](Self::list) on first use and cached for the life
+    /// of the client.
+    pub async fn catalog(&self) -> Result<Arc<UnitCatalog>, ResponseError> {
+        if let Some(catalog) = self.catalog.lock().unwrap().as_ref() {
+            return Ok(catalog.clone());
         }
+        self.refresh_catalog().await
+    }
+
+    /// Re-fetch the unit catalog, replacing the cached copy.
+    pub async fn refresh_catalog(&self) -> Result<Arc<UnitCatalog>, ResponseError> {
+        let units = self.list().await?;
+        let catalog = Arc::new(UnitCatalog::new(units.get_items().clone()));
+        *self.catalog.lock().unwrap() = Some(catalog.clone());
+        Ok(catalog)
     }
 
     pub async fn list(&self) -> Result<DataWrapper<Unit>, ResponseError> {